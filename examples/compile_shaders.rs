@@ -0,0 +1,30 @@
+//! Writes the `.spv` fallbacks the app loads when one of its shaders fails to compile at runtime, next to each shader.
+//! Run from the repository root after changing a shader or anything it includes:
+//!
+//!     cargo run --example compile_shaders
+
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+use ash_test::app::scene::runtime_shader_keys;
+use ash_test::vk_assist::shader_compiler;
+
+fn main() {
+    let mut failed = false;
+    for key in runtime_shader_keys() {
+        let output = PathBuf::from(format!("{}.spv", key.path.display()));
+        let written =
+            shader_compiler::compile_glsl(&key.path, &key.defines).and_then(|shader| fs::write(&output, &shader.spirv).map_err(|err| err.to_string()));
+        match written {
+            Ok(()) => println!("{} -> {}", key.path.display(), output.display()),
+            Err(err) => {
+                println!("Failed to compile {}:\n{}", key.path.display(), err);
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+}
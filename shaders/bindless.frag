@@ -0,0 +1,40 @@
+#version 450
+
+#extension GL_ARB_separate_shader_objects : enable
+#extension GL_EXT_nonuniform_qualifier : enable
+
+#include "lighting.glsl"
+
+struct Material {
+    vec4 tint;
+    float roughness;
+    float metallic;
+    uint textureSlot;
+    uint shading;
+};
+
+layout (set = 1, binding = 0) uniform texture2D textures[];
+layout (set = 1, binding = 1) uniform sampler textureSampler;
+layout (set = 1, binding = 2) readonly buffer Materials {
+    Material materials[];
+};
+
+layout(push_constant) uniform PushConstants {
+    mat4 model;
+    uint material;
+} pushConstants;
+
+layout (location = 0) in vec3 fragColor;
+layout (location = 1) in vec2 fragTexCoord;
+layout (location = 2) in vec3 fragWorldPos;
+layout (location = 3) in vec3 fragNormal;
+layout (location = 4) flat in vec3 fragCameraPos;
+
+layout (location = 0) out vec4 outColor;
+
+void main() {
+    Material material = materials[pushConstants.material];
+    vec4 albedo = texture(sampler2D(textures[nonuniformEXT(material.textureSlot)], textureSampler), fragTexCoord) * material.tint;
+    vec3 color = shade(material.shading, albedo.rgb, material.roughness, material.metallic, fragWorldPos, fragNormal, fragCameraPos);
+    outColor = vec4(color, albedo.a);
+}
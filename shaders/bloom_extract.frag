@@ -0,0 +1,24 @@
+#version 450
+
+#extension GL_ARB_separate_shader_objects : enable
+
+#include "post.glsl"
+
+layout (set = 0, binding = 0) uniform sampler postSampler;
+layout (set = 0, binding = 1) uniform texture2D source;
+
+layout (location = 0) in vec2 fragTexCoord;
+
+layout (location = 0) out vec4 outColor;
+
+// Keeps what is brighter than the threshold after exposure. The soft knee fades bright parts into the bloom instead of
+// popping them in.
+void main() {
+    vec3 color = texture(sampler2D(source, postSampler), fragTexCoord).rgb * post.exposure;
+    float brightness = max(color.r, max(color.g, color.b));
+    float knee = post.bloomThreshold * 0.5;
+    float soft = clamp(brightness - post.bloomThreshold + knee, 0.0, 2.0 * knee);
+    soft = soft * soft / (4.0 * knee + 0.0001);
+    float contribution = max(soft, brightness - post.bloomThreshold) / max(brightness, 0.0001);
+    outColor = vec4(color * contribution, 1.0);
+}
@@ -0,0 +1,25 @@
+#version 450
+
+#extension GL_ARB_separate_shader_objects : enable
+
+#include "post.glsl"
+
+layout (set = 0, binding = 0) uniform sampler postSampler;
+layout (set = 0, binding = 1) uniform texture2D source;
+
+layout (location = 0) in vec2 fragTexCoord;
+
+layout (location = 0) out vec4 outColor;
+
+// A 9 tap Gaussian along blurDirection in 5 lookups, each linear lookup between two taps weighing both.
+void main() {
+    vec2 step = post.blurDirection * post.texelSize;
+    vec2 near = step * 1.3846153846;
+    vec2 far = step * 3.2307692308;
+    vec3 color = texture(sampler2D(source, postSampler), fragTexCoord).rgb * 0.2270270270;
+    color += texture(sampler2D(source, postSampler), fragTexCoord + near).rgb * 0.3162162162;
+    color += texture(sampler2D(source, postSampler), fragTexCoord - near).rgb * 0.3162162162;
+    color += texture(sampler2D(source, postSampler), fragTexCoord + far).rgb * 0.0702702703;
+    color += texture(sampler2D(source, postSampler), fragTexCoord - far).rgb * 0.0702702703;
+    outColor = vec4(color, 1.0);
+}
@@ -1,18 +0,0 @@
-C:/VulkanSDK/1.2.148.1/Bin32/glslc.exe vertexbuffer.vert -o vertexbuffer.vert.spv
-C:/VulkanSDK/1.2.148.1/Bin32/glslc.exe vertexbuffer.frag -o vertexbuffer.frag.spv
-
-C:/VulkanSDK/1.2.148.1/Bin32/glslc.exe base.vert -o base.vert.spv
-C:/VulkanSDK/1.2.148.1/Bin32/glslc.exe base.frag -o base.frag.spv
-
-C:/VulkanSDK/1.2.148.1/Bin32/glslc.exe ubo.vert -o ubo.vert.spv
-C:/VulkanSDK/1.2.148.1/Bin32/glslc.exe ubo.frag -o ubo.frag.spv
-
-C:/VulkanSDK/1.2.148.1/Bin32/glslc.exe texture.vert -o texture.vert.spv
-C:/VulkanSDK/1.2.148.1/Bin32/glslc.exe texture.frag -o texture.frag.spv
-
-C:/VulkanSDK/1.2.148.1/Bin32/glslc.exe depthbuffer.vert -o depthbuffer.vert.spv
-C:/VulkanSDK/1.2.148.1/Bin32/glslc.exe depthbuffer.frag -o depthbuffer.frag.spv
-
-C:/VulkanSDK/1.2.148.1/Bin32/glslc.exe pushconst.vert -o pushconst.vert.spv
-C:/VulkanSDK/1.2.148.1/Bin32/glslc.exe pushconst.frag -o pushconst.frag.spv
-pause
\ No newline at end of file
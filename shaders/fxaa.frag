@@ -0,0 +1,48 @@
+#version 450
+
+#extension GL_ARB_separate_shader_objects : enable
+
+#include "post.glsl"
+
+layout (set = 0, binding = 0) uniform sampler postSampler;
+layout (set = 0, binding = 1) uniform texture2D source;
+
+layout (location = 0) in vec2 fragTexCoord;
+
+layout (location = 0) out vec4 outColor;
+
+const float FXAA_SPAN_MAX = 8.0;
+const float FXAA_REDUCE_MUL = 1.0 / 8.0;
+const float FXAA_REDUCE_MIN = 1.0 / 128.0;
+
+float luma(vec3 color) {
+    return dot(color, vec3(0.299, 0.587, 0.114));
+}
+
+vec3 fetch(vec2 texCoord) {
+    return texture(sampler2D(source, postSampler), texCoord).rgb;
+}
+
+// FXAA on the display-encoded image: blurs along the edge the luma of the 4 diagonal neighbors runs across, falling
+// back to the shorter blur when the longer one picks up colors from past the edge.
+void main() {
+    vec3 colorM = fetch(fragTexCoord);
+    float lumaNW = luma(fetch(fragTexCoord + vec2(-1.0, -1.0) * post.texelSize));
+    float lumaNE = luma(fetch(fragTexCoord + vec2(1.0, -1.0) * post.texelSize));
+    float lumaSW = luma(fetch(fragTexCoord + vec2(-1.0, 1.0) * post.texelSize));
+    float lumaSE = luma(fetch(fragTexCoord + vec2(1.0, 1.0) * post.texelSize));
+    float lumaM = luma(colorM);
+    float lumaMin = min(lumaM, min(min(lumaNW, lumaNE), min(lumaSW, lumaSE)));
+    float lumaMax = max(lumaM, max(max(lumaNW, lumaNE), max(lumaSW, lumaSE)));
+
+    vec2 direction = vec2(-((lumaNW + lumaNE) - (lumaSW + lumaSE)), (lumaNW + lumaSW) - (lumaNE + lumaSE));
+    float directionReduce = max((lumaNW + lumaNE + lumaSW + lumaSE) * (0.25 * FXAA_REDUCE_MUL), FXAA_REDUCE_MIN);
+    float inverseDirectionMin = 1.0 / (min(abs(direction.x), abs(direction.y)) + directionReduce);
+    direction = clamp(direction * inverseDirectionMin, vec2(-FXAA_SPAN_MAX), vec2(FXAA_SPAN_MAX)) * post.texelSize;
+
+    vec3 colorA = 0.5 * (fetch(fragTexCoord + direction * (1.0 / 3.0 - 0.5)) + fetch(fragTexCoord + direction * (2.0 / 3.0 - 0.5)));
+    vec3 colorB = colorA * 0.5 + 0.25 * (fetch(fragTexCoord - direction * 0.5) + fetch(fragTexCoord + direction * 0.5));
+    float lumaB = luma(colorB);
+    vec3 color = (lumaB < lumaMin || lumaB > lumaMax) ? colorA : colorB;
+    outColor = vec4(toTarget(color), 1.0);
+}
@@ -0,0 +1,162 @@
+// Lights, their shadows and the two shading models, shared by the static and bindless fragment shaders. Laid out
+// like `Lights` in src/app/lights.rs and `Shadows` in src/app/shadows.rs.
+
+const uint LIGHT_DIRECTIONAL = 0;
+const uint LIGHT_POINT = 1;
+const uint LIGHT_SPOT = 2;
+
+const uint SHADING_BLINN_PHONG = 0;
+const uint SHADING_PBR = 1;
+
+const float PI = 3.14159265;
+
+struct Light {
+    vec4 positionRange;
+    vec4 directionOuterCos;
+    vec4 colorIntensity;
+    uint kind;
+    float innerCos;
+    // First shadow map in the atlas, or -1 without shadows. Directional lights have one per cascade.
+    int shadowMap;
+    uint _padding;
+};
+
+layout (std430, set = 0, binding = 1) readonly buffer Lights {
+    vec4 ambient;
+    uint lightCount;
+    Light lights[];
+} sceneLights;
+
+layout (set = 0, binding = 2) uniform texture2D shadowAtlas;
+layout (set = 0, binding = 3) uniform samplerShadow shadowSampler;
+layout (std430, set = 0, binding = 4) readonly buffer Shadows {
+    uint atlasTiles;
+    uint cascadeCount;
+    int pcfRadius;
+    float texelSize;
+    mat4 matrices[];
+} shadows;
+
+// Pushes the lookup off the surface a little, so it doesn't shadow itself.
+const float SHADOW_NORMAL_OFFSET = 0.02;
+
+// Fraction of the light shadow map `map` lets through to `worldPos`, averaged over the PCF kernel, or -1 when the
+// point is outside the map.
+float sampleShadowMap(uint map, vec3 worldPos, vec3 N) {
+    vec4 clip = shadows.matrices[map] * vec4(worldPos + N * SHADOW_NORMAL_OFFSET, 1.0);
+    if (clip.w <= 0.0) {
+        return -1.0;
+    }
+    vec3 coord = clip.xyz / clip.w;
+    vec2 uv = coord.xy * 0.5 + 0.5;
+    if (any(lessThan(uv, vec2(0.0))) || any(greaterThan(uv, vec2(1.0))) || coord.z > 1.0) {
+        return -1.0;
+    }
+
+    // Kept inside the map's tile, so the kernel doesn't pick up its neighbors.
+    float tiles = float(shadows.atlasTiles);
+    vec2 tileMin = vec2(float(map % shadows.atlasTiles), float(map / shadows.atlasTiles)) / tiles;
+    vec2 tileMax = tileMin + vec2(1.0 / tiles);
+    vec2 atlasUv = tileMin + uv / tiles;
+    float lit = 0.0;
+    for (int x = -shadows.pcfRadius; x <= shadows.pcfRadius; x++) {
+        for (int y = -shadows.pcfRadius; y <= shadows.pcfRadius; y++) {
+            vec2 sampleUv = clamp(atlasUv + vec2(float(x), float(y)) * shadows.texelSize, tileMin, tileMax - vec2(shadows.texelSize));
+            lit += texture(sampler2DShadow(shadowAtlas, shadowSampler), vec3(sampleUv, coord.z));
+        }
+    }
+    float kernel = float(2 * shadows.pcfRadius + 1);
+    return lit / (kernel * kernel);
+}
+
+// Fraction of `light` reaching `worldPos` past the shadow casters. Directional lights use the finest cascade covering
+// the point; past the last one nothing is shadowed.
+float shadowFactor(Light light, vec3 worldPos, vec3 N) {
+    if (light.shadowMap < 0) {
+        return 1.0;
+    }
+    uint mapCount = light.kind == LIGHT_DIRECTIONAL ? shadows.cascadeCount : 1;
+    for (uint i = 0; i < mapCount; i++) {
+        float lit = sampleShadowMap(uint(light.shadowMap) + i, worldPos, N);
+        if (lit >= 0.0) {
+            return lit;
+        }
+    }
+    return 1.0;
+}
+
+// Direction from the surface towards the light in `toLight`, and the light's radiance reaching the surface.
+vec3 incomingLight(Light light, vec3 worldPos, out vec3 toLight) {
+    vec3 radiance = light.colorIntensity.rgb * light.colorIntensity.a;
+    if (light.kind == LIGHT_DIRECTIONAL) {
+        toLight = -light.directionOuterCos.xyz;
+        return radiance;
+    }
+
+    vec3 offset = light.positionRange.xyz - worldPos;
+    float dist = length(offset);
+    toLight = offset / max(dist, 0.0001);
+    // Inverse square, windowed so it reaches zero at the light's range.
+    float window = clamp(1.0 - pow(dist / light.positionRange.w, 4.0), 0.0, 1.0);
+    float attenuation = window * window / max(dist * dist, 0.0001);
+    if (light.kind == LIGHT_SPOT) {
+        float cosAngle = dot(-toLight, light.directionOuterCos.xyz);
+        attenuation *= smoothstep(light.directionOuterCos.w, light.innerCos, cosAngle);
+    }
+    return radiance * attenuation;
+}
+
+vec3 blinnPhong(vec3 albedo, float roughness, vec3 N, vec3 V, vec3 L, vec3 radiance) {
+    vec3 H = normalize(L + V);
+    float NdotL = max(dot(N, L), 0.0);
+    // At least 1, as fully rough surfaces would otherwise raise to the power 0 and light up everywhere.
+    float shininess = max(2.0 / max(roughness * roughness * roughness * roughness, 0.001) - 2.0, 1.0);
+    float specular = pow(max(dot(N, H), 0.0), shininess) * (shininess + 8.0) / (8.0 * PI);
+    return (albedo / PI + vec3(0.04) * specular) * radiance * NdotL;
+}
+
+float distributionGGX(float NdotH, float roughness) {
+    float a = roughness * roughness;
+    float a2 = a * a;
+    float d = NdotH * NdotH * (a2 - 1.0) + 1.0;
+    return a2 / max(PI * d * d, 0.0001);
+}
+
+float geometrySmith(float NdotV, float NdotL, float roughness) {
+    float k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+    return NdotV / (NdotV * (1.0 - k) + k) * NdotL / (NdotL * (1.0 - k) + k);
+}
+
+vec3 fresnelSchlick(float cosTheta, vec3 F0) {
+    return F0 + (1.0 - F0) * pow(clamp(1.0 - cosTheta, 0.0, 1.0), 5.0);
+}
+
+vec3 pbr(vec3 albedo, float roughness, float metallic, vec3 N, vec3 V, vec3 L, vec3 radiance) {
+    vec3 H = normalize(L + V);
+    float NdotL = max(dot(N, L), 0.0);
+    float NdotV = max(dot(N, V), 0.0001);
+    vec3 F0 = mix(vec3(0.04), albedo, metallic);
+    vec3 F = fresnelSchlick(max(dot(H, V), 0.0), F0);
+    vec3 specular = distributionGGX(max(dot(N, H), 0.0), roughness) * geometrySmith(NdotV, NdotL, roughness) * F / (4.0 * NdotV * max(NdotL, 0.0001));
+    vec3 diffuse = (vec3(1.0) - F) * (1.0 - metallic) * albedo / PI;
+    return (diffuse + specular) * radiance * NdotL;
+}
+
+// Color of a surface lit by every light in the scene.
+vec3 shade(uint shading, vec3 albedo, float roughness, float metallic, vec3 worldPos, vec3 normal, vec3 cameraPos) {
+    vec3 N = normalize(normal);
+    vec3 V = normalize(cameraPos - worldPos);
+    roughness = clamp(roughness, 0.04, 1.0);
+    vec3 color = sceneLights.ambient.rgb * albedo;
+    for (uint i = 0; i < sceneLights.lightCount; i++) {
+        vec3 L;
+        Light light = sceneLights.lights[i];
+        vec3 radiance = incomingLight(light, worldPos, L) * shadowFactor(light, worldPos, N);
+        if (shading == SHADING_BLINN_PHONG) {
+            color += blinnPhong(albedo, roughness, N, V, L, radiance);
+        } else {
+            color += pbr(albedo, roughness, metallic, N, V, L, radiance);
+        }
+    }
+    return color;
+}
@@ -0,0 +1,32 @@
+// Shared by the post-processing passes. Every pass pushes the same constants, laid out like PostConstants in post.rs.
+
+layout (push_constant) uniform PostConstants {
+    vec2 texelSize;
+    vec2 blurDirection;
+    float exposure;
+    float gamma;
+    float bloomThreshold;
+    float bloomIntensity;
+    uint tonemapper;
+    uint flags;
+} post;
+
+const uint POST_BLOOM = 1u;
+const uint POST_GAMMA = 2u;
+const uint POST_GRADING = 4u;
+const uint POST_SRGB_TARGET = 8u;
+
+vec3 srgbToLinear(vec3 color) {
+    vec3 low = color / 12.92;
+    vec3 high = pow((color + 0.055) / 1.055, vec3(2.4));
+    return mix(high, low, vec3(lessThanEqual(color, vec3(0.04045))));
+}
+
+// Display-encoded color as the pass's target stores it. sRGB targets encode on write, so it is decoded first. Without
+// gamma correction the color is linear and written as it is.
+vec3 toTarget(vec3 display) {
+    if ((post.flags & POST_SRGB_TARGET) != 0u && (post.flags & POST_GAMMA) != 0u) {
+        return srgbToLinear(display);
+    }
+    return display;
+}
@@ -3,14 +3,28 @@
 
 #extension GL_ARB_separate_shader_objects : enable
 
-layout (binding = 1) uniform sampler2D texSampler;
+#include "lighting.glsl"
+
+layout (set = 1, binding = 0) uniform texture2D diffuseTexture;
+layout (set = 1, binding = 1) uniform sampler diffuseSampler;
+layout (set = 1, binding = 2) uniform MaterialParams {
+    vec4 tint;
+    float roughness;
+    float metallic;
+    uint shading;
+} material;
 
 layout (location = 0) in vec3 fragColor;
 layout (location = 1) in vec2 fragTexCoord;
+layout (location = 2) in vec3 fragWorldPos;
+layout (location = 3) in vec3 fragNormal;
+layout (location = 4) flat in vec3 fragCameraPos;
 
 layout (location = 0) out vec4 outColor;
 
 void main() {
 
-    outColor = texture(texSampler, fragTexCoord);
+    vec4 albedo = texture(sampler2D(diffuseTexture, diffuseSampler), fragTexCoord) * material.tint;
+    vec3 color = shade(material.shading, albedo.rgb, material.roughness, material.metallic, fragWorldPos, fragNormal, fragCameraPos);
+    outColor = vec4(color, albedo.a);
 }
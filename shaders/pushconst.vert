@@ -6,9 +6,13 @@
 layout (location = 0) in vec3 inPosition;
 layout (location = 1) in vec3 inColor;
 layout (location = 2) in vec2 inTexCoord;
+layout (location = 3) in vec3 inNormal;
 
 layout (location = 0) out vec3 fragColor;
 layout (location = 1) out vec2 fragTexCoord;
+layout (location = 2) out vec3 fragWorldPos;
+layout (location = 3) out vec3 fragNormal;
+layout (location = 4) flat out vec3 fragCameraPos;
 
 layout (set = 0, binding = 0) uniform UniformBufferObject {
     mat4 view;
@@ -24,7 +28,12 @@ out gl_PerVertex {
 
 void main() {
 
-    gl_Position = ubo.proj * ubo.view * pushConstants.model * vec4(inPosition, 1.0);
+    vec4 worldPos = pushConstants.model * vec4(inPosition, 1.0);
+    gl_Position = ubo.proj * ubo.view * worldPos;
     fragColor = inColor;
     fragTexCoord = inTexCoord;
+    fragWorldPos = worldPos.xyz;
+    // The inverse transpose keeps normals perpendicular to their surface when the model is scaled unevenly.
+    fragNormal = transpose(inverse(mat3(pushConstants.model))) * inNormal;
+    fragCameraPos = -(transpose(mat3(ubo.view)) * ubo.view[3].xyz);
 }
\ No newline at end of file
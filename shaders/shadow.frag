@@ -0,0 +1,7 @@
+#version 450
+
+#extension GL_ARB_separate_shader_objects : enable
+
+// Depth only, the shadow pass has no color attachment.
+void main() {
+}
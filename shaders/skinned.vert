@@ -0,0 +1,46 @@
+#version 450
+
+#extension GL_ARB_separate_shader_objects : enable
+
+layout (location = 0) in vec3 inPosition;
+layout (location = 1) in vec3 inColor;
+layout (location = 2) in vec2 inTexCoord;
+layout (location = 3) in vec3 inNormal;
+layout (location = 5) in uvec4 inJoints;
+layout (location = 6) in vec4 inWeights;
+
+layout (location = 0) out vec3 fragColor;
+layout (location = 1) out vec2 fragTexCoord;
+layout (location = 2) out vec3 fragWorldPos;
+layout (location = 3) out vec3 fragNormal;
+layout (location = 4) flat out vec3 fragCameraPos;
+
+layout (set = 0, binding = 0) uniform UniformBufferObject {
+    mat4 view;
+    mat4 proj;
+} ubo;
+layout (std430, set = 2, binding = 0) readonly buffer JointMatrices {
+    mat4 joints[];
+} jointMatrices;
+layout(push_constant) uniform PushConstants {
+    mat4 model;
+    uint jointOffset;
+} pushConstants;
+
+void main() {
+    uint base = pushConstants.jointOffset;
+    mat4 skin = inWeights.x * jointMatrices.joints[base + inJoints.x]
+              + inWeights.y * jointMatrices.joints[base + inJoints.y]
+              + inWeights.z * jointMatrices.joints[base + inJoints.z]
+              + inWeights.w * jointMatrices.joints[base + inJoints.w];
+
+    mat4 model = pushConstants.model * skin;
+    vec4 worldPos = model * vec4(inPosition, 1.0);
+    gl_Position = ubo.proj * ubo.view * worldPos;
+    fragColor = inColor;
+    fragTexCoord = inTexCoord;
+    fragWorldPos = worldPos.xyz;
+    // The inverse transpose keeps normals perpendicular to their surface when the model is scaled unevenly.
+    fragNormal = transpose(inverse(mat3(model))) * inNormal;
+    fragCameraPos = -(transpose(mat3(ubo.view)) * ubo.view[3].xyz);
+}
@@ -0,0 +1,63 @@
+#version 450
+
+#extension GL_ARB_separate_shader_objects : enable
+
+#include "post.glsl"
+
+layout (set = 0, binding = 0) uniform sampler postSampler;
+layout (set = 0, binding = 1) uniform texture2D hdrColor;
+layout (set = 0, binding = 2) uniform texture2D bloom;
+layout (set = 0, binding = 3) uniform texture2D gradingLut;
+
+layout (location = 0) in vec2 fragTexCoord;
+
+layout (location = 0) out vec4 outColor;
+
+// Side of the grading LUT's cube, as LUT_SIZE in post.rs.
+const float LUT_SIZE = 16.0;
+
+// Krzysztof Narkowicz's fit of the ACES filmic curve.
+vec3 aces(vec3 color) {
+    return clamp((color * (2.51 * color + 0.03)) / (color * (2.43 * color + 0.59) + 0.14), 0.0, 1.0);
+}
+
+vec3 reinhard(vec3 color) {
+    return color / (1.0 + color);
+}
+
+// Looks a display-encoded color up in the LUT: LUT_SIZE slices of red by green side by side, blue picking the slice,
+// blended between the two nearest slices.
+vec3 grade(vec3 color) {
+    float blue = color.b * (LUT_SIZE - 1.0);
+    float slice = floor(blue);
+    float nextSlice = min(slice + 1.0, LUT_SIZE - 1.0);
+    vec2 texel = (color.rg * (LUT_SIZE - 1.0) + 0.5) / vec2(LUT_SIZE * LUT_SIZE, LUT_SIZE);
+    vec3 low = texture(sampler2D(gradingLut, postSampler), texel + vec2(slice / LUT_SIZE, 0.0)).rgb;
+    vec3 high = texture(sampler2D(gradingLut, postSampler), texel + vec2(nextSlice / LUT_SIZE, 0.0)).rgb;
+    return mix(low, high, blue - slice);
+}
+
+void main() {
+    vec3 color = texture(sampler2D(hdrColor, postSampler), fragTexCoord).rgb * post.exposure;
+    if ((post.flags & POST_BLOOM) != 0u) {
+        color += texture(sampler2D(bloom, postSampler), fragTexCoord).rgb * post.bloomIntensity;
+    }
+
+    if (post.tonemapper == 0u) {
+        color = aces(color);
+    } else if (post.tonemapper == 1u) {
+        color = reinhard(color);
+    } else {
+        color = clamp(color, 0.0, 1.0);
+    }
+
+    // Left linear without gamma correction.
+    vec3 display = color;
+    if ((post.flags & POST_GAMMA) != 0u) {
+        display = pow(color, vec3(1.0 / post.gamma));
+    }
+    if ((post.flags & POST_GRADING) != 0u) {
+        display = grade(display);
+    }
+    outColor = vec4(toTarget(display), 1.0);
+}
@@ -0,0 +1,628 @@
+#![allow(dead_code)]
+
+use crate::vk_assist;
+use crate::vk_model;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use ash::vk;
+
+use vk_assist::gltf_loader;
+use vk_assist::mesh_cache;
+use vk_assist::model_loader::{self as mdl, ImportOptions};
+use vk_assist::primitives::Primitive;
+use vk_assist::types::buffer as bfr;
+use vk_assist::types::image as img;
+use vk_assist::types::vulkan_device::VulkanDevice;
+use vk_model::advanced_model::GFXModel;
+
+use super::materials::{MaterialParams, MaterialPipeline};
+use super::scene::MAX_FRAMES_IN_FLIGHT;
+
+/// Typed index into one of the registry's pools. The generation makes handles to freed and reused slots miss instead of
+/// resolving to whatever took the slot over.
+pub struct Handle<T> {
+    index: u32,
+    generation: u32,
+    _marker: PhantomData<fn() -> T>,
+}
+
+// Written out by hand since derive would require T to implement them too.
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Handle<T> {
+        *self
+    }
+}
+
+impl<T> Copy for Handle<T> {}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Handle<T>) -> bool {
+        self.index == other.index && self.generation == other.generation
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> Hash for Handle<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.index.hash(state);
+        self.generation.hash(state);
+    }
+}
+
+impl<T> fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Handle({}v{})", self.index, self.generation)
+    }
+}
+
+pub type TextureHandle = Handle<img::Image>;
+pub type MaterialHandle = Handle<Material>;
+pub type MeshHandle = Handle<GFXModel>;
+
+/// A named set of textures and shading parameters a mesh is drawn with.
+pub struct Material {
+    pub name: String,
+    pub diffuse: TextureHandle,
+    pub params: MaterialParams,
+    pub pipeline: MaterialPipeline,
+}
+
+struct Slot<T, D> {
+    generation: u32,
+    value: Option<Arc<T>>,
+    /// Handle references handed out by `load_*` and not yet released.
+    refs: u32,
+    key: String,
+    /// Handles this entry holds a reference to, released when it is freed.
+    depends: D,
+}
+
+/// Slots of one asset type, deduplicated by key.
+struct Pool<T, D> {
+    slots: Vec<Slot<T, D>>,
+    free_slots: Vec<u32>,
+    by_key: HashMap<String, u32>,
+}
+
+impl<T, D> Pool<T, D> {
+    fn new() -> Pool<T, D> {
+        Pool {
+            slots: vec![],
+            free_slots: vec![],
+            by_key: HashMap::new(),
+        }
+    }
+
+    fn slot(&self, handle: Handle<T>) -> Option<&Slot<T, D>> {
+        self.slots
+            .get(handle.index as usize)
+            .filter(|slot| slot.generation == handle.generation && slot.value.is_some())
+    }
+
+    fn handle(&self, index: u32) -> Handle<T> {
+        Handle {
+            index,
+            generation: self.slots[index as usize].generation,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Takes another reference to the live entry `handle` points at. Fails for stale handles.
+    fn acquire(&mut self, handle: Handle<T>) -> bool {
+        match self.slots.get_mut(handle.index as usize) {
+            Some(slot) if slot.generation == handle.generation && slot.value.is_some() => {
+                slot.refs += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Takes another reference to the entry stored under `key`, if there is one.
+    fn acquire_key(&mut self, key: &str) -> Option<Handle<T>> {
+        let index = *self.by_key.get(key)?;
+        self.slots[index as usize].refs += 1;
+        Some(self.handle(index))
+    }
+
+    fn insert(&mut self, key: String, value: T, depends: D) -> Handle<T> {
+        let index = match self.free_slots.pop() {
+            Some(index) => {
+                let slot = &mut self.slots[index as usize];
+                slot.value = Some(Arc::new(value));
+                slot.refs = 1;
+                slot.key = key.clone();
+                slot.depends = depends;
+                index
+            }
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    value: Some(Arc::new(value)),
+                    refs: 1,
+                    key: key.clone(),
+                    depends,
+                });
+                self.slots.len() as u32 - 1
+            }
+        };
+        self.by_key.insert(key, index);
+        self.handle(index)
+    }
+
+    fn release(&mut self, handle: Handle<T>) {
+        let slot = &mut self.slots[handle.index as usize];
+        if slot.generation != handle.generation || slot.value.is_none() {
+            println!("Released a stale asset handle {:?}", handle);
+            return;
+        }
+        if slot.refs == 0 {
+            println!("Released asset '{}' more often than it was loaded", slot.key);
+            return;
+        }
+        slot.refs -= 1;
+    }
+
+    /// Empties every slot that has no handle references left and whose value nothing outside the registry holds on
+    /// to, returning the values so their own resources and dependencies can be dealt with.
+    fn take_unused(&mut self) -> Vec<(T, D)>
+    where
+        D: Clone,
+    {
+        let mut unused = vec![];
+        for (index, slot) in self.slots.iter_mut().enumerate() {
+            let is_unused = slot.refs == 0 && slot.value.as_ref().is_some_and(|value| Arc::strong_count(value) == 1);
+            if !is_unused {
+                continue;
+            }
+            let value = slot.value.take().and_then(|value| Arc::try_unwrap(value).ok());
+            self.by_key.remove(&slot.key);
+            slot.generation = slot.generation.wrapping_add(1);
+            self.free_slots.push(index as u32);
+            if let Some(value) = value {
+                unused.push((value, slot.depends.clone()));
+            }
+        }
+        unused
+    }
+
+    fn live_count(&self) -> usize {
+        self.slots.iter().filter(|slot| slot.value.is_some()).count()
+    }
+}
+
+/// What a mesh is built from.
+#[derive(Clone, Debug)]
+pub enum MeshGeometry {
+    /// A model file, imported with these settings.
+    File {
+        path: PathBuf,
+        options: ImportOptions,
+    },
+    Primitive(Primitive),
+}
+
+/// Where a mesh was loaded from, kept so it can be reloaded.
+#[derive(Clone, Debug)]
+pub struct MeshSource {
+    pub geometry: MeshGeometry,
+    pub material: MaterialHandle,
+}
+
+impl MeshSource {
+    /// The model file and the files its import reads along with it, like the material libraries of OBJs. Primitives
+    /// read none.
+    pub fn files(&self) -> Vec<PathBuf> {
+        match &self.geometry {
+            MeshGeometry::File { path, .. } => std::iter::once(path.clone()).chain(mesh_cache::source_dependencies(path)).collect(),
+            MeshGeometry::Primitive(_) => vec![],
+        }
+    }
+}
+
+/// What one `AssetRegistry::reload` replaced.
+#[derive(Default)]
+pub struct Reloaded {
+    /// Textures whose image was replaced. Handles and bindless slots stay the same, only the descriptors pointing at
+    /// the old image need updating.
+    pub textures: Vec<TextureHandle>,
+    /// The old and new model of every reloaded mesh, for the caller to swap into its instances. The new ones are
+    /// uploaded already.
+    pub meshes: Vec<(Arc<GFXModel>, Arc<GFXModel>)>,
+}
+
+impl Reloaded {
+    pub fn is_empty(&self) -> bool {
+        self.textures.is_empty() && self.meshes.is_empty()
+    }
+}
+
+/// GPU resources that have been replaced or dropped but may still be read by a frame in flight.
+enum Retired {
+    /// Destroyed once the material sets holding it have moved on to the new image too.
+    Texture(Arc<img::Image>),
+    /// A model's buffers, destroyed once no instance holds the model anymore.
+    Model(Arc<GFXModel>),
+    Buffer(bfr::Buffer),
+}
+
+/// Runs an importer, turning a panic into an error message so saving a broken file doesn't take the app down. The model
+/// importers return their errors, this is for the texture loader and anything they miss.
+fn catch_import<T>(import: impl FnOnce() -> T) -> Result<T, String> {
+    panic::catch_unwind(panic::AssertUnwindSafe(import)).map_err(|payload| {
+        payload
+            .downcast_ref::<String>()
+            .cloned()
+            .or_else(|| payload.downcast_ref::<&str>().map(|message| message.to_string()))
+            .unwrap_or_else(|| "unknown error".to_string())
+    })
+}
+
+/// Imports a model file. glTF and GLB files are loaded as skinned meshes, everything else goes through the model loader
+/// with `options`. Import warnings are printed.
+fn import_file(path: &Path, options: &ImportOptions) -> Result<GFXModel, String> {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+    if extension == "gltf" || extension == "glb" {
+        let (mesh, skin) = gltf_loader::load_skinned_gltf(path)?;
+        println!("Loaded {:?}: {} joints, {} clips", path, skin.skeleton.joints.len(), skin.clips.len());
+        return Ok(GFXModel::from_skinned_mesh_data(mesh, skin));
+    }
+    let (model, warnings) = mdl::load_model_with_options(path, options)?;
+    for warning in warnings.iter() {
+        println!("Warning importing {:?}: {}", path, warning);
+    }
+    Ok(model)
+}
+
+/// Owns every loaded texture, material and mesh. Loads are deduplicated: a second load of the same texture path, the same
+/// material name or the same mesh with the same import settings and material returns the first one's handle with its
+/// reference count raised. Entries are freed once all their handles have been released and no instance holds the
+/// model anymore; GPU resources are destroyed `MAX_FRAMES_IN_FLIGHT` frames later so in-flight command buffers are done
+/// with them.
+pub struct AssetRegistry {
+    device: Arc<VulkanDevice>,
+    command_pool: vk::CommandPool,
+
+    textures: Pool<img::Image, PathBuf>,
+    materials: Pool<Material, TextureHandle>,
+    meshes: Pool<GFXModel, MeshSource>,
+
+    frame: u64,
+    /// Resources waiting for the frame they were retired in to leave the GPU.
+    retired: Vec<(u64, Retired)>,
+}
+
+impl AssetRegistry {
+    pub fn new(device: Arc<VulkanDevice>, command_pool: vk::CommandPool) -> AssetRegistry {
+        AssetRegistry {
+            device,
+            command_pool,
+            textures: Pool::new(),
+            materials: Pool::new(),
+            meshes: Pool::new(),
+            frame: 0,
+            retired: vec![],
+        }
+    }
+
+    pub fn load_texture(&mut self, path: &Path) -> TextureHandle {
+        let key = path.to_string_lossy().into_owned();
+        if let Some(handle) = self.textures.acquire_key(&key) {
+            return handle;
+        }
+        let texture = img::create_texture_image(self.device.clone(), self.command_pool, path);
+        self.textures.insert(key, texture, path.to_path_buf())
+    }
+
+    /// Materials are keyed by name; asking for an existing one returns it and leaves the other arguments unused. A freed
+    /// texture is an error.
+    pub fn load_material(&mut self, name: &str, diffuse: TextureHandle, params: MaterialParams, pipeline: MaterialPipeline) -> Result<MaterialHandle, String> {
+        if let Some(handle) = self.materials.acquire_key(name) {
+            return Ok(handle);
+        }
+        if !self.textures.acquire(diffuse) {
+            return Err(format!("Material '{}' loaded with a freed texture", name));
+        }
+        let material = Material {
+            name: name.to_string(),
+            diffuse,
+            params,
+            pipeline,
+        };
+        Ok(self.materials.insert(name.to_string(), material, diffuse))
+    }
+
+    /// Loads a mesh drawn with `material`. glTF and GLB files are loaded as skinned meshes, everything else goes
+    /// through the model loader with `options`, and the result is uploaded. Import warnings are printed; a file that
+    /// can't be imported, or a freed material, is an error.
+    pub fn load_mesh(&mut self, path: &Path, options: &ImportOptions, material: MaterialHandle) -> Result<MeshHandle, String> {
+        let geometry = MeshGeometry::File {
+            path: path.to_path_buf(),
+            options: options.clone(),
+        };
+        self.load_geometry(format!("{} {:?}", path.to_string_lossy(), options), geometry, material)
+    }
+
+    /// Builds and uploads a primitive drawn with `material`, deduplicated like loaded meshes.
+    pub fn load_primitive(&mut self, primitive: &Primitive, material: MaterialHandle) -> Result<MeshHandle, String> {
+        self.load_geometry(format!("{:?}", primitive), MeshGeometry::Primitive(*primitive), material)
+    }
+
+    fn load_geometry(&mut self, geometry_key: String, geometry: MeshGeometry, material: MaterialHandle) -> Result<MeshHandle, String> {
+        let material_key = &self.materials.slot(material).ok_or("Mesh loaded with a freed material")?.key;
+        let key = format!("{} {}", geometry_key, material_key);
+        if let Some(handle) = self.meshes.acquire_key(&key) {
+            return Ok(handle);
+        }
+        let source = MeshSource { geometry, material };
+        let model = self.import_model(&source)?;
+        self.materials.acquire(material);
+        Ok(self.meshes.insert(key, model, source))
+    }
+
+    fn import_model(&self, source: &MeshSource) -> Result<GFXModel, String> {
+        let mut model = match &source.geometry {
+            MeshGeometry::File { path, options } => import_file(path, options)?,
+            MeshGeometry::Primitive(primitive) => GFXModel::from_mesh_data(primitive.mesh()),
+        };
+        model.upload(self.device.clone(), self.command_pool);
+        Ok(model)
+    }
+
+    pub fn texture(&self, handle: TextureHandle) -> Option<Arc<img::Image>> {
+        self.textures.slot(handle).and_then(|slot| slot.value.clone())
+    }
+
+    /// Slot of a live texture in the bindless texture array. Each texture keeps its slot across reloads, and a freed
+    /// texture's slot goes to the next one loaded.
+    pub fn texture_slot(&self, handle: TextureHandle) -> Option<u32> {
+        self.textures.slot(handle).map(|_| handle.index)
+    }
+
+    pub fn material(&self, handle: MaterialHandle) -> Option<Arc<Material>> {
+        self.materials.slot(handle).and_then(|slot| slot.value.clone())
+    }
+
+    pub fn mesh(&self, handle: MeshHandle) -> Option<Arc<GFXModel>> {
+        self.meshes.slot(handle).and_then(|slot| slot.value.clone())
+    }
+
+    /// Material a live model was imported with. Models replaced by a reload aren't found.
+    pub fn mesh_material(&self, model: &Arc<GFXModel>) -> Option<MaterialHandle> {
+        self.meshes
+            .slots
+            .iter()
+            .find(|slot| slot.value.as_ref().is_some_and(|value| Arc::ptr_eq(value, model)))
+            .map(|slot| slot.depends.material)
+    }
+
+    pub fn release_texture(&mut self, handle: TextureHandle) {
+        self.textures.release(handle);
+    }
+
+    pub fn release_material(&mut self, handle: MaterialHandle) {
+        self.materials.release(handle);
+    }
+
+    pub fn release_mesh(&mut self, handle: MeshHandle) {
+        self.meshes.release(handle);
+    }
+
+    /// Number of handle references to a mesh. Instances holding the model are not counted here.
+    pub fn mesh_refs(&self, handle: MeshHandle) -> u32 {
+        self.meshes.slot(handle).map_or(0, |slot| slot.refs)
+    }
+
+    /// Live textures, materials and meshes.
+    pub fn counts(&self) -> (usize, usize, usize) {
+        (self.textures.live_count(), self.materials.live_count(), self.meshes.live_count())
+    }
+
+    /// Source files of every loaded texture and mesh, and the files the meshes' imports read along with them, like the
+    /// material libraries of OBJs.
+    pub fn source_paths(&self) -> Vec<PathBuf> {
+        let textures = self.textures.slots.iter().filter(|slot| slot.value.is_some()).map(|slot| slot.depends.clone());
+        let meshes = self
+            .meshes
+            .slots
+            .iter()
+            .filter(|slot| slot.value.is_some())
+            .flat_map(|slot| slot.depends.files());
+        textures.chain(meshes).collect()
+    }
+
+    /// Re-imports the textures and meshes loaded from any of `changed`, meshes also when a file their import reads
+    /// changed. Handles keep pointing at the same entries, and a texture's materials keep using it, so meshes aren't
+    /// touched when only their texture changed. The old textures are retired once no material set points at them, the
+    /// old models once no instance holds them. A failed import is reported and leaves the old asset in place.
+    pub fn reload(&mut self, changed: &[PathBuf]) -> Reloaded {
+        let mut reloaded = Reloaded::default();
+        for index in 0..self.textures.slots.len() {
+            let slot = &self.textures.slots[index];
+            if slot.value.is_none() || !changed.contains(&slot.depends) {
+                continue;
+            }
+            let path = slot.depends.clone();
+            let (device, command_pool) = (self.device.clone(), self.command_pool);
+            match catch_import(|| img::create_texture_image(device, command_pool, &path)) {
+                Ok(texture) => {
+                    println!("Reloaded texture {:?}", path);
+                    if let Some(old) = self.textures.slots[index].value.replace(Arc::new(texture)) {
+                        self.retire(Retired::Texture(old));
+                    }
+                    reloaded.textures.push(self.textures.handle(index as u32));
+                }
+                Err(err) => println!("Failed to reload texture {:?}: {}", path, err),
+            }
+        }
+
+        for index in 0..self.meshes.slots.len() {
+            let slot = &self.meshes.slots[index];
+            let old = match slot.value.as_ref() {
+                Some(old) => old.clone(),
+                None => continue,
+            };
+            let source = slot.depends.clone();
+            let files = source.files();
+            if !files.iter().any(|path| changed.contains(path)) {
+                continue;
+            }
+            match catch_import(|| self.import_model(&source)).and_then(|model| model) {
+                Ok(model) => {
+                    println!("Reloaded model {:?}", files[0]);
+                    let model = Arc::new(model);
+                    self.meshes.slots[index].value = Some(model.clone());
+                    self.retire(Retired::Model(old.clone()));
+                    reloaded.meshes.push((old, model));
+                }
+                Err(err) => println!("Failed to reload model {:?}: {}", files[0], err),
+            }
+        }
+        reloaded
+    }
+
+    fn retire(&mut self, resource: Retired) {
+        self.retired.push((self.frame, resource));
+    }
+
+    /// Hands a buffer that may still be in use by a frame in flight to the registry, which destroys it once it can't be.
+    pub fn retire_buffer(&mut self, buffer: bfr::Buffer) {
+        self.retire(Retired::Buffer(buffer));
+    }
+
+    /// Call once per frame, after waiting on the frame's fence. Frees entries nobody uses anymore and destroys the
+    /// resources retired at least `MAX_FRAMES_IN_FLIGHT` frames ago.
+    pub fn end_frame(&mut self) {
+        self.frame += 1;
+
+        // Meshes first, so the materials and textures they release can go in the same pass.
+        for (model, source) in self.meshes.take_unused() {
+            self.retire(Retired::Model(Arc::new(model)));
+            self.materials.release(source.material);
+        }
+        for (_material, diffuse) in self.materials.take_unused() {
+            self.textures.release(diffuse);
+        }
+        for (texture, _path) in self.textures.take_unused() {
+            self.retire(Retired::Texture(Arc::new(texture)));
+        }
+
+        let frame = self.frame;
+        self.retired.retain_mut(|(retired_at, resource)| {
+            if frame - *retired_at < MAX_FRAMES_IN_FLIGHT as u64 {
+                return true;
+            }
+            match resource {
+                Retired::Texture(texture) if Arc::strong_count(texture) > 1 => return true,
+                Retired::Texture(texture) => texture.vk_destroy(),
+                Retired::Model(model) if Arc::strong_count(model) > 1 => return true,
+                Retired::Model(model) => model.vk_destroy(),
+                Retired::Buffer(buffer) => buffer.vk_destroy(),
+            }
+            false
+        });
+    }
+
+    /// Destroys every texture, mesh buffer and retired resource, used or not. Only for shutdown, after the device is idle.
+    pub fn vk_destroy(&mut self) {
+        for (_, resource) in self.retired.drain(..) {
+            match resource {
+                Retired::Texture(texture) => texture.vk_destroy(),
+                Retired::Model(model) => model.vk_destroy(),
+                Retired::Buffer(mut buffer) => buffer.vk_destroy(),
+            }
+        }
+        for slot in self.meshes.slots.iter_mut() {
+            if let Some(model) = slot.value.take() {
+                model.vk_destroy();
+            }
+        }
+        for slot in self.textures.slots.iter_mut() {
+            if let Some(texture) = slot.value.take() {
+                texture.vk_destroy();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loads_with_the_same_key_share_one_entry() {
+        let mut pool: Pool<String, ()> = Pool::new();
+        let first = pool.insert("a".to_string(), "first".to_string(), ());
+        let again = pool.acquire_key("a").unwrap();
+        assert_eq!(first, again);
+        assert_eq!(pool.slot(first).unwrap().refs, 2);
+        assert!(pool.acquire_key("b").is_none());
+    }
+
+    #[test]
+    fn entries_are_freed_once_released_and_unshared() {
+        let mut pool: Pool<String, u32> = Pool::new();
+        let handle = pool.insert("a".to_string(), "value".to_string(), 7);
+        assert!(pool.take_unused().is_empty());
+
+        pool.release(handle);
+        let shared = pool.slot(handle).unwrap().value.clone().unwrap();
+        assert!(pool.take_unused().is_empty(), "an entry held outside the pool must stay");
+
+        drop(shared);
+        let unused = pool.take_unused();
+        assert_eq!(unused, vec![("value".to_string(), 7)]);
+        assert_eq!(pool.live_count(), 0);
+        assert!(pool.acquire_key("a").is_none());
+    }
+
+    #[test]
+    fn stale_handles_miss_the_reused_slot() {
+        let mut pool: Pool<String, ()> = Pool::new();
+        let old = pool.insert("a".to_string(), "old".to_string(), ());
+        pool.release(old);
+        pool.take_unused();
+
+        let new = pool.insert("b".to_string(), "new".to_string(), ());
+        assert_eq!(new.index, old.index);
+        assert_ne!(new, old);
+        assert!(pool.slot(old).is_none());
+        assert!(!pool.acquire(old));
+        assert!(pool.acquire(new));
+        assert_eq!(pool.slot(new).unwrap().refs, 2);
+
+        // Releasing through the stale handle leaves the new entry alone.
+        pool.release(old);
+        assert_eq!(pool.slot(new).unwrap().refs, 2);
+    }
+
+    #[test]
+    fn only_file_meshes_are_watched() {
+        let material = MaterialHandle {
+            index: 0,
+            generation: 0,
+            _marker: PhantomData,
+        };
+        let primitive = MeshSource {
+            geometry: MeshGeometry::Primitive(Primitive::Cube { size: 1.0 }),
+            material,
+        };
+        assert!(primitive.files().is_empty());
+        let file = MeshSource {
+            geometry: MeshGeometry::File {
+                path: PathBuf::from("assets/missing.ply"),
+                options: ImportOptions::default(),
+            },
+            material,
+        };
+        assert_eq!(file.files(), vec![PathBuf::from("assets/missing.ply")]);
+    }
+}
@@ -10,6 +10,7 @@ use memoffset::offset_of;
 use nalgebra_glm::{identity, look_at, perspective};
 use nalgebra_glm::{Mat4, Vec2, Vec3, Vec4};
 
+use std::collections::HashMap;
 use std::f32::consts::PI;
 use std::ffi::CString;
 use std::path::Path;
@@ -18,30 +19,214 @@ use std::sync::Arc;
 
 use image::GenericImageView;
 
+use vk_assist::gltf_loader;
 use vk_assist::misc_util as misc;
 use vk_assist::model_loader as mdl;
 use vk_assist::structures::{get_rect_as_intermediate, UniformBufferObject, Vertex};
 use vk_assist::types::{buffer as bfr, command as cmd, image as img};
 use vk_assist::types::{buffer, command, vulkan_device, vulkan_device::VulkanDevice, vulkan_surface::VulkanSurface, vulkan_swap_chain::*};
 use vk_model::advanced_model::*;
+use vk_model::animation::AnimationPlayer;
 use vk_model::MeshSize;
 
-const TEXTURE_PATH: &'static str = "assets/fighterdiffuse.bmp";
-const MODEL_PATH: &'static str = "assets/fighter.obj";
+use super::asset_registry::{AssetRegistry, MaterialHandle, MeshHandle, Reloaded, TextureHandle};
+use super::file_watcher::FileWatcher;
+use super::instances::{GInstance, Instances};
+use super::manifest::AssetManifest;
+
+pub const MANIFEST_PATH: &str = "assets/manifest.json";
 
 pub struct Assets {
-    pub fighter: Arc<GFXModel>,
+    pub manifest: AssetManifest,
+    pub registry: AssetRegistry,
+    /// Handles for the manifest's names. Textures and materials are kept loaded by the meshes using them, materials only
+    /// instances use by Assets, and meshes by Assets until `release_models` and then by the instances holding them; a
+    /// handle to a freed entry resolves to none.
+    pub textures: HashMap<String, TextureHandle>,
+    pub materials: HashMap<String, MaterialHandle>,
+    pub models: HashMap<String, MeshHandle>,
+    /// The manifest's primary model, drawn by the static pipeline.
+    pub primary: Arc<GFXModel>,
+    /// First skinned model in the manifest, if any was loaded.
+    pub skinned: Option<Arc<GFXModel>>,
+    /// Source files of everything loaded, for hot reloading.
+    watcher: FileWatcher,
 }
 
 impl Assets {
     pub fn init(device: Arc<VulkanDevice>, command_pool: vk::CommandPool) -> Assets {
-        let texture = img::create_texture_image(device.clone(), command_pool, &Path::new(TEXTURE_PATH));
-        let model = mdl::load_model(Path::new(MODEL_PATH), texture);
+        Assets::from_manifest(device, command_pool, Path::new(MANIFEST_PATH))
+    }
+
+    /// Loads every model in the manifest at `manifest_path` along with the materials and textures it uses. A bad
+    /// manifest, or a model that isn't optional and is missing or fails to import, panics. Materials and textures no
+    /// model loaded uses are skipped.
+    pub fn from_manifest(device: Arc<VulkanDevice>, command_pool: vk::CommandPool, manifest_path: &Path) -> Assets {
+        let manifest = match AssetManifest::load(manifest_path) {
+            Ok(manifest) => manifest,
+            Err(err) => panic!("{}", err),
+        };
+
+        let mut registry = AssetRegistry::new(device, command_pool);
+        let mut textures = HashMap::new();
+        let mut materials = HashMap::new();
+        let mut models = HashMap::new();
+        for (name, entry) in manifest.models.iter() {
+            if let Some(path) = entry.path.as_ref().map(Path::new).filter(|path| entry.optional && !path.exists()) {
+                println!("Skipping optional model '{}', {:?} doesn't exist", name, path);
+                continue;
+            }
+            let loaded = load_material(&mut registry, &manifest, &mut textures, &mut materials, &entry.material).and_then(|material| {
+                match (entry.path.as_ref(), entry.primitive.as_ref()) {
+                    (Some(path), _) => registry.load_mesh(Path::new(path), &entry.import.to_options(), material),
+                    (None, Some(primitive)) => registry.load_primitive(primitive, material),
+                    (None, None) => unreachable!("validated manifest has a model with neither a path nor a primitive"),
+                }
+            });
+            match loaded {
+                Ok(handle) => models.insert(name.clone(), handle),
+                Err(err) => panic!("Failed to load model '{}': {}", name, err),
+            };
+        }
+        let mut instance_materials = vec![];
+        for name in manifest.instances.iter().filter_map(|instance| instance.material.as_ref()) {
+            match load_material(&mut registry, &manifest, &mut textures, &mut materials, name) {
+                Ok(handle) => instance_materials.push(handle),
+                Err(err) => panic!("Failed to load material '{}': {}", name, err),
+            }
+        }
+        // The meshes hold their materials and the materials their textures.
+        for &handle in materials.values().filter(|handle| !instance_materials.contains(handle)) {
+            registry.release_material(handle);
+        }
+        for &handle in textures.values() {
+            registry.release_texture(handle);
+        }
+
+        let primary = registry.mesh(models[&manifest.primary_model]).expect("Primary model failed to load!");
+        let skinned = manifest
+            .models
+            .keys()
+            .filter_map(|name| models.get(name).and_then(|&handle| registry.mesh(handle)))
+            .find(|model| model.skin.is_some());
+        let (texture_count, material_count, mesh_count) = registry.counts();
+        println!("Loaded {} textures, {} materials and {} meshes", texture_count, material_count, mesh_count);
+        let mut watcher = FileWatcher::new();
+        for path in registry.source_paths() {
+            watcher.watch(&path);
+        }
+
+        Assets {
+            manifest,
+            registry,
+            textures,
+            materials,
+            models,
+            primary,
+            skinned,
+            watcher,
+        }
+    }
+
+    /// Reloads the assets whose source files changed on disk, with `primary` and `skinned` already switched to the
+    /// reloaded models, so the caller can swap them into its instances and point its material sets at the new textures.
+    /// Files a reloaded mesh started reading, like a new material library, are watched from then on.
+    pub fn poll_reloads(&mut self) -> Reloaded {
+        let changed = self.watcher.poll();
+        if changed.is_empty() {
+            return Reloaded::default();
+        }
+        let reloaded = self.registry.reload(&changed);
+        for path in self.registry.source_paths() {
+            self.watcher.watch(&path);
+        }
+        for (old, new) in reloaded.meshes.iter() {
+            if Arc::ptr_eq(&self.primary, old) {
+                self.primary = new.clone();
+            }
+            if let Some(skinned) = self.skinned.as_mut().filter(|skinned| Arc::ptr_eq(skinned, old)) {
+                *skinned = new.clone();
+            }
+        }
+        reloaded
+    }
+
+    /// The manifest's instances, skipping those of optional models that weren't loaded. Skinned instances play the clip
+    /// their entry names, or the first if the model has clips.
+    pub fn instances(&self) -> Instances {
+        let mut instances = Instances::new();
+        for entry in self.manifest.instances.iter() {
+            let model = match self.model(&entry.model) {
+                Some(model) => model,
+                None => {
+                    println!("Skipping an instance of '{}', the model isn't loaded", entry.model);
+                    continue;
+                }
+            };
+            let material = match entry.material.as_ref() {
+                Some(name) => self.materials.get(name).copied(),
+                None => self.material_for(&model),
+            };
+            let clip_count = model.skin.as_ref().map_or(0, |skin| skin.clips.len());
+            let mut instance = GInstance::new(model, entry.transform()).with_material(material);
+            match entry.animation {
+                Some(clip) => instance = instance.with_animation(AnimationPlayer::new(clip)),
+                None if clip_count > 0 => instance = instance.with_animation(AnimationPlayer::new(0)),
+                None => {}
+            }
+            instances.push(instance);
+        }
+        instances
+    }
 
-        Assets { fighter: Arc::new(model) }
+    /// Drops the references Assets took on the manifest's meshes, once the instances hold the models they draw. Meshes
+    /// no instance uses are freed at the next `AssetRegistry::end_frame`, the others once their last instance is gone.
+    pub fn release_models(&mut self) {
+        for &handle in self.models.values() {
+            self.registry.release_mesh(handle);
+        }
     }
 
-    // pub fn vk_destroy(&mut self) {
-    //     self.fighter.diffuse_tex.vk_destroy();
-    // }
+    /// The model loaded for `name`, if the manifest has it and it is still loaded.
+    pub fn model(&self, name: &str) -> Option<Arc<GFXModel>> {
+        self.models.get(name).and_then(|&handle| self.registry.mesh(handle))
+    }
+
+    /// Every loaded material, the primary model's first.
+    pub fn material_handles(&self) -> Vec<MaterialHandle> {
+        let primary = self.material_for(&self.primary).expect("Primary model has no material!");
+        let mut names: Vec<&String> = self.materials.keys().collect();
+        names.sort();
+        let others = names.into_iter().map(|name| self.materials[name]).filter(|&handle| handle != primary);
+        std::iter::once(primary).chain(others).collect()
+    }
+
+    /// Material `model` was loaded with, if the registry still has its mesh.
+    pub fn material_for(&self, model: &Arc<GFXModel>) -> Option<MaterialHandle> {
+        self.registry.mesh_material(model)
+    }
+
+    pub fn vk_destroy(&mut self) {
+        self.registry.vk_destroy();
+    }
+}
+
+/// Handle of a manifest material, loading it and its texture on first use.
+fn load_material(
+    registry: &mut AssetRegistry,
+    manifest: &AssetManifest,
+    textures: &mut HashMap<String, TextureHandle>,
+    materials: &mut HashMap<String, MaterialHandle>,
+    name: &str,
+) -> Result<MaterialHandle, String> {
+    if let Some(&handle) = materials.get(name) {
+        return Ok(handle);
+    }
+    let (entry, texture_path) = manifest.resolve_material(name)?;
+    let texture = *textures
+        .entry(entry.diffuse.clone())
+        .or_insert_with(|| registry.load_texture(Path::new(texture_path)));
+    let handle = registry.load_material(name, texture, entry.to_params(), entry.to_pipeline())?;
+    materials.insert(name.to_string(), handle);
+    Ok(handle)
 }
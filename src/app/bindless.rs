@@ -0,0 +1,432 @@
+#![allow(dead_code)]
+
+use crate::pipelines;
+use crate::vk_assist;
+use std::sync::Arc;
+
+use ash::version::DeviceV1_0;
+use ash::vk;
+use nalgebra_glm::Vec4;
+
+use std::ffi::c_void;
+use std::ptr;
+
+use pipelines::reflected_layout::PipelineInterface;
+use vk_assist::types::buffer as bfr;
+use vk_assist::types::vulkan_device::{VulkanDevice, MAX_BINDLESS_TEXTURES};
+
+use super::materials::{GpuMaterial, ShadingModel, MATERIAL_SET};
+use super::shaders::ShaderKey;
+
+/// Fragment shader of the bindless static pipeline. It takes the place of the material set with the bindless set.
+pub fn fragment_shader_key() -> ShaderKey {
+    ShaderKey::new("shaders/bindless.frag")
+}
+
+/// A material as the bindless fragment shader reads it from the material buffer.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct BindlessMaterial {
+    pub tint: Vec4,
+    pub roughness: f32,
+    pub metallic: f32,
+    /// Slot of the material's texture in the texture array.
+    pub texture: u32,
+    pub shading: ShadingModel,
+}
+
+/// Every material in one descriptor set: the textures in a partially bound array indexed by the registry's texture
+/// slots, one sampler, and the material parameters in a storage buffer indexed by the material index pushed with each
+/// draw. Static draws bind it once instead of once per material.
+pub struct BindlessMaterials {
+    device: Arc<VulkanDevice>,
+    pub layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    /// One per swapchain image, so texture slots can be rewritten without touching a set a frame in flight reads.
+    pub descriptor_sets: Vec<vk::DescriptorSet>,
+    sampler: vk::Sampler,
+    material_buffer: bfr::Buffer,
+    /// For each swapchain image, the image view written to each texture slot.
+    written: Vec<Vec<vk::ImageView>>,
+}
+
+impl BindlessMaterials {
+    /// Sets up the bindless set for `materials`, indexed like them. `None` if one of their textures has a slot past the
+    /// end of the texture array.
+    pub fn new(device: Arc<VulkanDevice>, materials: &[GpuMaterial], swapchain_image_count: usize) -> Option<BindlessMaterials> {
+        let slots = materials.iter().map(|material| material.texture_slot);
+        if let Err(err) = check_texture_slots(slots, MAX_BINDLESS_TEXTURES) {
+            println!("{}", err);
+            return None;
+        }
+
+        let layout = create_set_layout(&device);
+        let descriptor_pool = create_descriptor_pool(&device, swapchain_image_count as u32);
+        let layouts = vec![layout; swapchain_image_count];
+        let descriptor_set_allocate_info = vk::DescriptorSetAllocateInfo {
+            s_type: vk::StructureType::DESCRIPTOR_SET_ALLOCATE_INFO,
+            p_next: ptr::null(),
+            descriptor_pool,
+            descriptor_set_count: layouts.len() as u32,
+            p_set_layouts: layouts.as_ptr(),
+        };
+        let descriptor_sets = unsafe {
+            device
+                .logical_device
+                .allocate_descriptor_sets(&descriptor_set_allocate_info)
+                .expect("Failed to allocate bindless descriptor sets!")
+        };
+
+        let sampler = create_sampler(&device);
+        let material_buffer = create_material_buffer(&device, materials);
+        for &descriptor_set in descriptor_sets.iter() {
+            write_shared_bindings(&device, descriptor_set, sampler, &material_buffer);
+        }
+
+        let mut bindless = BindlessMaterials {
+            device,
+            layout,
+            descriptor_pool,
+            descriptor_sets,
+            sampler,
+            material_buffer,
+            written: vec![vec![vk::ImageView::null(); MAX_BINDLESS_TEXTURES as usize]; swapchain_image_count],
+        };
+        for image_index in 0..swapchain_image_count {
+            bindless.update_set(image_index, materials);
+        }
+        Some(bindless)
+    }
+
+    /// Fails unless `interface` declares the material set the way this set is laid out.
+    pub fn check_shaders(interface: &PipelineInterface) -> Result<(), String> {
+        let expected = [
+            (0, vk::DescriptorType::SAMPLED_IMAGE),
+            (1, vk::DescriptorType::SAMPLER),
+            (2, vk::DescriptorType::STORAGE_BUFFER),
+        ];
+        let bindings = interface.set_bindings(MATERIAL_SET);
+        let same = bindings.len() == expected.len()
+            && bindings
+                .iter()
+                .zip(expected.iter())
+                .all(|(binding, &(number, descriptor_type))| binding.binding == number && binding.descriptor_type == descriptor_type);
+        if !same {
+            return Err(format!("bindless set {} is declared as {:?}", MATERIAL_SET, bindings));
+        }
+        if bindings[0].count > MAX_BINDLESS_TEXTURES {
+            return Err(format!(
+                "shaders index {} bindless textures, there are {}",
+                bindings[0].count, MAX_BINDLESS_TEXTURES
+            ));
+        }
+        Ok(())
+    }
+
+    /// Writes the textures of `materials` that changed since `image_index`'s set was last updated into their slots.
+    /// Call right before re-recording that image's command buffer.
+    pub fn update_set(&mut self, image_index: usize, materials: &[GpuMaterial]) {
+        let textures = materials.iter().map(|material| (material.texture_slot, material.texture.view));
+        for (slot, image_view) in changed_slots(&mut self.written[image_index], textures) {
+            let descriptor_image_infos = [vk::DescriptorImageInfo {
+                sampler: vk::Sampler::null(),
+                image_view,
+                image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            }];
+            let descriptor_write_sets = [vk::WriteDescriptorSet {
+                s_type: vk::StructureType::WRITE_DESCRIPTOR_SET,
+                p_next: ptr::null(),
+                dst_set: self.descriptor_sets[image_index],
+                dst_binding: 0,
+                dst_array_element: slot,
+                descriptor_count: 1,
+                descriptor_type: vk::DescriptorType::SAMPLED_IMAGE,
+                p_image_info: descriptor_image_infos.as_ptr(),
+                p_buffer_info: ptr::null(),
+                p_texel_buffer_view: ptr::null(),
+            }];
+            unsafe {
+                self.device.logical_device.update_descriptor_sets(&descriptor_write_sets, &[]);
+            }
+        }
+    }
+
+    pub fn vk_destroy(&mut self) {
+        unsafe {
+            self.device.logical_device.destroy_sampler(self.sampler, None);
+            self.device.logical_device.destroy_descriptor_pool(self.descriptor_pool, None);
+            self.device.logical_device.destroy_descriptor_set_layout(self.layout, None);
+        }
+        self.material_buffer.vk_destroy();
+    }
+}
+
+/// Fails if one of the texture slots is past the end of a texture array of `capacity`.
+pub fn check_texture_slots(slots: impl IntoIterator<Item = u32>, capacity: u32) -> Result<(), String> {
+    match slots.into_iter().find(|&slot| slot >= capacity) {
+        Some(slot) => Err(format!("Texture slot {} doesn't fit in the {} bindless textures", slot, capacity)),
+        None => Ok(()),
+    }
+}
+
+/// The slots whose image view differs from the one last written to them, with their new view. `written` is updated to
+/// match, so a slot that a freed texture handed on to a new one is rewritten, and an unchanged slot is left alone.
+fn changed_slots(written: &mut [vk::ImageView], textures: impl IntoIterator<Item = (u32, vk::ImageView)>) -> Vec<(u32, vk::ImageView)> {
+    let mut changed = vec![];
+    for (slot, image_view) in textures {
+        let written = &mut written[slot as usize];
+        if *written != image_view {
+            *written = image_view;
+            changed.push((slot, image_view));
+        }
+    }
+    changed
+}
+
+/// The texture array is partially bound, so slots without a texture are fine as long as no draw reads them, and
+/// update-after-bind, which is what lifts it to the device's much larger update-after-bind descriptor limits.
+fn create_set_layout(device: &VulkanDevice) -> vk::DescriptorSetLayout {
+    let layout_bindings = [
+        vk::DescriptorSetLayoutBinding {
+            binding: 0,
+            descriptor_type: vk::DescriptorType::SAMPLED_IMAGE,
+            descriptor_count: MAX_BINDLESS_TEXTURES,
+            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+            p_immutable_samplers: ptr::null(),
+        },
+        vk::DescriptorSetLayoutBinding {
+            binding: 1,
+            descriptor_type: vk::DescriptorType::SAMPLER,
+            descriptor_count: 1,
+            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+            p_immutable_samplers: ptr::null(),
+        },
+        vk::DescriptorSetLayoutBinding {
+            binding: 2,
+            descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+            descriptor_count: 1,
+            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+            p_immutable_samplers: ptr::null(),
+        },
+    ];
+    let binding_flags = [
+        vk::DescriptorBindingFlags::PARTIALLY_BOUND | vk::DescriptorBindingFlags::UPDATE_AFTER_BIND,
+        vk::DescriptorBindingFlags::empty(),
+        vk::DescriptorBindingFlags::empty(),
+    ];
+    let binding_flags_create_info = vk::DescriptorSetLayoutBindingFlagsCreateInfo {
+        s_type: vk::StructureType::DESCRIPTOR_SET_LAYOUT_BINDING_FLAGS_CREATE_INFO,
+        p_next: ptr::null(),
+        binding_count: binding_flags.len() as u32,
+        p_binding_flags: binding_flags.as_ptr(),
+    };
+
+    let layout_create_info = vk::DescriptorSetLayoutCreateInfo {
+        s_type: vk::StructureType::DESCRIPTOR_SET_LAYOUT_CREATE_INFO,
+        p_next: &binding_flags_create_info as *const _ as *const c_void,
+        flags: vk::DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL,
+        binding_count: layout_bindings.len() as u32,
+        p_bindings: layout_bindings.as_ptr(),
+    };
+
+    unsafe {
+        device
+            .logical_device
+            .create_descriptor_set_layout(&layout_create_info, None)
+            .expect("Failed to create bindless Descriptor Set Layout!")
+    }
+}
+
+/// Update-after-bind sets need a pool of their own, created with the matching flag.
+fn create_descriptor_pool(device: &VulkanDevice, set_count: u32) -> vk::DescriptorPool {
+    let pool_sizes = [
+        vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::SAMPLED_IMAGE,
+            descriptor_count: MAX_BINDLESS_TEXTURES * set_count,
+        },
+        vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::SAMPLER,
+            descriptor_count: set_count,
+        },
+        vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::STORAGE_BUFFER,
+            descriptor_count: set_count,
+        },
+    ];
+
+    let descriptor_pool_create_info = vk::DescriptorPoolCreateInfo {
+        s_type: vk::StructureType::DESCRIPTOR_POOL_CREATE_INFO,
+        p_next: ptr::null(),
+        flags: vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND,
+        max_sets: set_count,
+        pool_size_count: pool_sizes.len() as u32,
+        p_pool_sizes: pool_sizes.as_ptr(),
+    };
+
+    unsafe {
+        device
+            .logical_device
+            .create_descriptor_pool(&descriptor_pool_create_info, None)
+            .expect("Failed to create bindless Descriptor Pool!")
+    }
+}
+
+/// Like `Image::create_sampler`, but without a mip level limit, since it samples every texture.
+fn create_sampler(device: &VulkanDevice) -> vk::Sampler {
+    let sampler_create_info = vk::SamplerCreateInfo {
+        s_type: vk::StructureType::SAMPLER_CREATE_INFO,
+        p_next: ptr::null(),
+        flags: vk::SamplerCreateFlags::empty(),
+        mag_filter: vk::Filter::LINEAR,
+        min_filter: vk::Filter::LINEAR,
+        address_mode_u: vk::SamplerAddressMode::REPEAT,
+        address_mode_v: vk::SamplerAddressMode::REPEAT,
+        address_mode_w: vk::SamplerAddressMode::REPEAT,
+        anisotropy_enable: vk::TRUE,
+        max_anisotropy: 16.0,
+        compare_enable: vk::FALSE,
+        compare_op: vk::CompareOp::ALWAYS,
+        mipmap_mode: vk::SamplerMipmapMode::LINEAR,
+        min_lod: 0.0,
+        max_lod: vk::LOD_CLAMP_NONE,
+        mip_lod_bias: 0.0,
+        border_color: vk::BorderColor::INT_OPAQUE_BLACK,
+        unnormalized_coordinates: vk::FALSE,
+    };
+    unsafe {
+        device
+            .logical_device
+            .create_sampler(&sampler_create_info, None)
+            .expect("Failed to create bindless Sampler!")
+    }
+}
+
+fn create_material_buffer(device: &Arc<VulkanDevice>, materials: &[GpuMaterial]) -> bfr::Buffer {
+    let bindless_materials: Vec<BindlessMaterial> = materials
+        .iter()
+        .map(|material| BindlessMaterial {
+            tint: material.params.tint,
+            roughness: material.params.roughness,
+            metallic: material.params.metallic,
+            texture: material.texture_slot,
+            shading: material.params.shading,
+        })
+        .collect();
+    let buffer_size = (std::mem::size_of::<BindlessMaterial>() * bindless_materials.len()) as u64;
+    let material_buffer = bfr::create_buffer(
+        device.clone(),
+        buffer_size,
+        vk::BufferUsageFlags::STORAGE_BUFFER,
+        vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        &device.get_physical_device_memory_properties(),
+    );
+    unsafe {
+        let data_ptr = device
+            .logical_device
+            .map_memory(material_buffer.memory, 0, buffer_size, vk::MemoryMapFlags::empty())
+            .expect("Failed to Map Memory") as *mut BindlessMaterial;
+
+        data_ptr.copy_from_nonoverlapping(bindless_materials.as_ptr(), bindless_materials.len());
+
+        device.logical_device.unmap_memory(material_buffer.memory);
+    }
+    material_buffer
+}
+
+/// Writes the sampler and the material buffer, which stay the same for the set's lifetime.
+fn write_shared_bindings(device: &VulkanDevice, descriptor_set: vk::DescriptorSet, sampler: vk::Sampler, material_buffer: &bfr::Buffer) {
+    let descriptor_sampler_infos = [vk::DescriptorImageInfo {
+        sampler,
+        image_view: vk::ImageView::null(),
+        image_layout: vk::ImageLayout::UNDEFINED,
+    }];
+    let descriptor_buffer_infos = [vk::DescriptorBufferInfo {
+        buffer: material_buffer.buffer,
+        offset: 0,
+        range: vk::WHOLE_SIZE,
+    }];
+    let descriptor_write_sets = [
+        vk::WriteDescriptorSet {
+            // sampler
+            s_type: vk::StructureType::WRITE_DESCRIPTOR_SET,
+            p_next: ptr::null(),
+            dst_set: descriptor_set,
+            dst_binding: 1,
+            dst_array_element: 0,
+            descriptor_count: 1,
+            descriptor_type: vk::DescriptorType::SAMPLER,
+            p_image_info: descriptor_sampler_infos.as_ptr(),
+            p_buffer_info: ptr::null(),
+            p_texel_buffer_view: ptr::null(),
+        },
+        vk::WriteDescriptorSet {
+            // material parameters
+            s_type: vk::StructureType::WRITE_DESCRIPTOR_SET,
+            p_next: ptr::null(),
+            dst_set: descriptor_set,
+            dst_binding: 2,
+            dst_array_element: 0,
+            descriptor_count: 1,
+            descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+            p_image_info: ptr::null(),
+            p_buffer_info: descriptor_buffer_infos.as_ptr(),
+            p_texel_buffer_view: ptr::null(),
+        },
+    ];
+    unsafe {
+        device.logical_device.update_descriptor_sets(&descriptor_write_sets, &[]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ash::vk::Handle;
+    use vk_assist::spirv_reflect::reflect;
+
+    fn view(raw: u64) -> vk::ImageView {
+        vk::ImageView::from_raw(raw)
+    }
+
+    fn interface(vertex: &str, fragment: &str) -> PipelineInterface {
+        let vertex = reflect(&std::fs::read(format!("shaders/{}.spv", vertex)).unwrap()).unwrap();
+        let fragment = reflect(&std::fs::read(format!("shaders/{}.spv", fragment)).unwrap()).unwrap();
+        PipelineInterface::from_stages(&[&vertex, &fragment]).unwrap()
+    }
+
+    #[test]
+    fn slots_past_the_capacity_are_rejected() {
+        assert!(check_texture_slots(vec![0, 3, 7], 8).is_ok());
+        assert!(check_texture_slots(vec![], 0).is_ok());
+        let err = check_texture_slots(vec![0, 8, 9], 8).unwrap_err();
+        assert!(err.contains("slot 8"), "{}", err);
+        assert!(check_texture_slots(vec![MAX_BINDLESS_TEXTURES], MAX_BINDLESS_TEXTURES).is_err());
+    }
+
+    #[test]
+    fn only_changed_slots_are_rewritten() {
+        let mut written = vec![vk::ImageView::null(); 4];
+        let textures = vec![(0, view(1)), (2, view(2)), (0, view(1))];
+        assert_eq!(changed_slots(&mut written, textures.clone()), vec![(0, view(1)), (2, view(2))]);
+        assert!(changed_slots(&mut written, textures).is_empty());
+        assert_eq!(written, vec![view(1), vk::ImageView::null(), view(2), vk::ImageView::null()]);
+    }
+
+    #[test]
+    fn a_reused_slot_is_rewritten_with_the_new_texture() {
+        let mut written = vec![vk::ImageView::null(); 4];
+        changed_slots(&mut written, vec![(0, view(1)), (1, view(2))]);
+
+        // Slot 1's texture was freed and the next texture loaded took the slot over, or it was reloaded in place.
+        let changed = changed_slots(&mut written, vec![(0, view(1)), (1, view(3))]);
+        assert_eq!(changed, vec![(1, view(3))]);
+        assert_eq!(written[1], view(3));
+    }
+
+    #[test]
+    fn only_the_bindless_shader_fits_the_bindless_set() {
+        assert!(BindlessMaterials::check_shaders(&interface("pushconst.vert", "bindless.frag")).is_ok());
+        let err = BindlessMaterials::check_shaders(&interface("pushconst.vert", "pushconst.frag")).unwrap_err();
+        assert!(err.contains("bindless set"), "{}", err);
+    }
+}
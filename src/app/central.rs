@@ -2,7 +2,9 @@ use super::camera::Camera;
 use super::input_model::{InputKey, InputModel};
 use super::scene;
 use super::time_manager::{PrintFPSPeriod, TimeManager};
+use crate::vk_assist::mesh_export::InstanceExport;
 use std::cell::RefCell;
+use std::path::Path;
 use std::rc::Rc;
 use winit::event::MouseButton::Other;
 
@@ -56,7 +58,11 @@ impl App {
                 Event::WindowEvent { event, .. } => match event {
                     WindowEvent::Resized(physical_size) => {}
                     WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
-                    WindowEvent::KeyboardInput { input, .. } => App::key_handler(input, &mut self.input_model, &mut self.camera, control_flow), // match input {
+                    WindowEvent::KeyboardInput { input, .. } => {
+                        App::key_handler(input, &mut self.input_model, &mut self.camera, control_flow);
+                        self.post_key_handler(input);
+                        self.export_key_handler(input);
+                    }
                     WindowEvent::MouseInput { button, state, .. } => self.mouse_button_handler(button, state),
                     _ => {}
                 },
@@ -104,6 +110,43 @@ impl App {
         }
     }
 
+    /// Post-processing toggles, only from window events so a press isn't handled twice.
+    pub fn post_key_handler(&mut self, input: KeyboardInput) {
+        if let KeyboardInput {
+            virtual_keycode: Some(key),
+            state: ES::Pressed,
+            ..
+        } = input
+        {
+            let mut renderer = self.renderer.borrow_mut();
+            if let Some(settings) = renderer.post_settings().with_key(key) {
+                renderer.set_post_settings(settings);
+                println!("Post-processing: {:?}", settings);
+            }
+        }
+    }
+
+    /// F9 writes the scene to OBJ with baked transforms, F10 to glTF with a node per instance.
+    pub fn export_key_handler(&mut self, input: KeyboardInput) {
+        let (path, mode) = match input {
+            KeyboardInput {
+                virtual_keycode: Some(VKC::F9),
+                state: ES::Pressed,
+                ..
+            } => ("export/scene.obj", InstanceExport::Baked),
+            KeyboardInput {
+                virtual_keycode: Some(VKC::F10),
+                state: ES::Pressed,
+                ..
+            } => ("export/scene.gltf", InstanceExport::Nodes),
+            _ => return,
+        };
+        match self.renderer.borrow().export_scene(Path::new(path), mode) {
+            Ok(()) => println!("Exported the scene to {}", path),
+            Err(err) => println!("Failed to export the scene to {}: {}", path, err),
+        }
+    }
+
     #[allow(unused_variables)]
     pub fn mouse_button_handler(&mut self, button: MouseButton, state: ES) {
         match button {
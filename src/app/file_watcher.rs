@@ -0,0 +1,65 @@
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+/// How often `FileWatcher::poll` actually looks at the files.
+pub const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Notices changes to a set of files by polling their modification times. A file that is missing counts as unchanged
+/// until it shows up again, so editors that save by deleting and rewriting only trigger once.
+pub struct FileWatcher {
+    files: HashMap<PathBuf, Option<SystemTime>>,
+    last_poll: Instant,
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+impl FileWatcher {
+    pub fn new() -> FileWatcher {
+        FileWatcher {
+            files: HashMap::new(),
+            last_poll: Instant::now(),
+        }
+    }
+
+    /// Starts watching `path`. Watching a file twice is fine.
+    pub fn watch(&mut self, path: &Path) {
+        if !self.files.contains_key(path) {
+            self.files.insert(path.to_path_buf(), modified(path));
+        }
+    }
+
+    pub fn unwatch(&mut self, path: &Path) {
+        self.files.remove(path);
+    }
+
+    /// Files whose modification time changed since the last poll. Returns nothing if called again within
+    /// `POLL_INTERVAL`.
+    pub fn poll(&mut self) -> Vec<PathBuf> {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return vec![];
+        }
+        self.last_poll = Instant::now();
+
+        let mut changed = vec![];
+        for (path, last_modified) in self.files.iter_mut() {
+            let now_modified = modified(path);
+            if now_modified.is_some() && now_modified != *last_modified {
+                *last_modified = now_modified;
+                changed.push(path.clone());
+            }
+        }
+        changed
+    }
+}
+
+impl Default for FileWatcher {
+    fn default() -> FileWatcher {
+        FileWatcher::new()
+    }
+}
@@ -0,0 +1,123 @@
+#![allow(dead_code)]
+
+use crate::vk_assist;
+use std::sync::Arc;
+
+use ash::vk;
+
+use vk_assist::render_graph::{CompiledGraph, ImageDesc, ImageExtent, ImportedImage, Load, PassDesc, PassId, RenderGraph, ResourceId};
+use vk_assist::types::vulkan_device::VulkanDevice;
+use vk_assist::types::vulkan_swap_chain::VulkanSwapChain;
+
+use super::post::{PostEffect, PostPass, PostSettings, HDR_FORMAT, LDR_FORMAT};
+
+pub const CLEAR_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+
+/// The passes of a frame, compiled for the current swapchain.
+pub struct FrameGraph {
+    pub graph: CompiledGraph,
+    pub swapchain_format: vk::Format,
+    /// Renders the shadow maps into the shadow atlas.
+    pub shadow: PassId,
+    /// Draws the scene views into the HDR image, multisampled and resolved into it when MSAA is on, sampling the
+    /// shadow atlas.
+    pub scene: PassId,
+    /// The post-processing chain from the HDR image to the swapchain image, in order.
+    pub post: Vec<PostPass>,
+}
+
+impl FrameGraph {
+    pub fn new(
+        device: Arc<VulkanDevice>,
+        swap_chain: &VulkanSwapChain,
+        swapchain_imageviews: &[vk::ImageView],
+        depth_format: vk::Format,
+        msaa_samples: vk::SampleCountFlags,
+        shadow_atlas: ImportedImage,
+        post_settings: &PostSettings,
+    ) -> FrameGraph {
+        let mut graph = RenderGraph::new();
+        let backbuffer = graph.import_image(
+            "swapchain",
+            ImportedImage::swapchain(&swap_chain.images, swapchain_imageviews, swap_chain.format, swap_chain.extent),
+        );
+        let depth = graph.create_image("depth", ImageDesc::new(depth_format, msaa_samples));
+        let hdr = graph.create_image("hdr", ImageDesc::new(HDR_FORMAT, vk::SampleCountFlags::TYPE_1));
+        let shadow_atlas = graph.import_image("shadow_atlas", shadow_atlas);
+        let clear_color = Load::Clear(vk::ClearValue {
+            color: vk::ClearColorValue { float32: CLEAR_COLOR },
+        });
+        let clear_depth = Load::Clear(vk::ClearValue {
+            depth_stencil: vk::ClearDepthStencilValue { depth: 1.0, stencil: 0 },
+        });
+
+        let shadow = graph.add_pass(PassDesc::new("shadow").depth(shadow_atlas, clear_depth));
+        let scene = if msaa_samples == vk::SampleCountFlags::TYPE_1 {
+            PassDesc::new("scene").color(hdr, clear_color)
+        } else {
+            let color = graph.create_image("hdr_msaa", ImageDesc::new(HDR_FORMAT, msaa_samples));
+            PassDesc::new("scene").color(color, clear_color).resolve(hdr)
+        };
+        let scene = graph.add_pass(scene.depth(depth, clear_depth).sampled(shadow_atlas, vk::PipelineStageFlags::FRAGMENT_SHADER));
+
+        let mut post = vec![];
+        let mut add_post_pass = |graph: &mut RenderGraph, effect: PostEffect, name: &str, inputs: Vec<ResourceId>, target: ResourceId| {
+            let mut pass = PassDesc::new(name).color(target, Load::DontCare);
+            for (i, &input) in inputs.iter().enumerate() {
+                if !inputs[..i].contains(&input) {
+                    pass = pass.sampled(input, vk::PipelineStageFlags::FRAGMENT_SHADER);
+                }
+            }
+            post.push(PostPass {
+                pass: graph.add_pass(pass),
+                effect,
+                inputs,
+                to_swapchain: target == backbuffer,
+            });
+        };
+
+        let half = ImageExtent::Scaled(0.5);
+        let bloom = if post_settings.bloom {
+            let bright = graph.create_image("bloom_bright", ImageDesc::new(HDR_FORMAT, vk::SampleCountFlags::TYPE_1).extent(half));
+            let blurred_h = graph.create_image("bloom_blur_h", ImageDesc::new(HDR_FORMAT, vk::SampleCountFlags::TYPE_1).extent(half));
+            let blurred = graph.create_image("bloom", ImageDesc::new(HDR_FORMAT, vk::SampleCountFlags::TYPE_1).extent(half));
+            add_post_pass(&mut graph, PostEffect::BloomExtract, "bloom_extract", vec![hdr], bright);
+            add_post_pass(&mut graph, PostEffect::BloomBlurHorizontal, "bloom_blur_h", vec![bright], blurred_h);
+            add_post_pass(&mut graph, PostEffect::BloomBlurVertical, "bloom_blur_v", vec![blurred_h], blurred);
+            blurred
+        } else {
+            // Bound but not read, the set still needs an image there.
+            hdr
+        };
+        if post_settings.fxaa {
+            let ldr = graph.create_image("ldr", ImageDesc::new(LDR_FORMAT, vk::SampleCountFlags::TYPE_1));
+            add_post_pass(&mut graph, PostEffect::Tonemap, "tonemap", vec![hdr, bloom], ldr);
+            add_post_pass(&mut graph, PostEffect::Fxaa, "fxaa", vec![ldr], backbuffer);
+        } else {
+            add_post_pass(&mut graph, PostEffect::Tonemap, "tonemap", vec![hdr, bloom], backbuffer);
+        }
+
+        FrameGraph {
+            graph: graph.compile(device, swap_chain.extent),
+            swapchain_format: swap_chain.format,
+            shadow,
+            scene,
+            post,
+        }
+    }
+
+    /// Render pass the scene pipelines are built for. The scene always draws into `HDR_FORMAT`, so compiling the graph
+    /// again for any swapchain, or with other post effects, gives a compatible one.
+    pub fn scene_render_pass(&self) -> vk::RenderPass {
+        self.graph.render_pass(self.scene)
+    }
+
+    /// Render pass of the shadow pipeline. It only depends on the shadow atlas, so it stays compatible across swapchains.
+    pub fn shadow_render_pass(&self) -> vk::RenderPass {
+        self.graph.render_pass(self.shadow)
+    }
+
+    pub fn vk_destroy(&mut self) {
+        self.graph.vk_destroy();
+    }
+}
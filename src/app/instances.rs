@@ -7,28 +7,183 @@ use nalgebra_glm::{Mat4, Vec2, Vec3, Vec4};
 use std::f32::consts::PI;
 use std::sync::Arc;
 use vk_model::advanced_model::*;
+use vk_model::animation::AnimationPlayer;
+use vk_model::bounds::{Aabb, BoundingSphere, Frustum};
 
-const TEXTURE_PATH: &'static str = "assets/fighterdiffuse.bmp";
-const MODEL_PATH: &'static str = "assets/fighter.obj";
+use super::asset_registry::MaterialHandle;
+use super::lod::{self, LodSettings};
 
 pub struct GInstance {
     pub asset: Arc<GFXModel>,
 
     pub model_matrix: Mat4,
+    /// Level of detail picked by the last `Instances::select_lods`, 0 being the full mesh.
+    pub lod: usize,
+    /// Clip playback for skinned assets. A skinned instance without one is drawn in its rest pose.
+    pub animation: Option<AnimationPlayer>,
+    /// Skinning matrices of the pose from the last `Instances::animate`. Empty for unskinned assets.
+    pub joint_matrices: Vec<Mat4>,
+    /// Mesh-space box around the skinned mesh in that pose, which can reach well outside the bind pose's bounds.
+    pub posed_bounds: Option<Aabb>,
+    /// Where this instance's joint matrices start in the current frame's joint buffer, if it got any.
+    pub joint_offset: Option<u32>,
+    /// Set once the joint buffer has been too full for this instance, so that is only reported once.
+    pub joint_overflow_reported: bool,
+    /// Drawn with the default material when there is none.
+    pub material: Option<MaterialHandle>,
 }
 
 impl GInstance {
     pub fn new(asset: Arc<GFXModel>, model_matrix: Mat4) -> GInstance {
-        GInstance { asset, model_matrix }
+        GInstance {
+            asset,
+            model_matrix,
+            lod: 0,
+            animation: None,
+            joint_matrices: vec![],
+            posed_bounds: None,
+            joint_offset: None,
+            joint_overflow_reported: false,
+            material: None,
+        }
+    }
+
+    pub fn with_animation(mut self, animation: AnimationPlayer) -> GInstance {
+        self.animation = Some(animation);
+        self
+    }
+
+    pub fn with_material(mut self, material: Option<MaterialHandle>) -> GInstance {
+        self.material = material;
+        self
+    }
+
+    /// Mesh-space bounds as currently drawn: the posed box for skinned instances, the asset's otherwise.
+    pub fn bounds(&self) -> Aabb {
+        self.posed_bounds.unwrap_or(self.asset.bounds)
+    }
+
+    pub fn bounding_sphere(&self) -> BoundingSphere {
+        match self.posed_bounds {
+            Some(bounds) => BoundingSphere {
+                center: bounds.center(),
+                radius: bounds.extents().norm(),
+            },
+            None => self.asset.bounding_sphere,
+        }
+    }
+}
+
+pub const MAX_LOD_STATS: usize = 8;
+
+/// What the last `Instances::cull` and `Instances::select_lods` did.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CullStats {
+    pub tested: u32,
+    pub visible: u32,
+    /// Visible instances per level of detail.
+    pub visible_per_lod: [u32; MAX_LOD_STATS],
+    pub culled_by_sphere: u32,
+    pub culled_by_aabb: u32,
+}
+
+impl CullStats {
+    pub fn culled(&self) -> u32 {
+        self.culled_by_sphere + self.culled_by_aabb
     }
 }
 
+#[derive(Default)]
 pub struct Instances {
     pub g_instances: Vec<GInstance>,
+    /// Indices into `g_instances` that survived the last cull. Only these get draw calls recorded.
+    pub visible: Vec<usize>,
+    pub cull_stats: CullStats,
 }
 
 impl Instances {
-    // pub fn new() -> Instances {
+    pub fn new() -> Instances {
+        Instances {
+            g_instances: Vec::new(),
+            visible: Vec::new(),
+            cull_stats: CullStats::default(),
+        }
+    }
+
+    pub fn push(&mut self, instance: GInstance) {
+        self.visible.push(self.g_instances.len());
+        self.g_instances.push(instance);
+    }
 
-    // }
+    /// Rebuilds `visible` from the instances whose world bounds touch any of `frustums`, one per view. The cheap sphere
+    /// test runs first and the box test only decides the instances the sphere could not reject.
+    pub fn cull(&mut self, frustums: &[Frustum]) {
+        let mut stats = CullStats::default();
+        self.visible.clear();
+        for (i, inst) in self.g_instances.iter().enumerate() {
+            stats.tested += 1;
+            let sphere = inst.bounding_sphere().transformed(&inst.model_matrix);
+            let in_sphere: Vec<&Frustum> = frustums.iter().filter(|frustum| frustum.intersects_sphere(&sphere)).collect();
+            if in_sphere.is_empty() {
+                stats.culled_by_sphere += 1;
+                continue;
+            }
+            let aabb = inst.bounds().transformed(&inst.model_matrix);
+            if !in_sphere.iter().any(|frustum| frustum.intersects_aabb(&aabb)) {
+                stats.culled_by_aabb += 1;
+            } else {
+                stats.visible += 1;
+                self.visible.push(i);
+            }
+        }
+        self.cull_stats = stats;
+    }
+
+    /// Picks the level of detail of every visible instance from its projected size. Run after `cull`.
+    pub fn select_lods(&mut self, view: &Mat4, proj: &Mat4, settings: &LodSettings) {
+        self.cull_stats.visible_per_lod = [0; MAX_LOD_STATS];
+        for &i in self.visible.iter() {
+            let inst = &mut self.g_instances[i];
+            let size = lod::projected_size(&inst.bounding_sphere().transformed(&inst.model_matrix), view, proj);
+            inst.lod = lod::select_lod(inst.lod, size, inst.asset.lod_count(), settings);
+            self.cull_stats.visible_per_lod[inst.lod.min(MAX_LOD_STATS - 1)] += 1;
+        }
+    }
+
+    /// Moves every animated instance's clips forward by `delta_t` seconds, then poses every skinned instance and
+    /// bounds it in that pose. Run before `cull`.
+    pub fn animate(&mut self, delta_t: f32) {
+        for inst in self.g_instances.iter_mut() {
+            let skin = match inst.asset.skin.as_ref() {
+                Some(skin) => skin,
+                None => {
+                    inst.joint_matrices.clear();
+                    inst.posed_bounds = None;
+                    continue;
+                }
+            };
+            let pose = match inst.animation.as_mut() {
+                Some(animation) => {
+                    animation.advance(delta_t, &skin.clips);
+                    animation.pose(&skin.skeleton, &skin.clips)
+                }
+                None => skin.skeleton.rest_pose(),
+            };
+            inst.joint_matrices = skin.skeleton.joint_matrices(&pose);
+            inst.posed_bounds = Some(skin.posed_bounds(&inst.joint_matrices));
+        }
+    }
+
+    /// Points every instance of `old` at `new`, for assets that were reloaded.
+    pub fn replace_asset(&mut self, old: &Arc<GFXModel>, new: &Arc<GFXModel>) {
+        for inst in self.g_instances.iter_mut() {
+            if Arc::ptr_eq(&inst.asset, old) {
+                inst.asset = new.clone();
+            }
+        }
+    }
+
+    pub fn visible_instances(&self) -> impl Iterator<Item = &GInstance> {
+        self.visible.iter().map(move |&i| &self.g_instances[i])
+    }
 }
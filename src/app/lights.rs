@@ -0,0 +1,301 @@
+#![allow(dead_code)]
+
+use crate::vk_assist;
+use std::sync::Arc;
+
+use ash::version::DeviceV1_0;
+use ash::vk;
+use nalgebra_glm::{Vec3, Vec4};
+
+use vk_assist::types::buffer as bfr;
+use vk_assist::types::vulkan_device::VulkanDevice;
+
+/// Binding of the light buffer in the camera set, set 0.
+pub const LIGHT_BINDING: u32 = 1;
+/// Most lights a frame can shade with.
+pub const MAX_LIGHTS: usize = 64;
+
+/// The values are the light types the shaders switch on.
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum LightKind {
+    /// Lights everything from `direction`, like the sun.
+    Directional = 0,
+    /// Shines every way from `position`, fading out at `range`.
+    Point = 1,
+    /// A point light limited to a cone around `direction`.
+    Spot = 2,
+}
+
+/// A light in the scene. Fields a kind doesn't use are ignored.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Light {
+    pub kind: LightKind,
+    pub position: Vec3,
+    /// Direction the light travels in.
+    pub direction: Vec3,
+    pub color: Vec3,
+    pub intensity: f32,
+    pub range: f32,
+    /// Half angles in radians of the spot cone: full brightness inside `inner_angle`, none outside `outer_angle`.
+    pub inner_angle: f32,
+    pub outer_angle: f32,
+    /// Whether the light gets shadow maps. Only directional and spot lights cast shadows.
+    pub casts_shadows: bool,
+}
+
+impl Light {
+    pub fn directional(direction: Vec3, color: Vec3, intensity: f32) -> Light {
+        Light {
+            kind: LightKind::Directional,
+            position: Vec3::zeros(),
+            direction: direction.normalize(),
+            color,
+            intensity,
+            range: 0.0,
+            inner_angle: 0.0,
+            outer_angle: 0.0,
+            casts_shadows: true,
+        }
+    }
+
+    pub fn point(position: Vec3, color: Vec3, intensity: f32, range: f32) -> Light {
+        Light {
+            kind: LightKind::Point,
+            position,
+            direction: Vec3::new(0.0, -1.0, 0.0),
+            color,
+            intensity,
+            range,
+            inner_angle: 0.0,
+            outer_angle: 0.0,
+            casts_shadows: false,
+        }
+    }
+
+    /// A point light at `position` shining down `direction`, within `angles`, the inner and outer cone half angles.
+    pub fn spot(position: Vec3, direction: Vec3, color: Vec3, intensity: f32, range: f32, angles: (f32, f32)) -> Light {
+        assert!(angles.0 <= angles.1, "Spot light inner angle is wider than the outer angle");
+        Light {
+            kind: LightKind::Spot,
+            position,
+            direction: direction.normalize(),
+            color,
+            intensity,
+            range,
+            inner_angle: angles.0,
+            outer_angle: angles.1,
+            casts_shadows: true,
+        }
+    }
+
+    /// Without shadow maps.
+    pub fn without_shadows(mut self) -> Light {
+        self.casts_shadows = false;
+        self
+    }
+
+    fn to_gpu(self, shadow_map: Option<u32>) -> GpuLight {
+        GpuLight {
+            position_range: Vec4::new(self.position.x, self.position.y, self.position.z, self.range),
+            direction_outer_cos: Vec4::new(self.direction.x, self.direction.y, self.direction.z, self.outer_angle.cos()),
+            color_intensity: Vec4::new(self.color.x, self.color.y, self.color.z, self.intensity),
+            kind: self.kind,
+            inner_cos: self.inner_angle.cos(),
+            shadow_map: shadow_map.map_or(-1, |map| map as i32),
+            _padding: 0,
+        }
+    }
+}
+
+/// A light as `Light` in `lighting.glsl` reads it.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct GpuLight {
+    position_range: Vec4,
+    direction_outer_cos: Vec4,
+    color_intensity: Vec4,
+    kind: LightKind,
+    inner_cos: f32,
+    /// First shadow map, or -1.
+    shadow_map: i32,
+    _padding: u32,
+}
+
+/// Start of the light buffer, before the lights.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct LightHeader {
+    ambient: Vec4,
+    count: u32,
+    _padding: [u32; 3],
+}
+
+/// The light buffer's header and lights, each light with its first shadow map from `shadow_maps`. Only the first
+/// `MAX_LIGHTS` lights fit in the buffer, the rest are left out.
+fn pack_lights(ambient: Vec3, lights: &[Light], shadow_maps: &[Option<u32>]) -> (LightHeader, Vec<GpuLight>) {
+    let lights: Vec<GpuLight> = lights
+        .iter()
+        .take(MAX_LIGHTS)
+        .enumerate()
+        .map(|(i, light)| light.to_gpu(shadow_maps.get(i).copied().flatten()))
+        .collect();
+    let header = LightHeader {
+        ambient: Vec4::new(ambient.x, ambient.y, ambient.z, 0.0),
+        count: lights.len() as u32,
+        _padding: [0; 3],
+    };
+    (header, lights)
+}
+
+/// The lights of the scene and the storage buffers the shaders read them from, one per swapchain image.
+pub struct Lights {
+    device: Arc<VulkanDevice>,
+    /// Added to every lit surface, so the sides facing away from the lights aren't black.
+    pub ambient: Vec3,
+    pub lights: Vec<Light>,
+    buffers: Vec<bfr::Buffer>,
+}
+
+impl Lights {
+    pub fn new(device: Arc<VulkanDevice>, swapchain_image_count: usize) -> Lights {
+        let buffer_size = (std::mem::size_of::<LightHeader>() + std::mem::size_of::<GpuLight>() * MAX_LIGHTS) as u64;
+        let buffers = (0..swapchain_image_count)
+            .map(|_| {
+                bfr::create_buffer(
+                    device.clone(),
+                    buffer_size,
+                    vk::BufferUsageFlags::STORAGE_BUFFER,
+                    vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                    &device.get_physical_device_memory_properties(),
+                )
+            })
+            .collect();
+        Lights {
+            device,
+            ambient: Vec3::new(0.03, 0.03, 0.03),
+            lights: vec![],
+            buffers,
+        }
+    }
+
+    /// A sun, a warm point light and a spot light over the origin, so a scene without its own lights still shows off
+    /// both shading models.
+    pub fn with_default_rig(mut self) -> Lights {
+        self.push(Light::directional(Vec3::new(-0.4, -1.0, -0.3), Vec3::new(1.0, 0.96, 0.9), 2.0));
+        self.push(Light::point(Vec3::new(3.0, 2.0, 3.0), Vec3::new(1.0, 0.6, 0.3), 15.0, 12.0));
+        self.push(Light::spot(
+            Vec3::new(-3.0, 4.0, 0.0),
+            Vec3::new(0.6, -1.0, 0.0),
+            Vec3::new(0.4, 0.6, 1.0),
+            30.0,
+            15.0,
+            (0.3, 0.45),
+        ));
+        self
+    }
+
+    /// Index of the new light.
+    pub fn push(&mut self, light: Light) -> usize {
+        assert!(self.lights.len() < MAX_LIGHTS, "At most {} lights", MAX_LIGHTS);
+        self.lights.push(light);
+        self.lights.len() - 1
+    }
+
+    pub fn buffers(&self) -> Vec<vk::Buffer> {
+        self.buffers.iter().map(|buffer| buffer.buffer).collect()
+    }
+
+    /// Writes the lights into `image_index`'s buffer, which no frame in flight may be reading, each with its first
+    /// shadow map from `shadow_maps`.
+    pub fn upload(&self, image_index: usize, shadow_maps: &[Option<u32>]) {
+        let (header, lights) = pack_lights(self.ambient, &self.lights, shadow_maps);
+        let size = (std::mem::size_of::<LightHeader>() + std::mem::size_of::<GpuLight>() * lights.len()) as u64;
+        let memory = self.buffers[image_index].memory;
+        unsafe {
+            let data_ptr = self
+                .device
+                .logical_device
+                .map_memory(memory, 0, size, vk::MemoryMapFlags::empty())
+                .expect("Failed to Map Memory") as *mut u8;
+
+            (data_ptr as *mut LightHeader).copy_from_nonoverlapping(&header, 1);
+            (data_ptr.add(std::mem::size_of::<LightHeader>()) as *mut GpuLight).copy_from_nonoverlapping(lights.as_ptr(), lights.len());
+
+            self.device.logical_device.unmap_memory(memory);
+        }
+    }
+
+    pub fn vk_destroy(&mut self) {
+        for buffer in self.buffers.iter_mut() {
+            buffer.vk_destroy();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use memoffset::offset_of;
+
+    #[test]
+    fn gpu_structs_follow_the_std430_layout_of_lighting_glsl() {
+        // Light: three vec4s, then uint kind, float innerCos, int shadowMap and the padding to the vec4 alignment.
+        assert_eq!(offset_of!(GpuLight, position_range), 0);
+        assert_eq!(offset_of!(GpuLight, direction_outer_cos), 16);
+        assert_eq!(offset_of!(GpuLight, color_intensity), 32);
+        assert_eq!(offset_of!(GpuLight, kind), 48);
+        assert_eq!(offset_of!(GpuLight, inner_cos), 52);
+        assert_eq!(offset_of!(GpuLight, shadow_map), 56);
+        assert_eq!(std::mem::size_of::<GpuLight>(), 64);
+        assert_eq!(std::mem::size_of::<LightKind>(), 4);
+        // Lights: vec4 ambient, uint lightCount, and the light array from the next 16 byte boundary.
+        assert_eq!(offset_of!(LightHeader, ambient), 0);
+        assert_eq!(offset_of!(LightHeader, count), 16);
+        assert_eq!(std::mem::size_of::<LightHeader>(), 32);
+    }
+
+    #[test]
+    fn lights_are_packed_with_their_shadow_maps() {
+        let lights = vec![
+            Light::directional(Vec3::new(0.0, -2.0, 0.0), Vec3::new(1.0, 0.96, 0.9), 2.0),
+            Light::point(Vec3::new(3.0, 2.0, 3.0), Vec3::new(1.0, 0.6, 0.3), 15.0, 12.0),
+            Light::spot(Vec3::zeros(), Vec3::new(0.0, 0.0, -1.0), Vec3::new(1.0, 1.0, 1.0), 30.0, 15.0, (0.3, 0.45)),
+        ];
+        let (header, gpu) = pack_lights(Vec3::new(0.1, 0.2, 0.3), &lights, &[Some(0), None, Some(4)]);
+        assert_eq!(header.ambient, Vec4::new(0.1, 0.2, 0.3, 0.0));
+        assert_eq!(header.count, 3);
+        assert_eq!(gpu.len(), 3);
+
+        let sun = &gpu[0];
+        assert_eq!(sun.kind, LightKind::Directional);
+        assert_eq!(sun.shadow_map, 0);
+        assert_eq!(sun.direction_outer_cos, Vec4::new(0.0, -1.0, 0.0, 1.0));
+        assert_eq!(sun.color_intensity, Vec4::new(1.0, 0.96, 0.9, 2.0));
+
+        let point = &gpu[1];
+        assert_eq!(point.shadow_map, -1);
+        assert_eq!(point.position_range, Vec4::new(3.0, 2.0, 3.0, 12.0));
+
+        let spot = &gpu[2];
+        assert_eq!(spot.kind, LightKind::Spot);
+        assert_eq!(spot.shadow_map, 4);
+        assert_eq!(spot.inner_cos, 0.3f32.cos());
+        assert_eq!(spot.direction_outer_cos.w, 0.45f32.cos());
+    }
+
+    #[test]
+    fn lights_without_a_shadow_map_entry_get_none() {
+        let lights = vec![Light::directional(Vec3::new(0.0, -1.0, 0.0), Vec3::new(1.0, 1.0, 1.0), 1.0)];
+        let (_, gpu) = pack_lights(Vec3::zeros(), &lights, &[]);
+        assert_eq!(gpu[0].shadow_map, -1);
+    }
+
+    #[test]
+    fn lights_past_the_maximum_are_left_out() {
+        let light = Light::point(Vec3::zeros(), Vec3::new(1.0, 1.0, 1.0), 1.0, 5.0);
+        let (header, gpu) = pack_lights(Vec3::zeros(), &vec![light; MAX_LIGHTS + 3], &[]);
+        assert_eq!(header.count as usize, MAX_LIGHTS);
+        assert_eq!(gpu.len(), MAX_LIGHTS);
+    }
+}
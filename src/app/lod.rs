@@ -0,0 +1,89 @@
+#![allow(dead_code)]
+
+use nalgebra_glm::{Mat4, Vec4};
+
+use crate::vk_model::bounds::BoundingSphere;
+
+/// Runtime LOD selection. `thresholds[i]` is the screen size below which level `i + 1` is used instead of level `i`;
+/// screen size is the projected diameter as a fraction of the viewport height.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LodSettings {
+    pub thresholds: Vec<f32>,
+    /// Fraction of a threshold the size has to move past before switching, so instances sitting right at a threshold
+    /// don't flicker between levels.
+    pub hysteresis: f32,
+}
+
+impl Default for LodSettings {
+    fn default() -> LodSettings {
+        LodSettings {
+            thresholds: vec![0.4, 0.2, 0.1],
+            hysteresis: 0.1,
+        }
+    }
+}
+
+/// Projected diameter of a world space sphere as a fraction of the viewport height. Spheres the camera is inside or
+/// touching count as infinitely large.
+pub fn projected_size(sphere: &BoundingSphere, view: &Mat4, proj: &Mat4) -> f32 {
+    let center = view * Vec4::new(sphere.center.x, sphere.center.y, sphere.center.z, 1.0);
+    let depth = -center.z;
+    if depth <= sphere.radius {
+        return f32::MAX;
+    }
+    // proj[(1, 1)] is cot(fov_y / 2); it is negative here because the projection is flipped for Vulkan.
+    sphere.radius * proj[(1, 1)].abs() / depth
+}
+
+/// Level to draw at `size`, moving at most as far from `current` as the size justifies once hysteresis is applied.
+pub fn select_lod(current: usize, size: f32, lod_count: usize, settings: &LodSettings) -> usize {
+    let coarsest = lod_count.saturating_sub(1).min(settings.thresholds.len());
+    let mut level = current.min(coarsest);
+    while level < coarsest && size < settings.thresholds[level] * (1.0 - settings.hysteresis) {
+        level += 1;
+    }
+    while level > 0 && size > settings.thresholds[level - 1] * (1.0 + settings.hysteresis) {
+        level -= 1;
+    }
+    level
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra_glm::Vec3;
+
+    #[test]
+    fn lod_holds_inside_the_hysteresis_band() {
+        let settings = LodSettings::default();
+        // Threshold 0.4 between levels 0 and 1, band 0.36 to 0.44.
+        assert_eq!(select_lod(0, 0.37, 4, &settings), 0);
+        assert_eq!(select_lod(1, 0.43, 4, &settings), 1);
+        assert_eq!(select_lod(0, 0.35, 4, &settings), 1);
+        assert_eq!(select_lod(1, 0.45, 4, &settings), 0);
+    }
+
+    #[test]
+    fn lod_jumps_several_levels_and_clamps_to_the_chain() {
+        let settings = LodSettings::default();
+        assert_eq!(select_lod(0, 0.01, 4, &settings), 3);
+        assert_eq!(select_lod(3, 1.0, 4, &settings), 0);
+        assert_eq!(select_lod(0, 0.01, 2, &settings), 1);
+        assert_eq!(select_lod(5, 0.01, 1, &settings), 0);
+    }
+
+    #[test]
+    fn projected_size_shrinks_with_distance() {
+        let view = nalgebra_glm::look_at(&Vec3::new(0.0, 0.0, 0.0), &Vec3::new(0.0, 0.0, -1.0), &Vec3::new(0.0, 1.0, 0.0));
+        let mut proj = nalgebra_glm::perspective(1.0, std::f32::consts::FRAC_PI_2, 0.1, 100.0);
+        proj[(1, 1)] *= -1.0;
+        let at = |z: f32| BoundingSphere {
+            center: Vec3::new(0.0, 0.0, z),
+            radius: 1.0,
+        };
+        // cot(45 degrees) is 1, so the size is the radius over the depth.
+        assert!((projected_size(&at(-10.0), &view, &proj) - 0.1).abs() < 1e-5);
+        assert!(projected_size(&at(-20.0), &view, &proj) < projected_size(&at(-10.0), &view, &proj));
+        assert_eq!(projected_size(&at(-0.5), &view, &proj), f32::MAX);
+    }
+}
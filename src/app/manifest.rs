@@ -0,0 +1,463 @@
+#![allow(dead_code)]
+
+use crate::vk_assist;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use nalgebra_glm::{Mat4, Vec3, Vec4};
+use serde_derive::Deserialize;
+
+use vk_assist::mesh_processing::NormalMode;
+use vk_assist::mesh_simplify::LodChainSettings;
+use vk_assist::model_loader::{ImportOptions, MissingUvs, UpAxis};
+use vk_assist::primitives::Primitive;
+
+use super::materials::{MaterialParams, MaterialPipeline, ShadingModel};
+
+/// The asset manifest: named textures, materials and models, and the instances of those models the scene starts with.
+/// Paths are relative to the working directory, like every other asset path. Names are what the rest of the app refers
+/// to assets by.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AssetManifest {
+    /// Static model whose material is the default material.
+    pub primary_model: String,
+    #[serde(default)]
+    pub textures: BTreeMap<String, TextureEntry>,
+    #[serde(default)]
+    pub materials: BTreeMap<String, MaterialEntry>,
+    pub models: BTreeMap<String, ModelEntry>,
+    /// In draw order. The scene turns the first one, so there has to be one and its model can't be optional.
+    pub instances: Vec<InstanceEntry>,
+    /// Image of the color grading LUT, laid out as `tonemap.frag` reads it. Without one the grade is procedural.
+    #[serde(default)]
+    pub grading_lut: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TextureEntry {
+    pub path: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MaterialEntry {
+    /// Name of a texture in the manifest.
+    pub diffuse: String,
+    /// RGBA multiplied into the diffuse texture.
+    #[serde(default)]
+    pub tint: Option<[f32; 4]>,
+    #[serde(default)]
+    pub roughness: Option<f32>,
+    #[serde(default)]
+    pub metallic: Option<f32>,
+    #[serde(default)]
+    pub pipeline: Option<PipelineEntry>,
+    #[serde(default)]
+    pub shading: Option<ShadingEntry>,
+}
+
+impl MaterialEntry {
+    /// Parameters with anything left out at the `MaterialParams` default.
+    pub fn to_params(&self) -> MaterialParams {
+        let defaults = MaterialParams::default();
+        MaterialParams::new(
+            self.tint.map_or(defaults.tint, |tint| Vec4::new(tint[0], tint[1], tint[2], tint[3])),
+            self.roughness.unwrap_or(defaults.roughness),
+            self.metallic.unwrap_or(defaults.metallic),
+            match self.shading {
+                Some(ShadingEntry::BlinnPhong) => ShadingModel::BlinnPhong,
+                Some(ShadingEntry::Pbr) => ShadingModel::Pbr,
+                None => defaults.shading,
+            },
+        )
+    }
+
+    pub fn to_pipeline(&self) -> MaterialPipeline {
+        match self.pipeline {
+            Some(PipelineEntry::Opaque) | None => MaterialPipeline::Opaque,
+            Some(PipelineEntry::Blend) => MaterialPipeline::AlphaBlend,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PipelineEntry {
+    Opaque,
+    Blend,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ShadingEntry {
+    BlinnPhong,
+    Pbr,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ModelEntry {
+    /// OBJ, PLY or STL for static models, glTF/GLB for skinned ones. Models are either loaded from a path or built
+    /// from a primitive.
+    #[serde(default)]
+    pub path: Option<String>,
+    #[serde(default)]
+    pub primitive: Option<Primitive>,
+    /// Name of a material in the manifest.
+    pub material: String,
+    /// Only used with a path.
+    #[serde(default)]
+    pub import: ImportEntry,
+    /// Skip the model with a message instead of failing when its file is missing.
+    #[serde(default)]
+    pub optional: bool,
+}
+
+impl ModelEntry {
+    pub fn is_gltf(&self) -> bool {
+        let path = match self.path.as_ref() {
+            Some(path) => Path::new(path),
+            None => return false,
+        };
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+        extension == "gltf" || extension == "glb"
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct InstanceEntry {
+    /// Name of a model in the manifest. Instances of an optional model that wasn't loaded are skipped.
+    pub model: String,
+    #[serde(default)]
+    pub position: [f32; 3],
+    /// Applied around X, then Y, then Z.
+    #[serde(default)]
+    pub rotation_degrees: [f32; 3],
+    #[serde(default = "default_scale")]
+    pub scale: f32,
+    /// Name of a material in the manifest, drawn with instead of the model's.
+    #[serde(default)]
+    pub material: Option<String>,
+    /// Clip a skinned instance plays. Defaults to the first clip of a model that has any.
+    #[serde(default)]
+    pub animation: Option<usize>,
+}
+
+fn default_scale() -> f32 {
+    1.0
+}
+
+impl InstanceEntry {
+    /// Scales, rotates, then moves the model into place.
+    pub fn transform(&self) -> Mat4 {
+        let [x, y, z] = self.rotation_degrees;
+        let rotation = nalgebra_glm::rotation(z.to_radians(), &Vec3::z())
+            * nalgebra_glm::rotation(y.to_radians(), &Vec3::y())
+            * nalgebra_glm::rotation(x.to_radians(), &Vec3::x());
+        nalgebra_glm::translation(&Vec3::from(self.position)) * rotation * nalgebra_glm::scaling(&Vec3::repeat(self.scale))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum NormalsEntry {
+    Smooth,
+    Flat,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MissingUvsEntry {
+    Zero,
+    Planar,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+pub enum UpAxisEntry {
+    Y,
+    Z,
+}
+
+/// Import settings of a model. Anything left out keeps the `ImportOptions` default.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ImportEntry {
+    pub normals: Option<NormalsEntry>,
+    /// Only used with smooth normals.
+    pub crease_angle_degrees: Option<f32>,
+    pub missing_uvs: Option<MissingUvsEntry>,
+    pub vertex_colors: Option<bool>,
+    pub up_axis: Option<UpAxisEntry>,
+    pub unit_scale: Option<f32>,
+    pub flip_winding: Option<bool>,
+    pub recenter: Option<bool>,
+    pub lod_levels: Option<u32>,
+}
+
+impl ImportEntry {
+    pub fn to_options(&self) -> ImportOptions {
+        let defaults = ImportOptions::default();
+        let normal_mode = match (self.normals, self.crease_angle_degrees) {
+            (Some(NormalsEntry::Flat), _) => NormalMode::Flat,
+            (_, Some(degrees)) => NormalMode::Smooth {
+                crease_angle: degrees.to_radians(),
+            },
+            (Some(NormalsEntry::Smooth), None) | (None, None) => defaults.normal_mode,
+        };
+        ImportOptions {
+            normal_mode,
+            lod_chain: LodChainSettings {
+                levels: self.lod_levels.unwrap_or(defaults.lod_chain.levels),
+                ..defaults.lod_chain
+            },
+            missing_uvs: match self.missing_uvs {
+                Some(MissingUvsEntry::Zero) => MissingUvs::Zero,
+                Some(MissingUvsEntry::Planar) => MissingUvs::Planar,
+                None => defaults.missing_uvs,
+            },
+            vertex_colors: self.vertex_colors.unwrap_or(defaults.vertex_colors),
+            up_axis: match self.up_axis {
+                Some(UpAxisEntry::Y) => UpAxis::Y,
+                Some(UpAxisEntry::Z) => UpAxis::Z,
+                None => defaults.up_axis,
+            },
+            unit_scale: self.unit_scale.unwrap_or(defaults.unit_scale),
+            flip_winding: self.flip_winding.unwrap_or(defaults.flip_winding),
+            recenter: self.recenter.unwrap_or(defaults.recenter),
+        }
+    }
+}
+
+impl AssetManifest {
+    /// Reads and checks a manifest. Errors name the file and what is wrong with it.
+    pub fn load(path: &Path) -> Result<AssetManifest, String> {
+        let text = fs::read_to_string(path).map_err(|err| format!("Failed to read asset manifest {:?}: {}", path, err))?;
+        let manifest: AssetManifest = serde_json::from_str(&text).map_err(|err| format!("Failed to parse asset manifest {:?}: {}", path, err))?;
+        let problems = manifest.validate();
+        if !problems.is_empty() {
+            return Err(format!("Asset manifest {:?} is invalid:\n  {}", path, problems.join("\n  ")));
+        }
+        Ok(manifest)
+    }
+
+    /// Every reference to a name that isn't defined, and every value out of range.
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = vec![];
+        match self.models.get(&self.primary_model) {
+            None => problems.push(format!("primary model '{}' is not defined", self.primary_model)),
+            Some(model) if model.is_gltf() => problems.push(format!("primary model '{}' is a glTF, it has to be a static model", self.primary_model)),
+            Some(model) if model.optional => problems.push(format!("primary model '{}' can't be optional", self.primary_model)),
+            Some(_) => {}
+        }
+        for (name, material) in self.materials.iter() {
+            if !self.textures.contains_key(&material.diffuse) {
+                problems.push(format!("material '{}' uses undefined texture '{}'", name, material.diffuse));
+            }
+            for &(field, value) in [("roughness", material.roughness), ("metallic", material.metallic)].iter() {
+                if let Some(value) = value.filter(|value| !(0.0..=1.0).contains(value)) {
+                    problems.push(format!("material '{}' has {} {}, it has to be between 0 and 1", name, field, value));
+                }
+            }
+        }
+        for (name, model) in self.models.iter() {
+            match (&model.path, &model.primitive) {
+                (Some(_), Some(_)) => problems.push(format!("model '{}' has both a path and a primitive", name)),
+                (None, None) => problems.push(format!("model '{}' has neither a path nor a primitive", name)),
+                _ => {}
+            }
+            for (field, value) in model.primitive.iter().flat_map(|primitive| primitive.sizes()) {
+                if value <= 0.0 {
+                    problems.push(format!("model '{}' has primitive {} {}, it has to be above 0", name, field, value));
+                }
+            }
+            if !self.materials.contains_key(&model.material) {
+                problems.push(format!("model '{}' uses undefined material '{}'", name, model.material));
+            }
+        }
+        match self.instances.first().and_then(|instance| self.models.get(&instance.model)) {
+            Some(model) if model.optional => problems.push(format!("the first instance uses optional model '{}'", self.instances[0].model)),
+            None if self.instances.is_empty() => problems.push("there are no instances".to_string()),
+            _ => {}
+        }
+        for (i, instance) in self.instances.iter().enumerate() {
+            if !self.models.contains_key(&instance.model) {
+                problems.push(format!("instance {} uses undefined model '{}'", i, instance.model));
+            }
+            if let Some(material) = instance.material.as_ref().filter(|material| !self.materials.contains_key(*material)) {
+                problems.push(format!("instance {} uses undefined material '{}'", i, material));
+            }
+            if instance.scale <= 0.0 {
+                problems.push(format!("instance {} has scale {}, it has to be above 0", i, instance.scale));
+            }
+        }
+        problems
+    }
+
+    /// The material called `name` and the path of its diffuse texture.
+    pub fn resolve_material(&self, name: &str) -> Result<(&MaterialEntry, &str), String> {
+        let material = self.materials.get(name).ok_or_else(|| format!("material '{}' is not defined", name))?;
+        match self.textures.get(&material.diffuse) {
+            Some(texture) => Ok((material, &texture.path)),
+            None => Err(format!("material '{}' uses undefined texture '{}'", name, material.diffuse)),
+        }
+    }
+
+    /// Path of the diffuse texture a model's material uses. Only valid on a validated manifest.
+    pub fn diffuse_path(&self, model: &ModelEntry) -> &str {
+        let material = &self.materials[&model.material];
+        &self.textures[&material.diffuse].path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MANIFEST: &str = r#"{
+        "primary_model": "crate",
+        "textures": { "wood": { "path": "assets/wood.png" } },
+        "materials": {
+            "wood": { "diffuse": "wood" },
+            "painted": { "diffuse": "wood", "tint": [1.0, 0.0, 0.0, 1.0] }
+        },
+        "models": {
+            "crate": { "path": "assets/crate.obj", "material": "wood" },
+            "ball": { "primitive": { "shape": "uv_sphere", "radius": 0.5, "segments": 16, "rings": 8 }, "material": "wood" }
+        },
+        "instances": [
+            { "model": "crate" },
+            { "model": "crate", "position": [2.0, 0.0, 0.0], "material": "painted" }
+        ]
+    }"#;
+
+    fn parse(text: &str) -> AssetManifest {
+        serde_json::from_str(text).expect("Failed to parse the test manifest")
+    }
+
+    fn with_instances(instances: &str) -> AssetManifest {
+        let mut manifest = parse(MANIFEST);
+        manifest.instances = serde_json::from_str(instances).expect("Failed to parse the test instances");
+        manifest
+    }
+
+    #[test]
+    fn the_shipped_manifest_is_valid() {
+        let manifest = AssetManifest::load(Path::new(crate::app::assets::MANIFEST_PATH)).unwrap();
+        assert!(!manifest.instances.is_empty());
+    }
+
+    #[test]
+    fn the_grading_lut_is_optional() {
+        assert_eq!(parse(MANIFEST).grading_lut, None);
+        let with_lut = MANIFEST.replacen('{', r#"{ "grading_lut": "assets/lut.png","#, 1);
+        assert_eq!(parse(&with_lut).grading_lut.as_deref(), Some("assets/lut.png"));
+    }
+
+    #[test]
+    fn models_are_a_path_or_a_primitive() {
+        let manifest = parse(MANIFEST);
+        assert_eq!(
+            manifest.models["ball"].primitive,
+            Some(Primitive::UvSphere {
+                radius: 0.5,
+                segments: 16,
+                rings: 8
+            })
+        );
+        assert!(!manifest.models["ball"].is_gltf());
+
+        let mut manifest = manifest;
+        manifest.models.get_mut("ball").unwrap().path = Some("assets/ball.obj".to_string());
+        manifest.models.get_mut("crate").unwrap().path = None;
+        assert_eq!(
+            manifest.validate(),
+            vec![
+                "model 'ball' has both a path and a primitive".to_string(),
+                "model 'crate' has neither a path nor a primitive".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn primitive_sizes_have_to_be_above_zero() {
+        let mut manifest = parse(MANIFEST);
+        manifest.models.get_mut("ball").unwrap().primitive = Some(Primitive::Torus {
+            major_radius: 1.0,
+            minor_radius: 0.0,
+            segments: 8,
+            tube_segments: 8,
+        });
+        assert_eq!(
+            manifest.validate(),
+            vec!["model 'ball' has primitive minor_radius 0, it has to be above 0".to_string()]
+        );
+    }
+
+    #[test]
+    fn primitives_need_their_shape_parameters() {
+        let missing_radius = r#"{ "shape": "cone", "height": 1.0, "segments": 8 }"#;
+        assert!(serde_json::from_str::<Primitive>(missing_radius).is_err());
+        let unknown_shape = r#"{ "shape": "teapot", "size": 1.0 }"#;
+        assert!(serde_json::from_str::<Primitive>(unknown_shape).is_err());
+        let extra_field = r#"{ "shape": "cube", "size": 1.0, "segments": 8 }"#;
+        assert!(serde_json::from_str::<Primitive>(extra_field).is_err());
+    }
+
+    #[test]
+    fn instances_default_to_an_untransformed_model() {
+        let manifest = parse(MANIFEST);
+        assert!(manifest.validate().is_empty());
+        let instance = &manifest.instances[0];
+        assert_eq!(instance.transform(), Mat4::identity());
+        assert_eq!(instance.material, None);
+        assert_eq!(instance.animation, None);
+        assert_eq!(manifest.instances[1].material.as_deref(), Some("painted"));
+    }
+
+    #[test]
+    fn instances_need_defined_models_and_materials() {
+        let manifest = with_instances(r#"[{ "model": "barrel" }, { "model": "crate", "material": "steel" }]"#);
+        assert_eq!(
+            manifest.validate(),
+            vec![
+                "instance 0 uses undefined model 'barrel'".to_string(),
+                "instance 1 uses undefined material 'steel'".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn the_first_instance_has_to_be_loaded() {
+        let mut manifest = with_instances("[]");
+        assert_eq!(manifest.validate(), vec!["there are no instances".to_string()]);
+        manifest.models.get_mut("crate").unwrap().optional = true;
+        manifest.primary_model = "missing".to_string();
+        manifest.instances = serde_json::from_str(r#"[{ "model": "crate" }]"#).unwrap();
+        assert!(manifest.validate().contains(&"the first instance uses optional model 'crate'".to_string()));
+    }
+
+    #[test]
+    fn instances_need_a_positive_scale() {
+        let manifest = with_instances(r#"[{ "model": "crate", "scale": 0.0 }]"#);
+        assert_eq!(manifest.validate(), vec!["instance 0 has scale 0, it has to be above 0".to_string()]);
+    }
+
+    #[test]
+    fn unknown_instance_fields_are_rejected() {
+        let text = MANIFEST.replace(r#"{ "model": "crate" }"#, r#"{ "model": "crate", "colour": 1 }"#);
+        assert!(serde_json::from_str::<AssetManifest>(&text).is_err());
+    }
+
+    #[test]
+    fn transforms_scale_then_rotate_then_move() {
+        let manifest = with_instances(r#"[{ "model": "crate", "position": [1.0, 2.0, 3.0], "rotation_degrees": [0.0, 90.0, 0.0], "scale": 2.0 }]"#);
+        let point = manifest.instances[0].transform() * Vec4::new(1.0, 0.0, 0.0, 1.0);
+        // (1, 0, 0) scaled to (2, 0, 0), turned a quarter around Y to (0, 0, -2), then moved.
+        assert!((point - Vec4::new(1.0, 2.0, 1.0, 1.0)).norm() < 1e-5, "{:?}", point);
+    }
+}
@@ -0,0 +1,429 @@
+#![allow(dead_code)]
+
+use crate::pipelines;
+use crate::vk_assist;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use ash::version::DeviceV1_0;
+use ash::vk;
+use nalgebra_glm::Vec4;
+
+use std::ptr;
+
+use pipelines::reflected_layout::PipelineInterface;
+use vk_assist::types::buffer as bfr;
+use vk_assist::types::descriptor_allocator::{DescriptorAllocator, DescriptorLayoutCache};
+use vk_assist::types::image as img;
+use vk_assist::types::vulkan_device::VulkanDevice;
+
+use super::asset_registry::{AssetRegistry, MaterialHandle};
+use super::bindless::BindlessMaterials;
+use super::instances::{GInstance, Instances};
+
+/// Descriptor set the material is bound to: texture at binding 0, sampler at 1 and `MaterialParams` at 2.
+pub const MATERIAL_SET: u32 = 1;
+
+/// Static pipeline a material is drawn with. Draws are sorted in this order, so blended materials come after the
+/// opaque ones. Blended draws aren't sorted by depth.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum MaterialPipeline {
+    Opaque,
+    AlphaBlend,
+}
+
+impl MaterialPipeline {
+    pub const ALL: [MaterialPipeline; 2] = [MaterialPipeline::Opaque, MaterialPipeline::AlphaBlend];
+}
+
+/// How a material is lit. The values are the ones `shade` in `lighting.glsl` switches on.
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ShadingModel {
+    /// Lambert diffuse with a Blinn-Phong highlight whose size comes from the roughness. Metallic is ignored.
+    BlinnPhong = 0,
+    /// Metallic-roughness PBR: GGX distribution, Smith geometry and Schlick fresnel.
+    Pbr = 1,
+}
+
+/// Shading parameters, laid out like the `MaterialParams` uniform block in the shaders.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MaterialParams {
+    pub tint: Vec4,
+    pub roughness: f32,
+    pub metallic: f32,
+    pub shading: ShadingModel,
+    _padding: f32,
+}
+
+impl MaterialParams {
+    pub fn new(tint: Vec4, roughness: f32, metallic: f32, shading: ShadingModel) -> MaterialParams {
+        MaterialParams {
+            tint,
+            roughness,
+            metallic,
+            shading,
+            _padding: 0.0,
+        }
+    }
+}
+
+impl Default for MaterialParams {
+    fn default() -> MaterialParams {
+        MaterialParams::new(Vec4::new(1.0, 1.0, 1.0, 1.0), 1.0, 0.0, ShadingModel::Pbr)
+    }
+}
+
+/// GPU side of one registry material.
+pub struct GpuMaterial {
+    pub handle: MaterialHandle,
+    pub pipeline: MaterialPipeline,
+    pub params: MaterialParams,
+    /// Held so the registry doesn't destroy it while the descriptor sets still point at it.
+    pub texture: Arc<img::Image>,
+    pub sampler: vk::Sampler,
+    /// Where the registry put the texture in the bindless texture array.
+    pub texture_slot: u32,
+    /// One per swapchain image, so a reloaded texture can be written into the sets of images that aren't in flight.
+    pub descriptor_sets: Vec<vk::DescriptorSet>,
+    /// Sets still pointing at the previous texture.
+    stale: Vec<bool>,
+}
+
+/// Descriptor sets of the materials the assets loaded, and the static pipeline each kind of material is drawn with.
+pub struct Materials {
+    device: Arc<VulkanDevice>,
+    /// Owned by the scene's layout cache.
+    pub layout: vk::DescriptorSetLayout,
+    params_buffer: bfr::Buffer,
+    pub materials: Vec<GpuMaterial>,
+    by_handle: HashMap<MaterialHandle, usize>,
+    /// Index of the material for instances without one, or with one that isn't loaded.
+    pub default: usize,
+    /// Built and destroyed with the scene's other pipelines.
+    pub pipelines: BTreeMap<MaterialPipeline, vk::Pipeline>,
+    /// Every material in one set, if the device supports bindless textures. Static draws use it instead of the
+    /// per-material sets, which skinned draws keep using.
+    pub bindless: Option<BindlessMaterials>,
+}
+
+impl Materials {
+    /// Sets up `handles`, the first one being the default. The set layout is set `MATERIAL_SET` of `interface`, and the
+    /// sets come from `descriptors`, which has to outlive the materials.
+    pub fn new(
+        device: Arc<VulkanDevice>,
+        registry: &AssetRegistry,
+        handles: &[MaterialHandle],
+        interface: &PipelineInterface,
+        swapchain_image_count: usize,
+        descriptors: &mut DescriptorAllocator,
+        layouts: &mut DescriptorLayoutCache,
+    ) -> Materials {
+        assert!(!handles.is_empty(), "Need at least one material!");
+        let layout = interface.set_layout(layouts, MATERIAL_SET);
+
+        let alignment = device.get_physical_device_properties().limits.min_uniform_buffer_offset_alignment.max(1);
+        let params_stride = (std::mem::size_of::<MaterialParams>() as u64).div_ceil(alignment) * alignment;
+        let params_buffer = bfr::create_buffer(
+            device.clone(),
+            params_stride * handles.len() as u64,
+            vk::BufferUsageFlags::UNIFORM_BUFFER,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            &device.get_physical_device_memory_properties(),
+        );
+
+        let mut materials = vec![];
+        let mut by_handle = HashMap::new();
+        for (i, &handle) in handles.iter().enumerate() {
+            let material = registry.material(handle).expect("Material isn't loaded!");
+            let texture = registry.texture(material.diffuse).expect("Material uses a freed texture!");
+            let sampler = texture.create_sampler();
+            let texture_slot = registry.texture_slot(material.diffuse).expect("Material uses a freed texture!");
+            write_params(&device, &params_buffer, params_stride * i as u64, &material.params);
+
+            let descriptor_sets = descriptors.allocate(&vec![layout; swapchain_image_count]);
+            for &descriptor_set in descriptor_sets.iter() {
+                let params_range = (params_buffer.buffer, params_stride * i as u64);
+                write_descriptor_set(&device, descriptor_set, &texture, sampler, params_range);
+            }
+
+            by_handle.insert(handle, materials.len());
+            materials.push(GpuMaterial {
+                handle,
+                pipeline: material.pipeline,
+                params: material.params,
+                texture,
+                sampler,
+                texture_slot,
+                stale: vec![false; descriptor_sets.len()],
+                descriptor_sets,
+            });
+        }
+
+        let bindless = if device.descriptor_indexing {
+            BindlessMaterials::new(device.clone(), &materials, swapchain_image_count)
+        } else {
+            None
+        };
+        match bindless {
+            Some(_) => println!("Drawing static materials bindless"),
+            None => println!("Drawing static materials with a descriptor set each"),
+        }
+
+        Materials {
+            device,
+            layout,
+            params_buffer,
+            materials,
+            by_handle,
+            default: 0,
+            pipelines: BTreeMap::new(),
+            bindless,
+        }
+    }
+
+    /// Layout of the material set of the static pipelines: the bindless set if there is one.
+    pub fn static_set_layout(&self) -> vk::DescriptorSetLayout {
+        self.bindless.as_ref().map_or(self.layout, |bindless| bindless.layout)
+    }
+
+    pub fn index(&self, handle: Option<MaterialHandle>) -> usize {
+        handle.and_then(|handle| self.by_handle.get(&handle).copied()).unwrap_or(self.default)
+    }
+
+    /// Visible unskinned instances with their material index, sorted by pipeline, then material, then asset, so each is
+    /// bound once and the instances of an asset share its buffer binding within a material.
+    pub fn draw_order<'a>(&self, instances: &'a Instances) -> Vec<(&'a GInstance, usize)> {
+        let mut draws: Vec<(&GInstance, usize)> = instances
+            .visible_instances()
+            .filter(|inst| inst.asset.skin.is_none())
+            .map(|inst| (inst, self.index(inst.material)))
+            .collect();
+        draws.sort_by_key(|&(inst, material)| (self.materials[material].pipeline, material, Arc::as_ptr(&inst.asset)));
+        draws
+    }
+
+    /// Picks up textures the registry reloaded. The sets pointing at the old ones are rewritten by `update_sets`.
+    pub fn refresh_textures(&mut self, registry: &AssetRegistry) {
+        for material in self.materials.iter_mut() {
+            let texture = registry.material(material.handle).and_then(|registered| registry.texture(registered.diffuse));
+            if let Some(texture) = texture.filter(|texture| !Arc::ptr_eq(texture, &material.texture)) {
+                material.texture = texture;
+                for stale in material.stale.iter_mut() {
+                    *stale = true;
+                }
+            }
+        }
+    }
+
+    /// Points `image_index`'s stale sets at their material's current texture. Each image's sets are only read by that
+    /// image's command buffer, so call this right before re-recording it.
+    pub fn update_sets(&mut self, image_index: usize) {
+        for material in self.materials.iter_mut().filter(|material| material.stale[image_index]) {
+            update_texture_descriptor(&self.device, material.descriptor_sets[image_index], &material.texture);
+            material.stale[image_index] = false;
+        }
+        if let Some(bindless) = self.bindless.as_mut() {
+            bindless.update_set(image_index, &self.materials);
+        }
+    }
+
+    /// Destroys everything but the pipelines, the set layout and the descriptor sets.
+    pub fn vk_destroy(&mut self) {
+        unsafe {
+            for material in self.materials.iter() {
+                self.device.logical_device.destroy_sampler(material.sampler, None);
+            }
+        }
+        self.params_buffer.vk_destroy();
+        if let Some(bindless) = self.bindless.as_mut() {
+            bindless.vk_destroy();
+        }
+    }
+}
+
+fn write_params(device: &VulkanDevice, params_buffer: &bfr::Buffer, offset: u64, params: &MaterialParams) {
+    let size = std::mem::size_of::<MaterialParams>() as u64;
+    unsafe {
+        let data_ptr = device
+            .logical_device
+            .map_memory(params_buffer.memory, offset, size, vk::MemoryMapFlags::empty())
+            .expect("Failed to Map Memory") as *mut MaterialParams;
+
+        data_ptr.copy_from_nonoverlapping(params, 1);
+
+        device.logical_device.unmap_memory(params_buffer.memory);
+    }
+}
+
+/// Writes all three bindings; `params` is the params buffer and the material's offset in it.
+fn write_descriptor_set(device: &VulkanDevice, descriptor_set: vk::DescriptorSet, texture: &img::Image, sampler: vk::Sampler, params: (vk::Buffer, u64)) {
+    let descriptor_image_infos = [vk::DescriptorImageInfo {
+        sampler: vk::Sampler::null(),
+        image_view: texture.view,
+        image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+    }];
+    let descriptor_sampler_infos = [vk::DescriptorImageInfo {
+        sampler,
+        image_view: vk::ImageView::null(),
+        image_layout: vk::ImageLayout::UNDEFINED,
+    }];
+    let descriptor_buffer_infos = [vk::DescriptorBufferInfo {
+        buffer: params.0,
+        offset: params.1,
+        range: std::mem::size_of::<MaterialParams>() as u64,
+    }];
+    let descriptor_write_sets = [
+        vk::WriteDescriptorSet {
+            // diffuse texture
+            s_type: vk::StructureType::WRITE_DESCRIPTOR_SET,
+            p_next: ptr::null(),
+            dst_set: descriptor_set,
+            dst_binding: 0,
+            dst_array_element: 0,
+            descriptor_count: 1,
+            descriptor_type: vk::DescriptorType::SAMPLED_IMAGE,
+            p_image_info: descriptor_image_infos.as_ptr(),
+            p_buffer_info: ptr::null(),
+            p_texel_buffer_view: ptr::null(),
+        },
+        vk::WriteDescriptorSet {
+            // sampler
+            s_type: vk::StructureType::WRITE_DESCRIPTOR_SET,
+            p_next: ptr::null(),
+            dst_set: descriptor_set,
+            dst_binding: 1,
+            dst_array_element: 0,
+            descriptor_count: 1,
+            descriptor_type: vk::DescriptorType::SAMPLER,
+            p_image_info: descriptor_sampler_infos.as_ptr(),
+            p_buffer_info: ptr::null(),
+            p_texel_buffer_view: ptr::null(),
+        },
+        vk::WriteDescriptorSet {
+            // material parameters
+            s_type: vk::StructureType::WRITE_DESCRIPTOR_SET,
+            p_next: ptr::null(),
+            dst_set: descriptor_set,
+            dst_binding: 2,
+            dst_array_element: 0,
+            descriptor_count: 1,
+            descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+            p_image_info: ptr::null(),
+            p_buffer_info: descriptor_buffer_infos.as_ptr(),
+            p_texel_buffer_view: ptr::null(),
+        },
+    ];
+    unsafe {
+        device.logical_device.update_descriptor_sets(&descriptor_write_sets, &[]);
+    }
+}
+
+/// Points the texture binding of `descriptor_set` at `texture`. The set must not be in use by a pending command buffer.
+fn update_texture_descriptor(device: &VulkanDevice, descriptor_set: vk::DescriptorSet, texture: &img::Image) {
+    let descriptor_image_infos = [vk::DescriptorImageInfo {
+        sampler: vk::Sampler::null(),
+        image_view: texture.view,
+        image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+    }];
+    let descriptor_write_sets = [vk::WriteDescriptorSet {
+        s_type: vk::StructureType::WRITE_DESCRIPTOR_SET,
+        p_next: ptr::null(),
+        dst_set: descriptor_set,
+        dst_binding: 0,
+        dst_array_element: 0,
+        descriptor_count: 1,
+        descriptor_type: vk::DescriptorType::SAMPLED_IMAGE,
+        p_image_info: descriptor_image_infos.as_ptr(),
+        p_buffer_info: ptr::null(),
+        p_texel_buffer_view: ptr::null(),
+    }];
+    unsafe {
+        device.logical_device.update_descriptor_sets(&descriptor_write_sets, &[]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::manifest::AssetManifest;
+    use memoffset::offset_of;
+
+    const MANIFEST: &str = r#"{
+        "primary_model": "crate",
+        "textures": { "wood": { "path": "assets/wood.png" }, "metal": { "path": "assets/metal.png" } },
+        "materials": {
+            "wood": { "diffuse": "wood", "shading": "blinn_phong", "roughness": 0.8 },
+            "steel": { "diffuse": "metal", "shading": "pbr", "roughness": 0.3, "metallic": 1.0 },
+            "glass": { "diffuse": "metal", "tint": [1.0, 1.0, 1.0, 0.25], "pipeline": "blend" },
+            "missing": { "diffuse": "stone" }
+        },
+        "models": { "crate": { "path": "assets/crate.obj", "material": "wood" } },
+        "instances": [{ "model": "crate" }]
+    }"#;
+
+    fn manifest() -> AssetManifest {
+        serde_json::from_str(MANIFEST).expect("Failed to parse the test manifest")
+    }
+
+    #[test]
+    fn params_are_laid_out_like_the_material_params_block() {
+        assert_eq!(offset_of!(MaterialParams, tint), 0);
+        assert_eq!(offset_of!(MaterialParams, roughness), 16);
+        assert_eq!(offset_of!(MaterialParams, metallic), 20);
+        assert_eq!(offset_of!(MaterialParams, shading), 24);
+        assert_eq!(std::mem::size_of::<MaterialParams>(), 32);
+    }
+
+    #[test]
+    fn materials_resolve_their_texture_by_name() {
+        let manifest = manifest();
+        let (wood, path) = manifest.resolve_material("wood").unwrap();
+        assert_eq!(wood.diffuse, "wood");
+        assert_eq!(path, "assets/wood.png");
+        assert_eq!(manifest.resolve_material("steel").unwrap().1, "assets/metal.png");
+        assert_eq!(manifest.resolve_material("glass").unwrap().1, "assets/metal.png");
+    }
+
+    #[test]
+    fn unknown_textures_and_materials_are_errors() {
+        let manifest = manifest();
+        let err = manifest.resolve_material("missing").unwrap_err();
+        assert_eq!(err, "material 'missing' uses undefined texture 'stone'");
+        assert!(manifest.validate().contains(&err));
+        assert_eq!(manifest.resolve_material("brick").unwrap_err(), "material 'brick' is not defined");
+    }
+
+    #[test]
+    fn each_shading_model_gets_its_params_and_pipeline() {
+        let manifest = manifest();
+        let material = |name| manifest.resolve_material(name).unwrap().0;
+
+        let wood = material("wood");
+        assert_eq!(
+            wood.to_params(),
+            MaterialParams::new(Vec4::new(1.0, 1.0, 1.0, 1.0), 0.8, 0.0, ShadingModel::BlinnPhong)
+        );
+        assert_eq!(wood.to_pipeline(), MaterialPipeline::Opaque);
+
+        let steel = material("steel").to_params();
+        assert_eq!(steel.shading, ShadingModel::Pbr);
+        assert_eq!((steel.roughness, steel.metallic), (0.3, 1.0));
+
+        let glass = material("glass");
+        assert_eq!(
+            glass.to_params(),
+            MaterialParams::new(Vec4::new(1.0, 1.0, 1.0, 0.25), 1.0, 0.0, ShadingModel::Pbr)
+        );
+        assert_eq!(glass.to_pipeline(), MaterialPipeline::AlphaBlend);
+    }
+
+    #[test]
+    fn blended_materials_are_ordered_after_opaque_ones() {
+        let mut pipelines = MaterialPipeline::ALL.to_vec();
+        pipelines.reverse();
+        pipelines.sort();
+        assert_eq!(pipelines, vec![MaterialPipeline::Opaque, MaterialPipeline::AlphaBlend]);
+    }
+}
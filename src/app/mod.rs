@@ -1,10 +1,23 @@
+pub mod asset_registry;
 pub mod assets;
+pub mod bindless;
 pub mod camera;
 pub mod central;
 pub mod debug;
+pub mod file_watcher;
+pub mod frame_graph;
 pub mod input_model;
 pub mod instances;
+pub mod lights;
+pub mod lod;
+pub mod manifest;
+pub mod materials;
 pub mod platforms;
+pub mod post;
 pub mod scene;
+pub mod shaders;
+pub mod shadows;
+pub mod skinning;
 pub mod time_manager;
 pub mod tools;
+pub mod views;
@@ -0,0 +1,626 @@
+#![allow(dead_code)]
+
+use crate::vk_assist;
+use std::sync::Arc;
+
+use ash::version::DeviceV1_0;
+use ash::vk;
+use ash::vk::DeviceFnV1_0;
+use image::GenericImageView;
+use nalgebra_glm::Vec2;
+use winit::event::VirtualKeyCode;
+
+use std::ffi::c_void;
+use std::path::Path;
+use std::ptr;
+
+use crate::pipelines::pipeline_builder::GraphicsPipelineBuilder;
+use vk_assist::render_graph::{PassId, ResourceId};
+use vk_assist::types::buffer as bfr;
+use vk_assist::types::command as cmd;
+use vk_assist::types::descriptor_allocator::{DescriptorAllocator, DescriptorLayoutCache};
+use vk_assist::types::image as img;
+use vk_assist::types::vulkan_device::VulkanDevice;
+
+use super::frame_graph::FrameGraph;
+use super::shaders::{ShaderKey, ShaderLibrary, ShaderStages};
+
+/// Format the scene is drawn in, before tonemapping.
+pub const HDR_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+/// Format of the tonemapped image FXAA reads. It holds display-encoded colors, or linear ones with gamma correction off.
+pub const LDR_FORMAT: vk::Format = vk::Format::R8G8B8A8_UNORM;
+/// Side of the color grading LUT's cube, as `LUT_SIZE` in `tonemap.frag`.
+pub const LUT_SIZE: u32 = 16;
+
+const FLAG_BLOOM: u32 = 1;
+const FLAG_GAMMA: u32 = 2;
+const FLAG_GRADING: u32 = 4;
+const FLAG_SRGB_TARGET: u32 = 8;
+
+/// The values are the curves `tonemap.frag` switches on.
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Tonemapper {
+    Aces = 0,
+    Reinhard = 1,
+    /// Clamps to the displayable range.
+    Clamp = 2,
+}
+
+impl Tonemapper {
+    pub fn next(self) -> Tonemapper {
+        match self {
+            Tonemapper::Aces => Tonemapper::Reinhard,
+            Tonemapper::Reinhard => Tonemapper::Clamp,
+            Tonemapper::Clamp => Tonemapper::Aces,
+        }
+    }
+}
+
+/// How the HDR scene is turned into the swapchain image. Bloom and FXAA add passes to the frame graph, so changing
+/// them rebuilds it; the rest only changes push constants.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PostSettings {
+    /// Scales the scene's colors before tonemapping.
+    pub exposure: f32,
+    pub tonemapper: Tonemapper,
+    /// Encodes with a power curve of `gamma`. Off, the tonemapped colors are written linear.
+    pub gamma_correction: bool,
+    pub gamma: f32,
+    pub bloom: bool,
+    /// Brightness after exposure where colors start to bloom.
+    pub bloom_threshold: f32,
+    pub bloom_intensity: f32,
+    pub fxaa: bool,
+    /// Runs the tonemapped image through the color grading LUT.
+    pub color_grading: bool,
+}
+
+impl Default for PostSettings {
+    fn default() -> PostSettings {
+        PostSettings {
+            exposure: 1.0,
+            tonemapper: Tonemapper::Aces,
+            gamma_correction: true,
+            gamma: 2.2,
+            bloom: true,
+            bloom_threshold: 1.0,
+            bloom_intensity: 0.2,
+            fxaa: true,
+            color_grading: false,
+        }
+    }
+}
+
+impl PostSettings {
+    /// The settings after pressing `key`, if it is one of the post-processing keys: F1 bloom, F2 FXAA, F3 color
+    /// grading, F4 gamma correction, F5 the next tonemapper, and page up and down for exposure.
+    pub fn with_key(mut self, key: VirtualKeyCode) -> Option<PostSettings> {
+        match key {
+            VirtualKeyCode::F1 => self.bloom = !self.bloom,
+            VirtualKeyCode::F2 => self.fxaa = !self.fxaa,
+            VirtualKeyCode::F3 => self.color_grading = !self.color_grading,
+            VirtualKeyCode::F4 => self.gamma_correction = !self.gamma_correction,
+            VirtualKeyCode::F5 => self.tonemapper = self.tonemapper.next(),
+            VirtualKeyCode::PageUp => self.exposure *= 1.25,
+            VirtualKeyCode::PageDown => self.exposure /= 1.25,
+            _ => return None,
+        }
+        Some(self)
+    }
+
+    /// Whether going from `self` to `other` changes the passes of the frame graph.
+    pub fn changes_passes(&self, other: &PostSettings) -> bool {
+        self.bloom != other.bloom || self.fxaa != other.fxaa
+    }
+}
+
+/// A fullscreen pass of the post-processing chain.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PostEffect {
+    /// Keeps the parts of the HDR image bright enough to bloom, at half resolution.
+    BloomExtract,
+    BloomBlurHorizontal,
+    BloomBlurVertical,
+    /// Exposure, bloom, tonemapping, gamma and color grading.
+    Tonemap,
+    Fxaa,
+}
+
+impl PostEffect {
+    pub fn shader_keys(self) -> (ShaderKey, ShaderKey) {
+        let fragment = match self {
+            PostEffect::BloomExtract => "shaders/bloom_extract.frag",
+            PostEffect::BloomBlurHorizontal | PostEffect::BloomBlurVertical => "shaders/blur.frag",
+            PostEffect::Tonemap => "shaders/tonemap.frag",
+            PostEffect::Fxaa => "shaders/fxaa.frag",
+        };
+        (ShaderKey::new("shaders/fullscreen.vert"), ShaderKey::new(fragment))
+    }
+
+    fn blur_direction(self) -> Vec2 {
+        match self {
+            PostEffect::BloomBlurHorizontal => Vec2::new(1.0, 0.0),
+            PostEffect::BloomBlurVertical => Vec2::new(0.0, 1.0),
+            _ => Vec2::zeros(),
+        }
+    }
+}
+
+/// A post-processing pass in the frame graph. `inputs` are bound to bindings 1 and on of the pass's set, in order.
+#[derive(Clone, Debug)]
+pub struct PostPass {
+    pub pass: PassId,
+    pub effect: PostEffect,
+    pub inputs: Vec<ResourceId>,
+    /// Whether the pass writes the swapchain image rather than an image the graph owns.
+    pub to_swapchain: bool,
+}
+
+/// Pushed to every post-processing pass. Laid out like `PostConstants` in `post.glsl`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct PostConstants {
+    texel_size: Vec2,
+    blur_direction: Vec2,
+    exposure: f32,
+    gamma: f32,
+    bloom_threshold: f32,
+    bloom_intensity: f32,
+    tonemapper: Tonemapper,
+    flags: u32,
+}
+
+/// Pipeline and descriptor set of one pass, built for the frame graph it was compiled in.
+struct PostPipeline {
+    pass: PassId,
+    effect: PostEffect,
+    pipeline_layout: vk::PipelineLayout,
+    pipeline: vk::Pipeline,
+    descriptor_set: vk::DescriptorSet,
+    flags: u32,
+}
+
+/// The post-processing chain's settings, and the pipelines and descriptor sets for the post passes of the current
+/// frame graph. The sets point at the graph's images, so they are rebuilt with the graph.
+pub struct Post {
+    device: Arc<VulkanDevice>,
+    pub settings: PostSettings,
+    /// Linear and clamped to the edge, for every input.
+    sampler: vk::Sampler,
+    lut: img::Image,
+    descriptors: DescriptorAllocator,
+    pipelines: Vec<PostPipeline>,
+}
+
+impl Post {
+    /// Grades with the LUT image at `grading_lut`, or with `grade` if there is none.
+    pub fn new(device: Arc<VulkanDevice>, command_pool: vk::CommandPool, settings: PostSettings, grading_lut: Option<&Path>) -> Post {
+        Post {
+            sampler: create_linear_sampler(&device),
+            lut: create_grading_lut(device.clone(), command_pool, grading_lut),
+            descriptors: DescriptorAllocator::new(device.clone()),
+            device,
+            settings,
+            pipelines: vec![],
+        }
+    }
+
+    /// Fails unless every effect's shaders declare the shared push constants and a single set.
+    pub fn check_shaders(shaders: &mut ShaderLibrary) -> Result<(), String> {
+        for &effect in [PostEffect::BloomExtract, PostEffect::BloomBlurHorizontal, PostEffect::Tonemap, PostEffect::Fxaa].iter() {
+            let stages = shaders.stages(&effect.shader_keys())?;
+            check_effect_shaders(&stages)?;
+        }
+        Ok(())
+    }
+
+    /// Builds a pipeline for each post pass of `frame_graph` and points its set at the pass's inputs. The pipelines
+    /// and sets from before have to be destroyed first.
+    pub fn recreate_pipelines(&mut self, frame_graph: &FrameGraph, shaders: &mut ShaderLibrary, layouts: &mut DescriptorLayoutCache) {
+        for post_pass in frame_graph.post.iter() {
+            let stages = shaders.stages(&post_pass.effect.shader_keys()).expect("Failed to reflect the post shaders!");
+            check_effect_shaders(&stages).expect("Post shaders don't fit the post pipeline!");
+            let set_layout = stages.interface.set_layout(layouts, 0);
+            let pipeline_layout = stages.interface.create_pipeline_layout(self.device.clone(), &[set_layout]);
+            let pipeline = GraphicsPipelineBuilder::new(pipeline_layout, frame_graph.graph.render_pass(post_pass.pass))
+                .shader(vk::ShaderStageFlags::VERTEX, &stages.vertex)
+                .shader(vk::ShaderStageFlags::FRAGMENT, &stages.fragment)
+                .cull(vk::CullModeFlags::NONE, vk::FrontFace::COUNTER_CLOCKWISE)
+                .depth(false, false, vk::CompareOp::ALWAYS)
+                .dynamic_states(&[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR])
+                .build(self.device.clone());
+
+            let descriptor_set = self.descriptors.allocate_one(set_layout);
+            let views: Vec<vk::ImageView> = post_pass.inputs.iter().map(|&input| frame_graph.graph.image_view(input)).collect();
+            self.write_descriptor_set(descriptor_set, &stages, &views);
+
+            let srgb_target = post_pass.to_swapchain && is_srgb(frame_graph.swapchain_format);
+            self.pipelines.push(PostPipeline {
+                pass: post_pass.pass,
+                effect: post_pass.effect,
+                pipeline_layout,
+                pipeline,
+                descriptor_set,
+                flags: if srgb_target { FLAG_SRGB_TARGET } else { 0 },
+            });
+        }
+    }
+
+    pub fn destroy_pipelines(&mut self) {
+        for pipeline in self.pipelines.drain(..) {
+            unsafe {
+                self.device.logical_device.destroy_pipeline(pipeline.pipeline, None);
+                self.device.logical_device.destroy_pipeline_layout(pipeline.pipeline_layout, None);
+            }
+        }
+        self.descriptors.reset();
+    }
+
+    /// Draws the fullscreen triangle of `pass`. Records inside the pass, which is `extent` big.
+    pub fn record(&self, command_buffer: vk::CommandBuffer, pass: PassId, extent: vk::Extent2D) {
+        let pipeline = match self.pipelines.iter().find(|pipeline| pipeline.pass == pass) {
+            Some(pipeline) => pipeline,
+            None => return,
+        };
+        let device = &self.device.logical_device;
+        let viewport = vk::Viewport {
+            x: 0.0,
+            y: 0.0,
+            width: extent.width as f32,
+            height: extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        };
+        let scissor = vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent,
+        };
+        let constants = self.constants(pipeline, extent);
+        unsafe {
+            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline.pipeline);
+            device.cmd_set_viewport(command_buffer, 0, &[viewport]);
+            device.cmd_set_scissor(command_buffer, 0, &[scissor]);
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline.pipeline_layout,
+                0,
+                &[pipeline.descriptor_set],
+                &[],
+            );
+            device.fp_v1_0().cmd_push_constants(
+                command_buffer,
+                pipeline.pipeline_layout,
+                vk::ShaderStageFlags::FRAGMENT,
+                0,
+                std::mem::size_of::<PostConstants>() as u32,
+                &constants as *const _ as *const c_void,
+            );
+            device.cmd_draw(command_buffer, 3, 1, 0, 0);
+        }
+    }
+
+    pub fn vk_destroy(&mut self) {
+        self.destroy_pipelines();
+        self.descriptors.vk_destroy();
+        unsafe {
+            self.device.logical_device.destroy_sampler(self.sampler, None);
+        }
+        self.lut.vk_destroy();
+    }
+
+    fn constants(&self, pipeline: &PostPipeline, extent: vk::Extent2D) -> PostConstants {
+        let settings = &self.settings;
+        let mut flags = pipeline.flags;
+        if settings.bloom {
+            flags |= FLAG_BLOOM;
+        }
+        if settings.gamma_correction {
+            flags |= FLAG_GAMMA;
+        }
+        if settings.color_grading {
+            flags |= FLAG_GRADING;
+        }
+        PostConstants {
+            texel_size: Vec2::new(1.0 / extent.width as f32, 1.0 / extent.height as f32),
+            blur_direction: pipeline.effect.blur_direction(),
+            exposure: settings.exposure,
+            gamma: settings.gamma,
+            bloom_threshold: settings.bloom_threshold,
+            bloom_intensity: settings.bloom_intensity,
+            tonemapper: settings.tonemapper,
+            flags,
+        }
+    }
+
+    /// Writes the sampler to binding 0, `views` to bindings 1 and on and the grading LUT after them, skipping
+    /// bindings the shaders don't declare.
+    fn write_descriptor_set(&self, descriptor_set: vk::DescriptorSet, stages: &ShaderStages, views: &[vk::ImageView]) {
+        let sampler_infos = [vk::DescriptorImageInfo {
+            sampler: self.sampler,
+            image_view: vk::ImageView::null(),
+            image_layout: vk::ImageLayout::UNDEFINED,
+        }];
+        let image_infos: Vec<[vk::DescriptorImageInfo; 1]> = views
+            .iter()
+            .chain(std::iter::once(&self.lut.view))
+            .map(|&image_view| {
+                [vk::DescriptorImageInfo {
+                    sampler: vk::Sampler::null(),
+                    image_view,
+                    image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                }]
+            })
+            .collect();
+
+        let descriptor_write_sets: Vec<vk::WriteDescriptorSet> = stages
+            .interface
+            .set_bindings(0)
+            .iter()
+            .filter_map(|binding| {
+                let image_info = match binding.binding {
+                    0 => &sampler_infos,
+                    index => image_infos.get(index as usize - 1)?,
+                };
+                Some(vk::WriteDescriptorSet {
+                    s_type: vk::StructureType::WRITE_DESCRIPTOR_SET,
+                    p_next: ptr::null(),
+                    dst_set: descriptor_set,
+                    dst_binding: binding.binding,
+                    dst_array_element: 0,
+                    descriptor_count: 1,
+                    descriptor_type: binding.descriptor_type,
+                    p_image_info: image_info.as_ptr(),
+                    p_buffer_info: ptr::null(),
+                    p_texel_buffer_view: ptr::null(),
+                })
+            })
+            .collect();
+        unsafe {
+            self.device.logical_device.update_descriptor_sets(&descriptor_write_sets, &[]);
+        }
+    }
+}
+
+fn check_effect_shaders(stages: &ShaderStages) -> Result<(), String> {
+    stages.interface.check_set_count(1)?;
+    stages.interface.check_push_constants(std::mem::size_of::<PostConstants>())
+}
+
+fn is_srgb(format: vk::Format) -> bool {
+    matches!(
+        format,
+        vk::Format::B8G8R8A8_SRGB | vk::Format::R8G8B8A8_SRGB | vk::Format::A8B8G8R8_SRGB_PACK32 | vk::Format::R8G8B8_SRGB | vk::Format::B8G8R8_SRGB
+    )
+}
+
+/// A mild filmic grade of a display-encoded color: a little more saturation and contrast, shadows pushed cool and
+/// highlights warm.
+fn grade(color: [f32; 3]) -> [f32; 3] {
+    let luma = color[0] * 0.2126 + color[1] * 0.7152 + color[2] * 0.0722;
+    let cool = [0.97, 0.99, 1.04];
+    let warm = [1.05, 1.0, 0.93];
+    let mut graded = [0.0; 3];
+    for i in 0..3 {
+        let saturated = luma + (color[i] - luma) * 1.15;
+        let contrasted = (saturated - 0.5) * 1.1 + 0.5;
+        let tint = cool[i] + (warm[i] - cool[i]) * luma;
+        graded[i] = (contrasted * tint).clamp(0.0, 1.0);
+    }
+    graded
+}
+
+/// The grading LUT as `tonemap.frag` reads it: `LUT_SIZE` slices of red by green side by side, one per blue value, as
+/// RGBA rows. Each entry is `grade` of the color it stands for.
+fn procedural_lut_pixels() -> Vec<u8> {
+    let step = 1.0 / (LUT_SIZE - 1) as f32;
+    let mut pixels: Vec<u8> = Vec::with_capacity((LUT_SIZE * LUT_SIZE * LUT_SIZE * 4) as usize);
+    for green in 0..LUT_SIZE {
+        for blue in 0..LUT_SIZE {
+            for red in 0..LUT_SIZE {
+                let graded = grade([red as f32 * step, green as f32 * step, blue as f32 * step]);
+                pixels.extend(graded.iter().map(|channel| (channel * 255.0).round() as u8));
+                pixels.push(255);
+            }
+        }
+    }
+    pixels
+}
+
+/// Reads a grading LUT from an image laid out like `procedural_lut_pixels`, as the usual neutral LUT strips are. It
+/// has to be `LUT_SIZE` squared by `LUT_SIZE` pixels.
+fn load_lut_pixels(path: &Path) -> Result<Vec<u8>, String> {
+    let image = image::open(path).map_err(|err| format!("Failed to read grading LUT {:?}: {}", path, err))?;
+    let (width, height) = image.dimensions();
+    if (width, height) != (LUT_SIZE * LUT_SIZE, LUT_SIZE) {
+        return Err(format!(
+            "Grading LUT {:?} is {}x{}, it has to be {}x{}",
+            path,
+            width,
+            height,
+            LUT_SIZE * LUT_SIZE,
+            LUT_SIZE
+        ));
+    }
+    Ok(image.to_rgba().into_raw())
+}
+
+/// Uploads the grading LUT from the image at `path`, or the procedural grade without one or if it can't be used.
+fn create_grading_lut(device: Arc<VulkanDevice>, command_pool: vk::CommandPool, path: Option<&Path>) -> img::Image {
+    let (width, height) = (LUT_SIZE * LUT_SIZE, LUT_SIZE);
+    let pixels = match path.map(load_lut_pixels) {
+        Some(Ok(pixels)) => pixels,
+        Some(Err(err)) => {
+            println!("{}, using the procedural grade instead", err);
+            procedural_lut_pixels()
+        }
+        None => procedural_lut_pixels(),
+    };
+
+    let size = pixels.len() as vk::DeviceSize;
+    let mut staging_buffer = bfr::create_buffer(
+        device.clone(),
+        size,
+        vk::BufferUsageFlags::TRANSFER_SRC,
+        vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        &device.get_physical_device_memory_properties(),
+    );
+    unsafe {
+        let data_ptr = device
+            .logical_device
+            .map_memory(staging_buffer.memory, 0, size, vk::MemoryMapFlags::empty())
+            .expect("Failed to Map Memory") as *mut u8;
+        data_ptr.copy_from_nonoverlapping(pixels.as_ptr(), pixels.len());
+        device.logical_device.unmap_memory(staging_buffer.memory);
+    }
+
+    let lut = img::Image::new(
+        device.clone(),
+        width,
+        height,
+        1,
+        vk::SampleCountFlags::TYPE_1,
+        LDR_FORMAT,
+        vk::ImageTiling::OPTIMAL,
+        vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    );
+    img::transition_image_layout(
+        device.clone(),
+        command_pool,
+        device.graphics_queue,
+        lut.image,
+        LDR_FORMAT,
+        vk::ImageLayout::UNDEFINED,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        1,
+    );
+    cmd::copy_buffer_to_image(
+        device.clone(),
+        command_pool,
+        device.graphics_queue,
+        staging_buffer.buffer,
+        lut.image,
+        width,
+        height,
+    );
+    img::transition_image_layout(
+        device.clone(),
+        command_pool,
+        device.graphics_queue,
+        lut.image,
+        LDR_FORMAT,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        1,
+    );
+    staging_buffer.vk_destroy();
+    lut
+}
+
+fn create_linear_sampler(device: &VulkanDevice) -> vk::Sampler {
+    let sampler_create_info = vk::SamplerCreateInfo {
+        s_type: vk::StructureType::SAMPLER_CREATE_INFO,
+        p_next: ptr::null(),
+        flags: vk::SamplerCreateFlags::empty(),
+        mag_filter: vk::Filter::LINEAR,
+        min_filter: vk::Filter::LINEAR,
+        address_mode_u: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+        address_mode_v: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+        address_mode_w: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+        anisotropy_enable: vk::FALSE,
+        max_anisotropy: 1.0,
+        compare_enable: vk::FALSE,
+        compare_op: vk::CompareOp::ALWAYS,
+        mipmap_mode: vk::SamplerMipmapMode::NEAREST,
+        min_lod: 0.0,
+        max_lod: 0.0,
+        mip_lod_bias: 0.0,
+        border_color: vk::BorderColor::FLOAT_OPAQUE_BLACK,
+        unnormalized_coordinates: vk::FALSE,
+    };
+    unsafe {
+        device
+            .logical_device
+            .create_sampler(&sampler_create_info, None)
+            .expect("Failed to create post Sampler!")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lut_entry(pixels: &[u8], red: u32, green: u32, blue: u32) -> [u8; 4] {
+        let index = ((green * LUT_SIZE * LUT_SIZE + blue * LUT_SIZE + red) * 4) as usize;
+        [pixels[index], pixels[index + 1], pixels[index + 2], pixels[index + 3]]
+    }
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("ash_test_post_{}_{}.png", name, std::process::id()))
+    }
+
+    #[test]
+    fn procedural_lut_is_laid_out_as_tonemap_reads_it() {
+        let pixels = procedural_lut_pixels();
+        assert_eq!(pixels.len(), (LUT_SIZE * LUT_SIZE * LUT_SIZE * 4) as usize);
+        let top = (LUT_SIZE - 1) as f32;
+        for &(red, green, blue) in [(0, 0, 0), (LUT_SIZE - 1, 0, 0), (0, LUT_SIZE - 1, 0), (0, 0, LUT_SIZE - 1), (3, 7, 11)].iter() {
+            let graded = grade([red as f32 / top, green as f32 / top, blue as f32 / top]);
+            let expected: Vec<u8> = graded.iter().map(|channel| (channel * 255.0).round() as u8).collect();
+            let entry = lut_entry(&pixels, red, green, blue);
+            assert_eq!(&entry[..3], &expected[..], "entry ({}, {}, {})", red, green, blue);
+            assert_eq!(entry[3], 255);
+        }
+    }
+
+    #[test]
+    fn grade_keeps_colors_displayable() {
+        assert_eq!(grade([0.0; 3]), [0.0; 3]);
+        for &color in [[1.0, 1.0, 1.0], [1.0, 0.0, 0.0], [0.5, 0.5, 0.5]].iter() {
+            assert!(grade(color).iter().all(|channel| (0.0..=1.0).contains(channel)));
+        }
+        // More contrast: dark grays get darker and bright ones brighter.
+        assert!(grade([0.2; 3])[1] < 0.2);
+        assert!(grade([0.8; 3])[1] > 0.8);
+    }
+
+    #[test]
+    fn lut_images_load_in_the_same_layout() {
+        let path = temp_path("lut");
+        let pixels = procedural_lut_pixels();
+        image::RgbaImage::from_raw(LUT_SIZE * LUT_SIZE, LUT_SIZE, pixels.clone())
+            .unwrap()
+            .save(&path)
+            .unwrap();
+        let loaded = load_lut_pixels(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.unwrap(), pixels);
+    }
+
+    #[test]
+    fn lut_images_of_the_wrong_size_or_missing_are_rejected() {
+        let path = temp_path("small_lut");
+        image::RgbaImage::new(LUT_SIZE, LUT_SIZE).save(&path).unwrap();
+        let loaded = load_lut_pixels(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(loaded.unwrap_err().contains(&format!("it has to be {}x{}", LUT_SIZE * LUT_SIZE, LUT_SIZE)));
+        assert!(load_lut_pixels(&temp_path("missing_lut"))
+            .unwrap_err()
+            .starts_with("Failed to read grading LUT"));
+    }
+
+    #[test]
+    fn gamma_and_grading_keys_set_the_flags() {
+        let settings = PostSettings::default()
+            .with_key(VirtualKeyCode::F4)
+            .unwrap()
+            .with_key(VirtualKeyCode::F3)
+            .unwrap();
+        assert!(!settings.gamma_correction);
+        assert!(settings.color_grading);
+        assert!(!settings.changes_passes(&PostSettings::default()));
+        assert_eq!(PostSettings::default().with_key(VirtualKeyCode::A), None);
+    }
+}
@@ -21,6 +21,7 @@ use winit::window::Window;
 use std::f32::consts::PI;
 use std::ffi::c_void;
 use std::ffi::CString;
+use std::collections::BTreeMap;
 use std::path::Path;
 use std::ptr;
 use std::sync::Arc;
@@ -29,25 +30,38 @@ use std::time::Instant;
 use super::camera::Camera;
 use image::GenericImageView;
 
+use vk_assist::mesh_export::{self, InstanceExport};
 use vk_assist::misc_util as misc;
 use vk_assist::model_loader as mdl;
-use vk_assist::structures::{get_rect_as_intermediate, Vertex, ViewProjUBO};
+use vk_assist::structures::{get_rect_as_intermediate, BindlessPushConstants, MeshVertex, SkinVertex, Vertex, ViewProjUBO};
+use vk_assist::types::descriptor_allocator::{DescriptorAllocator, DescriptorLayoutCache};
 use vk_assist::types::frame_manager::FrameManager;
 use vk_assist::types::{buffer as bfr, command as cmd, image as img};
 use vk_assist::types::{vulkan_device, vulkan_device::VulkanDevice, vulkan_surface::VulkanSurface, vulkan_swap_chain::*};
 use vk_model::advanced_model::*;
+use vk_model::animation::AnimationPlayer;
+use vk_model::bounds::Frustum;
 use vk_model::MeshSize;
 
 use super::assets::Assets;
+use super::bindless::{self, BindlessMaterials};
+use super::frame_graph::FrameGraph;
 use super::instances::*;
+use super::lights::Lights;
+use super::lod::LodSettings;
+use super::materials::{MaterialPipeline, Materials, MATERIAL_SET};
+use super::post::{Post, PostEffect, PostSettings};
+use super::shaders::{ShaderKey, ShaderLibrary, ShaderStages};
+use super::shadows::{self, Shadows};
+use super::skinning::{self, SkinnedDraw};
+use super::views::{self, FrameViews, SceneView, ViewportRect, MAX_VIEWS};
 
 //mod pipelines;
 use pipelines::current_pipeline_util as pipe;
+use pipelines::reflected_layout::PipelineInterface;
 
 // Constants
 const WINDOW_TITLE: &'static str = "Vulkan App";
-const TEXTURE_PATH: &'static str = "assets/fighterdiffuse.bmp";
-const MODEL_PATH: &'static str = "assets/fighter.obj";
 use super::debug::ValidationInfo;
 use ash::vk::make_version;
 
@@ -55,7 +69,7 @@ use std::os::raw::c_char;
 
 pub const APPLICATION_VERSION: u32 = make_version(1, 0, 0);
 pub const ENGINE_VERSION: u32 = make_version(1, 0, 0);
-pub const API_VERSION: u32 = make_version(1, 0, 92);
+pub const API_VERSION: u32 = make_version(1, 1, 0);
 
 pub const WINDOW_WIDTH: u32 = 800;
 pub const WINDOW_HEIGHT: u32 = 600;
@@ -64,6 +78,168 @@ pub const VALIDATION: ValidationInfo = ValidationInfo {
     required_validation_layers: ["VK_LAYER_KHRONOS_validation"],
 };
 pub const MAX_FRAMES_IN_FLIGHT: usize = 2;
+/// Where F12 dumps the render graph.
+pub const RENDER_GRAPH_DUMP: &str = "render_graph.dot";
+
+/// Vertex and fragment shaders of the static pipeline, with the bindless fragment shader if `bindless`.
+fn static_shader_keys(bindless: bool) -> (ShaderKey, ShaderKey) {
+    let fragment = if bindless {
+        bindless::fragment_shader_key()
+    } else {
+        ShaderKey::new("shaders/pushconst.frag")
+    };
+    (ShaderKey::new("shaders/pushconst.vert"), fragment)
+}
+
+/// Every shader the app compiles at runtime, each once. `examples/compile_shaders` writes their `.spv` fallbacks.
+pub fn runtime_shader_keys() -> Vec<ShaderKey> {
+    let mut pairs = vec![static_shader_keys(false), static_shader_keys(true), skinning::shader_keys(), shadows::shader_keys()];
+    let effects = [PostEffect::BloomExtract, PostEffect::BloomBlurHorizontal, PostEffect::BloomBlurVertical, PostEffect::Tonemap, PostEffect::Fxaa];
+    pairs.extend(effects.iter().map(|effect| effect.shader_keys()));
+    let mut keys = vec![];
+    for (vertex, fragment) in pairs {
+        for key in [vertex, fragment] {
+            if !keys.contains(&key) {
+                keys.push(key);
+            }
+        }
+    }
+    keys
+}
+
+/// `stages` with the camera, set 0 binding 0, bound at a dynamic offset so each view can read its own.
+fn with_view_offsets(mut stages: ShaderStages) -> ShaderStages {
+    stages.interface.use_dynamic_offset(0, 0);
+    stages
+}
+
+/// Fails unless `stages` fit the static pipeline: `MeshVertex` input, a `Mat4` model matrix push constant and the camera
+/// and material sets as declared by `ubo_interface`. Bindless shaders push `BindlessPushConstants` and declare the
+/// bindless set in place of the material set.
+fn check_static_shaders(stages: &ShaderStages, ubo_interface: &PipelineInterface, bindless: bool) -> Result<(), String> {
+    stages.interface.check_vertex_input(&MeshVertex::get_attribute_descriptions())?;
+    stages.interface.check_set_count(MATERIAL_SET + 1)?;
+    stages.interface.check_set_matches(ubo_interface, 0)?;
+    if bindless {
+        stages.interface.check_push_constants(std::mem::size_of::<BindlessPushConstants>())?;
+        BindlessMaterials::check_shaders(&stages.interface)
+    } else {
+        stages.interface.check_push_constants(std::mem::size_of::<Mat4>())?;
+        stages.interface.check_set_matches(ubo_interface, MATERIAL_SET)
+    }
+}
+
+/// One static pipeline per kind of material.
+fn create_static_pipelines(
+    device: Arc<VulkanDevice>,
+    render_pass: vk::RenderPass,
+    pipeline_layout: vk::PipelineLayout,
+    msaa_samples: vk::SampleCountFlags,
+    stages: &ShaderStages,
+) -> BTreeMap<MaterialPipeline, vk::Pipeline> {
+    MaterialPipeline::ALL
+        .iter()
+        .map(|&kind| {
+            let shaders = (stages.vertex.as_slice(), stages.fragment.as_slice());
+            let pipeline = pipe::create_graphics_pipeline(
+                device.clone(),
+                render_pass,
+                pipeline_layout,
+                msaa_samples,
+                shaders,
+                kind == MaterialPipeline::AlphaBlend,
+            );
+            (kind, pipeline)
+        })
+        .collect()
+}
+
+/// Records the visible unskinned instances, binding each pipeline and material once, or the bindless set once and
+/// pushing each draw's material index, and each asset's vertex and index buffers whenever the asset changes. The camera
+/// set must be bound already.
+fn record_static_draws(
+    device: &VulkanDevice,
+    command_buffer: vk::CommandBuffer,
+    pipeline_layout: vk::PipelineLayout,
+    materials: &Materials,
+    instances: &Instances,
+    image_index: usize,
+) {
+    let mut bound_pipeline = None;
+    let mut bound_material = None;
+    let mut bound_asset = None;
+    if let Some(bindless) = materials.bindless.as_ref() {
+        unsafe {
+            device.logical_device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline_layout,
+                MATERIAL_SET,
+                &[bindless.descriptor_sets[image_index]],
+                &[],
+            );
+        }
+    }
+    for (inst, material_index) in materials.draw_order(instances) {
+        let material = &materials.materials[material_index];
+        let buffers = match inst.asset.buffers.as_ref() {
+            Some(buffers) => buffers,
+            None => continue,
+        };
+        unsafe {
+            if bound_pipeline != Some(material.pipeline) {
+                device
+                    .logical_device
+                    .cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, materials.pipelines[&material.pipeline]);
+                bound_pipeline = Some(material.pipeline);
+            }
+            if materials.bindless.is_none() && bound_material != Some(material_index) {
+                device.logical_device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    pipeline_layout,
+                    MATERIAL_SET,
+                    &[material.descriptor_sets[image_index]],
+                    &[],
+                );
+                bound_material = Some(material_index);
+            }
+            if bound_asset != Some(Arc::as_ptr(&inst.asset)) {
+                buffers.bind(&device.logical_device, command_buffer, false);
+                bound_asset = Some(Arc::as_ptr(&inst.asset));
+            }
+
+            let fn_device = device.logical_device.fp_v1_0();
+            if materials.bindless.is_some() {
+                let push_constants = BindlessPushConstants {
+                    model: inst.model_matrix,
+                    material: material_index as u32,
+                };
+                fn_device.cmd_push_constants(
+                    command_buffer,
+                    pipeline_layout,
+                    vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                    0,
+                    std::mem::size_of::<BindlessPushConstants>() as u32,
+                    &push_constants as *const _ as *const c_void,
+                );
+            } else {
+                let state_ptr: *const c_void = &inst.model_matrix as *const _ as *const c_void;
+                fn_device.cmd_push_constants(
+                    command_buffer,
+                    pipeline_layout,
+                    vk::ShaderStageFlags::VERTEX,
+                    0,
+                    std::mem::size_of::<Mat4>() as u32,
+                    state_ptr,
+                );
+            }
+
+            let (first_index, index_count) = inst.asset.lod_range(inst.lod);
+            device.logical_device.cmd_draw_indexed(command_buffer, index_count, 1, first_index, 0, 0);
+        }
+    }
+}
 
 pub struct VulkanApp {
     window: Arc<Window>,
@@ -81,30 +257,43 @@ pub struct VulkanApp {
 
     swap_chain: VulkanSwapChain,
     swapchain_imageviews: Vec<vk::ImageView>,
-    swapchain_framebuffers: Vec<vk::Framebuffer>,
+    /// Render passes, framebuffers and attachments of the frame, rebuilt with the swapchain.
+    frame_graph: FrameGraph,
 
-    render_pass: vk::RenderPass,
+    /// Owns every descriptor set layout, shared between pipelines that declare a set the same way.
+    layouts: DescriptorLayoutCache,
     ubo_layout: vk::DescriptorSetLayout,
+    /// Interface of the static shaders `ubo_layout` and the material layout were built from. Reloaded shaders have to
+    /// keep sets 0 and 1 the same.
+    ubo_interface: PipelineInterface,
     pipeline_layout: vk::PipelineLayout,
-    graphics_pipeline: vk::Pipeline,
-
-    color_image: img::Image,
-    depth_image: img::Image,
 
     msaa_samples: vk::SampleCountFlags,
 
-    texture_sampler: vk::Sampler,
-
+    shaders: ShaderLibrary,
     assets: Assets,
+    /// Descriptor sets of the loaded materials, and the static pipelines.
+    materials: Materials,
     instances: Instances,
+    lod_settings: LodSettings,
     //model: Arc<GFXModel>,
-    vertex_buffer: bfr::Buffer,
-    index_buffer: bfr::Buffer,
+    /// Pipeline and joint buffers for the skinned asset, if one was loaded.
+    skinned: Option<SkinnedDraw>,
 
     current_ubo: ViewProjUBO,
+    /// Drawn in order, each with its own camera slot in the uniform buffers.
+    views: Vec<SceneView>,
+    ubo_stride: u64,
     uniform_buffers: Vec<bfr::Buffer>,
-
-    descriptor_pool: vk::DescriptorPool,
+    /// Uploaded with the camera each frame, into the buffer bound at set 0 binding 1.
+    lights: Lights,
+    /// Shadow atlas, its sampler and the shadow map matrices at set 0 bindings 2 to 4, and the shadow pipeline.
+    shadows: Shadows,
+    /// Post-processing settings, and the pipelines of the passes from the HDR image to the swapchain image.
+    post: Post,
+
+    /// Sets that live as long as the scene: the camera, material and joint sets.
+    descriptors: DescriptorAllocator,
     descriptor_sets: Vec<vk::DescriptorSet>,
     command_pool: vk::CommandPool,
     command_buffers: Vec<vk::CommandBuffer>,
@@ -158,79 +347,110 @@ impl VulkanApp {
         let swapchain_imageviews = misc::create_image_views(device.clone(), swap_chain.format, &swap_chain.images);
 
         //init pipeline
-        let render_pass = pipe::create_render_pass(instance.clone(), device.clone(), swap_chain.format, msaa_samples);
-        let ubo_layout = pipe::create_descriptor_set_layout(device.clone());
-        let (graphics_pipeline, pipeline_layout) = pipe::create_graphics_pipeline(device.clone(), render_pass, swap_chain.extent, ubo_layout, msaa_samples);
-        let command_pool = misc::create_command_pool(&device.logical_device, &device.queue_family);
-        let color_image = misc::create_color_resources(device.clone(), swap_chain.format, swap_chain.extent, msaa_samples);
-        let depth_image = misc::create_depth_resources(
-            instance.clone(),
-            device.clone(),
-            device.physical_device,
-            command_pool,
-            device.graphics_queue,
-            swap_chain.extent,
-            msaa_samples,
-        );
-        let swapchain_framebuffers = pipe::create_framebuffers(
+        let depth_format = misc::find_depth_format(instance.clone(), device.physical_device);
+        let mut shadows = Shadows::new(device.clone(), depth_format, swap_chain.images.len());
+        let frame_graph = FrameGraph::new(
             device.clone(),
-            render_pass,
+            &swap_chain,
             &swapchain_imageviews,
-            depth_image.view,
-            color_image.view,
-            swap_chain.extent,
+            depth_format,
+            msaa_samples,
+            shadows.atlas_import(),
+            &PostSettings::default(),
         );
+        let render_pass = frame_graph.scene_render_pass();
+        let mut shaders = ShaderLibrary::new();
+        let static_stages = with_view_offsets(shaders.stages(&static_shader_keys(false)).expect("Failed to reflect the static shaders!"));
+        let ubo_interface = static_stages.interface.clone();
+        check_static_shaders(&static_stages, &ubo_interface, false).expect("Static shaders don't fit the static pipeline!");
+        let mut layouts = DescriptorLayoutCache::new(device.clone());
+        let mut descriptors = DescriptorAllocator::new(device.clone());
+        let ubo_layout = ubo_interface.set_layout(&mut layouts, 0);
+        let command_pool = misc::create_command_pool(&device.logical_device, &device.queue_family);
 
         //init scene buffers
         img::check_mipmap_support(instance.clone(), device.physical_device, vk::Format::R8G8B8A8_UNORM);
-        let assets = Assets::init(device.clone(), command_pool);
-        let mut instances = Instances { g_instances: Vec::new() };
-        instances.g_instances.push(GInstance::new(assets.fighter.clone(), Mat4::identity()));
-        //let rectangle = get_rect_as_intermediate(1.0, 1.0);
-        //let model = assets.fighter.clone();
-        let texture_sampler = assets.fighter.diffuse_tex.create_sampler();
-        let vertex_buffer = VulkanApp::create_vertex_buffer(
-            &instance,
+        let mut assets = Assets::init(device.clone(), command_pool);
+        let mut materials = Materials::new(
             device.clone(),
-            device.physical_device,
-            command_pool,
-            device.graphics_queue,
-            &assets.fighter,
+            &assets.registry,
+            &assets.material_handles(),
+            &ubo_interface,
+            swap_chain.images.len(),
+            &mut descriptors,
+            &mut layouts,
         );
-        let index_buffer = VulkanApp::create_index_buffer(
-            &instance,
+        let static_stages = if materials.bindless.is_some() {
+            let stages = with_view_offsets(shaders.stages(&static_shader_keys(true)).expect("Failed to reflect the bindless shaders!"));
+            check_static_shaders(&stages, &ubo_interface, true).expect("Bindless shaders don't fit the static pipeline!");
+            stages
+        } else {
+            static_stages
+        };
+        let pipeline_layout = static_stages
+            .interface
+            .create_pipeline_layout(device.clone(), &[ubo_layout, materials.static_set_layout()]);
+        materials.pipelines = create_static_pipelines(device.clone(), render_pass, pipeline_layout, msaa_samples, &static_stages);
+        let shadow_stages = shaders.stages(&shadows::shader_keys()).expect("Failed to reflect the shadow shaders!");
+        Shadows::check_shaders(&shadow_stages).expect("Shadow shaders don't fit the shadow pipeline!");
+        shadows.recreate_pipeline(frame_graph.shadow_render_pass(), &shadow_stages);
+        let mut post = Post::new(
             device.clone(),
-            device.physical_device,
             command_pool,
-            device.graphics_queue,
-            &assets.fighter,
+            PostSettings::default(),
+            assets.manifest.grading_lut.as_ref().map(Path::new),
         );
+        post.recreate_pipelines(&frame_graph, &mut shaders, &mut layouts);
+        let mut instances = assets.instances();
+        //let rectangle = get_rect_as_intermediate(1.0, 1.0);
+        //let model = assets.fighter.clone();
+        let skinned = assets.skinned.clone().map(|model| {
+            let skinned_stages = with_view_offsets(shaders.stages(&skinning::shader_keys()).expect("Failed to reflect the skinned shaders!"));
+            let mut skinned = SkinnedDraw::new(
+                device.clone(),
+                model,
+                &swap_chain,
+                skinned_stages.interface.clone(),
+                &mut descriptors,
+                &mut layouts,
+            );
+            skinned.check_shaders(&skinned_stages, &ubo_interface).expect("Skinned shaders don't fit the skinned pipeline!");
+            skinned.recreate_pipeline(render_pass, &[ubo_layout, materials.layout], msaa_samples, &skinned_stages);
+            skinned
+        });
+        assets.release_models();
 
         let ubo = VulkanApp::create_ubo(swap_chain.extent);
-        let uniform_buffers = pipe::create_uniform_buffers(device.clone(), swap_chain.images.len());
-        let descriptor_pool = pipe::create_descriptor_pool(device.clone(), swap_chain.images.len());
+        let lod_settings = LodSettings::default();
+        instances.animate(0.0);
+        instances.cull(&[Frustum::from_view_proj(&(ubo.proj * ubo.view))]);
+        instances.select_lods(&ubo.view, &ubo.proj, &lod_settings);
+        let scene_views = vec![SceneView::main(ViewportRect::full())];
+        let ubo_stride = views::ubo_stride(device.get_physical_device_properties().limits.min_uniform_buffer_offset_alignment);
+        let uniform_buffers = pipe::create_uniform_buffers(device.clone(), swap_chain.images.len(), ubo_stride * MAX_VIEWS as u64);
+        let lights = Lights::new(device.clone(), swap_chain.images.len()).with_default_rig();
         let descriptor_sets = pipe::create_descriptor_sets(
             device.clone(),
-            descriptor_pool,
+            &mut descriptors,
             ubo_layout,
             &uniform_buffers,
-            &assets.fighter.diffuse_tex,
-            texture_sampler,
+            &lights.buffers(),
             swap_chain.images.len(),
         );
+        shadows.write_descriptor_sets(&descriptor_sets);
         //init command buffers
         let command_buffers = VulkanApp::create_command_buffers(
             device.clone(),
             command_pool,
-            graphics_pipeline,
-            &swapchain_framebuffers,
-            render_pass,
-            swap_chain.extent,
-            &vertex_buffer,
-            &index_buffer,
+            &materials,
+            &frame_graph,
+            &FrameViews::new(&scene_views, swap_chain.extent, ubo_stride),
             &instances,
             pipeline_layout,
             &descriptor_sets,
+            skinned.as_ref(),
+            &shadows,
+            &post,
         );
         let sync_ojbects = misc::create_sync_objects(&device.logical_device, MAX_FRAMES_IN_FLIGHT);
 
@@ -248,29 +468,32 @@ impl VulkanApp {
 
             swap_chain,
             swapchain_imageviews,
-            swapchain_framebuffers,
+            frame_graph,
 
             pipeline_layout,
+            layouts,
             ubo_layout,
-            render_pass,
-            graphics_pipeline,
-
-            color_image,
-            depth_image,
+            ubo_interface,
 
             msaa_samples,
 
+            shaders,
             assets,
+            materials,
             instances,
+            lod_settings,
             //model,
-            texture_sampler,
-            vertex_buffer,
-            index_buffer,
+            skinned,
 
             current_ubo: ubo,
+            views: scene_views,
+            ubo_stride,
             uniform_buffers,
+            lights,
+            shadows,
+            post,
 
-            descriptor_pool,
+            descriptors,
             descriptor_sets,
 
             command_pool,
@@ -285,107 +508,6 @@ impl VulkanApp {
         }
     }
 
-    fn create_vertex_buffer(
-        instance: &ash::Instance,
-        device: Arc<VulkanDevice>,
-        physical_device: vk::PhysicalDevice,
-        command_pool: vk::CommandPool,
-        submit_queue: vk::Queue,
-        mesh: &GFXModel,
-    ) -> bfr::Buffer {
-        let buffer_size = mesh.vertices_size();
-        let device_memory_properties = unsafe { instance.get_physical_device_memory_properties(physical_device) };
-
-        let staging_buffer = bfr::create_buffer(
-            device.clone(),
-            buffer_size,
-            vk::BufferUsageFlags::TRANSFER_SRC,
-            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
-            &device_memory_properties,
-        );
-
-        unsafe {
-            let data_ptr = device
-                .logical_device
-                .map_memory(staging_buffer.memory, 0, buffer_size, vk::MemoryMapFlags::empty())
-                .expect("Failed to Map Memory") as *mut Vertex;
-
-            data_ptr.copy_from_nonoverlapping(mesh.vertices.as_ptr(), mesh.vertices.len());
-
-            device.logical_device.unmap_memory(staging_buffer.memory);
-        }
-
-        let vertex_buffer = bfr::create_buffer(
-            device.clone(),
-            buffer_size,
-            vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::VERTEX_BUFFER,
-            vk::MemoryPropertyFlags::DEVICE_LOCAL,
-            &device_memory_properties,
-        );
-
-        bfr::copy_buffer(
-            device.clone(),
-            submit_queue,
-            command_pool,
-            staging_buffer.buffer,
-            vertex_buffer.buffer,
-            buffer_size,
-        );
-
-        vertex_buffer
-    }
-
-    fn create_index_buffer(
-        instance: &ash::Instance,
-        device: Arc<VulkanDevice>,
-        physical_device: vk::PhysicalDevice,
-        command_pool: vk::CommandPool,
-        submit_queue: vk::Queue,
-        mesh: &GFXModel,
-    ) -> bfr::Buffer {
-        // let buffer_size = std::mem::size_of_val(&mesh.indices) as vk::DeviceSize;
-        let buffer_size = mesh.indices_size();
-        let device_memory_properties = unsafe { instance.get_physical_device_memory_properties(physical_device) };
-
-        let staging_buffer = bfr::create_buffer(
-            device.clone(),
-            buffer_size,
-            vk::BufferUsageFlags::TRANSFER_SRC,
-            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
-            &device_memory_properties,
-        );
-
-        unsafe {
-            let data_ptr = device
-                .logical_device
-                .map_memory(staging_buffer.memory, 0, buffer_size, vk::MemoryMapFlags::empty())
-                .expect("Failed to Map Memory") as *mut u32;
-
-            data_ptr.copy_from_nonoverlapping(mesh.indices.as_ptr(), mesh.indices.len());
-
-            device.logical_device.unmap_memory(staging_buffer.memory);
-        }
-
-        let index_buffer = bfr::create_buffer(
-            device.clone(),
-            buffer_size,
-            vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::INDEX_BUFFER,
-            vk::MemoryPropertyFlags::DEVICE_LOCAL,
-            &device_memory_properties,
-        );
-
-        bfr::copy_buffer(
-            device.clone(),
-            submit_queue,
-            command_pool,
-            staging_buffer.buffer,
-            index_buffer.buffer,
-            buffer_size,
-        );
-
-        index_buffer
-    }
-
     fn create_ubo(image_size: vk::Extent2D) -> ViewProjUBO {
         let mut ubo = ViewProjUBO {
             view: look_at(&Vec3::new(0.0, 0.0, 20.0), &Vec3::new(0.0, 0.0, 0.0), &Vec3::new(0.0, 1.0, 0.0)),
@@ -401,20 +523,20 @@ impl VulkanApp {
     fn create_command_buffers(
         device: Arc<VulkanDevice>,
         command_pool: vk::CommandPool,
-        graphics_pipeline: vk::Pipeline,
-        framebuffers: &Vec<vk::Framebuffer>,
-        render_pass: vk::RenderPass,
-        surface_extent: vk::Extent2D,
-        vertex_buffer: &bfr::Buffer,
-        index_buffer: &bfr::Buffer,
+        materials: &Materials,
+        frame_graph: &FrameGraph,
+        views: &FrameViews,
         instances: &Instances,
         pipeline_layout: vk::PipelineLayout,
         descriptor_sets: &Vec<vk::DescriptorSet>,
+        skinned: Option<&SkinnedDraw>,
+        shadows: &Shadows,
+        post: &Post,
     ) -> Vec<vk::CommandBuffer> {
         let command_buffer_allocate_info = vk::CommandBufferAllocateInfo {
             s_type: vk::StructureType::COMMAND_BUFFER_ALLOCATE_INFO,
             p_next: ptr::null(),
-            command_buffer_count: framebuffers.len() as u32,
+            command_buffer_count: descriptor_sets.len() as u32,
             command_pool,
             level: vk::CommandBufferLevel::PRIMARY,
         };
@@ -441,73 +563,36 @@ impl VulkanApp {
                     .expect("Failed to begin recording Command Buffer at beginning!");
             }
 
-            let clear_values = [
-                vk::ClearValue {
-                    // clear value for color buffer
-                    color: vk::ClearColorValue { float32: [0.0, 0.0, 0.0, 1.0] },
-                },
-                vk::ClearValue {
-                    // clear value for depth buffer
-                    depth_stencil: vk::ClearDepthStencilValue { depth: 1.0, stencil: 0 },
-                },
-            ];
-
-            let render_pass_begin_info = vk::RenderPassBeginInfo {
-                s_type: vk::StructureType::RENDER_PASS_BEGIN_INFO,
-                p_next: ptr::null(),
-                render_pass,
-                framebuffer: framebuffers[i],
-                render_area: vk::Rect2D {
-                    offset: vk::Offset2D { x: 0, y: 0 },
-                    extent: surface_extent,
-                },
-                clear_value_count: clear_values.len() as u32,
-                p_clear_values: clear_values.as_ptr(),
-            };
-
             unsafe {
-                device
-                    .logical_device
-                    .cmd_begin_render_pass(command_buffer, &render_pass_begin_info, vk::SubpassContents::INLINE);
-                device
-                    .logical_device
-                    .cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, graphics_pipeline);
-
-                let vertex_buffers = [vertex_buffer.buffer];
-                let offsets = [0_u64];
-                let descriptor_sets_to_bind = [descriptor_sets[i]];
-
-                device.logical_device.cmd_bind_vertex_buffers(command_buffer, 0, &vertex_buffers, &offsets);
-                device
-                    .logical_device
-                    .cmd_bind_index_buffer(command_buffer, index_buffer.buffer, 0, vk::IndexType::UINT32);
-                device.logical_device.cmd_bind_descriptor_sets(
-                    command_buffer,
-                    vk::PipelineBindPoint::GRAPHICS,
-                    pipeline_layout,
-                    0,
-                    &descriptor_sets_to_bind,
-                    &[],
-                );
-
-                for (_, inst) in instances.g_instances.iter().enumerate() {
-                    let fn_device = device.logical_device.fp_v1_0();
-                    let state_ptr: *const c_void = &inst.model_matrix as *const _ as *const c_void;
-                    fn_device.cmd_push_constants(
-                        command_buffer,
-                        pipeline_layout,
-                        vk::ShaderStageFlags::VERTEX,
-                        0,
-                        std::mem::size_of::<Mat4>() as u32,
-                        state_ptr,
-                    );
-
-                    device
-                        .logical_device
-                        .cmd_draw_indexed(command_buffer, inst.asset.indices.len() as u32, 1, 0, 0, 0);
-                }
-
-                device.logical_device.cmd_end_render_pass(command_buffer);
+                frame_graph.graph.execute(command_buffer, i, |graph_pass, command_buffer| {
+                    if graph_pass == frame_graph.shadow {
+                        shadows.record(command_buffer, instances);
+                        return;
+                    }
+                    if graph_pass != frame_graph.scene {
+                        post.record(command_buffer, graph_pass, frame_graph.graph.extent(graph_pass));
+                        return;
+                    }
+                    for pass in views.passes.iter() {
+                        device.logical_device.cmd_set_viewport(command_buffer, 0, &[pass.viewport]);
+                        device.logical_device.cmd_set_scissor(command_buffer, 0, &[pass.scissor]);
+
+                        let descriptor_sets_to_bind = [descriptor_sets[i]];
+                        device.logical_device.cmd_bind_descriptor_sets(
+                            command_buffer,
+                            vk::PipelineBindPoint::GRAPHICS,
+                            pipeline_layout,
+                            0,
+                            &descriptor_sets_to_bind,
+                            &[pass.ubo_offset],
+                        );
+
+                        record_static_draws(&device, command_buffer, pipeline_layout, materials, instances, i);
+                        if let Some(skinned) = skinned {
+                            skinned.record(command_buffer, descriptor_sets[i], i, instances, pass.ubo_offset, materials);
+                        }
+                    }
+                });
 
                 device
                     .logical_device
@@ -524,15 +609,16 @@ impl VulkanApp {
         device: Arc<VulkanDevice>,
         command_pool: vk::CommandPool,
         command_buffer: &mut vk::CommandBuffer,
-        graphics_pipeline: vk::Pipeline,
-        framebuffer: &vk::Framebuffer,
-        render_pass: vk::RenderPass,
-        surface_extent: vk::Extent2D,
-        vertex_buffer: &bfr::Buffer,
-        index_buffer: &bfr::Buffer,
+        materials: &Materials,
+        frame_graph: &FrameGraph,
+        views: &FrameViews,
         instances: &Instances,
         pipeline_layout: vk::PipelineLayout,
         descriptor_set: &vk::DescriptorSet,
+        skinned: Option<&SkinnedDraw>,
+        shadows: &Shadows,
+        post: &Post,
+        image_index: usize,
     ) {
         unsafe {
             device.logical_device.free_command_buffers(command_pool, &[*command_buffer]);
@@ -555,72 +641,36 @@ impl VulkanApp {
                 .expect("Failed to begin recording Command Buffer at beginning!");
         }
 
-        let clear_values = [
-            vk::ClearValue {
-                // clear value for color buffer
-                color: vk::ClearColorValue { float32: [0.0, 0.0, 0.0, 1.0] },
-            },
-            vk::ClearValue {
-                // clear value for depth buffer
-                depth_stencil: vk::ClearDepthStencilValue { depth: 1.0, stencil: 0 },
-            },
-        ];
-
-        let render_pass_begin_info = vk::RenderPassBeginInfo {
-            s_type: vk::StructureType::RENDER_PASS_BEGIN_INFO,
-            p_next: ptr::null(),
-            render_pass,
-            framebuffer: *framebuffer,
-            render_area: vk::Rect2D {
-                offset: vk::Offset2D { x: 0, y: 0 },
-                extent: surface_extent,
-            },
-            clear_value_count: clear_values.len() as u32,
-            p_clear_values: clear_values.as_ptr(),
-        };
-
         unsafe {
-            device
-                .logical_device
-                .cmd_begin_render_pass(*command_buffer, &render_pass_begin_info, vk::SubpassContents::INLINE);
-            device
-                .logical_device
-                .cmd_bind_pipeline(*command_buffer, vk::PipelineBindPoint::GRAPHICS, graphics_pipeline);
-
-            let vertex_buffers = [vertex_buffer.buffer];
-            let offsets = [0_u64];
-            let descriptor_sets_to_bind = [*descriptor_set];
-
-            device.logical_device.cmd_bind_vertex_buffers(*command_buffer, 0, &vertex_buffers, &offsets);
-            device
-                .logical_device
-                .cmd_bind_index_buffer(*command_buffer, index_buffer.buffer, 0, vk::IndexType::UINT32);
-            device.logical_device.cmd_bind_descriptor_sets(
-                *command_buffer,
-                vk::PipelineBindPoint::GRAPHICS,
-                pipeline_layout,
-                0,
-                &descriptor_sets_to_bind,
-                &[],
-            );
+            frame_graph.graph.execute(*command_buffer, image_index, |graph_pass, command_buffer| {
+                if graph_pass == frame_graph.shadow {
+                    shadows.record(command_buffer, instances);
+                    return;
+                }
+                if graph_pass != frame_graph.scene {
+                    post.record(command_buffer, graph_pass, frame_graph.graph.extent(graph_pass));
+                    return;
+                }
+                for pass in views.passes.iter() {
+                    device.logical_device.cmd_set_viewport(command_buffer, 0, &[pass.viewport]);
+                    device.logical_device.cmd_set_scissor(command_buffer, 0, &[pass.scissor]);
 
-            for (_, inst) in instances.g_instances.iter().enumerate() {
-                let fn_device = device.logical_device.fp_v1_0();
-                let state_ptr: *const c_void = &inst.model_matrix as *const _ as *const c_void;
-                fn_device.cmd_push_constants(
-                    *command_buffer,
-                    pipeline_layout,
-                    vk::ShaderStageFlags::VERTEX,
-                    0,
-                    std::mem::size_of::<Mat4>() as u32,
-                    state_ptr,
-                );
+                    let descriptor_sets_to_bind = [*descriptor_set];
+                    device.logical_device.cmd_bind_descriptor_sets(
+                        command_buffer,
+                        vk::PipelineBindPoint::GRAPHICS,
+                        pipeline_layout,
+                        0,
+                        &descriptor_sets_to_bind,
+                        &[pass.ubo_offset],
+                    );
 
-                device
-                    .logical_device
-                    .cmd_draw_indexed(*command_buffer, inst.asset.indices.len() as u32, 1, 0, 0, 0);
-            }
-            device.logical_device.cmd_end_render_pass(*command_buffer);
+                    record_static_draws(&device, command_buffer, pipeline_layout, materials, instances, image_index);
+                    if let Some(skinned) = skinned {
+                        skinned.record(command_buffer, *descriptor_set, image_index, instances, pass.ubo_offset, materials);
+                    }
+                }
+            });
 
             device
                 .logical_device
@@ -633,39 +683,61 @@ impl VulkanApp {
 // Runtime
 impl VulkanApp {
     fn update_uniform_buffer(&mut self, current_image: usize) {
-        let ubos = [self.current_ubo];
+        let ubo = self.current_ubo;
+        self.update_uniform_buffer_with_cam(&ubo, current_image);
+    }
+
+    /// Writes each view's camera, `ubo` for the views that follow the frame's camera, at the view's offset, and the lights.
+    fn update_uniform_buffer_with_cam(&mut self, ubo: &vk_assist::structures::ViewProjUBO, current_image: usize) {
+        let ubos: Vec<ViewProjUBO> = self.views.iter().map(|view| view.ubo(ubo, self.swap_chain.extent)).collect();
 
-        let buffer_size = (std::mem::size_of::<ViewProjUBO>() * ubos.len()) as u64;
+        let buffer_size = self.ubo_stride * ubos.len() as u64;
 
         unsafe {
             let data_ptr = self
                 .device
                 .logical_device
                 .map_memory(self.uniform_buffers[current_image].memory, 0, buffer_size, vk::MemoryMapFlags::empty())
-                .expect("Failed to Map Memory") as *mut ViewProjUBO;
+                .expect("Failed to Map Memory") as *mut u8;
 
-            data_ptr.copy_from_nonoverlapping(ubos.as_ptr(), ubos.len());
+            for (i, view_ubo) in ubos.iter().enumerate() {
+                let view_ptr = data_ptr.add(self.ubo_stride as usize * i) as *mut ViewProjUBO;
+                view_ptr.copy_from_nonoverlapping(view_ubo, 1);
+            }
 
             self.device.logical_device.unmap_memory(self.uniform_buffers[current_image].memory);
         }
+        self.lights.upload(current_image, self.shadows.light_maps());
     }
 
-    fn update_uniform_buffer_with_cam(&mut self, ubo: &vk_assist::structures::ViewProjUBO, current_image: usize) {
-        let ubos = [*ubo];
-
-        let buffer_size = (std::mem::size_of::<ViewProjUBO>() * ubos.len()) as u64;
+    /// Culls against the frustums of all views and picks levels of detail as seen from the first one.
+    fn update_visibility(&mut self, ubo: &ViewProjUBO) {
+        let ubos: Vec<ViewProjUBO> = self.views.iter().map(|view| view.ubo(ubo, self.swap_chain.extent)).collect();
+        let frustums: Vec<Frustum> = ubos.iter().map(|view_ubo| Frustum::from_view_proj(&(view_ubo.proj * view_ubo.view))).collect();
+        self.instances.cull(&frustums);
+        self.instances.select_lods(&ubos[0].view, &ubos[0].proj, &self.lod_settings);
+    }
 
-        unsafe {
-            let data_ptr = self
-                .device
-                .logical_device
-                .map_memory(self.uniform_buffers[current_image].memory, 0, buffer_size, vk::MemoryMapFlags::empty())
-                .expect("Failed to Map Memory") as *mut ViewProjUBO;
+    /// Fits the shadow maps to the first view's camera and writes them for `current_image`. Runs before the command
+    /// buffer is recorded, which draws one tile per shadow map.
+    fn update_shadows(&mut self, ubo: &ViewProjUBO, current_image: usize) {
+        let view_ubo = self.views[0].ubo(ubo, self.swap_chain.extent);
+        self.shadows.update(&self.lights.lights, &view_ubo, current_image);
+    }
 
-            data_ptr.copy_from_nonoverlapping(ubos.as_ptr(), ubos.len());
+    fn frame_views(&self) -> FrameViews {
+        FrameViews::new(&self.views, self.swap_chain.extent, self.ubo_stride)
+    }
 
-            self.device.logical_device.unmap_memory(self.uniform_buffers[current_image].memory);
-        }
+    /// Replaces the views drawn each frame, e.g. with `ViewportRect::split` rects for split screen.
+    pub fn set_views(&mut self, views: Vec<SceneView>) {
+        assert!(
+            !views.is_empty() && views.len() <= MAX_VIEWS,
+            "A frame needs 1 to {} views, got {}",
+            MAX_VIEWS,
+            views.len()
+        );
+        self.views = views;
     }
 
     pub fn draw_frame(&mut self, delta_t: f32) {
@@ -696,30 +768,42 @@ impl VulkanApp {
                 },
             }
         };
+        self.assets.registry.end_frame();
+        self.apply_asset_reloads(image_index as usize);
+        self.reload_shaders();
 
         self.instances.g_instances[0].model_matrix = nalgebra_glm::rotate(
             &self.instances.g_instances[0].model_matrix,
             std::f32::consts::PI / 4.0 * delta_t,
             &Vec3::new(0.0, 1.0, 0.0),
         );
+        let ubo = self.current_ubo;
+        self.instances.animate(delta_t);
+        self.update_visibility(&ubo);
+        self.update_shadows(&ubo, image_index as usize);
+        if let Some(skinned) = self.skinned.as_ref() {
+            skinned.update_joints(&mut self.instances, image_index as usize);
+        }
         // unsafe {
         //     self.device
         //         .logical_device
         //         .free_command_buffers(self.command_pool, &[self.command_buffers[image_index as usize]]);
         // }
+        let frame_views = self.frame_views();
         VulkanApp::write_command_buffer(
             self.device.clone(),
             self.command_pool,
             &mut self.command_buffers[image_index as usize],
-            self.graphics_pipeline,
-            &self.swapchain_framebuffers[image_index as usize],
-            self.render_pass,
-            self.swap_chain.extent,
-            &self.vertex_buffer,
-            &self.index_buffer,
+            &self.materials,
+            &self.frame_graph,
+            &frame_views,
             &self.instances,
             self.pipeline_layout,
             &self.descriptor_sets[image_index as usize],
+            self.skinned.as_ref(),
+            &self.shadows,
+            &self.post,
+            image_index as usize,
         );
         //self.current_ubo.model = nalgebra_glm::rotate(&self.current_ubo.model, std::f32::consts::PI / 2.0 * delta_t, &Vec3::new(0.0, 1.0, 0.0));
         self.update_uniform_buffer(image_index as usize);
@@ -808,36 +892,47 @@ impl VulkanApp {
                 },
             }
         };
+        self.assets.registry.end_frame();
+        self.apply_asset_reloads(image_index as usize);
+        self.reload_shaders();
 
         self.instances.g_instances[0].model_matrix = nalgebra_glm::rotate(
             &self.instances.g_instances[0].model_matrix,
             std::f32::consts::PI / 4.0 * delta_t,
             &Vec3::new(0.0, 1.0, 0.0),
         );
+        let ubo = ViewProjUBO {
+            view: camera.view_mat,
+            proj: camera.perspective_mat,
+        };
+        self.instances.animate(delta_t);
+        self.update_visibility(&ubo);
+        self.update_shadows(&ubo, image_index as usize);
+        if let Some(skinned) = self.skinned.as_ref() {
+            skinned.update_joints(&mut self.instances, image_index as usize);
+        }
         // unsafe {
         //     self.device
         //         .logical_device
         //         .free_command_buffers(self.command_pool, &[self.command_buffers[image_index as usize]]);
         // }
+        let frame_views = self.frame_views();
         VulkanApp::write_command_buffer(
             self.device.clone(),
             self.command_pool,
             &mut self.command_buffers[image_index as usize],
-            self.graphics_pipeline,
-            &self.swapchain_framebuffers[image_index as usize],
-            self.render_pass,
-            self.swap_chain.extent,
-            &self.vertex_buffer,
-            &self.index_buffer,
+            &self.materials,
+            &self.frame_graph,
+            &frame_views,
             &self.instances,
             self.pipeline_layout,
             &self.descriptor_sets[image_index as usize],
+            self.skinned.as_ref(),
+            &self.shadows,
+            &self.post,
+            image_index as usize,
         );
         //self.current_ubo.model = nalgebra_glm::rotate(&self.current_ubo.model, std::f32::consts::PI / 2.0 * delta_t, &Vec3::new(0.0, 1.0, 0.0));
-        let ubo = ViewProjUBO {
-            view: camera.view_mat,
-            proj: camera.perspective_mat,
-        };
         self.update_uniform_buffer_with_cam(&ubo, image_index as usize);
 
         //Get Semaphores for frame.
@@ -896,6 +991,134 @@ impl VulkanApp {
         self.current_frame = (self.current_frame + 1) % MAX_FRAMES_IN_FLIGHT;
     }
 
+    pub fn cull_stats(&self) -> CullStats {
+        self.instances.cull_stats
+    }
+
+    pub fn set_lod_settings(&mut self, settings: LodSettings) {
+        self.lod_settings = settings;
+    }
+
+    /// The scene's lights, uploaded again with every frame.
+    pub fn lights_mut(&mut self) -> &mut Lights {
+        &mut self.lights
+    }
+
+    /// Writes the current instances to OBJ+MTL or glTF, picked by the extension of `path`, for inspection in other tools.
+    pub fn export_scene(&self, path: &Path, mode: InstanceExport) -> std::io::Result<()> {
+        mesh_export::export_instances(path, &self.instances.g_instances, mode)
+    }
+
+    pub fn post_settings(&self) -> PostSettings {
+        self.post.settings
+    }
+
+    /// Takes effect from the next frame. Turning bloom or FXAA on or off rebuilds the frame graph.
+    pub fn set_post_settings(&mut self, settings: PostSettings) {
+        let rebuild = self.post.settings.changes_passes(&settings);
+        self.post.settings = settings;
+        if !rebuild {
+            return;
+        }
+        // Recorded command buffers may still use the old graph.
+        unsafe { self.device.logical_device.device_wait_idle().expect("Failed to wait device idle!") };
+        self.post.destroy_pipelines();
+        self.frame_graph.vk_destroy();
+        self.frame_graph = self.create_frame_graph();
+        self.post.recreate_pipelines(&self.frame_graph, &mut self.shaders, &mut self.layouts);
+    }
+
+    /// Writes the frame's render graph as Graphviz dot, to render with `dot -Tsvg`.
+    pub fn dump_render_graph(&self, path: &Path) -> std::io::Result<()> {
+        std::fs::write(path, self.frame_graph.graph.to_graphviz())
+    }
+
+    /// Swaps hot-reloaded assets in at the frame boundary. Instances get the new models, which the registry uploaded and
+    /// whose old buffers it destroys once no frame in flight reads them, and `image_index`'s material sets are pointed at
+    /// reloaded textures.
+    fn apply_asset_reloads(&mut self, image_index: usize) {
+        let reloaded = self.assets.poll_reloads();
+        if !reloaded.textures.is_empty() {
+            self.materials.refresh_textures(&self.assets.registry);
+        }
+        for (old, new) in reloaded.meshes {
+            self.instances.replace_asset(&old, &new);
+            if let Some(skinned) = self.skinned.as_mut().filter(|skinned| Arc::ptr_eq(&skinned.model, &old)) {
+                skinned.replace_model(new);
+            }
+        }
+
+        self.materials.update_sets(image_index);
+    }
+
+    /// The shader library's stages for the static, skinned and shadow pipelines, checked against the layouts and vertex
+    /// formats those pipelines are built with. The post shaders are checked too.
+    fn pipeline_shaders(&mut self) -> Result<(ShaderStages, Option<ShaderStages>, ShaderStages), String> {
+        let bindless = self.materials.bindless.is_some();
+        let static_stages = with_view_offsets(self.shaders.stages(&static_shader_keys(bindless))?);
+        check_static_shaders(&static_stages, &self.ubo_interface, bindless)?;
+        let skinned_stages = match self.skinned.as_ref() {
+            Some(skinned) => {
+                let stages = with_view_offsets(self.shaders.stages(&skinning::shader_keys())?);
+                skinned.check_shaders(&stages, &self.ubo_interface)?;
+                Some(stages)
+            }
+            None => None,
+        };
+        let shadow_stages = self.shaders.stages(&shadows::shader_keys())?;
+        Shadows::check_shaders(&shadow_stages)?;
+        Post::check_shaders(&mut self.shaders)?;
+        Ok((static_stages, skinned_stages, shadow_stages))
+    }
+
+    /// Builds the static and skinned pipelines for the scene pass, the shadow pipeline for the shadow pass and the post
+    /// pipelines for the post passes, from the shader library's SPIR-V.
+    fn create_pipelines(&mut self) {
+        let (static_stages, skinned_stages, shadow_stages) = self.pipeline_shaders().expect("Shaders don't fit their pipelines!");
+        let render_pass = self.frame_graph.scene_render_pass();
+        let static_layouts = [self.ubo_layout, self.materials.static_set_layout()];
+        self.pipeline_layout = static_stages.interface.create_pipeline_layout(self.device.clone(), &static_layouts);
+        self.materials.pipelines = create_static_pipelines(self.device.clone(), render_pass, self.pipeline_layout, self.msaa_samples, &static_stages);
+        if let (Some(skinned), Some(stages)) = (self.skinned.as_mut(), skinned_stages.as_ref()) {
+            skinned.recreate_pipeline(render_pass, &[self.ubo_layout, self.materials.layout], self.msaa_samples, stages);
+        }
+        self.shadows.recreate_pipeline(self.frame_graph.shadow_render_pass(), &shadow_stages);
+        self.post.recreate_pipelines(&self.frame_graph, &mut self.shaders, &mut self.layouts);
+    }
+
+    /// Rebuilds the pipelines when one of their shaders was recompiled. Shaders that fail to compile keep their last
+    /// good SPIR-V, and so do shaders that compile but no longer fit their pipeline's layout or vertex format, so the
+    /// pipelines only ever change to working ones.
+    fn reload_shaders(&mut self) {
+        let changed = self.shaders.poll_changes();
+        if changed.is_empty() {
+            return;
+        }
+        if let Err(err) = self.pipeline_shaders() {
+            println!("Reloaded shaders don't fit their pipelines, keeping the old ones: {}", err);
+            self.shaders.revert(&changed);
+            return;
+        }
+        // Recorded command buffers may still reference the old pipelines.
+        unsafe { self.device.logical_device.device_wait_idle().expect("Failed to wait device idle!") };
+        self.destroy_pipelines();
+        self.create_pipelines();
+    }
+
+    fn destroy_pipelines(&mut self) {
+        unsafe {
+            for (_, pipeline) in std::mem::take(&mut self.materials.pipelines) {
+                self.device.logical_device.destroy_pipeline(pipeline, None);
+            }
+            self.device.logical_device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+        if let Some(skinned) = self.skinned.as_mut() {
+            skinned.destroy_pipeline();
+        }
+        self.shadows.destroy_pipeline();
+        self.post.destroy_pipelines();
+    }
+
     fn recreate_swapchain(&mut self) {
         println!("VulkanApp.recreate_swap_chain");
 
@@ -915,64 +1138,45 @@ impl VulkanApp {
         self.swap_chain = new_swap_chain;
 
         self.swapchain_imageviews = misc::create_image_views(self.device.clone(), self.swap_chain.format, &self.swap_chain.images);
-        self.render_pass = pipe::create_render_pass(self.instance.clone(), self.device.clone(), self.swap_chain.format, self.msaa_samples);
-        let (graphics_pipeline, pipeline_layout) = pipe::create_graphics_pipeline(
-            self.device.clone(),
-            self.render_pass,
-            self.swap_chain.extent,
-            self.ubo_layout,
-            self.msaa_samples,
-        );
-        self.graphics_pipeline = graphics_pipeline;
-        self.pipeline_layout = pipeline_layout;
-
-        self.color_image = misc::create_color_resources(self.device.clone(), self.swap_chain.format, self.swap_chain.extent, self.msaa_samples);
+        self.frame_graph = self.create_frame_graph();
+        // Viewport and scissor are dynamic and the scene and shadow render passes don't depend on the swapchain, so
+        // only the post pipelines, whose sets point at the graph's images, go stale.
+        self.post.destroy_pipelines();
+        self.post.recreate_pipelines(&self.frame_graph, &mut self.shaders, &mut self.layouts);
 
-        self.depth_image = misc::create_depth_resources(
-            self.instance.clone(),
-            self.device.clone(),
-            self.device.physical_device,
-            self.command_pool,
-            self.device.graphics_queue,
-            self.swap_chain.extent,
-            self.msaa_samples,
-        );
-
-        self.swapchain_framebuffers = pipe::create_framebuffers(
-            self.device.clone(),
-            self.render_pass,
-            &self.swapchain_imageviews,
-            self.depth_image.view,
-            self.color_image.view,
-            self.swap_chain.extent,
-        );
         self.command_buffers = VulkanApp::create_command_buffers(
             self.device.clone(),
             self.command_pool,
-            self.graphics_pipeline,
-            &self.swapchain_framebuffers,
-            self.render_pass,
-            self.swap_chain.extent,
-            &self.vertex_buffer,
-            &self.index_buffer,
+            &self.materials,
+            &self.frame_graph,
+            &self.frame_views(),
             &self.instances,
             self.pipeline_layout,
             &self.descriptor_sets,
+            self.skinned.as_ref(),
+            &self.shadows,
+            &self.post,
         );
     }
 
+    fn create_frame_graph(&self) -> FrameGraph {
+        let depth_format = misc::find_depth_format(self.instance.clone(), self.device.physical_device);
+        FrameGraph::new(
+            self.device.clone(),
+            &self.swap_chain,
+            &self.swapchain_imageviews,
+            depth_format,
+            self.msaa_samples,
+            self.shadows.atlas_import(),
+            &self.post.settings,
+        )
+    }
+
     fn cleanup_swapchain(&mut self) {
         unsafe {
-            self.depth_image.vk_destroy();
-            self.color_image.vk_destroy();
+            self.frame_graph.vk_destroy();
 
             self.device.logical_device.free_command_buffers(self.command_pool, &self.command_buffers);
-            for &framebuffer in self.swapchain_framebuffers.iter() {
-                self.device.logical_device.destroy_framebuffer(framebuffer, None);
-            }
-            self.device.logical_device.destroy_pipeline(self.graphics_pipeline, None);
-            self.device.logical_device.destroy_pipeline_layout(self.pipeline_layout, None);
-            self.device.logical_device.destroy_render_pass(self.render_pass, None);
             for &image_view in self.swapchain_imageviews.iter() {
                 self.device.logical_device.destroy_image_view(image_view, None);
             }
@@ -993,24 +1197,29 @@ impl Drop for VulkanApp {
             }
 
             self.cleanup_swapchain();
+            self.destroy_pipelines();
 
-            self.device.logical_device.destroy_descriptor_pool(self.descriptor_pool, None);
+            self.descriptors.vk_destroy();
 
             for i in 0..self.uniform_buffers.len() {
                 self.uniform_buffers[i].vk_destroy();
             }
+            self.lights.vk_destroy();
+            self.shadows.vk_destroy();
+            self.post.vk_destroy();
 
-            self.index_buffer.vk_destroy();
-            self.vertex_buffer.vk_destroy();
+            if let Some(skinned) = self.skinned.as_mut() {
+                skinned.vk_destroy();
+            }
 
-            self.device.logical_device.destroy_sampler(self.texture_sampler, None);
-            //self.assets.vk_destroy();
+            self.materials.vk_destroy();
+            self.assets.vk_destroy();
 
-            self.device.logical_device.destroy_descriptor_set_layout(self.ubo_layout, None);
+            self.layouts.vk_destroy();
 
             self.device.logical_device.destroy_command_pool(self.command_pool, None);
 
-            self.device.logical_device.destroy_device(None);
+            self.device.vk_destroy();
             self.vulkan_surface.surface_loader.destroy_surface(self.vulkan_surface.surface, None);
 
             if VALIDATION.is_enable {
@@ -1034,6 +1243,10 @@ impl VulkanApp {
                 WindowEvent::KeyboardInput { input, .. } => match input {
                     KeyboardInput { virtual_keycode, state, .. } => match (virtual_keycode, state) {
                         (Some(VirtualKeyCode::Escape), ElementState::Pressed) => *control_flow = ControlFlow::Exit,
+                        (Some(VirtualKeyCode::F12), ElementState::Pressed) => match self.dump_render_graph(Path::new(RENDER_GRAPH_DUMP)) {
+                            Ok(()) => println!("Render graph written to {}", RENDER_GRAPH_DUMP),
+                            Err(err) => println!("Failed to write the render graph: {}", err),
+                        },
                         _ => {}
                     },
                 },
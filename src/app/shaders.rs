@@ -0,0 +1,166 @@
+#![allow(dead_code)]
+
+use crate::pipelines;
+use crate::vk_assist;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+use pipelines::reflected_layout::PipelineInterface;
+use vk_assist::shader_compiler::{self, CompiledShader};
+use vk_assist::spirv_reflect;
+
+use super::file_watcher::FileWatcher;
+
+/// One shader stage as a pipeline asks for it: a GLSL file and the defines to compile it with.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ShaderKey {
+    pub path: PathBuf,
+    pub defines: Vec<(String, String)>,
+}
+
+impl ShaderKey {
+    pub fn new(path: &str) -> ShaderKey {
+        ShaderKey {
+            path: PathBuf::from(path),
+            defines: vec![],
+        }
+    }
+
+    pub fn with_define(mut self, name: &str, value: &str) -> ShaderKey {
+        self.defines.push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+/// Vertex and fragment SPIR-V for a pipeline, with the layout they declare.
+pub struct ShaderStages {
+    pub vertex: Vec<u8>,
+    pub fragment: Vec<u8>,
+    pub interface: PipelineInterface,
+}
+
+/// Compiles shaders on first use and recompiles them when their sources or includes change on disk. A shader that
+/// stops compiling keeps its last good SPIR-V, so a typo doesn't take the pipeline down.
+pub struct ShaderLibrary {
+    shaders: HashMap<ShaderKey, CompiledShader>,
+    /// What recompiled shaders replaced, for `revert`.
+    previous: HashMap<ShaderKey, CompiledShader>,
+    watcher: FileWatcher,
+}
+
+impl ShaderLibrary {
+    pub fn new() -> ShaderLibrary {
+        ShaderLibrary {
+            shaders: HashMap::new(),
+            previous: HashMap::new(),
+            watcher: FileWatcher::new(),
+        }
+    }
+
+    /// SPIR-V for `key`, compiled on first use. If the GLSL doesn't compile the first time, the offline-compiled `.spv`
+    /// next to it is used instead; without one that panics.
+    pub fn get(&mut self, key: &ShaderKey) -> Vec<u8> {
+        if let Some(shader) = self.shaders.get(key) {
+            return shader.spirv.clone();
+        }
+
+        let shader = match shader_compiler::compile_glsl(&key.path, &key.defines) {
+            Ok(shader) => shader,
+            Err(err) => {
+                let fallback = PathBuf::from(format!("{}.spv", key.path.display()));
+                println!("Failed to compile shader {:?}, using {:?}:\n{}", key.path, fallback, err);
+                let spirv = match fs::read(&fallback) {
+                    Ok(spirv) => spirv,
+                    Err(read_err) => panic!("No usable shader for {:?}: {}", key.path, read_err),
+                };
+                CompiledShader {
+                    spirv,
+                    dependencies: vec![key.path.clone()],
+                }
+            }
+        };
+        for dependency in shader.dependencies.iter() {
+            self.watcher.watch(dependency);
+        }
+        let spirv = shader.spirv.clone();
+        self.shaders.insert(key.clone(), shader);
+        spirv
+    }
+
+    /// Recompiles the shaders built from a file that changed. Returns those that compiled; the others are reported
+    /// and keep their previous SPIR-V.
+    pub fn poll_changes(&mut self) -> Vec<ShaderKey> {
+        let changed = self.watcher.poll();
+        if changed.is_empty() {
+            return vec![];
+        }
+
+        let mut rebuilt = vec![];
+        for (key, shader) in self.shaders.iter_mut() {
+            if !shader.dependencies.iter().any(|dependency| changed.contains(dependency)) {
+                continue;
+            }
+            match shader_compiler::compile_glsl(&key.path, &key.defines) {
+                Ok(new_shader) => {
+                    println!("Recompiled shader {:?}", key.path);
+                    for dependency in new_shader.dependencies.iter() {
+                        self.watcher.watch(dependency);
+                    }
+                    self.previous.insert(key.clone(), std::mem::replace(shader, new_shader));
+                    rebuilt.push(key.clone());
+                }
+                Err(err) => println!("Failed to recompile shader {:?}, keeping the last good one:\n{}", key.path, err),
+            }
+        }
+        rebuilt
+    }
+
+    /// Goes back to the SPIR-V `keys` had before the last `poll_changes`, for shaders that compiled but don't fit
+    /// their pipeline.
+    pub fn revert(&mut self, keys: &[ShaderKey]) {
+        for key in keys.iter() {
+            if let Some(shader) = self.previous.remove(key) {
+                self.shaders.insert(key.clone(), shader);
+            }
+        }
+    }
+
+    /// Vertex and fragment SPIR-V for a pipeline, reflected to find the layout they need.
+    pub fn stages(&mut self, keys: &(ShaderKey, ShaderKey)) -> Result<ShaderStages, String> {
+        let vertex = self.get(&keys.0);
+        let fragment = self.get(&keys.1);
+        let vertex_reflection = spirv_reflect::reflect(&vertex).map_err(|err| format!("{}: {}", keys.0.path.display(), err))?;
+        let fragment_reflection = spirv_reflect::reflect(&fragment).map_err(|err| format!("{}: {}", keys.1.path.display(), err))?;
+        let interface = PipelineInterface::from_stages(&[&vertex_reflection, &fragment_reflection])?;
+        Ok(ShaderStages { vertex, fragment, interface })
+    }
+}
+
+impl Default for ShaderLibrary {
+    fn default() -> ShaderLibrary {
+        ShaderLibrary::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fallback_spirv_is_up_to_date() {
+        let keys = crate::app::scene::runtime_shader_keys();
+        assert!(keys.iter().any(|key| key.path == PathBuf::from("shaders/tonemap.frag")));
+        for key in keys {
+            let compiled = shader_compiler::compile_glsl(&key.path, &key.defines).unwrap();
+            let fallback = PathBuf::from(format!("{}.spv", key.path.display()));
+            let checked_in = fs::read(&fallback).unwrap_or_default();
+            assert!(
+                checked_in == compiled.spirv,
+                "{:?} is out of date, run `cargo run --example compile_shaders`",
+                fallback
+            );
+        }
+    }
+}
@@ -0,0 +1,547 @@
+#![allow(dead_code)]
+
+use crate::vk_assist;
+use crate::vk_model;
+use std::sync::Arc;
+
+use ash::version::DeviceV1_0;
+use ash::vk;
+use ash::vk::DeviceFnV1_0;
+use nalgebra_glm::{Mat4, Vec3, Vec4};
+
+use std::ffi::c_void;
+use std::ptr;
+
+use crate::pipelines::pipeline_builder::GraphicsPipelineBuilder;
+use vk_assist::render_graph::ImportedImage;
+use vk_assist::structures::{MeshVertex, ViewProjUBO};
+use vk_assist::types::buffer as bfr;
+use vk_assist::types::image as img;
+use vk_assist::types::vulkan_device::VulkanDevice;
+use vk_model::advanced_model::MeshBuffers;
+use vk_model::bounds::Frustum;
+
+use super::instances::{GInstance, Instances};
+use super::lights::{Light, LightKind};
+use super::shaders::{ShaderKey, ShaderStages};
+
+/// Side of the shadow atlas in texels.
+pub const SHADOW_ATLAS_SIZE: u32 = 4096;
+/// Tiles per side of the atlas. Each shadow map gets a tile.
+pub const SHADOW_ATLAS_TILES: u32 = 4;
+pub const MAX_SHADOW_MAPS: usize = (SHADOW_ATLAS_TILES * SHADOW_ATLAS_TILES) as usize;
+/// Shadow maps of the directional light, each covering a farther slice of the camera frustum.
+pub const CASCADE_COUNT: usize = 4;
+
+/// Vertex and fragment shader of the depth-only shadow pipeline.
+pub fn shader_keys() -> (ShaderKey, ShaderKey) {
+    (ShaderKey::new("shaders/shadow.vert"), ShaderKey::new("shaders/shadow.frag"))
+}
+
+/// Start of the shadow buffer, before the shadow map matrices. Laid out like `Shadows` in `lighting.glsl`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct ShadowHeader {
+    atlas_tiles: u32,
+    cascade_count: u32,
+    pcf_radius: i32,
+    texel_size: f32,
+}
+
+/// Shadow maps for the directional and spot lights, all rendered into tiles of one depth atlas by the shadow pass.
+/// The first shadow-casting directional light gets `CASCADE_COUNT` cascades fitted to the camera, every shadow-casting
+/// spot light one map covering its cone. Lights that don't fit in the atlas, and point lights, cast no shadows.
+/// Only unskinned instances cast shadows.
+pub struct Shadows {
+    device: Arc<VulkanDevice>,
+    /// Survives swapchain recreation, so the sets sampling it are only written once.
+    atlas: img::Image,
+    pub format: vk::Format,
+    /// Compares against the stored depth and filters the results.
+    sampler: vk::Sampler,
+    /// The header and the light view-projection of every shadow map, one buffer per swapchain image.
+    buffers: Vec<bfr::Buffer>,
+
+    pub pipeline_layout: vk::PipelineLayout,
+    pub pipeline: vk::Pipeline,
+
+    /// How far from the camera the cascades reach. Past it the directional light casts no shadows.
+    pub cascade_distance: f32,
+    /// Between even splits at 0 and logarithmic splits at 1, which give the cascades near the camera more detail.
+    pub cascade_split_lambda: f32,
+    /// Texels on each side of the center the lookup filters over: 0 takes one comparison, 1 averages 3x3 and so on.
+    pub pcf_radius: i32,
+
+    /// Light view-projection of each shadow map rendered this frame, in tile order.
+    matrices: Vec<Mat4>,
+    /// First shadow map of each light, if it got any.
+    light_maps: Vec<Option<u32>>,
+}
+
+impl Shadows {
+    /// `depth_format` from `misc_util::find_depth_format`, like the scene's depth buffer.
+    pub fn new(device: Arc<VulkanDevice>, depth_format: vk::Format, swapchain_image_count: usize) -> Shadows {
+        let atlas = img::Image::new_depth_map(
+            device.clone(),
+            SHADOW_ATLAS_SIZE,
+            SHADOW_ATLAS_SIZE,
+            1,
+            vk::SampleCountFlags::TYPE_1,
+            depth_format,
+            vk::ImageTiling::OPTIMAL,
+            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        );
+        let buffer_size = (std::mem::size_of::<ShadowHeader>() + std::mem::size_of::<Mat4>() * MAX_SHADOW_MAPS) as u64;
+        let buffers = (0..swapchain_image_count)
+            .map(|_| {
+                bfr::create_buffer(
+                    device.clone(),
+                    buffer_size,
+                    vk::BufferUsageFlags::STORAGE_BUFFER,
+                    vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+                    &device.get_physical_device_memory_properties(),
+                )
+            })
+            .collect();
+
+        Shadows {
+            sampler: create_comparison_sampler(&device),
+            device,
+            atlas,
+            format: depth_format,
+            buffers,
+            pipeline_layout: vk::PipelineLayout::null(),
+            pipeline: vk::Pipeline::null(),
+            cascade_distance: 60.0,
+            cascade_split_lambda: 0.75,
+            pcf_radius: 1,
+            matrices: vec![],
+            light_maps: vec![],
+        }
+    }
+
+    /// The atlas for the frame graph. Its contents are rebuilt every frame, after the previous frame's lookups.
+    pub fn atlas_import(&self) -> ImportedImage {
+        let extent = vk::Extent2D {
+            width: SHADOW_ATLAS_SIZE,
+            height: SHADOW_ATLAS_SIZE,
+        };
+        ImportedImage {
+            images: vec![self.atlas.image],
+            views: vec![self.atlas.view],
+            format: self.format,
+            samples: vk::SampleCountFlags::TYPE_1,
+            extent,
+            initial_layout: vk::ImageLayout::UNDEFINED,
+            initial_stage: vk::PipelineStageFlags::FRAGMENT_SHADER,
+            initial_access: vk::AccessFlags::empty(),
+            final_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        }
+    }
+
+    /// Points the camera sets at the atlas, the comparison sampler and the shadow buffers, set `i` at buffer `i`.
+    pub fn write_descriptor_sets(&self, descriptor_sets: &[vk::DescriptorSet]) {
+        for (i, &descriptor_set) in descriptor_sets.iter().enumerate() {
+            let atlas_infos = [vk::DescriptorImageInfo {
+                sampler: vk::Sampler::null(),
+                image_view: self.atlas.view,
+                image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            }];
+            let sampler_infos = [vk::DescriptorImageInfo {
+                sampler: self.sampler,
+                image_view: vk::ImageView::null(),
+                image_layout: vk::ImageLayout::UNDEFINED,
+            }];
+            let buffer_infos = [vk::DescriptorBufferInfo {
+                buffer: self.buffers[i].buffer,
+                offset: 0,
+                range: vk::WHOLE_SIZE,
+            }];
+            let write = |binding: u32, descriptor_type: vk::DescriptorType| vk::WriteDescriptorSet {
+                s_type: vk::StructureType::WRITE_DESCRIPTOR_SET,
+                p_next: ptr::null(),
+                dst_set: descriptor_set,
+                dst_binding: binding,
+                dst_array_element: 0,
+                descriptor_count: 1,
+                descriptor_type,
+                p_image_info: ptr::null(),
+                p_buffer_info: ptr::null(),
+                p_texel_buffer_view: ptr::null(),
+            };
+            let descriptor_write_sets = [
+                vk::WriteDescriptorSet {
+                    p_image_info: atlas_infos.as_ptr(),
+                    ..write(2, vk::DescriptorType::SAMPLED_IMAGE)
+                },
+                vk::WriteDescriptorSet {
+                    p_image_info: sampler_infos.as_ptr(),
+                    ..write(3, vk::DescriptorType::SAMPLER)
+                },
+                vk::WriteDescriptorSet {
+                    p_buffer_info: buffer_infos.as_ptr(),
+                    ..write(4, vk::DescriptorType::STORAGE_BUFFER)
+                },
+            ];
+            unsafe {
+                self.device.logical_device.update_descriptor_sets(&descriptor_write_sets, &[]);
+            }
+        }
+    }
+
+    /// Fails unless `stages` fit the shadow pipeline: `MeshVertex` input, no descriptor sets and the light's
+    /// model-view-projection as the only push constant.
+    pub fn check_shaders(stages: &ShaderStages) -> Result<(), String> {
+        stages.interface.check_vertex_input(&MeshVertex::get_attribute_descriptions())?;
+        stages.interface.check_set_count(0)?;
+        stages.interface.check_push_constants(std::mem::size_of::<Mat4>())
+    }
+
+    /// Builds the depth-only pipeline for the shadow pass. Slope-scaled depth bias keeps surfaces from shadowing
+    /// themselves, and nothing is culled so single-sided geometry still casts.
+    pub fn recreate_pipeline(&mut self, render_pass: vk::RenderPass, stages: &ShaderStages) {
+        self.pipeline_layout = stages.interface.create_pipeline_layout(self.device.clone(), &[]);
+        self.pipeline = GraphicsPipelineBuilder::new(self.pipeline_layout, render_pass)
+            .shader(vk::ShaderStageFlags::VERTEX, &stages.vertex)
+            .shader(vk::ShaderStageFlags::FRAGMENT, &stages.fragment)
+            .vertex_input(&MeshVertex::get_binding_descriptions(), &MeshVertex::get_attribute_descriptions())
+            .cull(vk::CullModeFlags::NONE, vk::FrontFace::COUNTER_CLOCKWISE)
+            .depth_bias(1.25, 1.75)
+            .blend_attachments(&[])
+            .dynamic_states(&[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR])
+            .build(self.device.clone());
+    }
+
+    pub fn destroy_pipeline(&mut self) {
+        unsafe {
+            self.device.logical_device.destroy_pipeline(self.pipeline, None);
+            self.device.logical_device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+    }
+
+    /// Hands out the atlas tiles to this frame's shadow-casting lights, fits their shadow maps and writes them into the
+    /// shadow buffer for `image_index`. Cascades follow `camera`, the first view's camera.
+    pub fn update(&mut self, lights: &[Light], camera: &ViewProjUBO, image_index: usize) {
+        self.matrices.clear();
+        self.light_maps.clear();
+        let mut has_cascades = false;
+        for light in lights.iter() {
+            let matrices = match light.kind {
+                LightKind::Directional if light.casts_shadows && !has_cascades => {
+                    has_cascades = true;
+                    cascade_matrices(light, camera, self.cascade_distance, self.cascade_split_lambda)
+                }
+                LightKind::Spot if light.casts_shadows => vec![spot_matrix(light)],
+                _ => vec![],
+            };
+            if matrices.is_empty() || self.matrices.len() + matrices.len() > MAX_SHADOW_MAPS {
+                self.light_maps.push(None);
+                continue;
+            }
+            self.light_maps.push(Some(self.matrices.len() as u32));
+            self.matrices.extend(matrices);
+        }
+
+        let header = ShadowHeader {
+            atlas_tiles: SHADOW_ATLAS_TILES,
+            cascade_count: CASCADE_COUNT as u32,
+            pcf_radius: self.pcf_radius,
+            texel_size: 1.0 / SHADOW_ATLAS_SIZE as f32,
+        };
+        let size = (std::mem::size_of::<ShadowHeader>() + std::mem::size_of::<Mat4>() * self.matrices.len()) as u64;
+        let memory = self.buffers[image_index].memory;
+        unsafe {
+            let data_ptr = self
+                .device
+                .logical_device
+                .map_memory(memory, 0, size, vk::MemoryMapFlags::empty())
+                .expect("Failed to Map Memory") as *mut u8;
+
+            (data_ptr as *mut ShadowHeader).copy_from_nonoverlapping(&header, 1);
+            (data_ptr.add(std::mem::size_of::<ShadowHeader>()) as *mut Mat4).copy_from_nonoverlapping(self.matrices.as_ptr(), self.matrices.len());
+
+            self.device.logical_device.unmap_memory(memory);
+        }
+    }
+
+    /// First shadow map of each light from the last `update`, in the order of the lights.
+    pub fn light_maps(&self) -> &[Option<u32>] {
+        &self.light_maps
+    }
+
+    /// Draws the unskinned instances into the tile of every shadow map from the last `update`, each from its own asset's
+    /// buffers and only into the maps whose light frustum it touches. Records inside the shadow pass.
+    pub fn record(&self, command_buffer: vk::CommandBuffer, instances: &Instances) {
+        let device = &self.device.logical_device;
+        let tile_size = SHADOW_ATLAS_SIZE / SHADOW_ATLAS_TILES;
+        // Grouped by asset, so the maps that draw several instances of one asset bind its buffers once.
+        let mut casters: Vec<(&GInstance, &MeshBuffers)> = instances
+            .g_instances
+            .iter()
+            .filter(|inst| inst.asset.skin.is_none())
+            .filter_map(|inst| inst.asset.buffers.as_ref().map(|buffers| (inst, buffers)))
+            .collect();
+        casters.sort_by_key(|(inst, _)| Arc::as_ptr(&inst.asset));
+        let mut bound_asset = None;
+        unsafe {
+            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+
+            for (map, light_view_proj) in self.matrices.iter().enumerate() {
+                let offset = vk::Offset2D {
+                    x: (map as u32 % SHADOW_ATLAS_TILES * tile_size) as i32,
+                    y: (map as u32 / SHADOW_ATLAS_TILES * tile_size) as i32,
+                };
+                let viewport = vk::Viewport {
+                    x: offset.x as f32,
+                    y: offset.y as f32,
+                    width: tile_size as f32,
+                    height: tile_size as f32,
+                    min_depth: 0.0,
+                    max_depth: 1.0,
+                };
+                let scissor = vk::Rect2D {
+                    offset,
+                    extent: vk::Extent2D {
+                        width: tile_size,
+                        height: tile_size,
+                    },
+                };
+                device.cmd_set_viewport(command_buffer, 0, &[viewport]);
+                device.cmd_set_scissor(command_buffer, 0, &[scissor]);
+
+                let frustum = Frustum::from_view_proj(light_view_proj);
+                for &(inst, buffers) in casters.iter().filter(|(inst, _)| casts_into(&frustum, inst)) {
+                    if bound_asset != Some(Arc::as_ptr(&inst.asset)) {
+                        buffers.bind(device, command_buffer, false);
+                        bound_asset = Some(Arc::as_ptr(&inst.asset));
+                    }
+                    let light_model_view_proj = light_view_proj * inst.model_matrix;
+                    device.fp_v1_0().cmd_push_constants(
+                        command_buffer,
+                        self.pipeline_layout,
+                        vk::ShaderStageFlags::VERTEX,
+                        0,
+                        std::mem::size_of::<Mat4>() as u32,
+                        &light_model_view_proj as *const _ as *const c_void,
+                    );
+                    let (first_index, index_count) = inst.asset.lod_range(inst.lod);
+                    device.cmd_draw_indexed(command_buffer, index_count, 1, first_index, 0, 0);
+                }
+            }
+        }
+    }
+
+    pub fn vk_destroy(&mut self) {
+        for buffer in self.buffers.iter_mut() {
+            buffer.vk_destroy();
+        }
+        unsafe {
+            self.device.logical_device.destroy_sampler(self.sampler, None);
+        }
+        self.atlas.vk_destroy();
+    }
+}
+
+/// One orthographic shadow map per slice of the camera frustum, the slices split between the camera's near plane
+/// and `cascade_distance`. Each map covers a sphere around its slice, so it doesn't change size as the camera
+/// turns, and moves in whole texels, so shadow edges don't crawl as the camera moves.
+fn cascade_matrices(light: &Light, camera: &ViewProjUBO, cascade_distance: f32, split_lambda: f32) -> Vec<Mat4> {
+    let inverse_proj = nalgebra_glm::inverse(&camera.proj);
+    let inverse_view = nalgebra_glm::inverse(&camera.view);
+    let unproject = |x: f32, y: f32, z: f32| {
+        let corner = inverse_proj * Vec4::new(x, y, z, 1.0);
+        corner.xyz() / corner.w
+    };
+    // The frustum's far corners in view space. The corners at any depth lie on the rays through them.
+    let far_corners = [
+        unproject(-1.0, -1.0, 1.0),
+        unproject(1.0, -1.0, 1.0),
+        unproject(-1.0, 1.0, 1.0),
+        unproject(1.0, 1.0, 1.0),
+    ];
+    let near = -unproject(0.0, 0.0, -1.0).z;
+    let far = -far_corners[0].z;
+    let splits = cascade_splits(near, far.min(cascade_distance).max(near), split_lambda);
+
+    let tile_size = (SHADOW_ATLAS_SIZE / SHADOW_ATLAS_TILES) as f32;
+    (0..CASCADE_COUNT)
+        .map(|i| {
+            let corners: Vec<Vec3> = [splits[i], splits[i + 1]]
+                .iter()
+                .flat_map(|&depth| far_corners.iter().map(move |corner| corner * (depth / far)))
+                .map(|corner| (inverse_view * Vec4::new(corner.x, corner.y, corner.z, 1.0)).xyz())
+                .collect();
+            let center = corners.iter().fold(Vec3::zeros(), |sum, corner| sum + corner) / corners.len() as f32;
+            let radius = corners.iter().map(|corner| (corner - center).norm()).fold(0.0_f32, f32::max);
+            // Rounded up, so the map's texel size doesn't flicker with the radius.
+            let radius = (radius * 16.0).ceil() / 16.0;
+
+            // Backed off past the slice, so casters between it and the light are still drawn.
+            let back_off = radius + cascade_distance;
+            let eye = center - light.direction * back_off;
+            let view = nalgebra_glm::look_at(&eye, &center, &up_for(&light.direction));
+            let mut proj = nalgebra_glm::ortho_rh_zo(-radius, radius, -radius, radius, 0.0, back_off + radius);
+
+            let origin = proj * view * Vec4::new(0.0, 0.0, 0.0, 1.0) * (tile_size / 2.0);
+            proj[(0, 3)] += (origin.x.round() - origin.x) * 2.0 / tile_size;
+            proj[(1, 3)] += (origin.y.round() - origin.y) * 2.0 / tile_size;
+            proj * view
+        })
+        .collect()
+}
+
+/// View depths where the cascades start and end, `CASCADE_COUNT + 1` of them from `near` to `far`. Each one blends the
+/// logarithmic split, weighted by `lambda`, with the even split.
+pub fn cascade_splits(near: f32, far: f32, lambda: f32) -> [f32; CASCADE_COUNT + 1] {
+    let mut splits = [0.0; CASCADE_COUNT + 1];
+    for (i, split) in splits.iter_mut().enumerate() {
+        let fraction = i as f32 / CASCADE_COUNT as f32;
+        let logarithmic = near * (far / near).powf(fraction);
+        let uniform = near + (far - near) * fraction;
+        *split = lambda * logarithmic + (1.0 - lambda) * uniform;
+    }
+    splits
+}
+
+/// Whether `inst` touches the shadow map whose light frustum is `frustum`, by the same sphere and box tests as camera
+/// culling.
+fn casts_into(frustum: &Frustum, inst: &GInstance) -> bool {
+    frustum.intersects_sphere(&inst.bounding_sphere().transformed(&inst.model_matrix))
+        && frustum.intersects_aabb(&inst.bounds().transformed(&inst.model_matrix))
+}
+
+/// A perspective shadow map from the spot light's position, just wide enough for its cone and as deep as its range.
+fn spot_matrix(light: &Light) -> Mat4 {
+    let view = nalgebra_glm::look_at(&light.position, &(light.position + light.direction), &up_for(&light.direction));
+    let fov = (light.outer_angle * 2.0).min(std::f32::consts::PI * 0.95);
+    let proj = nalgebra_glm::perspective_rh_zo(1.0, fov, (light.range * 0.005).max(0.05), light.range);
+    proj * view
+}
+
+/// An up vector that isn't parallel to `direction`.
+fn up_for(direction: &Vec3) -> Vec3 {
+    if direction.y.abs() > 0.99 {
+        Vec3::new(1.0, 0.0, 0.0)
+    } else {
+        Vec3::new(0.0, 1.0, 0.0)
+    }
+}
+
+fn create_comparison_sampler(device: &VulkanDevice) -> vk::Sampler {
+    let sampler_create_info = vk::SamplerCreateInfo {
+        s_type: vk::StructureType::SAMPLER_CREATE_INFO,
+        p_next: ptr::null(),
+        flags: vk::SamplerCreateFlags::empty(),
+        mag_filter: vk::Filter::LINEAR,
+        min_filter: vk::Filter::LINEAR,
+        address_mode_u: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+        address_mode_v: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+        address_mode_w: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+        anisotropy_enable: vk::FALSE,
+        max_anisotropy: 1.0,
+        compare_enable: vk::TRUE,
+        compare_op: vk::CompareOp::LESS_OR_EQUAL,
+        mipmap_mode: vk::SamplerMipmapMode::NEAREST,
+        min_lod: 0.0,
+        max_lod: 0.0,
+        mip_lod_bias: 0.0,
+        border_color: vk::BorderColor::FLOAT_OPAQUE_WHITE,
+        unnormalized_coordinates: vk::FALSE,
+    };
+    unsafe {
+        device
+            .logical_device
+            .create_sampler(&sampler_create_info, None)
+            .expect("Failed to create shadow Sampler!")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn camera() -> ViewProjUBO {
+        let view = nalgebra_glm::look_at(&Vec3::new(0.0, 2.0, 20.0), &Vec3::new(0.0, 0.0, 0.0), &Vec3::new(0.0, 1.0, 0.0));
+        let proj = nalgebra_glm::perspective(16.0 / 9.0, std::f32::consts::PI / 4.0, 0.1, 100.0);
+        ViewProjUBO {
+            view,
+            proj: nalgebra_glm::scale(&proj, &Vec3::new(1.0, -1.0, 1.0)),
+        }
+    }
+
+    #[test]
+    fn splits_run_from_near_to_far() {
+        let splits = cascade_splits(0.1, 60.0, 0.75);
+        assert!((splits[0] - 0.1).abs() < 1e-5);
+        assert!((splits[CASCADE_COUNT] - 60.0).abs() < 1e-3);
+        assert!(splits.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", splits);
+    }
+
+    #[test]
+    fn lambda_blends_even_and_logarithmic_splits() {
+        let even = cascade_splits(1.0, 81.0, 0.0);
+        for (i, split) in even.iter().enumerate() {
+            assert!((split - (1.0 + 20.0 * i as f32)).abs() < 1e-3, "{:?}", even);
+        }
+        // 81 = 3^4, so each logarithmic split is 3 times the last.
+        let logarithmic = cascade_splits(1.0, 81.0, 1.0);
+        for (i, split) in logarithmic.iter().enumerate() {
+            assert!((split - 3.0_f32.powi(i as i32)).abs() < 1e-3, "{:?}", logarithmic);
+        }
+        let blended = cascade_splits(1.0, 81.0, 0.5);
+        for i in 0..=CASCADE_COUNT {
+            assert!((blended[i] - (even[i] + logarithmic[i]) / 2.0).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn each_cascade_covers_its_slice_of_the_camera_frustum() {
+        let camera = camera();
+        let light = Light::directional(Vec3::new(-1.0, -2.0, -1.0), Vec3::new(1.0, 1.0, 1.0), 1.0);
+        let cascade_distance = 40.0;
+        let matrices = cascade_matrices(&light, &camera, cascade_distance, 0.75);
+        assert_eq!(matrices.len(), CASCADE_COUNT);
+
+        let inverse_view_proj = nalgebra_glm::inverse(&(camera.proj * camera.view));
+        let inverse_proj = nalgebra_glm::inverse(&camera.proj);
+        let view_depth = |ndc_z: f32| {
+            let point = inverse_proj * Vec4::new(0.0, 0.0, ndc_z, 1.0);
+            -point.z / point.w
+        };
+        let splits = cascade_splits(view_depth(-1.0), cascade_distance, 0.75);
+        let eye = (nalgebra_glm::inverse(&camera.view) * Vec4::new(0.0, 0.0, 0.0, 1.0)).xyz();
+        let forward = (nalgebra_glm::inverse(&camera.view) * Vec4::new(0.0, 0.0, -1.0, 0.0)).xyz();
+        for (i, matrix) in matrices.iter().enumerate() {
+            for &depth in [splits[i], (splits[i] + splits[i + 1]) / 2.0, splits[i + 1]].iter() {
+                for &(x, y) in [(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0), (1.0, 1.0), (0.0, 0.0)].iter() {
+                    // The point on the ray through (x, y) at `depth` in front of the camera.
+                    let far = inverse_view_proj * Vec4::new(x, y, 1.0, 1.0);
+                    let ray = far.xyz() / far.w - eye;
+                    let point = eye + ray * (depth / ray.dot(&forward));
+
+                    let clip = matrix * Vec4::new(point.x, point.y, point.z, 1.0);
+                    let ndc = clip.xyz() / clip.w;
+                    assert!(
+                        ndc.x.abs() <= 1.0 + 1e-4 && ndc.y.abs() <= 1.0 + 1e-4 && ndc.z >= -1e-4 && ndc.z <= 1.0 + 1e-4,
+                        "cascade {} misses {:?}: {:?}",
+                        i,
+                        point,
+                        ndc
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn cascades_move_in_whole_texels() {
+        let light = Light::directional(Vec3::new(0.3, -1.0, 0.2), Vec3::new(1.0, 1.0, 1.0), 1.0);
+        let half_tile = (SHADOW_ATLAS_SIZE / SHADOW_ATLAS_TILES) as f32 / 2.0;
+        for matrix in cascade_matrices(&light, &camera(), 60.0, 0.75).iter() {
+            let origin = matrix * Vec4::new(0.0, 0.0, 0.0, 1.0) * half_tile;
+            assert!((origin.x - origin.x.round()).abs() < 1e-2, "{}", origin.x);
+            assert!((origin.y - origin.y.round()).abs() < 1e-2, "{}", origin.y);
+        }
+    }
+}
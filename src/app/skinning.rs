@@ -0,0 +1,323 @@
+use crate::pipelines;
+use crate::vk_assist;
+use crate::vk_model;
+use std::sync::Arc;
+
+use ash::version::DeviceV1_0;
+use ash::vk;
+use nalgebra_glm::Mat4;
+
+use std::ffi::c_void;
+
+use pipelines::reflected_layout::PipelineInterface;
+use pipelines::skinned_pipeline as skin_pipe;
+use vk_assist::structures::{MeshVertex, SkinVertex, SkinnedPushConstants};
+use vk_assist::types::buffer as bfr;
+use vk_assist::types::descriptor_allocator::{DescriptorAllocator, DescriptorLayoutCache};
+use vk_assist::types::vulkan_device::VulkanDevice;
+use vk_assist::types::vulkan_swap_chain::VulkanSwapChain;
+use vk_model::advanced_model::GFXModel;
+
+use super::instances::Instances;
+use super::materials::{Materials, MATERIAL_SET};
+use super::shaders::{ShaderKey, ShaderStages};
+
+/// Gives each visible instance of `model` its offset into a joint buffer of `capacity` matrices and returns the
+/// matrices in that order. An instance that doesn't fit gets no offset and is reported the first time it happens.
+pub fn pack_joint_matrices(instances: &mut Instances, model: &Arc<GFXModel>, capacity: usize) -> Vec<Mat4> {
+    let mut matrices: Vec<Mat4> = vec![];
+    for inst in instances.g_instances.iter_mut() {
+        inst.joint_offset = None;
+    }
+    for &i in instances.visible.iter() {
+        let inst = &mut instances.g_instances[i];
+        if !Arc::ptr_eq(&inst.asset, model) || inst.joint_matrices.is_empty() {
+            continue;
+        }
+        if matrices.len() + inst.joint_matrices.len() > capacity {
+            if !inst.joint_overflow_reported {
+                inst.joint_overflow_reported = true;
+                println!("Joint buffer full, skipping skinned instance {}", i);
+            }
+            continue;
+        }
+        inst.joint_offset = Some(matrices.len() as u32);
+        matrices.extend_from_slice(&inst.joint_matrices);
+    }
+    matrices
+}
+
+/// Descriptor set of the joint matrices. Sets 0 and 1, the camera and the material, are shared with the static pipeline.
+pub const JOINT_SET: u32 = 2;
+
+/// Vertex and fragment shaders of the skinned pipeline.
+pub fn shader_keys() -> (ShaderKey, ShaderKey) {
+    (ShaderKey::new("shaders/skinned.vert"), ShaderKey::new("shaders/pushconst.frag"))
+}
+
+/// Everything needed to draw the instances of one skinned asset: the skinned pipeline and a joint matrix buffer per
+/// swapchain image. The asset's vertex, skin and index buffers belong to the model.
+pub struct SkinnedDraw {
+    device: Arc<VulkanDevice>,
+    pub model: Arc<GFXModel>,
+
+    /// Interface of the shaders `joint_layout` was built from. Reloaded shaders have to keep the joint set the same.
+    pub interface: PipelineInterface,
+    /// Owned by the scene's layout cache.
+    pub joint_layout: vk::DescriptorSetLayout,
+    pub pipeline_layout: vk::PipelineLayout,
+    pub pipeline: vk::Pipeline,
+
+    pub joint_buffers: Vec<bfr::Buffer>,
+    /// From the scene's descriptor allocator.
+    pub descriptor_sets: Vec<vk::DescriptorSet>,
+}
+
+impl SkinnedDraw {
+    /// Sets up everything but the pipeline, which `recreate_pipeline` builds once the render pass is known. The joint
+    /// set layout is set `JOINT_SET` of `interface`.
+    pub fn new(
+        device: Arc<VulkanDevice>,
+        model: Arc<GFXModel>,
+        swap_chain: &VulkanSwapChain,
+        interface: PipelineInterface,
+        descriptors: &mut DescriptorAllocator,
+        layouts: &mut DescriptorLayoutCache,
+    ) -> SkinnedDraw {
+        let joint_layout = interface.set_layout(layouts, JOINT_SET);
+        let joint_buffers = skin_pipe::create_joint_buffers(device.clone(), swap_chain.images.len());
+        let descriptor_sets = skin_pipe::create_descriptor_sets(device.clone(), descriptors, joint_layout, &joint_buffers);
+
+        SkinnedDraw {
+            device,
+            model,
+            interface,
+            joint_layout,
+            pipeline_layout: vk::PipelineLayout::null(),
+            pipeline: vk::Pipeline::null(),
+            joint_buffers,
+            descriptor_sets,
+        }
+    }
+
+    fn draws(&self, inst_asset: &Arc<GFXModel>) -> bool {
+        Arc::ptr_eq(inst_asset, &self.model)
+    }
+
+    /// Packs the joint matrices `Instances::animate` posed for every visible instance of the model into the joint
+    /// buffer for `image_index`. Instances that don't fit are left without an offset and skipped when drawing.
+    pub fn update_joints(&self, instances: &mut Instances, image_index: usize) {
+        let matrices = pack_joint_matrices(instances, &self.model, skin_pipe::MAX_JOINT_MATRICES);
+        if matrices.is_empty() {
+            return;
+        }
+
+        let memory = self.joint_buffers[image_index].memory;
+        let buffer_size = (std::mem::size_of::<Mat4>() * matrices.len()) as u64;
+        unsafe {
+            let data_ptr = self
+                .device
+                .logical_device
+                .map_memory(memory, 0, buffer_size, vk::MemoryMapFlags::empty())
+                .expect("Failed to Map Memory") as *mut Mat4;
+
+            data_ptr.copy_from_nonoverlapping(matrices.as_ptr(), matrices.len());
+
+            self.device.logical_device.unmap_memory(memory);
+        }
+    }
+
+    /// Records the draws for the model's visible instances into the current view, whose camera is at `ubo_offset`,
+    /// binding each instance's material. Expects to be inside the render pass with the view's viewport and scissor set.
+    pub fn record(
+        &self,
+        command_buffer: vk::CommandBuffer,
+        ubo_set: vk::DescriptorSet,
+        image_index: usize,
+        instances: &Instances,
+        ubo_offset: u32,
+        materials: &Materials,
+    ) {
+        let device = &self.device.logical_device;
+        unsafe {
+            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+
+            self.model
+                .buffers
+                .as_ref()
+                .expect("Skinned model isn't uploaded!")
+                .bind(device, command_buffer, true);
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                0,
+                &[ubo_set],
+                &[ubo_offset],
+            );
+            device.cmd_bind_descriptor_sets(
+                command_buffer,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pipeline_layout,
+                JOINT_SET,
+                &[self.descriptor_sets[image_index]],
+                &[],
+            );
+
+            let mut bound_material = None;
+            for inst in instances.visible_instances() {
+                let joint_offset = match inst.joint_offset {
+                    Some(joint_offset) if self.draws(&inst.asset) => joint_offset,
+                    _ => continue,
+                };
+                let material = materials.index(inst.material);
+                if bound_material != Some(material) {
+                    device.cmd_bind_descriptor_sets(
+                        command_buffer,
+                        vk::PipelineBindPoint::GRAPHICS,
+                        self.pipeline_layout,
+                        MATERIAL_SET,
+                        &[materials.materials[material].descriptor_sets[image_index]],
+                        &[],
+                    );
+                    bound_material = Some(material);
+                }
+                let push_constants = SkinnedPushConstants {
+                    model: inst.model_matrix,
+                    joint_offset,
+                };
+                let fn_device = device.fp_v1_0();
+                let state_ptr: *const c_void = &push_constants as *const _ as *const c_void;
+                fn_device.cmd_push_constants(
+                    command_buffer,
+                    self.pipeline_layout,
+                    vk::ShaderStageFlags::VERTEX,
+                    0,
+                    std::mem::size_of::<SkinnedPushConstants>() as u32,
+                    state_ptr,
+                );
+
+                let (first_index, index_count) = inst.asset.lod_range(inst.lod);
+                device.cmd_draw_indexed(command_buffer, index_count, 1, first_index, 0, 0);
+            }
+        }
+    }
+
+    /// Switches to a reloaded model. The registry destroys the old model's buffers once no frame in flight reads them.
+    pub fn replace_model(&mut self, model: Arc<GFXModel>) {
+        self.model = model;
+    }
+
+    /// Fails unless `stages` fit the skinned vertex layout, push constants and descriptor sets: the camera and material
+    /// sets as declared by `ubo_interface`, the joint set as the joint layout was built.
+    pub fn check_shaders(&self, stages: &ShaderStages, ubo_interface: &PipelineInterface) -> Result<(), String> {
+        let mut attributes = MeshVertex::get_attribute_descriptions().to_vec();
+        attributes.extend_from_slice(&SkinVertex::get_attribute_descriptions());
+        stages.interface.check_vertex_input(&attributes)?;
+        stages.interface.check_push_constants(std::mem::size_of::<SkinnedPushConstants>())?;
+        stages.interface.check_set_count(JOINT_SET + 1)?;
+        stages.interface.check_set_matches(ubo_interface, 0)?;
+        stages.interface.check_set_matches(ubo_interface, MATERIAL_SET)?;
+        stages.interface.check_set_matches(&self.interface, JOINT_SET)
+    }
+
+    /// Builds the pipeline for the current render pass and shaders, which `check_shaders` has accepted. `shared_layouts`
+    /// are the camera and material set layouts. The previous pipeline must have been destroyed.
+    pub fn recreate_pipeline(
+        &mut self,
+        render_pass: vk::RenderPass,
+        shared_layouts: &[vk::DescriptorSetLayout],
+        msaa_samples: vk::SampleCountFlags,
+        stages: &ShaderStages,
+    ) {
+        let mut set_layouts = shared_layouts.to_vec();
+        set_layouts.push(self.joint_layout);
+        let pipeline_layout = stages.interface.create_pipeline_layout(self.device.clone(), &set_layouts);
+        self.pipeline = skin_pipe::create_graphics_pipeline(
+            self.device.clone(),
+            render_pass,
+            pipeline_layout,
+            msaa_samples,
+            (&stages.vertex, &stages.fragment),
+        );
+        self.pipeline_layout = pipeline_layout;
+    }
+
+    pub fn destroy_pipeline(&mut self) {
+        unsafe {
+            self.device.logical_device.destroy_pipeline(self.pipeline, None);
+            self.device.logical_device.destroy_pipeline_layout(self.pipeline_layout, None);
+        }
+    }
+
+    /// Frees the joint buffers. The pipeline goes with the swapchain, the joint layout and sets with the scene's layout
+    /// cache and descriptor allocator, and the model's buffers with the asset registry.
+    pub fn vk_destroy(&mut self) {
+        for joint_buffer in self.joint_buffers.iter_mut() {
+            joint_buffer.vk_destroy();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::instances::GInstance;
+    use nalgebra_glm as glm;
+
+    fn model() -> Arc<GFXModel> {
+        Arc::new(GFXModel::new(vec![], vec![]))
+    }
+
+    fn posed(model: &Arc<GFXModel>, joints: usize, translation: f32) -> GInstance {
+        let mut inst = GInstance::new(model.clone(), Mat4::identity());
+        inst.joint_matrices = vec![glm::translation(&glm::vec3(translation, 0.0, 0.0)); joints];
+        inst
+    }
+
+    fn visible(g_instances: Vec<GInstance>) -> Instances {
+        let mut instances = Instances::new();
+        instances.visible = (0..g_instances.len()).collect();
+        instances.g_instances = g_instances;
+        instances
+    }
+
+    #[test]
+    fn visible_instances_of_the_model_are_packed_in_order() {
+        let skinned = model();
+        let other = model();
+        let mut instances = visible(vec![posed(&skinned, 2, 1.0), posed(&other, 3, 2.0), posed(&skinned, 4, 3.0)]);
+        instances.g_instances.push(posed(&skinned, 5, 4.0));
+
+        let matrices = pack_joint_matrices(&mut instances, &skinned, 16);
+        assert_eq!(matrices.len(), 6);
+        assert_eq!(matrices[1][(0, 3)], 1.0);
+        assert_eq!(matrices[2][(0, 3)], 3.0);
+        let offsets: Vec<_> = instances.g_instances.iter().map(|inst| inst.joint_offset).collect();
+        assert_eq!(offsets, vec![Some(0), None, Some(2), None]);
+    }
+
+    #[test]
+    fn unposed_instances_get_no_offset() {
+        let skinned = model();
+        let mut instances = visible(vec![posed(&skinned, 0, 0.0), posed(&skinned, 1, 0.0)]);
+        assert_eq!(pack_joint_matrices(&mut instances, &skinned, 16).len(), 1);
+        assert_eq!(instances.g_instances[0].joint_offset, None);
+        assert_eq!(instances.g_instances[1].joint_offset, Some(0));
+    }
+
+    #[test]
+    fn instances_past_the_capacity_are_skipped_and_reported_once() {
+        let skinned = model();
+        let mut instances = visible(vec![posed(&skinned, 3, 0.0), posed(&skinned, 2, 0.0), posed(&skinned, 1, 0.0)]);
+
+        for _ in 0..2 {
+            let matrices = pack_joint_matrices(&mut instances, &skinned, 4);
+            assert_eq!(matrices.len(), 4);
+            let offsets: Vec<_> = instances.g_instances.iter().map(|inst| inst.joint_offset).collect();
+            assert_eq!(offsets, vec![Some(0), None, Some(3)]);
+            let reported: Vec<_> = instances.g_instances.iter().map(|inst| inst.joint_overflow_reported).collect();
+            assert_eq!(reported, vec![false, true, false]);
+        }
+    }
+}
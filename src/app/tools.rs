@@ -44,3 +44,13 @@ pub fn read_shader_code(shader_path: &Path) -> Vec<u8> {
 
     bytes_code
 }
+
+/// 64-bit FNV-1a. Used for cache keys that have to stay stable between runs and compiler versions.
+pub fn hash_bytes(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for &byte in bytes.iter() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
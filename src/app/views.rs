@@ -0,0 +1,145 @@
+#![allow(dead_code)]
+
+use crate::vk_assist;
+
+use ash::vk;
+use nalgebra_glm::Mat4;
+
+use vk_assist::structures::ViewProjUBO;
+
+/// Most views a frame can draw. Each one takes a camera slot in every uniform buffer.
+pub const MAX_VIEWS: usize = 4;
+
+/// Part of the swapchain image a view draws into, as fractions of its width and height, so it follows resizes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ViewportRect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl ViewportRect {
+    pub fn new(x: f32, y: f32, width: f32, height: f32) -> ViewportRect {
+        ViewportRect { x, y, width, height }
+    }
+
+    pub fn full() -> ViewportRect {
+        ViewportRect::new(0.0, 0.0, 1.0, 1.0)
+    }
+
+    /// Column `index` of `count` equal side by side columns, for split screen.
+    pub fn split(index: u32, count: u32) -> ViewportRect {
+        assert!(index < count, "View {} of a {} way split", index, count);
+        let width = 1.0 / count as f32;
+        ViewportRect::new(width * index as f32, 0.0, width, 1.0)
+    }
+
+    /// A `scale` sized inset in the top right corner, `margin` away from the edges, for picture-in-picture.
+    pub fn inset(scale: f32, margin: f32) -> ViewportRect {
+        ViewportRect::new(1.0 - scale - margin, margin, scale, scale)
+    }
+
+    /// The rectangle in pixels. Never empty and never outside `extent`.
+    pub fn to_scissor(&self, extent: vk::Extent2D) -> vk::Rect2D {
+        let x = ((self.x.clamp(0.0, 1.0) * extent.width as f32).round() as u32).min(extent.width.saturating_sub(1));
+        let y = ((self.y.clamp(0.0, 1.0) * extent.height as f32).round() as u32).min(extent.height.saturating_sub(1));
+        let width = ((self.width * extent.width as f32).round() as u32).clamp(1, extent.width.max(1) - x);
+        let height = ((self.height * extent.height as f32).round() as u32).clamp(1, extent.height.max(1) - y);
+        vk::Rect2D {
+            offset: vk::Offset2D { x: x as i32, y: y as i32 },
+            extent: vk::Extent2D { width, height },
+        }
+    }
+
+    /// Viewport over exactly the pixels of `to_scissor`, with the full depth range.
+    pub fn to_viewport(&self, extent: vk::Extent2D) -> vk::Viewport {
+        let scissor = self.to_scissor(extent);
+        vk::Viewport {
+            x: scissor.offset.x as f32,
+            y: scissor.offset.y as f32,
+            width: scissor.extent.width as f32,
+            height: scissor.extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        }
+    }
+
+    pub fn aspect(&self, extent: vk::Extent2D) -> f32 {
+        let scissor = self.to_scissor(extent);
+        scissor.extent.width as f32 / scissor.extent.height as f32
+    }
+}
+
+/// Refits a symmetric perspective or orthographic projection to `aspect`, keeping its vertical extent.
+pub fn fit_aspect(proj: &Mat4, aspect: f32) -> Mat4 {
+    let mut fitted = *proj;
+    fitted[(0, 0)] = proj[(1, 1)].abs() / aspect;
+    fitted
+}
+
+/// One view of the scene: where it goes and, unless it follows the frame's camera, what it looks through.
+#[derive(Clone, Copy, Debug)]
+pub struct SceneView {
+    pub rect: ViewportRect,
+    pub camera: Option<ViewProjUBO>,
+}
+
+impl SceneView {
+    /// A view through the frame's camera.
+    pub fn main(rect: ViewportRect) -> SceneView {
+        SceneView { rect, camera: None }
+    }
+
+    pub fn with_camera(rect: ViewportRect, camera: ViewProjUBO) -> SceneView {
+        SceneView { rect, camera: Some(camera) }
+    }
+
+    /// The view's camera, or `main` if it has none, with the projection fitted to the view's shape.
+    pub fn ubo(&self, main: &ViewProjUBO, extent: vk::Extent2D) -> ViewProjUBO {
+        let camera = self.camera.unwrap_or(*main);
+        ViewProjUBO {
+            view: camera.view,
+            proj: fit_aspect(&camera.proj, self.rect.aspect(extent)),
+        }
+    }
+}
+
+/// Distance between the per-view cameras in a uniform buffer: the UBO size rounded up to the device's
+/// `minUniformBufferOffsetAlignment`.
+pub fn ubo_stride(min_alignment: u64) -> u64 {
+    let size = std::mem::size_of::<ViewProjUBO>() as u64;
+    let alignment = min_alignment.max(1);
+    size.div_ceil(alignment) * alignment
+}
+
+/// Dynamic state and camera offset for recording one view.
+#[derive(Clone, Copy, Debug)]
+pub struct ViewPass {
+    pub viewport: vk::Viewport,
+    pub scissor: vk::Rect2D,
+    /// Dynamic offset of the view's camera in the uniform buffer.
+    pub ubo_offset: u32,
+}
+
+/// Everything command recording needs to know about the views of a frame.
+#[derive(Clone, Debug)]
+pub struct FrameViews {
+    pub extent: vk::Extent2D,
+    pub passes: Vec<ViewPass>,
+}
+
+impl FrameViews {
+    pub fn new(views: &[SceneView], extent: vk::Extent2D, ubo_stride: u64) -> FrameViews {
+        let passes = views
+            .iter()
+            .enumerate()
+            .map(|(i, view)| ViewPass {
+                viewport: view.rect.to_viewport(extent),
+                scissor: view.rect.to_scissor(extent),
+                ubo_offset: (ubo_stride * i as u64) as u32,
+            })
+            .collect();
+        FrameViews { extent, passes }
+    }
+}
@@ -8,409 +8,45 @@ use ash::version::InstanceV1_0;
 use ash::vk;
 use nalgebra_glm::{Mat4, Vec2, Vec3, Vec4};
 
-use std::ffi::CString;
 use std::ptr;
 
-use vk_assist::structures::{Vertex, ViewProjUBO};
+use vk_assist::structures::{MeshVertex, ViewProjUBO};
 use vk_assist::types::buffer as bfr;
 use vk_assist::types::command as cmd;
+use vk_assist::types::descriptor_allocator::DescriptorAllocator;
 use vk_assist::types::image as img;
 use vk_assist::types::vulkan_device::*;
 
-pub fn create_render_pass(
-    instance: Arc<ash::Instance>,
-    device: Arc<VulkanDevice>,
-    surface_format: vk::Format,
-    msaa_samples: vk::SampleCountFlags,
-) -> vk::RenderPass {
-    let color_attachment = vk::AttachmentDescription {
-        flags: vk::AttachmentDescriptionFlags::empty(),
-        format: surface_format,
-        samples: msaa_samples,
-        load_op: vk::AttachmentLoadOp::CLEAR,
-        store_op: vk::AttachmentStoreOp::STORE,
-        stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
-        stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
-        initial_layout: vk::ImageLayout::UNDEFINED,
-        final_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-    };
-
-    let depth_attachment = vk::AttachmentDescription {
-        flags: vk::AttachmentDescriptionFlags::empty(),
-        format: misc::find_depth_format(instance.clone(), device.physical_device),
-        samples: msaa_samples,
-        load_op: vk::AttachmentLoadOp::CLEAR,
-        store_op: vk::AttachmentStoreOp::DONT_CARE,
-        stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
-        stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
-        initial_layout: vk::ImageLayout::UNDEFINED,
-        final_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
-    };
-
-    let color_attachment_resolve = vk::AttachmentDescription {
-        flags: vk::AttachmentDescriptionFlags::empty(),
-        format: surface_format,
-        samples: vk::SampleCountFlags::TYPE_1,
-        load_op: vk::AttachmentLoadOp::DONT_CARE,
-        store_op: vk::AttachmentStoreOp::STORE,
-        stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
-        stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
-        initial_layout: vk::ImageLayout::UNDEFINED,
-        final_layout: vk::ImageLayout::PRESENT_SRC_KHR,
-    };
-    let color_attachment_ref = vk::AttachmentReference {
-        attachment: 0,
-        layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-    };
-    let depth_attachment_ref = vk::AttachmentReference {
-        attachment: 1,
-        layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
-    };
-    let color_attachment_resolve_ref = vk::AttachmentReference {
-        attachment: 2,
-        layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-    };
-    let subpasses = [vk::SubpassDescription {
-        color_attachment_count: 1,
-        p_color_attachments: &color_attachment_ref,
-        p_depth_stencil_attachment: &depth_attachment_ref,
-        flags: vk::SubpassDescriptionFlags::empty(),
-        pipeline_bind_point: vk::PipelineBindPoint::GRAPHICS,
-        input_attachment_count: 0,
-        p_input_attachments: ptr::null(),
-        p_resolve_attachments: &color_attachment_resolve_ref,
-        preserve_attachment_count: 0,
-        p_preserve_attachments: ptr::null(),
-    }];
-
-    let render_pass_attachments = [color_attachment, depth_attachment, color_attachment_resolve];
-
-    let subpass_dependencies = [vk::SubpassDependency {
-        src_subpass: vk::SUBPASS_EXTERNAL,
-        dst_subpass: 0,
-        src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
-        dst_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
-        src_access_mask: vk::AccessFlags::empty(),
-        dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
-        dependency_flags: vk::DependencyFlags::empty(),
-    }];
-
-    let renderpass_create_info = vk::RenderPassCreateInfo {
-        s_type: vk::StructureType::RENDER_PASS_CREATE_INFO,
-        flags: vk::RenderPassCreateFlags::empty(),
-        p_next: ptr::null(),
-        attachment_count: render_pass_attachments.len() as u32,
-        p_attachments: render_pass_attachments.as_ptr(),
-        subpass_count: subpasses.len() as u32,
-        p_subpasses: subpasses.as_ptr(),
-        dependency_count: subpass_dependencies.len() as u32,
-        p_dependencies: subpass_dependencies.as_ptr(),
-    };
-    unsafe {
-        device
-            .logical_device
-            .create_render_pass(&renderpass_create_info, None)
-            .expect("Failed to create render pass!")
-    }
-}
+use super::pipeline_builder::{alpha_blend_attachment, GraphicsPipelineBuilder};
 
+/// Viewport and scissor are dynamic, so the pipeline survives swapchain resizes and can draw into any sub-rectangle.
+/// A `blended` pipeline alpha blends and tests depth without writing it.
 pub fn create_graphics_pipeline(
     device: Arc<VulkanDevice>,
     render_pass: vk::RenderPass,
-    swapchain_extent: vk::Extent2D,
-    ubo_set_layout: vk::DescriptorSetLayout,
+    pipeline_layout: vk::PipelineLayout,
     msaa_samples: vk::SampleCountFlags,
-) -> (vk::Pipeline, vk::PipelineLayout) {
-    let vert_shader_module = misc::create_shader_module(&device.logical_device, include_bytes!("../../shaders/pushconst.vert.spv").to_vec());
-    let frag_shader_module = misc::create_shader_module(&device.logical_device, include_bytes!("../../shaders/pushconst.frag.spv").to_vec());
-
-    let main_function_name = CString::new("main").unwrap(); // the beginning function name in shader code.
-
-    let shader_stages = [
-        vk::PipelineShaderStageCreateInfo {
-            // Vertex Shader
-            s_type: vk::StructureType::PIPELINE_SHADER_STAGE_CREATE_INFO,
-            p_next: ptr::null(),
-            flags: vk::PipelineShaderStageCreateFlags::empty(),
-            module: vert_shader_module,
-            p_name: main_function_name.as_ptr(),
-            p_specialization_info: ptr::null(),
-            stage: vk::ShaderStageFlags::VERTEX,
-        },
-        vk::PipelineShaderStageCreateInfo {
-            // Fragment Shader
-            s_type: vk::StructureType::PIPELINE_SHADER_STAGE_CREATE_INFO,
-            p_next: ptr::null(),
-            flags: vk::PipelineShaderStageCreateFlags::empty(),
-            module: frag_shader_module,
-            p_name: main_function_name.as_ptr(),
-            p_specialization_info: ptr::null(),
-            stage: vk::ShaderStageFlags::FRAGMENT,
-        },
-    ];
-
-    let binding_description = Vertex::get_binding_descriptions();
-    let attribute_description = Vertex::get_attribute_descriptions();
-
-    let vertex_input_state_create_info = vk::PipelineVertexInputStateCreateInfo {
-        s_type: vk::StructureType::PIPELINE_VERTEX_INPUT_STATE_CREATE_INFO,
-        p_next: ptr::null(),
-        flags: vk::PipelineVertexInputStateCreateFlags::empty(),
-        vertex_attribute_description_count: attribute_description.len() as u32,
-        p_vertex_attribute_descriptions: attribute_description.as_ptr(),
-        vertex_binding_description_count: binding_description.len() as u32,
-        p_vertex_binding_descriptions: binding_description.as_ptr(),
-    };
-    let vertex_input_assembly_state_info = vk::PipelineInputAssemblyStateCreateInfo {
-        s_type: vk::StructureType::PIPELINE_INPUT_ASSEMBLY_STATE_CREATE_INFO,
-        flags: vk::PipelineInputAssemblyStateCreateFlags::empty(),
-        p_next: ptr::null(),
-        primitive_restart_enable: vk::FALSE,
-        topology: vk::PrimitiveTopology::TRIANGLE_LIST,
-    };
-
-    let viewports = [vk::Viewport {
-        x: 0.0,
-        y: 0.0,
-        width: swapchain_extent.width as f32,
-        height: swapchain_extent.height as f32,
-        min_depth: 0.0,
-        max_depth: 1.0,
-    }];
-
-    let scissors = [vk::Rect2D {
-        offset: vk::Offset2D { x: 0, y: 0 },
-        extent: swapchain_extent,
-    }];
-
-    let viewport_state_create_info = vk::PipelineViewportStateCreateInfo {
-        s_type: vk::StructureType::PIPELINE_VIEWPORT_STATE_CREATE_INFO,
-        p_next: ptr::null(),
-        flags: vk::PipelineViewportStateCreateFlags::empty(),
-        scissor_count: scissors.len() as u32,
-        p_scissors: scissors.as_ptr(),
-        viewport_count: viewports.len() as u32,
-        p_viewports: viewports.as_ptr(),
-    };
-
-    let rasterization_statue_create_info = vk::PipelineRasterizationStateCreateInfo {
-        s_type: vk::StructureType::PIPELINE_RASTERIZATION_STATE_CREATE_INFO,
-        p_next: ptr::null(),
-        flags: vk::PipelineRasterizationStateCreateFlags::empty(),
-        depth_clamp_enable: vk::FALSE,
-        cull_mode: vk::CullModeFlags::BACK,
-        front_face: vk::FrontFace::COUNTER_CLOCKWISE,
-        line_width: 1.0,
-        polygon_mode: vk::PolygonMode::FILL,
-        rasterizer_discard_enable: vk::FALSE,
-        depth_bias_clamp: 0.0,
-        depth_bias_constant_factor: 0.0,
-        depth_bias_enable: vk::FALSE,
-        depth_bias_slope_factor: 0.0,
-    };
-
-    let multisample_state_create_info = vk::PipelineMultisampleStateCreateInfo {
-        s_type: vk::StructureType::PIPELINE_MULTISAMPLE_STATE_CREATE_INFO,
-        flags: vk::PipelineMultisampleStateCreateFlags::empty(),
-        p_next: ptr::null(),
-        rasterization_samples: msaa_samples,
-        sample_shading_enable: vk::FALSE,
-        min_sample_shading: 0.0,
-        p_sample_mask: ptr::null(),
-        alpha_to_one_enable: vk::FALSE,
-        alpha_to_coverage_enable: vk::FALSE,
-    };
-
-    let stencil_state = vk::StencilOpState {
-        fail_op: vk::StencilOp::KEEP,
-        pass_op: vk::StencilOp::KEEP,
-        depth_fail_op: vk::StencilOp::KEEP,
-        compare_op: vk::CompareOp::ALWAYS,
-        compare_mask: 0,
-        write_mask: 0,
-        reference: 0,
-    };
-
-    let depth_state_create_info = vk::PipelineDepthStencilStateCreateInfo {
-        s_type: vk::StructureType::PIPELINE_DEPTH_STENCIL_STATE_CREATE_INFO,
-        p_next: ptr::null(),
-        flags: vk::PipelineDepthStencilStateCreateFlags::empty(),
-        depth_test_enable: vk::TRUE,
-        depth_write_enable: vk::TRUE,
-        depth_compare_op: vk::CompareOp::LESS,
-        depth_bounds_test_enable: vk::FALSE,
-        stencil_test_enable: vk::FALSE,
-        front: stencil_state,
-        back: stencil_state,
-        max_depth_bounds: 1.0,
-        min_depth_bounds: 0.0,
-    };
-
-    let color_blend_attachment_states = [vk::PipelineColorBlendAttachmentState {
-        blend_enable: vk::FALSE,
-        color_write_mask: vk::ColorComponentFlags::all(),
-        src_color_blend_factor: vk::BlendFactor::ONE,
-        dst_color_blend_factor: vk::BlendFactor::ZERO,
-        color_blend_op: vk::BlendOp::ADD,
-        src_alpha_blend_factor: vk::BlendFactor::ONE,
-        dst_alpha_blend_factor: vk::BlendFactor::ZERO,
-        alpha_blend_op: vk::BlendOp::ADD,
-    }];
-
-    let color_blend_state = vk::PipelineColorBlendStateCreateInfo {
-        s_type: vk::StructureType::PIPELINE_COLOR_BLEND_STATE_CREATE_INFO,
-        p_next: ptr::null(),
-        flags: vk::PipelineColorBlendStateCreateFlags::empty(),
-        logic_op_enable: vk::FALSE,
-        logic_op: vk::LogicOp::COPY,
-        attachment_count: color_blend_attachment_states.len() as u32,
-        p_attachments: color_blend_attachment_states.as_ptr(),
-        blend_constants: [0.0, 0.0, 0.0, 0.0],
-    };
-    let set_layouts = [ubo_set_layout];
-
-    let pcr = vk::PushConstantRange {
-        stage_flags: vk::ShaderStageFlags::VERTEX,
-        size: std::mem::size_of::<Mat4>() as u32,
-        offset: 0,
-    };
-    let push_constant_ranges = [pcr];
-
-    let pipeline_layout_create_info = vk::PipelineLayoutCreateInfo {
-        s_type: vk::StructureType::PIPELINE_LAYOUT_CREATE_INFO,
-        p_next: ptr::null(),
-        flags: vk::PipelineLayoutCreateFlags::empty(),
-        set_layout_count: set_layouts.len() as u32,
-        p_set_layouts: set_layouts.as_ptr(),
-        push_constant_range_count: push_constant_ranges.len() as u32,
-        p_push_constant_ranges: push_constant_ranges.as_ptr(),
-    };
-
-    let pipeline_layout = unsafe {
-        device
-            .logical_device
-            .create_pipeline_layout(&pipeline_layout_create_info, None)
-            .expect("Failed to create pipeline layout!")
-    };
-
-    let graphic_pipeline_create_infos = [vk::GraphicsPipelineCreateInfo {
-        s_type: vk::StructureType::GRAPHICS_PIPELINE_CREATE_INFO,
-        p_next: ptr::null(),
-        flags: vk::PipelineCreateFlags::empty(),
-        stage_count: shader_stages.len() as u32,
-        p_stages: shader_stages.as_ptr(),
-        p_vertex_input_state: &vertex_input_state_create_info,
-        p_input_assembly_state: &vertex_input_assembly_state_info,
-        p_tessellation_state: ptr::null(),
-        p_viewport_state: &viewport_state_create_info,
-        p_rasterization_state: &rasterization_statue_create_info,
-        p_multisample_state: &multisample_state_create_info,
-        p_depth_stencil_state: &depth_state_create_info,
-        p_color_blend_state: &color_blend_state,
-        p_dynamic_state: ptr::null(),
-        layout: pipeline_layout,
-        render_pass,
-        subpass: 0,
-        base_pipeline_handle: vk::Pipeline::null(),
-        base_pipeline_index: -1,
-    }];
-
-    let graphics_pipelines = unsafe {
-        device
-            .logical_device
-            .create_graphics_pipelines(vk::PipelineCache::null(), &graphic_pipeline_create_infos, None)
-            .expect("Failed to create Graphics Pipeline!.")
-    };
-
-    unsafe {
-        device.logical_device.destroy_shader_module(vert_shader_module, None);
-        device.logical_device.destroy_shader_module(frag_shader_module, None);
-    }
-
-    (graphics_pipelines[0], pipeline_layout)
-}
-
-pub fn create_descriptor_set_layout(device: Arc<VulkanDevice>) -> vk::DescriptorSetLayout {
-    let ubo_layout_bindings = [
-        vk::DescriptorSetLayoutBinding {
-            // transform uniform
-            binding: 0,
-            descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
-            descriptor_count: 1,
-            stage_flags: vk::ShaderStageFlags::VERTEX,
-            p_immutable_samplers: ptr::null(),
-        },
-        vk::DescriptorSetLayoutBinding {
-            // sampler uniform
-            binding: 1,
-            descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
-            descriptor_count: 1,
-            stage_flags: vk::ShaderStageFlags::FRAGMENT,
-            p_immutable_samplers: ptr::null(),
-        },
-    ];
-
-    let ubo_layout_create_info = vk::DescriptorSetLayoutCreateInfo {
-        s_type: vk::StructureType::DESCRIPTOR_SET_LAYOUT_CREATE_INFO,
-        p_next: ptr::null(),
-        flags: vk::DescriptorSetLayoutCreateFlags::empty(),
-        binding_count: ubo_layout_bindings.len() as u32,
-        p_bindings: ubo_layout_bindings.as_ptr(),
-    };
-
-    unsafe {
-        device
-            .logical_device
-            .create_descriptor_set_layout(&ubo_layout_create_info, None)
-            .expect("Failed to create Descriptor Set Layout!")
-    }
-}
-
-pub fn create_framebuffers(
-    device: Arc<VulkanDevice>,
-    render_pass: vk::RenderPass,
-    image_views: &Vec<vk::ImageView>,
-    depth_image_view: vk::ImageView,
-    color_image_view: vk::ImageView,
-    swapchain_extent: vk::Extent2D,
-) -> Vec<vk::Framebuffer> {
-    let mut framebuffers = vec![];
-
-    for &image_view in image_views.iter() {
-        let attachments = [color_image_view, depth_image_view, image_view];
-
-        let framebuffer_create_info = vk::FramebufferCreateInfo {
-            s_type: vk::StructureType::FRAMEBUFFER_CREATE_INFO,
-            p_next: ptr::null(),
-            flags: vk::FramebufferCreateFlags::empty(),
-            render_pass,
-            attachment_count: attachments.len() as u32,
-            p_attachments: attachments.as_ptr(),
-            width: swapchain_extent.width,
-            height: swapchain_extent.height,
-            layers: 1,
-        };
-
-        let framebuffer = unsafe {
-            device
-                .logical_device
-                .create_framebuffer(&framebuffer_create_info, None)
-                .expect("Failed to create Framebuffer!")
-        };
-
-        framebuffers.push(framebuffer);
+    shaders: (&[u8], &[u8]),
+    blended: bool,
+) -> vk::Pipeline {
+    let mut builder = GraphicsPipelineBuilder::new(pipeline_layout, render_pass)
+        .shader(vk::ShaderStageFlags::VERTEX, shaders.0)
+        .shader(vk::ShaderStageFlags::FRAGMENT, shaders.1)
+        .vertex_input(&MeshVertex::get_binding_descriptions(), &MeshVertex::get_attribute_descriptions())
+        .samples(msaa_samples)
+        .dynamic_states(&[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR]);
+    if blended {
+        builder = builder.blend_attachments(&[alpha_blend_attachment()]).depth(true, false, vk::CompareOp::LESS);
     }
-
-    framebuffers
+    builder.build(device)
 }
 
 pub fn create_descriptor_sets(
     device: Arc<VulkanDevice>,
-    descriptor_pool: vk::DescriptorPool,
+    descriptors: &mut DescriptorAllocator,
     descriptor_set_layout: vk::DescriptorSetLayout,
     uniforms_buffers: &Vec<bfr::Buffer>,
-    texture: &img::Image,
-    texture_sampler: vk::Sampler,
+    light_buffers: &[vk::Buffer],
     swapchain_images_size: usize,
 ) -> Vec<vk::DescriptorSet> {
     let mut layouts: Vec<vk::DescriptorSetLayout> = vec![];
@@ -418,20 +54,7 @@ pub fn create_descriptor_sets(
         layouts.push(descriptor_set_layout);
     }
 
-    let descriptor_set_allocate_info = vk::DescriptorSetAllocateInfo {
-        s_type: vk::StructureType::DESCRIPTOR_SET_ALLOCATE_INFO,
-        p_next: ptr::null(),
-        descriptor_pool,
-        descriptor_set_count: swapchain_images_size as u32,
-        p_set_layouts: layouts.as_ptr(),
-    };
-
-    let descriptor_sets = unsafe {
-        device
-            .logical_device
-            .allocate_descriptor_sets(&descriptor_set_allocate_info)
-            .expect("Failed to allocate descriptor sets!")
-    };
+    let descriptor_sets = descriptors.allocate(&layouts);
 
     for (i, &descritptor_set) in descriptor_sets.iter().enumerate() {
         let descriptor_buffer_infos = [vk::DescriptorBufferInfo {
@@ -439,36 +62,36 @@ pub fn create_descriptor_sets(
             offset: 0,
             range: ::std::mem::size_of::<ViewProjUBO>() as u64,
         }];
-        let descriptor_image_infos = [vk::DescriptorImageInfo {
-            sampler: texture_sampler,
-            image_view: texture.view,
-            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        let light_buffer_infos = [vk::DescriptorBufferInfo {
+            buffer: light_buffers[i],
+            offset: 0,
+            range: vk::WHOLE_SIZE,
         }];
         let descriptor_write_sets = [
             vk::WriteDescriptorSet {
-                // transform uniform
+                // transform uniform, one per view at a dynamic offset
                 s_type: vk::StructureType::WRITE_DESCRIPTOR_SET,
                 p_next: ptr::null(),
                 dst_set: descritptor_set,
                 dst_binding: 0,
                 dst_array_element: 0,
                 descriptor_count: 1,
-                descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+                descriptor_type: vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
                 p_image_info: ptr::null(),
                 p_buffer_info: descriptor_buffer_infos.as_ptr(),
                 p_texel_buffer_view: ptr::null(),
             },
             vk::WriteDescriptorSet {
-                // sampler uniform
+                // lights
                 s_type: vk::StructureType::WRITE_DESCRIPTOR_SET,
                 p_next: ptr::null(),
                 dst_set: descritptor_set,
                 dst_binding: 1,
                 dst_array_element: 0,
                 descriptor_count: 1,
-                descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
-                p_image_info: descriptor_image_infos.as_ptr(),
-                p_buffer_info: ptr::null(),
+                descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+                p_image_info: ptr::null(),
+                p_buffer_info: light_buffer_infos.as_ptr(),
                 p_texel_buffer_view: ptr::null(),
             },
         ];
@@ -479,45 +102,15 @@ pub fn create_descriptor_sets(
     descriptor_sets
 }
 
-pub fn create_descriptor_pool(device: Arc<VulkanDevice>, swapchain_images_size: usize) -> vk::DescriptorPool {
-    let pool_sizes = [
-        vk::DescriptorPoolSize {
-            ty: vk::DescriptorType::UNIFORM_BUFFER,
-            descriptor_count: swapchain_images_size as u32,
-        },
-        vk::DescriptorPoolSize {
-            // sampler descriptor pool
-            ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
-            descriptor_count: swapchain_images_size as u32,
-        },
-    ];
-
-    let descriptor_pool_create_info = vk::DescriptorPoolCreateInfo {
-        s_type: vk::StructureType::DESCRIPTOR_POOL_CREATE_INFO,
-        p_next: ptr::null(),
-        flags: vk::DescriptorPoolCreateFlags::empty(),
-        max_sets: swapchain_images_size as u32,
-        pool_size_count: pool_sizes.len() as u32,
-        p_pool_sizes: pool_sizes.as_ptr(),
-    };
-
-    unsafe {
-        device
-            .logical_device
-            .create_descriptor_pool(&descriptor_pool_create_info, None)
-            .expect("Failed to create Descriptor Pool!")
-    }
-}
-
-pub fn create_uniform_buffers(device: Arc<VulkanDevice>, swapchain_image_count: usize) -> Vec<bfr::Buffer> {
-    let buffer_size = std::mem::size_of::<ViewProjUBO>();
+/// One uniform buffer of `buffer_size` bytes per swapchain image.
+pub fn create_uniform_buffers(device: Arc<VulkanDevice>, swapchain_image_count: usize, buffer_size: u64) -> Vec<bfr::Buffer> {
     let device_memory_properties = device.get_physical_device_memory_properties();
     let mut uniform_buffers = vec![];
 
     for _ in 0..swapchain_image_count {
         let uniform_buffer = bfr::create_buffer(
             device.clone(),
-            buffer_size as u64,
+            buffer_size,
             vk::BufferUsageFlags::UNIFORM_BUFFER,
             vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
             &device_memory_properties,
@@ -3,3 +3,6 @@ pub mod basic_ubo_pipeline;
 pub mod current_pipeline_util;
 pub mod depth_buffer_pipeline;
 pub mod msaa_pipeline;
+pub mod pipeline_builder;
+pub mod reflected_layout;
+pub mod skinned_pipeline;
@@ -0,0 +1,375 @@
+#![allow(dead_code)]
+
+use crate::vk_assist;
+use crate::vk_assist::misc_util as misc;
+use std::sync::Arc;
+
+use ash::version::DeviceV1_0;
+use ash::vk;
+
+use std::ffi::CString;
+use std::ptr;
+
+use vk_assist::types::vulkan_device::*;
+
+/// Writes every channel, no blending.
+pub fn opaque_attachment() -> vk::PipelineColorBlendAttachmentState {
+    vk::PipelineColorBlendAttachmentState {
+        blend_enable: vk::FALSE,
+        color_write_mask: vk::ColorComponentFlags::all(),
+        src_color_blend_factor: vk::BlendFactor::ONE,
+        dst_color_blend_factor: vk::BlendFactor::ZERO,
+        color_blend_op: vk::BlendOp::ADD,
+        src_alpha_blend_factor: vk::BlendFactor::ONE,
+        dst_alpha_blend_factor: vk::BlendFactor::ZERO,
+        alpha_blend_op: vk::BlendOp::ADD,
+    }
+}
+
+/// Regular alpha blending, `src * a + dst * (1 - a)`.
+pub fn alpha_blend_attachment() -> vk::PipelineColorBlendAttachmentState {
+    vk::PipelineColorBlendAttachmentState {
+        blend_enable: vk::TRUE,
+        src_color_blend_factor: vk::BlendFactor::SRC_ALPHA,
+        dst_color_blend_factor: vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+        ..opaque_attachment()
+    }
+}
+
+/// Pipeline layout without reflection, for pipelines whose shaders are baked in.
+pub fn create_pipeline_layout(
+    device: Arc<VulkanDevice>,
+    set_layouts: &[vk::DescriptorSetLayout],
+    push_constant_ranges: &[vk::PushConstantRange],
+) -> vk::PipelineLayout {
+    let pipeline_layout_create_info = vk::PipelineLayoutCreateInfo {
+        s_type: vk::StructureType::PIPELINE_LAYOUT_CREATE_INFO,
+        p_next: ptr::null(),
+        flags: vk::PipelineLayoutCreateFlags::empty(),
+        set_layout_count: set_layouts.len() as u32,
+        p_set_layouts: set_layouts.as_ptr(),
+        push_constant_range_count: push_constant_ranges.len() as u32,
+        p_push_constant_ranges: push_constant_ranges.as_ptr(),
+    };
+
+    unsafe {
+        device
+            .logical_device
+            .create_pipeline_layout(&pipeline_layout_create_info, None)
+            .expect("Failed to create pipeline layout!")
+    }
+}
+
+/// Describes a graphics pipeline piece by piece. The defaults are what most pipelines here use: filled triangle lists,
+/// back faces culled with counter-clockwise fronts, a `LESS` depth test that writes depth, one opaque color attachment
+/// and no multisampling. The viewport and scissor cover `extent` unless they are made dynamic.
+pub struct GraphicsPipelineBuilder {
+    layout: vk::PipelineLayout,
+    render_pass: vk::RenderPass,
+    subpass: u32,
+    stages: Vec<(vk::ShaderStageFlags, Vec<u8>)>,
+    vertex_bindings: Vec<vk::VertexInputBindingDescription>,
+    vertex_attributes: Vec<vk::VertexInputAttributeDescription>,
+    topology: vk::PrimitiveTopology,
+    polygon_mode: vk::PolygonMode,
+    cull_mode: vk::CullModeFlags,
+    front_face: vk::FrontFace,
+    /// Constant factor and slope factor.
+    depth_bias: Option<(f32, f32)>,
+    depth_test: bool,
+    depth_write: bool,
+    depth_compare_op: vk::CompareOp,
+    /// Front and back faces.
+    stencil: Option<(vk::StencilOpState, vk::StencilOpState)>,
+    blend_attachments: Vec<vk::PipelineColorBlendAttachmentState>,
+    samples: vk::SampleCountFlags,
+    dynamic_states: Vec<vk::DynamicState>,
+    extent: vk::Extent2D,
+}
+
+impl GraphicsPipelineBuilder {
+    pub fn new(layout: vk::PipelineLayout, render_pass: vk::RenderPass) -> GraphicsPipelineBuilder {
+        GraphicsPipelineBuilder {
+            layout,
+            render_pass,
+            subpass: 0,
+            stages: vec![],
+            vertex_bindings: vec![],
+            vertex_attributes: vec![],
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            polygon_mode: vk::PolygonMode::FILL,
+            cull_mode: vk::CullModeFlags::BACK,
+            front_face: vk::FrontFace::COUNTER_CLOCKWISE,
+            depth_bias: None,
+            depth_test: true,
+            depth_write: true,
+            depth_compare_op: vk::CompareOp::LESS,
+            stencil: None,
+            blend_attachments: vec![opaque_attachment()],
+            samples: vk::SampleCountFlags::TYPE_1,
+            dynamic_states: vec![],
+            extent: vk::Extent2D { width: 0, height: 0 },
+        }
+    }
+
+    pub fn subpass(mut self, subpass: u32) -> GraphicsPipelineBuilder {
+        self.subpass = subpass;
+        self
+    }
+
+    /// Adds a stage from SPIR-V. The entry point is always `main`.
+    pub fn shader(mut self, stage: vk::ShaderStageFlags, spirv: &[u8]) -> GraphicsPipelineBuilder {
+        self.stages.push((stage, spirv.to_vec()));
+        self
+    }
+
+    pub fn vertex_input(
+        mut self,
+        bindings: &[vk::VertexInputBindingDescription],
+        attributes: &[vk::VertexInputAttributeDescription],
+    ) -> GraphicsPipelineBuilder {
+        self.vertex_bindings.extend_from_slice(bindings);
+        self.vertex_attributes.extend_from_slice(attributes);
+        self
+    }
+
+    pub fn topology(mut self, topology: vk::PrimitiveTopology) -> GraphicsPipelineBuilder {
+        self.topology = topology;
+        self
+    }
+
+    pub fn polygon_mode(mut self, polygon_mode: vk::PolygonMode) -> GraphicsPipelineBuilder {
+        self.polygon_mode = polygon_mode;
+        self
+    }
+
+    pub fn cull(mut self, cull_mode: vk::CullModeFlags, front_face: vk::FrontFace) -> GraphicsPipelineBuilder {
+        self.cull_mode = cull_mode;
+        self.front_face = front_face;
+        self
+    }
+
+    pub fn depth_bias(mut self, constant_factor: f32, slope_factor: f32) -> GraphicsPipelineBuilder {
+        self.depth_bias = Some((constant_factor, slope_factor));
+        self
+    }
+
+    pub fn depth(mut self, test: bool, write: bool, compare_op: vk::CompareOp) -> GraphicsPipelineBuilder {
+        self.depth_test = test;
+        self.depth_write = write;
+        self.depth_compare_op = compare_op;
+        self
+    }
+
+    pub fn stencil(mut self, front: vk::StencilOpState, back: vk::StencilOpState) -> GraphicsPipelineBuilder {
+        self.stencil = Some((front, back));
+        self
+    }
+
+    /// One blend state per color attachment of the subpass, replacing the default single opaque one.
+    pub fn blend_attachments(mut self, attachments: &[vk::PipelineColorBlendAttachmentState]) -> GraphicsPipelineBuilder {
+        self.blend_attachments = attachments.to_vec();
+        self
+    }
+
+    pub fn samples(mut self, samples: vk::SampleCountFlags) -> GraphicsPipelineBuilder {
+        self.samples = samples;
+        self
+    }
+
+    pub fn dynamic_states(mut self, dynamic_states: &[vk::DynamicState]) -> GraphicsPipelineBuilder {
+        self.dynamic_states.extend_from_slice(dynamic_states);
+        self
+    }
+
+    /// Size of the static viewport and scissor.
+    pub fn extent(mut self, extent: vk::Extent2D) -> GraphicsPipelineBuilder {
+        self.extent = extent;
+        self
+    }
+
+    pub fn build(&self, device: Arc<VulkanDevice>) -> vk::Pipeline {
+        let dynamic_viewport = self.dynamic_states.contains(&vk::DynamicState::VIEWPORT) && self.dynamic_states.contains(&vk::DynamicState::SCISSOR);
+        assert!(
+            dynamic_viewport || (self.extent.width > 0 && self.extent.height > 0),
+            "Pipeline needs an extent unless its viewport and scissor are dynamic"
+        );
+
+        let main_function_name = CString::new("main").unwrap(); // the beginning function name in shader code.
+        let shader_modules: Vec<vk::ShaderModule> = self
+            .stages
+            .iter()
+            .map(|(_, spirv)| misc::create_shader_module(&device.logical_device, spirv.clone()))
+            .collect();
+        let shader_stages: Vec<vk::PipelineShaderStageCreateInfo> = self
+            .stages
+            .iter()
+            .zip(shader_modules.iter())
+            .map(|(&(stage, _), &module)| vk::PipelineShaderStageCreateInfo {
+                s_type: vk::StructureType::PIPELINE_SHADER_STAGE_CREATE_INFO,
+                p_next: ptr::null(),
+                flags: vk::PipelineShaderStageCreateFlags::empty(),
+                module,
+                p_name: main_function_name.as_ptr(),
+                p_specialization_info: ptr::null(),
+                stage,
+            })
+            .collect();
+
+        let vertex_input_state_create_info = vk::PipelineVertexInputStateCreateInfo {
+            s_type: vk::StructureType::PIPELINE_VERTEX_INPUT_STATE_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: vk::PipelineVertexInputStateCreateFlags::empty(),
+            vertex_attribute_description_count: self.vertex_attributes.len() as u32,
+            p_vertex_attribute_descriptions: self.vertex_attributes.as_ptr(),
+            vertex_binding_description_count: self.vertex_bindings.len() as u32,
+            p_vertex_binding_descriptions: self.vertex_bindings.as_ptr(),
+        };
+        let vertex_input_assembly_state_info = vk::PipelineInputAssemblyStateCreateInfo {
+            s_type: vk::StructureType::PIPELINE_INPUT_ASSEMBLY_STATE_CREATE_INFO,
+            flags: vk::PipelineInputAssemblyStateCreateFlags::empty(),
+            p_next: ptr::null(),
+            primitive_restart_enable: vk::FALSE,
+            topology: self.topology,
+        };
+
+        let viewports = [vk::Viewport {
+            x: 0.0,
+            y: 0.0,
+            width: self.extent.width as f32,
+            height: self.extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        }];
+
+        let scissors = [vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent: self.extent,
+        }];
+
+        let viewport_state_create_info = vk::PipelineViewportStateCreateInfo {
+            s_type: vk::StructureType::PIPELINE_VIEWPORT_STATE_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: vk::PipelineViewportStateCreateFlags::empty(),
+            scissor_count: scissors.len() as u32,
+            p_scissors: scissors.as_ptr(),
+            viewport_count: viewports.len() as u32,
+            p_viewports: viewports.as_ptr(),
+        };
+
+        let (depth_bias_constant_factor, depth_bias_slope_factor) = self.depth_bias.unwrap_or((0.0, 0.0));
+        let rasterization_statue_create_info = vk::PipelineRasterizationStateCreateInfo {
+            s_type: vk::StructureType::PIPELINE_RASTERIZATION_STATE_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: vk::PipelineRasterizationStateCreateFlags::empty(),
+            depth_clamp_enable: vk::FALSE,
+            cull_mode: self.cull_mode,
+            front_face: self.front_face,
+            line_width: 1.0,
+            polygon_mode: self.polygon_mode,
+            rasterizer_discard_enable: vk::FALSE,
+            depth_bias_clamp: 0.0,
+            depth_bias_constant_factor,
+            depth_bias_enable: self.depth_bias.is_some() as vk::Bool32,
+            depth_bias_slope_factor,
+        };
+
+        let multisample_state_create_info = vk::PipelineMultisampleStateCreateInfo {
+            s_type: vk::StructureType::PIPELINE_MULTISAMPLE_STATE_CREATE_INFO,
+            flags: vk::PipelineMultisampleStateCreateFlags::empty(),
+            p_next: ptr::null(),
+            rasterization_samples: self.samples,
+            sample_shading_enable: vk::FALSE,
+            min_sample_shading: 0.0,
+            p_sample_mask: ptr::null(),
+            alpha_to_one_enable: vk::FALSE,
+            alpha_to_coverage_enable: vk::FALSE,
+        };
+
+        let stencil_state = vk::StencilOpState {
+            fail_op: vk::StencilOp::KEEP,
+            pass_op: vk::StencilOp::KEEP,
+            depth_fail_op: vk::StencilOp::KEEP,
+            compare_op: vk::CompareOp::ALWAYS,
+            compare_mask: 0,
+            write_mask: 0,
+            reference: 0,
+        };
+        let (front, back) = self.stencil.unwrap_or((stencil_state, stencil_state));
+
+        let depth_state_create_info = vk::PipelineDepthStencilStateCreateInfo {
+            s_type: vk::StructureType::PIPELINE_DEPTH_STENCIL_STATE_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: vk::PipelineDepthStencilStateCreateFlags::empty(),
+            depth_test_enable: self.depth_test as vk::Bool32,
+            depth_write_enable: self.depth_write as vk::Bool32,
+            depth_compare_op: self.depth_compare_op,
+            depth_bounds_test_enable: vk::FALSE,
+            stencil_test_enable: self.stencil.is_some() as vk::Bool32,
+            front,
+            back,
+            max_depth_bounds: 1.0,
+            min_depth_bounds: 0.0,
+        };
+
+        let color_blend_state = vk::PipelineColorBlendStateCreateInfo {
+            s_type: vk::StructureType::PIPELINE_COLOR_BLEND_STATE_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: vk::PipelineColorBlendStateCreateFlags::empty(),
+            logic_op_enable: vk::FALSE,
+            logic_op: vk::LogicOp::COPY,
+            attachment_count: self.blend_attachments.len() as u32,
+            p_attachments: self.blend_attachments.as_ptr(),
+            blend_constants: [0.0, 0.0, 0.0, 0.0],
+        };
+
+        let dynamic_state_create_info = vk::PipelineDynamicStateCreateInfo {
+            s_type: vk::StructureType::PIPELINE_DYNAMIC_STATE_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: vk::PipelineDynamicStateCreateFlags::empty(),
+            dynamic_state_count: self.dynamic_states.len() as u32,
+            p_dynamic_states: self.dynamic_states.as_ptr(),
+        };
+
+        let graphic_pipeline_create_infos = [vk::GraphicsPipelineCreateInfo {
+            s_type: vk::StructureType::GRAPHICS_PIPELINE_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: vk::PipelineCreateFlags::empty(),
+            stage_count: shader_stages.len() as u32,
+            p_stages: shader_stages.as_ptr(),
+            p_vertex_input_state: &vertex_input_state_create_info,
+            p_input_assembly_state: &vertex_input_assembly_state_info,
+            p_tessellation_state: ptr::null(),
+            p_viewport_state: &viewport_state_create_info,
+            p_rasterization_state: &rasterization_statue_create_info,
+            p_multisample_state: &multisample_state_create_info,
+            p_depth_stencil_state: &depth_state_create_info,
+            p_color_blend_state: &color_blend_state,
+            p_dynamic_state: if self.dynamic_states.is_empty() {
+                ptr::null()
+            } else {
+                &dynamic_state_create_info
+            },
+            layout: self.layout,
+            render_pass: self.render_pass,
+            subpass: self.subpass,
+            base_pipeline_handle: vk::Pipeline::null(),
+            base_pipeline_index: -1,
+        }];
+
+        let graphics_pipelines = unsafe {
+            device
+                .logical_device
+                .create_graphics_pipelines(device.pipeline_cache, &graphic_pipeline_create_infos, None)
+                .expect("Failed to create Graphics Pipeline!.")
+        };
+
+        unsafe {
+            for &shader_module in shader_modules.iter() {
+                device.logical_device.destroy_shader_module(shader_module, None);
+            }
+        }
+
+        graphics_pipelines[0]
+    }
+}
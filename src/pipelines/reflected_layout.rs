@@ -0,0 +1,322 @@
+#![allow(dead_code)]
+
+use crate::vk_assist;
+use std::sync::Arc;
+
+use ash::version::DeviceV1_0;
+use ash::vk;
+
+use std::ptr;
+
+use vk_assist::spirv_reflect::{ReflectedBinding, ReflectedInput, ScalarKind, ShaderReflection};
+use vk_assist::types::descriptor_allocator::DescriptorLayoutCache;
+use vk_assist::types::vulkan_device::*;
+
+/// The layout a set of shader stages needs, merged from their reflections.
+#[derive(Clone, Debug)]
+pub struct PipelineInterface {
+    /// Sorted by set, then binding. A binding used by several stages appears once with their stage flags combined.
+    pub bindings: Vec<ReflectedBinding>,
+    /// Stages that read push constants, and the size of the largest block.
+    pub push_constants: Option<(vk::ShaderStageFlags, u32)>,
+    pub vertex_inputs: Vec<ReflectedInput>,
+}
+
+/// Scalar kind and component count of a vertex attribute format, for the formats vertex layouts here use.
+fn format_components(format: vk::Format) -> Option<(ScalarKind, u32)> {
+    Some(match format {
+        vk::Format::R32_SFLOAT => (ScalarKind::Float, 1),
+        vk::Format::R32G32_SFLOAT => (ScalarKind::Float, 2),
+        vk::Format::R32G32B32_SFLOAT => (ScalarKind::Float, 3),
+        vk::Format::R32G32B32A32_SFLOAT => (ScalarKind::Float, 4),
+        vk::Format::R8G8B8A8_UNORM | vk::Format::R16G16B16A16_SFLOAT => (ScalarKind::Float, 4),
+        vk::Format::R32_UINT => (ScalarKind::Uint, 1),
+        vk::Format::R32G32_UINT => (ScalarKind::Uint, 2),
+        vk::Format::R32G32B32_UINT => (ScalarKind::Uint, 3),
+        vk::Format::R32G32B32A32_UINT | vk::Format::R16G16B16A16_UINT | vk::Format::R8G8B8A8_UINT => (ScalarKind::Uint, 4),
+        vk::Format::R32_SINT => (ScalarKind::Sint, 1),
+        vk::Format::R32G32_SINT => (ScalarKind::Sint, 2),
+        vk::Format::R32G32B32_SINT => (ScalarKind::Sint, 3),
+        vk::Format::R32G32B32A32_SINT => (ScalarKind::Sint, 4),
+        _ => return None,
+    })
+}
+
+impl PipelineInterface {
+    /// Merges the reflections of a pipeline's stages. Fails if two stages declare the same binding differently.
+    pub fn from_stages(stages: &[&ShaderReflection]) -> Result<PipelineInterface, String> {
+        let mut bindings: Vec<ReflectedBinding> = vec![];
+        let mut push_constants: Option<(vk::ShaderStageFlags, u32)> = None;
+        let mut vertex_inputs = vec![];
+
+        for stage in stages.iter() {
+            for binding in stage.bindings.iter() {
+                match bindings.iter_mut().find(|b| b.set == binding.set && b.binding == binding.binding) {
+                    Some(existing) => {
+                        if existing.descriptor_type != binding.descriptor_type || existing.count != binding.count {
+                            return Err(format!(
+                                "set {} binding {} is {:?}[{}] in {:?} but {:?}[{}] in {:?}",
+                                binding.set,
+                                binding.binding,
+                                existing.descriptor_type,
+                                existing.count,
+                                existing.stage_flags,
+                                binding.descriptor_type,
+                                binding.count,
+                                binding.stage_flags
+                            ));
+                        }
+                        existing.stage_flags |= binding.stage_flags;
+                    }
+                    None => bindings.push(binding.clone()),
+                }
+            }
+            if stage.push_constant_size > 0 {
+                let (flags, size) = push_constants.unwrap_or((vk::ShaderStageFlags::empty(), 0));
+                push_constants = Some((flags | stage.stage, size.max(stage.push_constant_size)));
+            }
+            if stage.stage == vk::ShaderStageFlags::VERTEX {
+                vertex_inputs = stage.inputs.clone();
+            }
+        }
+        bindings.sort_by_key(|binding| (binding.set, binding.binding));
+
+        Ok(PipelineInterface {
+            bindings,
+            push_constants,
+            vertex_inputs,
+        })
+    }
+
+    /// Number of descriptor sets the pipeline layout needs, counting unused sets below the highest one.
+    pub fn set_count(&self) -> u32 {
+        self.bindings.iter().map(|binding| binding.set + 1).max().unwrap_or(0)
+    }
+
+    /// Fails unless the pipeline uses exactly `count` descriptor sets.
+    pub fn check_set_count(&self, count: u32) -> Result<(), String> {
+        if self.set_count() != count {
+            return Err(format!("shaders use {} descriptor sets, the pipeline provides {}", self.set_count(), count));
+        }
+        Ok(())
+    }
+
+    /// Marks a uniform buffer binding as bound with a dynamic offset, which reflection can't tell from the shader.
+    pub fn use_dynamic_offset(&mut self, set: u32, binding: u32) {
+        let uniform = self
+            .bindings
+            .iter_mut()
+            .find(|b| b.set == set && b.binding == binding && b.descriptor_type == vk::DescriptorType::UNIFORM_BUFFER);
+        if let Some(uniform) = uniform {
+            uniform.descriptor_type = vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC;
+        }
+    }
+
+    pub fn set_bindings(&self, set: u32) -> Vec<&ReflectedBinding> {
+        self.bindings.iter().filter(|binding| binding.set == set).collect()
+    }
+
+    /// Fails unless `set` is declared exactly the same way in both interfaces, so a set layout built for one can be
+    /// bound with the other.
+    pub fn check_set_matches(&self, other: &PipelineInterface, set: u32) -> Result<(), String> {
+        let ours = self.set_bindings(set);
+        let theirs = other.set_bindings(set);
+        let same = ours.len() == theirs.len()
+            && ours
+                .iter()
+                .zip(theirs.iter())
+                .all(|(a, b)| a.binding == b.binding && a.descriptor_type == b.descriptor_type && a.count == b.count && a.stage_flags == b.stage_flags);
+        if same {
+            Ok(())
+        } else {
+            Err(format!("descriptor set {} changed from {:?} to {:?}", set, theirs, ours))
+        }
+    }
+
+    /// Fails if the vertex shader reads a location `attributes` don't provide, or provide with another type.
+    pub fn check_vertex_input(&self, attributes: &[vk::VertexInputAttributeDescription]) -> Result<(), String> {
+        for input in self.vertex_inputs.iter() {
+            let attribute = attributes
+                .iter()
+                .find(|attribute| attribute.location == input.location)
+                .ok_or_else(|| format!("vertex input at location {} is missing from the vertex layout", input.location))?;
+            let (kind, components) =
+                format_components(attribute.format).ok_or_else(|| format!("unknown vertex format {:?} at location {}", attribute.format, input.location))?;
+            if kind != input.kind || components != input.components {
+                return Err(format!(
+                    "vertex input at location {} is {:?}x{} in the shader but {:?} in the vertex layout",
+                    input.location, input.kind, input.components, attribute.format
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Fails unless the push constant block is exactly `size` bytes, the size of the struct pushed to it.
+    pub fn check_push_constants(&self, size: usize) -> Result<(), String> {
+        let block_size = self.push_constants.map_or(0, |(_, block_size)| block_size as usize);
+        if block_size != size {
+            return Err(format!("push constant block is {} bytes but the pushed struct is {}", block_size, size));
+        }
+        Ok(())
+    }
+
+    pub fn set_layout_bindings(&self, set: u32) -> Vec<vk::DescriptorSetLayoutBinding> {
+        self.set_bindings(set)
+            .iter()
+            .map(|binding| vk::DescriptorSetLayoutBinding {
+                binding: binding.binding,
+                descriptor_type: binding.descriptor_type,
+                descriptor_count: binding.count,
+                stage_flags: binding.stage_flags,
+                p_immutable_samplers: ptr::null(),
+            })
+            .collect()
+    }
+
+    /// Layout of `set`, shared with every other set declared the same way. `layouts` owns it.
+    pub fn set_layout(&self, layouts: &mut DescriptorLayoutCache, set: u32) -> vk::DescriptorSetLayout {
+        layouts.get(&self.set_layout_bindings(set))
+    }
+
+    /// Pipeline layout over `set_layouts`, one per set in order, with the reflected push constant range.
+    pub fn create_pipeline_layout(&self, device: Arc<VulkanDevice>, set_layouts: &[vk::DescriptorSetLayout]) -> vk::PipelineLayout {
+        assert_eq!(
+            set_layouts.len() as u32,
+            self.set_count(),
+            "Pipeline layout needs one set layout per descriptor set"
+        );
+        let push_constant_ranges: Vec<vk::PushConstantRange> = self
+            .push_constants
+            .iter()
+            .map(|&(stage_flags, size)| vk::PushConstantRange { stage_flags, offset: 0, size })
+            .collect();
+
+        let pipeline_layout_create_info = vk::PipelineLayoutCreateInfo {
+            s_type: vk::StructureType::PIPELINE_LAYOUT_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: vk::PipelineLayoutCreateFlags::empty(),
+            set_layout_count: set_layouts.len() as u32,
+            p_set_layouts: set_layouts.as_ptr(),
+            push_constant_range_count: push_constant_ranges.len() as u32,
+            p_push_constant_ranges: push_constant_ranges.as_ptr(),
+        };
+
+        unsafe {
+            device
+                .logical_device
+                .create_pipeline_layout(&pipeline_layout_create_info, None)
+                .expect("Failed to create pipeline layout!")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vk_assist::spirv_reflect::reflect;
+    use vk_assist::structures::MeshVertex;
+
+    fn reflect_file(name: &str) -> ShaderReflection {
+        reflect(&std::fs::read(format!("shaders/{}.spv", name)).unwrap()).unwrap()
+    }
+
+    fn stage(stage: vk::ShaderStageFlags, bindings: &[(u32, u32, vk::DescriptorType)], push_constant_size: u32) -> ShaderReflection {
+        ShaderReflection {
+            stage,
+            bindings: bindings
+                .iter()
+                .map(|&(set, binding, descriptor_type)| ReflectedBinding {
+                    set,
+                    binding,
+                    descriptor_type,
+                    count: 1,
+                    stage_flags: stage,
+                    name: String::new(),
+                })
+                .collect(),
+            push_constant_size,
+            inputs: vec![],
+        }
+    }
+
+    #[test]
+    fn static_shaders_merge_and_fit_the_mesh_vertex() {
+        let (vertex, fragment) = (reflect_file("pushconst.vert"), reflect_file("pushconst.frag"));
+        let interface = PipelineInterface::from_stages(&[&vertex, &fragment]).unwrap();
+        assert_eq!(interface.set_count(), 2);
+        assert!(interface.check_set_count(2).is_ok());
+        assert!(interface.check_set_count(1).is_err());
+        assert_eq!(interface.push_constants, Some((vk::ShaderStageFlags::VERTEX, 64)));
+        assert!(interface.check_push_constants(64).is_ok());
+        assert!(interface.check_vertex_input(&MeshVertex::get_attribute_descriptions()).is_ok());
+        assert_eq!(interface.set_bindings(0)[0].stage_flags, vk::ShaderStageFlags::VERTEX);
+    }
+
+    #[test]
+    fn shared_bindings_combine_their_stages() {
+        let vertex = stage(vk::ShaderStageFlags::VERTEX, &[(0, 0, vk::DescriptorType::UNIFORM_BUFFER)], 64);
+        let fragment = stage(
+            vk::ShaderStageFlags::FRAGMENT,
+            &[(0, 1, vk::DescriptorType::SAMPLER), (0, 0, vk::DescriptorType::UNIFORM_BUFFER)],
+            68,
+        );
+        let interface = PipelineInterface::from_stages(&[&vertex, &fragment]).unwrap();
+        assert_eq!(interface.bindings.len(), 2);
+        assert_eq!(interface.bindings[0].stage_flags, vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT);
+        assert_eq!(interface.bindings[1].binding, 1);
+        // One range covering the largest block, for every stage that pushes.
+        assert_eq!(
+            interface.push_constants,
+            Some((vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT, 68))
+        );
+    }
+
+    #[test]
+    fn binding_type_conflicts_are_rejected() {
+        let vertex = stage(vk::ShaderStageFlags::VERTEX, &[(1, 2, vk::DescriptorType::UNIFORM_BUFFER)], 0);
+        let fragment = stage(vk::ShaderStageFlags::FRAGMENT, &[(1, 2, vk::DescriptorType::STORAGE_BUFFER)], 0);
+        let error = PipelineInterface::from_stages(&[&vertex, &fragment]).unwrap_err();
+        assert!(error.starts_with("set 1 binding 2 is UNIFORM_BUFFER[1]"), "{}", error);
+
+        let mut array = stage(vk::ShaderStageFlags::FRAGMENT, &[(1, 2, vk::DescriptorType::UNIFORM_BUFFER)], 0);
+        array.bindings[0].count = 4;
+        assert!(PipelineInterface::from_stages(&[&vertex, &array]).is_err());
+    }
+
+    #[test]
+    fn vertex_input_mismatches_are_rejected() {
+        let interface = PipelineInterface::from_stages(&[&reflect_file("pushconst.vert")]).unwrap();
+        let mut attributes = MeshVertex::get_attribute_descriptions();
+        attributes[2].format = vk::Format::R32G32B32_SFLOAT;
+        let error = interface.check_vertex_input(&attributes).unwrap_err();
+        assert!(error.starts_with("vertex input at location 2 is Floatx2"), "{}", error);
+        attributes[2].format = vk::Format::R32G32_UINT;
+        assert!(interface.check_vertex_input(&attributes).is_err());
+        assert!(interface.check_vertex_input(&attributes[..2]).unwrap_err().contains("missing"));
+    }
+
+    #[test]
+    fn push_constant_size_mismatches_are_rejected() {
+        let interface = PipelineInterface::from_stages(&[&reflect_file("tonemap.frag")]).unwrap();
+        assert!(interface.check_push_constants(40).is_ok());
+        assert_eq!(
+            interface.check_push_constants(44),
+            Err("push constant block is 40 bytes but the pushed struct is 44".to_string())
+        );
+        let without = PipelineInterface::from_stages(&[&reflect_file("fullscreen.vert")]).unwrap();
+        assert!(without.check_push_constants(0).is_ok());
+        assert!(without.check_push_constants(4).is_err());
+    }
+
+    #[test]
+    fn set_changes_are_caught() {
+        let interface = PipelineInterface::from_stages(&[&reflect_file("pushconst.frag")]).unwrap();
+        let bindless = PipelineInterface::from_stages(&[&reflect_file("bindless.frag")]).unwrap();
+        assert!(interface.check_set_matches(&bindless, 0).is_ok());
+        assert!(interface.check_set_matches(&bindless, 1).is_err());
+        let mut dynamic = PipelineInterface::from_stages(&[&reflect_file("pushconst.vert")]).unwrap();
+        dynamic.use_dynamic_offset(0, 0);
+        assert_eq!(dynamic.bindings[0].descriptor_type, vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC);
+    }
+}
@@ -0,0 +1,92 @@
+use crate::vk_assist;
+use std::sync::Arc;
+
+use ash::version::DeviceV1_0;
+use ash::vk;
+use nalgebra_glm::Mat4;
+
+use std::ptr;
+
+use vk_assist::structures::{MeshVertex, SkinVertex};
+use vk_assist::types::buffer as bfr;
+use vk_assist::types::descriptor_allocator::DescriptorAllocator;
+use vk_assist::types::vulkan_device::*;
+
+use super::pipeline_builder::GraphicsPipelineBuilder;
+
+/// Joint matrices that fit in one frame's joint buffer, shared by every skinned instance drawn that frame.
+pub const MAX_JOINT_MATRICES: usize = 4096;
+
+pub fn create_graphics_pipeline(
+    device: Arc<VulkanDevice>,
+    render_pass: vk::RenderPass,
+    pipeline_layout: vk::PipelineLayout,
+    msaa_samples: vk::SampleCountFlags,
+    shaders: (&[u8], &[u8]),
+) -> vk::Pipeline {
+    // Binding 0 is the regular mesh vertex, binding 1 the joints and weights running parallel to it.
+    GraphicsPipelineBuilder::new(pipeline_layout, render_pass)
+        .shader(vk::ShaderStageFlags::VERTEX, shaders.0)
+        .shader(vk::ShaderStageFlags::FRAGMENT, shaders.1)
+        .vertex_input(&MeshVertex::get_binding_descriptions(), &MeshVertex::get_attribute_descriptions())
+        .vertex_input(&SkinVertex::get_binding_descriptions(), &SkinVertex::get_attribute_descriptions())
+        .samples(msaa_samples)
+        .dynamic_states(&[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR])
+        .build(device)
+}
+
+/// One host visible joint buffer per swapchain image, so a frame in flight never sees the next frame's matrices.
+pub fn create_joint_buffers(device: Arc<VulkanDevice>, swapchain_image_count: usize) -> Vec<bfr::Buffer> {
+    let buffer_size = std::mem::size_of::<Mat4>() * MAX_JOINT_MATRICES;
+    let device_memory_properties = device.get_physical_device_memory_properties();
+    let mut joint_buffers = vec![];
+
+    for _ in 0..swapchain_image_count {
+        let joint_buffer = bfr::create_buffer(
+            device.clone(),
+            buffer_size as u64,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            &device_memory_properties,
+        );
+        joint_buffers.push(joint_buffer);
+    }
+
+    joint_buffers
+}
+
+pub fn create_descriptor_sets(
+    device: Arc<VulkanDevice>,
+    descriptors: &mut DescriptorAllocator,
+    joint_set_layout: vk::DescriptorSetLayout,
+    joint_buffers: &[bfr::Buffer],
+) -> Vec<vk::DescriptorSet> {
+    let layouts = vec![joint_set_layout; joint_buffers.len()];
+
+    let descriptor_sets = descriptors.allocate(&layouts);
+
+    for (i, &descriptor_set) in descriptor_sets.iter().enumerate() {
+        let descriptor_buffer_infos = [vk::DescriptorBufferInfo {
+            buffer: joint_buffers[i].buffer,
+            offset: 0,
+            range: vk::WHOLE_SIZE,
+        }];
+        let descriptor_write_sets = [vk::WriteDescriptorSet {
+            // joint matrices
+            s_type: vk::StructureType::WRITE_DESCRIPTOR_SET,
+            p_next: ptr::null(),
+            dst_set: descriptor_set,
+            dst_binding: 0,
+            dst_array_element: 0,
+            descriptor_count: 1,
+            descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+            p_image_info: ptr::null(),
+            p_buffer_info: descriptor_buffer_infos.as_ptr(),
+            p_texel_buffer_view: ptr::null(),
+        }];
+        unsafe {
+            device.logical_device.update_descriptor_sets(&descriptor_write_sets, &[]);
+        }
+    }
+    descriptor_sets
+}
@@ -0,0 +1,235 @@
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use nalgebra_glm::{Mat4, Vec2, Vec3, Vec4};
+
+use crate::vk_assist;
+use crate::vk_model;
+use vk_assist::mesh_processing as mp;
+use vk_assist::mesh_processing::NormalMode;
+use vk_assist::structures::{MeshVertex, SkinVertex};
+use vk_model::animation::{AnimationClip, Channel, ChannelValues, Interpolation};
+use vk_model::bounds::Aabb;
+use vk_model::mesh_data::{MeshData, Submesh};
+use vk_model::skeleton::{Joint, JointTransform, Skeleton, Skin};
+
+fn mat4_from_columns(columns: &[[f32; 4]; 4]) -> Mat4 {
+    let flat: Vec<f32> = columns.iter().flat_map(|column| column.iter().cloned()).collect();
+    Mat4::from_column_slice(&flat)
+}
+
+fn transform_of(node: &gltf::Node) -> JointTransform {
+    let (t, r, s) = node.transform().decomposed();
+    JointTransform {
+        translation: Vec3::new(t[0], t[1], t[2]),
+        rotation: Vec4::new(r[0], r[1], r[2], r[3]),
+        scale: Vec3::new(s[0], s[1], s[2]),
+    }
+}
+
+/// Loads the first skinned mesh of a glTF file (.gltf or .glb) with its skeleton and every animation that targets it.
+/// Each primitive becomes a submesh. Missing normals are generated and tangents always are; the mesh is not welded or
+/// reordered since the skin data has to stay parallel to the vertices. Unreadable files and files without a skinned mesh
+/// are errors.
+pub fn load_skinned_gltf(path: &Path) -> Result<(MeshData, Skin), String> {
+    let (document, buffers, _images) = gltf::import(path).map_err(|err| format!("Failed to load glTF file {:?}: {}", path, err))?;
+    let buffer_data = |buffer: gltf::Buffer| Some(&buffers[buffer.index()].0[..]);
+
+    let node = document
+        .nodes()
+        .find(|node| node.mesh().is_some() && node.skin().is_some())
+        .ok_or_else(|| format!("glTF file {:?} has no skinned mesh", path))?;
+    let mesh = node.mesh().unwrap();
+    let skin = node.skin().unwrap();
+
+    let mut vertices: Vec<MeshVertex> = vec![];
+    let mut skin_vertices: Vec<SkinVertex> = vec![];
+    let mut indices: Vec<u32> = vec![];
+    let mut submeshes = vec![];
+    let mut needs_normals = false;
+
+    for primitive in mesh.primitives() {
+        let reader = primitive.reader(buffer_data);
+        let base_vertex = vertices.len() as u32;
+        let positions: Vec<[f32; 3]> = match reader.read_positions() {
+            Some(positions) => positions.collect(),
+            None => return Err(format!("glTF file {:?} has a primitive without positions", path)),
+        };
+        let normals: Option<Vec<[f32; 3]>> = reader.read_normals().map(|normals| normals.collect());
+        let uvs: Option<Vec<[f32; 2]>> = reader.read_tex_coords(0).map(|uvs| uvs.into_f32().collect());
+        let joints: Option<Vec<[u16; 4]>> = reader.read_joints(0).map(|joints| joints.into_u16().collect());
+        let weights: Option<Vec<[f32; 4]>> = reader.read_weights(0).map(|weights| weights.into_f32().collect());
+        needs_normals |= normals.is_none();
+
+        for (i, p) in positions.iter().enumerate() {
+            // glTF puts v = 0 at the top of the image, textures here are loaded flipped so it is at the bottom.
+            let uv = uvs.as_ref().map_or(Vec2::zeros(), |uvs| Vec2::new(uvs[i][0], 1.0 - uvs[i][1]));
+            let mut vertex = MeshVertex::new(Vec3::new(p[0], p[1], p[2]), Vec3::new(1.0, 1.0, 1.0), uv);
+            if let Some(normals) = normals.as_ref() {
+                vertex.normal = Vec3::new(normals[i][0], normals[i][1], normals[i][2]).normalize();
+            }
+            vertices.push(vertex);
+
+            let w = weights.as_ref().map_or([1.0, 0.0, 0.0, 0.0], |weights| weights[i]);
+            let total = w[0] + w[1] + w[2] + w[3];
+            skin_vertices.push(SkinVertex {
+                joints: joints.as_ref().map_or([0; 4], |joints| joints[i]),
+                weights: if total > 0.0 {
+                    Vec4::new(w[0], w[1], w[2], w[3]) / total
+                } else {
+                    Vec4::new(1.0, 0.0, 0.0, 0.0)
+                },
+            });
+        }
+
+        let first_index = indices.len() as u32;
+        match reader.read_indices() {
+            Some(read) => indices.extend(read.into_u32().map(|index| index + base_vertex)),
+            None => indices.extend(base_vertex..vertices.len() as u32),
+        }
+        submeshes.push(Submesh {
+            first_index,
+            index_count: indices.len() as u32 - first_index,
+            material: primitive.material().index().map(|index| index as u32),
+        });
+    }
+
+    if needs_normals {
+        let source = mp::generate_normals(&mut vertices, &mut indices, NormalMode::default());
+        skin_vertices = source.iter().map(|&i| skin_vertices[i as usize]).collect();
+    }
+    let source = mp::generate_tangents(&mut vertices, &mut indices);
+    skin_vertices = source.iter().map(|&i| skin_vertices[i as usize]).collect();
+
+    let skeleton = load_skeleton(&document, &skin, &buffers);
+    let joint_of_node: HashMap<usize, usize> = skin.joints().enumerate().map(|(joint, node)| (node.index(), joint)).collect();
+    let clips = document.animations().map(|animation| load_clip(&animation, &joint_of_node, &buffers)).collect();
+
+    let mesh_data = MeshData {
+        bounds: Aabb::from_vertices(&vertices),
+        vertices,
+        indices,
+        submeshes,
+        materials: vec![],
+        lods: vec![],
+    };
+    let skin = Skin::new(&mesh_data.vertices, skin_vertices, skeleton, clips);
+    Ok((mesh_data, skin))
+}
+
+fn load_skeleton(document: &gltf::Document, skin: &gltf::Skin, buffers: &[gltf::buffer::Data]) -> Skeleton {
+    let mut parent_of: HashMap<usize, usize> = HashMap::new();
+    for node in document.nodes() {
+        for child in node.children() {
+            parent_of.insert(child.index(), node.index());
+        }
+    }
+    let nodes: Vec<gltf::Node> = document.nodes().collect();
+    let joint_nodes: Vec<usize> = skin.joints().map(|node| node.index()).collect();
+    let inverse_binds: Vec<Mat4> = match skin.reader(|buffer| Some(&buffers[buffer.index()].0[..])).read_inverse_bind_matrices() {
+        Some(matrices) => matrices.map(|m| mat4_from_columns(&m)).collect(),
+        None => vec![Mat4::identity(); joint_nodes.len()],
+    };
+
+    let joints = joint_nodes
+        .iter()
+        .enumerate()
+        .map(|(joint, &node_index)| {
+            // Walk up to the nearest ancestor that is a joint, collecting the transforms of any plain nodes in between.
+            let mut base = Mat4::identity();
+            let mut parent = None;
+            let mut ancestor = parent_of.get(&node_index).cloned();
+            while let Some(index) = ancestor {
+                if let Some(parent_joint) = joint_nodes.iter().position(|&n| n == index) {
+                    parent = Some(parent_joint);
+                    break;
+                }
+                base = mat4_from_columns(&nodes[index].transform().matrix()) * base;
+                ancestor = parent_of.get(&index).cloned();
+            }
+            let node = &nodes[node_index];
+            Joint {
+                name: node.name().map_or_else(|| format!("joint{}", joint), String::from),
+                parent,
+                inverse_bind: inverse_binds[joint],
+                rest: transform_of(node),
+                base,
+            }
+        })
+        .collect();
+
+    Skeleton::new(joints)
+}
+
+fn load_clip(animation: &gltf::Animation, joint_of_node: &HashMap<usize, usize>, buffers: &[gltf::buffer::Data]) -> AnimationClip {
+    let mut channels = vec![];
+    let mut duration: f32 = 0.0;
+    for channel in animation.channels() {
+        // Channels on nodes outside the skeleton (cameras, the mesh node itself) don't affect skinning.
+        let joint = match joint_of_node.get(&channel.target().node().index()) {
+            Some(&joint) => joint,
+            None => continue,
+        };
+        let reader = channel.reader(|buffer| Some(&buffers[buffer.index()].0[..]));
+        let times: Vec<f32> = match reader.read_inputs() {
+            Some(inputs) => inputs.collect(),
+            None => continue,
+        };
+        let values = match reader.read_outputs() {
+            Some(gltf::animation::util::ReadOutputs::Translations(values)) => ChannelValues::Translation(values.map(|v| Vec3::new(v[0], v[1], v[2])).collect()),
+            Some(gltf::animation::util::ReadOutputs::Rotations(values)) => {
+                ChannelValues::Rotation(values.into_f32().map(|q| Vec4::new(q[0], q[1], q[2], q[3])).collect())
+            }
+            Some(gltf::animation::util::ReadOutputs::Scales(values)) => ChannelValues::Scale(values.map(|v| Vec3::new(v[0], v[1], v[2])).collect()),
+            _ => continue,
+        };
+        duration = times.iter().cloned().fold(duration, f32::max);
+        channels.push(Channel {
+            joint,
+            interpolation: match channel.sampler().interpolation() {
+                gltf::animation::Interpolation::Step => Interpolation::Step,
+                gltf::animation::Interpolation::Linear => Interpolation::Linear,
+                gltf::animation::Interpolation::CubicSpline => Interpolation::CubicSpline,
+            },
+            times,
+            values,
+        });
+    }
+
+    AnimationClip {
+        name: animation.name().map_or_else(|| format!("animation{}", animation.index()), String::from),
+        duration,
+        channels,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_nodes_between_joints_fold_into_base() {
+        let json = r#"{
+            "asset": { "version": "2.0" },
+            "nodes": [
+                { "name": "hip", "children": [1] },
+                { "name": "offset", "translation": [0.0, 2.0, 0.0], "children": [2] },
+                { "name": "knee", "rotation": [0.0, 0.0, 0.38268343, 0.9238795] }
+            ],
+            "skins": [{ "joints": [0, 2] }]
+        }"#;
+        let document = gltf::Gltf::from_slice(json.as_bytes()).unwrap().document;
+        let skin = document.skins().next().unwrap();
+        let skeleton = load_skeleton(&document, &skin, &[]);
+
+        let knee = &skeleton.joints[1];
+        assert_eq!(knee.parent, Some(0));
+        assert_eq!(knee.base, nalgebra_glm::translation(&Vec3::new(0.0, 2.0, 0.0)));
+        // The local transform stays the node's own, so a channel overwriting it leaves the offset in place.
+        assert_eq!(knee.rest.translation, Vec3::zeros());
+        assert!((knee.rest.rotation.z - 0.38268343).abs() < 1e-6);
+        assert_eq!(skeleton.joints[0].base, Mat4::identity());
+    }
+}
@@ -0,0 +1,475 @@
+#![allow(dead_code)]
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use nalgebra_glm::{Vec2, Vec3, Vec4};
+
+use crate::app::tools::hash_bytes;
+use crate::vk_assist::structures::MeshVertex;
+use crate::vk_model::bounds::Aabb;
+use crate::vk_model::mesh_data::{MaterialRef, MeshData, MeshLod, Submesh};
+
+// Layout, all little endian:
+//   header    magic[8], format version u32, importer version u32, source hash u64, settings hash u64,
+//             vertex stride u32, vertex count u32, index count u32, submesh count u32, material count u32,
+//             lod count u32, bounds min f32x3, bounds max f32x3
+//   vertices  vertex count * MeshVertex as f32s, in field order
+//   indices   index count * u32
+//   submeshes first index u32, index count u32, material u32 (u32::MAX for none)
+//   materials name, diffuse texture; each a u32 byte length followed by utf-8
+//   lods      error f32, submesh count u32, then that many submeshes as above
+pub const MESH_CACHE_MAGIC: [u8; 8] = *b"ASHMESH\0";
+/// Bump whenever the layout above changes.
+pub const MESH_CACHE_FORMAT_VERSION: u32 = 2;
+pub const MESH_CACHE_EXTENSION: &str = "bmesh";
+
+const FLOATS_PER_VERTEX: usize = 15;
+const NO_MATERIAL: u32 = u32::MAX;
+
+/// Everything a baked mesh depends on. A cache file is only used when all three match.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CacheKey {
+    pub source_hash: u64,
+    pub settings_hash: u64,
+    pub importer_version: u32,
+}
+
+impl CacheKey {
+    /// The source hash covers the file itself and, for an OBJ, every material library it references, so editing an
+    /// MTL rebuilds the mesh too.
+    pub fn for_source(source_path: &Path, settings_hash: u64, importer_version: u32) -> io::Result<CacheKey> {
+        let mut source = fs::read(source_path)?;
+        if is_obj(source_path) {
+            for library in material_libraries(&String::from_utf8_lossy(&source)) {
+                let library_path = library_path(source_path, &library);
+                source.extend_from_slice(library.as_bytes());
+                source.push(0);
+                // A missing library still changes the key, the import differs once it shows up.
+                match fs::read(&library_path) {
+                    Ok(contents) => {
+                        source.push(1);
+                        source.extend_from_slice(&contents);
+                    }
+                    Err(_) => source.push(0),
+                }
+            }
+        }
+        Ok(CacheKey {
+            source_hash: hash_bytes(&source),
+            settings_hash,
+            importer_version,
+        })
+    }
+}
+
+fn is_obj(source_path: &Path) -> bool {
+    source_path.extension().and_then(|e| e.to_str()).unwrap_or("").eq_ignore_ascii_case("obj")
+}
+
+/// Where an OBJ's `mtllib` looks for `library`: relative to the OBJ.
+fn library_path(obj_path: &Path, library: &str) -> PathBuf {
+    obj_path.parent().map_or(PathBuf::from(library), |parent| parent.join(library))
+}
+
+/// Files other than `source_path` itself that importing it reads: the material libraries of an OBJ, whether they
+/// exist yet or not. Empty when the source can't be read.
+pub fn source_dependencies(source_path: &Path) -> Vec<PathBuf> {
+    if !is_obj(source_path) {
+        return vec![];
+    }
+    match fs::read(source_path) {
+        Ok(source) => material_libraries(&String::from_utf8_lossy(&source))
+            .iter()
+            .map(|library| library_path(source_path, library))
+            .collect(),
+        Err(_) => vec![],
+    }
+}
+
+/// File names from the `mtllib` lines of an OBJ, in order.
+fn material_libraries(obj_source: &str) -> Vec<String> {
+    let mut libraries = vec![];
+    for line in obj_source.lines() {
+        let mut words = line.split_whitespace();
+        if words.next() == Some("mtllib") {
+            libraries.extend(words.map(String::from));
+        }
+    }
+    libraries
+}
+
+/// Where the baked copy of `source_path` lives: next to it, with the cache extension appended.
+pub fn cache_path(source_path: &Path) -> PathBuf {
+    let mut file_name = source_path.file_name().map(|name| name.to_os_string()).unwrap_or_default();
+    file_name.push(".");
+    file_name.push(MESH_CACHE_EXTENSION);
+    source_path.with_file_name(file_name)
+}
+
+/// Returns the baked mesh if the cache file exists, is well formed and was built from the same key.
+pub fn read_mesh_cache(cache_path: &Path, key: &CacheKey) -> Option<MeshData> {
+    let bytes = fs::read(cache_path).ok()?;
+    match decode_mesh(&bytes, key) {
+        Ok(mesh) => Some(mesh),
+        Err(reason) => {
+            println!("Ignoring mesh cache {:?}: {}", cache_path, reason);
+            None
+        }
+    }
+}
+
+/// Writes through a temporary file so a crash mid-write never leaves a truncated cache behind.
+pub fn write_mesh_cache(cache_path: &Path, key: &CacheKey, mesh: &MeshData) -> io::Result<()> {
+    let temp_path = cache_path.with_extension(format!("{}.tmp", MESH_CACHE_EXTENSION));
+    fs::write(&temp_path, encode_mesh(key, mesh))?;
+    fs::rename(&temp_path, cache_path)
+}
+
+pub fn encode_mesh(key: &CacheKey, mesh: &MeshData) -> Vec<u8> {
+    let mut writer = ByteWriter {
+        bytes: Vec::with_capacity(128 + mesh.vertices.len() * std::mem::size_of::<MeshVertex>() + mesh.indices.len() * 4),
+    };
+
+    writer.bytes.extend_from_slice(&MESH_CACHE_MAGIC);
+    writer.u32(MESH_CACHE_FORMAT_VERSION);
+    writer.u32(key.importer_version);
+    writer.u64(key.source_hash);
+    writer.u64(key.settings_hash);
+    writer.u32(std::mem::size_of::<MeshVertex>() as u32);
+    writer.u32(mesh.vertices.len() as u32);
+    writer.u32(mesh.indices.len() as u32);
+    writer.u32(mesh.submeshes.len() as u32);
+    writer.u32(mesh.materials.len() as u32);
+    writer.u32(mesh.lods.len() as u32);
+    writer.vec3(&mesh.bounds.min);
+    writer.vec3(&mesh.bounds.max);
+
+    for v in mesh.vertices.iter() {
+        writer.vec3(&v.pos);
+        writer.vec3(&v.color);
+        writer.f32(v.uv.x);
+        writer.f32(v.uv.y);
+        writer.vec3(&v.normal);
+        writer.f32(v.tangent.x);
+        writer.f32(v.tangent.y);
+        writer.f32(v.tangent.z);
+        writer.f32(v.tangent.w);
+    }
+    for &index in mesh.indices.iter() {
+        writer.u32(index);
+    }
+    for submesh in mesh.submeshes.iter() {
+        writer.submesh(submesh);
+    }
+    for material in mesh.materials.iter() {
+        writer.string(&material.name);
+        writer.string(&material.diffuse_texture);
+    }
+    for lod in mesh.lods.iter() {
+        writer.f32(lod.error);
+        writer.u32(lod.submeshes.len() as u32);
+        for submesh in lod.submeshes.iter() {
+            writer.submesh(submesh);
+        }
+    }
+
+    writer.bytes
+}
+
+pub fn decode_mesh(bytes: &[u8], key: &CacheKey) -> Result<MeshData, String> {
+    let mut reader = ByteReader { bytes, position: 0 };
+
+    if reader.take(MESH_CACHE_MAGIC.len())? != MESH_CACHE_MAGIC {
+        return Err(String::from("not a mesh cache file"));
+    }
+    let format_version = reader.u32()?;
+    if format_version != MESH_CACHE_FORMAT_VERSION {
+        return Err(format!("format version {} (expected {})", format_version, MESH_CACHE_FORMAT_VERSION));
+    }
+    let cached_key = CacheKey {
+        importer_version: reader.u32()?,
+        source_hash: reader.u64()?,
+        settings_hash: reader.u64()?,
+    };
+    if cached_key != *key {
+        return Err(String::from("stale (source, import settings or importer changed)"));
+    }
+    let vertex_stride = reader.u32()? as usize;
+    if vertex_stride != std::mem::size_of::<MeshVertex>() || vertex_stride != FLOATS_PER_VERTEX * 4 {
+        return Err(format!("vertex stride {} does not match MeshVertex", vertex_stride));
+    }
+
+    let vertex_count = reader.u32()? as usize;
+    let index_count = reader.u32()? as usize;
+    let submesh_count = reader.u32()? as usize;
+    let material_count = reader.u32()? as usize;
+    let lod_count = reader.u32()? as usize;
+    let bounds = Aabb {
+        min: reader.vec3()?,
+        max: reader.vec3()?,
+    };
+
+    // Refuse counts the file can't possibly hold before allocating for them.
+    if vertex_count * vertex_stride + index_count * 4 + submesh_count * 12 > reader.remaining() {
+        return Err(String::from("truncated"));
+    }
+
+    let mut vertices = Vec::with_capacity(vertex_count);
+    for _ in 0..vertex_count {
+        vertices.push(MeshVertex {
+            pos: reader.vec3()?,
+            color: reader.vec3()?,
+            uv: Vec2::new(reader.f32()?, reader.f32()?),
+            normal: reader.vec3()?,
+            tangent: Vec4::new(reader.f32()?, reader.f32()?, reader.f32()?, reader.f32()?),
+        });
+    }
+
+    let mut indices = Vec::with_capacity(index_count);
+    for _ in 0..index_count {
+        let index = reader.u32()?;
+        if index as usize >= vertex_count {
+            return Err(format!("index {} out of range", index));
+        }
+        indices.push(index);
+    }
+
+    let mut submeshes = Vec::with_capacity(submesh_count);
+    for _ in 0..submesh_count {
+        submeshes.push(reader.submesh(index_count)?);
+    }
+
+    let mut materials = Vec::with_capacity(material_count.min(reader.remaining()));
+    for _ in 0..material_count {
+        materials.push(MaterialRef {
+            name: reader.string()?,
+            diffuse_texture: reader.string()?,
+        });
+    }
+
+    let mut lods = Vec::with_capacity(lod_count.min(reader.remaining()));
+    for _ in 0..lod_count {
+        let error = reader.f32()?;
+        let lod_submesh_count = reader.u32()? as usize;
+        let mut lod_submeshes = Vec::with_capacity(lod_submesh_count.min(reader.remaining()));
+        for _ in 0..lod_submesh_count {
+            lod_submeshes.push(reader.submesh(index_count)?);
+        }
+        lods.push(MeshLod {
+            submeshes: lod_submeshes,
+            error,
+        });
+    }
+
+    Ok(MeshData {
+        vertices,
+        indices,
+        submeshes,
+        materials,
+        lods,
+        bounds,
+    })
+}
+
+struct ByteWriter {
+    bytes: Vec<u8>,
+}
+
+impl ByteWriter {
+    fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    fn f32(&mut self, value: f32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    fn vec3(&mut self, value: &Vec3) {
+        self.f32(value.x);
+        self.f32(value.y);
+        self.f32(value.z);
+    }
+    fn string(&mut self, value: &str) {
+        self.u32(value.len() as u32);
+        self.bytes.extend_from_slice(value.as_bytes());
+    }
+    fn submesh(&mut self, submesh: &Submesh) {
+        self.u32(submesh.first_index);
+        self.u32(submesh.index_count);
+        self.u32(submesh.material.unwrap_or(NO_MATERIAL));
+    }
+}
+
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn remaining(&self) -> usize {
+        self.bytes.len() - self.position
+    }
+    fn take(&mut self, count: usize) -> Result<&'a [u8], String> {
+        if count > self.remaining() {
+            return Err(String::from("truncated"));
+        }
+        let slice = &self.bytes[self.position..self.position + count];
+        self.position += count;
+        Ok(slice)
+    }
+    fn u32(&mut self) -> Result<u32, String> {
+        let mut raw = [0u8; 4];
+        raw.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(raw))
+    }
+    fn u64(&mut self) -> Result<u64, String> {
+        let mut raw = [0u8; 8];
+        raw.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(raw))
+    }
+    fn f32(&mut self) -> Result<f32, String> {
+        Ok(f32::from_bits(self.u32()?))
+    }
+    fn vec3(&mut self) -> Result<Vec3, String> {
+        Ok(Vec3::new(self.f32()?, self.f32()?, self.f32()?))
+    }
+    fn string(&mut self) -> Result<String, String> {
+        let length = self.u32()? as usize;
+        String::from_utf8(self.take(length)?.to_vec()).map_err(|_| String::from("invalid utf-8 in string"))
+    }
+    fn submesh(&mut self, index_count: usize) -> Result<Submesh, String> {
+        let first_index = self.u32()?;
+        let count = self.u32()?;
+        let material = self.u32()?;
+        if first_index as usize + count as usize > index_count {
+            return Err(String::from("submesh out of range"));
+        }
+        Ok(Submesh {
+            first_index,
+            index_count: count,
+            material: if material == NO_MATERIAL { None } else { Some(material) },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ash_test_mesh_cache_{}_{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn sample_mesh() -> MeshData {
+        let vertex = |x: f32| MeshVertex {
+            pos: Vec3::new(x, 1.0, 2.0),
+            color: Vec3::new(0.25, 0.5, 0.75),
+            uv: Vec2::new(x, 0.5),
+            normal: Vec3::new(0.0, 1.0, 0.0),
+            tangent: Vec4::new(1.0, 0.0, 0.0, -1.0),
+        };
+        let submesh = Submesh {
+            first_index: 0,
+            index_count: 3,
+            material: Some(0),
+        };
+        MeshData {
+            vertices: vec![vertex(0.0), vertex(1.0), vertex(2.0)],
+            indices: vec![0, 1, 2],
+            submeshes: vec![submesh],
+            materials: vec![MaterialRef {
+                name: String::from("metal"),
+                diffuse_texture: String::from("metal.png"),
+            }],
+            lods: vec![MeshLod {
+                submeshes: vec![Submesh { material: None, ..submesh }],
+                error: 0.5,
+            }],
+            bounds: Aabb {
+                min: Vec3::new(0.0, 1.0, 2.0),
+                max: Vec3::new(2.0, 1.0, 2.0),
+            },
+        }
+    }
+
+    #[test]
+    fn encode_decode_round_trip() {
+        let key = CacheKey {
+            source_hash: 1,
+            settings_hash: 2,
+            importer_version: 3,
+        };
+        let mesh = sample_mesh();
+        let decoded = decode_mesh(&encode_mesh(&key, &mesh), &key).unwrap();
+        assert_eq!(decoded.vertices.len(), mesh.vertices.len());
+        for (decoded, original) in decoded.vertices.iter().zip(mesh.vertices.iter()) {
+            assert_eq!((decoded.pos, decoded.color, decoded.uv), (original.pos, original.color, original.uv));
+            assert_eq!((decoded.normal, decoded.tangent), (original.normal, original.tangent));
+        }
+        assert_eq!(decoded.indices, mesh.indices);
+        assert_eq!(decoded.submeshes, mesh.submeshes);
+        assert_eq!(decoded.materials, mesh.materials);
+        assert_eq!(decoded.lods.len(), 1);
+        assert_eq!(decoded.lods[0].submeshes, mesh.lods[0].submeshes);
+        assert_eq!(decoded.bounds.max, mesh.bounds.max);
+    }
+
+    #[test]
+    fn stale_or_truncated_files_are_rejected() {
+        let key = CacheKey {
+            source_hash: 1,
+            settings_hash: 2,
+            importer_version: 3,
+        };
+        let bytes = encode_mesh(&key, &sample_mesh());
+        let other = CacheKey { settings_hash: 4, ..key };
+        assert!(decode_mesh(&bytes, &other).is_err());
+        assert!(decode_mesh(&bytes[..bytes.len() - 1], &key).is_err());
+        assert!(decode_mesh(b"not a mesh", &key).is_err());
+    }
+
+    #[test]
+    fn key_changes_with_the_material_library() {
+        let dir = temp_dir("mtl");
+        let obj = dir.join("cube.obj");
+        fs::write(&obj, "mtllib cube.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n").unwrap();
+
+        let missing = CacheKey::for_source(&obj, 0, 1).unwrap();
+        fs::write(dir.join("cube.mtl"), "newmtl red\nKd 1 0 0\n").unwrap();
+        let red = CacheKey::for_source(&obj, 0, 1).unwrap();
+        fs::write(dir.join("cube.mtl"), "newmtl red\nKd 1 0 0\nmap_Kd red.png\n").unwrap();
+        let textured = CacheKey::for_source(&obj, 0, 1).unwrap();
+        let again = CacheKey::for_source(&obj, 0, 1).unwrap();
+
+        fs::remove_dir_all(&dir).unwrap();
+        assert_ne!(missing, red);
+        assert_ne!(red, textured);
+        assert_eq!(textured, again);
+    }
+
+    #[test]
+    fn source_dependencies_are_the_obj_material_libraries() {
+        let dir = std::env::temp_dir().join(format!("ash_test_mesh_cache_deps_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let obj = dir.join("model.obj");
+        fs::write(&obj, "mtllib model.mtl\nmtllib sub/extra.mtl\nv 0 0 0\n").unwrap();
+        let ply = dir.join("model.ply");
+        fs::write(&ply, "ply\n").unwrap();
+
+        assert_eq!(source_dependencies(&obj), vec![dir.join("model.mtl"), dir.join("sub/extra.mtl")]);
+        assert!(source_dependencies(&ply).is_empty());
+        assert!(source_dependencies(&dir.join("missing.obj")).is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn material_libraries_reads_every_mtllib_line() {
+        let source = "# comment\nmtllib a.mtl b.mtl\nv 0 0 0\n  mtllib c.mtl\nusemtl a\n";
+        assert_eq!(material_libraries(source), vec!["a.mtl", "b.mtl", "c.mtl"]);
+    }
+}
@@ -0,0 +1,664 @@
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::env;
+use std::fmt::Write as FmtWrite;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use gltf::json;
+use json::validation::Checked::Valid;
+
+use nalgebra_glm::{Mat3, Mat4, Vec3, Vec4};
+
+use crate::app::instances::GInstance;
+use crate::vk_assist::structures::MeshVertex;
+use crate::vk_model::advanced_model::GFXModel;
+use crate::vk_model::bounds::Aabb;
+use crate::vk_model::mesh_data::{MaterialRef, MeshData, Submesh};
+
+/// How instance transforms end up in an exported scene. OBJ has no node hierarchy, so it always bakes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InstanceExport {
+    /// Vertices are transformed into world space and every instance gets its own copy of the mesh.
+    Baked,
+    /// Each distinct mesh is written once and instances become nodes carrying their model matrix.
+    Nodes,
+}
+
+/// A mesh as it sits in memory, borrowed for writing. Only the full level of detail is exported. Material textures are
+/// paths relative to the working directory, as the importers resolve them.
+pub struct ExportMesh<'a> {
+    pub name: String,
+    pub vertices: &'a [MeshVertex],
+    pub indices: &'a [u32],
+    pub submeshes: &'a [Submesh],
+    pub materials: &'a [MaterialRef],
+    pub transform: Mat4,
+}
+
+impl<'a> ExportMesh<'a> {
+    pub fn from_mesh_data(name: &str, mesh: &'a MeshData) -> ExportMesh<'a> {
+        ExportMesh {
+            name: name.to_string(),
+            vertices: &mesh.vertices,
+            indices: &mesh.indices,
+            submeshes: &mesh.submeshes,
+            materials: &mesh.materials,
+            transform: Mat4::identity(),
+        }
+    }
+
+    pub fn from_model(name: &str, model: &'a GFXModel) -> ExportMesh<'a> {
+        ExportMesh {
+            name: name.to_string(),
+            vertices: &model.vertices,
+            indices: &model.indices,
+            submeshes: &model.submeshes,
+            materials: &model.materials,
+            transform: Mat4::identity(),
+        }
+    }
+
+    pub fn with_transform(mut self, transform: Mat4) -> ExportMesh<'a> {
+        self.transform = transform;
+        self
+    }
+
+    /// The full level of detail on its own: the vertices its submeshes use, in first-use order, and each submesh's
+    /// indices into them. Vertices that only reduced levels of detail reference are left out. With `bake` the vertices
+    /// are moved by `transform`, flipping the winding when it mirrors so it stays counter-clockwise. Values that aren't
+    /// finite are written as 0, JSON has no way to store them in accessor bounds.
+    fn prepared(&self, bake: bool) -> (Vec<MeshVertex>, Vec<Vec<u32>>) {
+        let mut remap: HashMap<u32, u32> = HashMap::new();
+        let mut vertices = vec![];
+        let mut submesh_indices = vec![];
+        for submesh in self.submeshes.iter() {
+            let range = submesh.first_index as usize..(submesh.first_index + submesh.index_count) as usize;
+            let indices: Vec<u32> = self.indices[range]
+                .iter()
+                .map(|&index| {
+                    *remap.entry(index).or_insert_with(|| {
+                        vertices.push(finite_vertex(&self.vertices[index as usize]));
+                        vertices.len() as u32 - 1
+                    })
+                })
+                .collect();
+            submesh_indices.push(indices);
+        }
+        if !bake {
+            return (vertices, submesh_indices);
+        }
+
+        let m = self.transform;
+        let linear: Mat3 = m.fixed_slice::<nalgebra_glm::U3, nalgebra_glm::U3>(0, 0).into_owned();
+        let normal_matrix = linear.try_inverse().unwrap_or_else(Mat3::identity).transpose();
+        let normalize = |v: Vec3| if v.norm() > 0.0 { v.normalize() } else { v };
+        let vertices = vertices
+            .iter()
+            .map(|v| {
+                let p = m * Vec4::new(v.pos.x, v.pos.y, v.pos.z, 1.0);
+                let t = normalize(linear * v.tangent.xyz());
+                finite_vertex(&MeshVertex {
+                    pos: p.xyz() / p.w,
+                    normal: normalize(normal_matrix * v.normal),
+                    tangent: Vec4::new(t.x, t.y, t.z, v.tangent.w),
+                    ..*v
+                })
+            })
+            .collect();
+        if linear.determinant() < 0.0 {
+            for indices in submesh_indices.iter_mut() {
+                for triangle in indices.chunks_exact_mut(3) {
+                    triangle.swap(1, 2);
+                }
+            }
+        }
+        (vertices, submesh_indices)
+    }
+}
+
+fn finite_vertex(v: &MeshVertex) -> MeshVertex {
+    let finite = |x: f32| if x.is_finite() { x } else { 0.0 };
+    MeshVertex {
+        pos: v.pos.map(finite),
+        color: v.color.map(finite),
+        uv: v.uv.map(finite),
+        normal: v.normal.map(finite),
+        tangent: v.tangent.map(finite),
+    }
+}
+
+/// `target` relative to the directory `from_dir`, both relative to the working directory unless absolute. Used to keep
+/// texture references pointing at the same file from wherever the export is written.
+pub fn relative_path(target: &Path, from_dir: &Path) -> PathBuf {
+    let absolute = |path: &Path| {
+        let path = if path.is_absolute() {
+            path.to_path_buf()
+        } else {
+            env::current_dir().unwrap_or_default().join(path)
+        };
+        // Lexically, the files don't have to exist.
+        let mut normal = PathBuf::new();
+        for component in path.components() {
+            match component {
+                Component::CurDir => {}
+                Component::ParentDir => {
+                    normal.pop();
+                }
+                other => normal.push(other),
+            }
+        }
+        normal
+    };
+    let target = absolute(target);
+    let from_dir = absolute(from_dir);
+    let target_components: Vec<Component> = target.components().collect();
+    let from_components: Vec<Component> = from_dir.components().collect();
+    let common = target_components.iter().zip(from_components.iter()).take_while(|(a, b)| a == b).count();
+    if common == 0 {
+        // Nothing shared, e.g. another drive.
+        return target;
+    }
+    let mut relative = PathBuf::new();
+    for _ in common..from_components.len() {
+        relative.push("..");
+    }
+    for component in target_components[common..].iter() {
+        relative.push(component);
+    }
+    relative
+}
+
+/// A relative path with forward slashes, as both OBJ and glTF expect.
+fn portable_path(path: &Path) -> String {
+    path.components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Percent-encodes everything but unreserved characters and slashes, glTF URIs have to be valid URIs.
+fn uri_encode(path: &str) -> String {
+    let mut uri = String::new();
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => uri.push(byte as char),
+            _ => write!(uri, "%{:02X}", byte).unwrap(),
+        }
+    }
+    uri
+}
+
+/// Where a material's texture is, seen from `out_dir`.
+fn texture_reference(texture: &str, out_dir: &Path) -> String {
+    portable_path(&relative_path(Path::new(texture), out_dir))
+}
+
+fn instance_meshes(instances: &[GInstance]) -> Vec<ExportMesh<'_>> {
+    instances
+        .iter()
+        .enumerate()
+        .map(|(i, inst)| ExportMesh::from_model(&format!("instance{}", i), &inst.asset).with_transform(inst.model_matrix))
+        .collect()
+}
+
+/// Writes `mesh` as OBJ+MTL or glTF depending on the extension of `path`.
+pub fn export_mesh(path: &Path, mesh: ExportMesh) -> io::Result<()> {
+    export_meshes(path, &[mesh], InstanceExport::Baked)
+}
+
+/// Writes every instance with its model matrix, baked or as nodes, as OBJ+MTL or glTF depending on `path`.
+pub fn export_instances(path: &Path, instances: &[GInstance], mode: InstanceExport) -> io::Result<()> {
+    export_meshes(path, &instance_meshes(instances), mode)
+}
+
+/// Creates the directory of `path` if needed.
+pub fn export_meshes(path: &Path, meshes: &[ExportMesh], mode: InstanceExport) -> io::Result<()> {
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+    match extension.as_str() {
+        "obj" => write_obj(path, meshes),
+        "gltf" => write_gltf(path, meshes, mode),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("can't export to {:?}", path))),
+    }
+}
+
+/// OBJ with the usual `v x y z r g b` colour extension, one object per mesh and a `usemtl` per submesh. The MTL goes
+/// next to it with the same stem. Transforms are always baked.
+pub fn write_obj(path: &Path, meshes: &[ExportMesh]) -> io::Result<()> {
+    let mtl_path = path.with_extension("mtl");
+    let out_dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut obj = String::new();
+    let mut mtl = String::new();
+    let mut written_materials: Vec<&str> = vec![];
+
+    writeln!(obj, "# exported by ash-test").unwrap();
+    writeln!(obj, "mtllib {}", mtl_path.file_name().unwrap().to_string_lossy()).unwrap();
+    let mut base = 1;
+    for mesh in meshes.iter() {
+        let (vertices, submesh_indices) = mesh.prepared(true);
+        writeln!(obj, "o {}", mesh.name).unwrap();
+        for v in vertices.iter() {
+            writeln!(obj, "v {} {} {} {} {} {}", v.pos.x, v.pos.y, v.pos.z, v.color.x, v.color.y, v.color.z).unwrap();
+        }
+        for v in vertices.iter() {
+            writeln!(obj, "vt {} {}", v.uv.x, v.uv.y).unwrap();
+        }
+        for v in vertices.iter() {
+            writeln!(obj, "vn {} {} {}", v.normal.x, v.normal.y, v.normal.z).unwrap();
+        }
+
+        for (submesh, indices) in mesh.submeshes.iter().zip(submesh_indices.iter()) {
+            match submesh.material.and_then(|material| mesh.materials.get(material as usize)) {
+                Some(material) => {
+                    writeln!(obj, "usemtl {}", material.name).unwrap();
+                    if !written_materials.contains(&material.name.as_str()) {
+                        written_materials.push(&material.name);
+                        writeln!(mtl, "newmtl {}", material.name).unwrap();
+                        writeln!(mtl, "Kd 1 1 1").unwrap();
+                        if !material.diffuse_texture.is_empty() {
+                            writeln!(mtl, "map_Kd {}", texture_reference(&material.diffuse_texture, out_dir)).unwrap();
+                        }
+                        writeln!(mtl).unwrap();
+                    }
+                }
+                None => writeln!(obj, "usemtl default").unwrap(),
+            }
+            for triangle in indices.chunks_exact(3) {
+                let (a, b, c) = (triangle[0] + base, triangle[1] + base, triangle[2] + base);
+                writeln!(obj, "f {}/{}/{} {}/{}/{} {}/{}/{}", a, a, a, b, b, b, c, c, c).unwrap();
+            }
+        }
+        base += vertices.len() as u32;
+    }
+    if mesh_uses_default_material(meshes) {
+        writeln!(mtl, "newmtl default\nKd 1 1 1").unwrap();
+    }
+
+    fs::write(path, obj)?;
+    fs::write(mtl_path, mtl)
+}
+
+fn mesh_uses_default_material(meshes: &[ExportMesh]) -> bool {
+    meshes.iter().any(|mesh| {
+        mesh.submeshes
+            .iter()
+            .any(|submesh| submesh.material.and_then(|m| mesh.materials.get(m as usize)).is_none())
+    })
+}
+
+/// The JSON document of a glTF file and its binary buffer, filled in as meshes are added.
+#[derive(Default)]
+struct GltfBuilder {
+    root: json::Root,
+    bin: Vec<u8>,
+    /// Materials already added, by name and texture.
+    materials: HashMap<(String, String), json::Index<json::Material>>,
+}
+
+impl GltfBuilder {
+    fn push_view(&mut self, bytes: Vec<u8>, target: json::buffer::Target) -> json::Index<json::buffer::View> {
+        self.root.buffer_views.push(json::buffer::View {
+            buffer: json::Index::new(0),
+            byte_length: bytes.len() as u32,
+            byte_offset: Some(self.bin.len() as u32),
+            byte_stride: None,
+            name: None,
+            target: Some(Valid(target)),
+            extensions: None,
+            extras: Default::default(),
+        });
+        self.bin.extend(bytes);
+        json::Index::new(self.root.buffer_views.len() as u32 - 1)
+    }
+
+    fn push_accessor(
+        &mut self,
+        view: json::Index<json::buffer::View>,
+        count: usize,
+        component_type: json::accessor::ComponentType,
+        type_: json::accessor::Type,
+        min_max: Option<(Vec3, Vec3)>,
+    ) -> json::Index<json::Accessor> {
+        let bound = |v: Vec3| json::Value::from(vec![v.x, v.y, v.z]);
+        self.root.accessors.push(json::Accessor {
+            buffer_view: Some(view),
+            byte_offset: 0,
+            count: count as u32,
+            component_type: Valid(json::accessor::GenericComponentType(component_type)),
+            type_: Valid(type_),
+            min: min_max.map(|(min, _)| bound(min)),
+            max: min_max.map(|(_, max)| bound(max)),
+            name: None,
+            normalized: false,
+            sparse: None,
+            extensions: None,
+            extras: Default::default(),
+        });
+        json::Index::new(self.root.accessors.len() as u32 - 1)
+    }
+
+    /// `floats` holds `count` elements of `type_`.
+    fn push_floats(&mut self, floats: &[f32], count: usize, type_: json::accessor::Type, min_max: Option<(Vec3, Vec3)>) -> json::Index<json::Accessor> {
+        let bytes = floats.iter().flat_map(|f| f.to_le_bytes().to_vec()).collect();
+        let view = self.push_view(bytes, json::buffer::Target::ArrayBuffer);
+        self.push_accessor(view, count, json::accessor::ComponentType::F32, type_, min_max)
+    }
+
+    fn push_indices(&mut self, indices: &[u32]) -> json::Index<json::Accessor> {
+        let bytes = indices.iter().flat_map(|index| index.to_le_bytes().to_vec()).collect();
+        let view = self.push_view(bytes, json::buffer::Target::ElementArrayBuffer);
+        self.push_accessor(view, indices.len(), json::accessor::ComponentType::U32, json::accessor::Type::Scalar, None)
+    }
+
+    /// Adds `material` once, with its texture as seen from `out_dir`.
+    fn material(&mut self, material: &MaterialRef, out_dir: &Path) -> json::Index<json::Material> {
+        let key = (material.name.clone(), material.diffuse_texture.clone());
+        if let Some(&index) = self.materials.get(&key) {
+            return index;
+        }
+        let base_color_texture = if material.diffuse_texture.is_empty() {
+            None
+        } else {
+            self.root.images.push(json::Image {
+                buffer_view: None,
+                mime_type: None,
+                name: None,
+                uri: Some(uri_encode(&texture_reference(&material.diffuse_texture, out_dir))),
+                extensions: None,
+                extras: Default::default(),
+            });
+            // One texture per image, sampled with the defaults.
+            self.root.textures.push(json::Texture {
+                name: None,
+                sampler: None,
+                source: json::Index::new(self.root.images.len() as u32 - 1),
+                extensions: None,
+                extras: Default::default(),
+            });
+            Some(json::texture::Info {
+                index: json::Index::new(self.root.textures.len() as u32 - 1),
+                tex_coord: 0,
+                extensions: None,
+                extras: Default::default(),
+            })
+        };
+        self.root.materials.push(json::Material {
+            name: Some(material.name.clone()),
+            pbr_metallic_roughness: json::material::PbrMetallicRoughness {
+                base_color_texture,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        let index = json::Index::new(self.root.materials.len() as u32 - 1);
+        self.materials.insert(key, index);
+        index
+    }
+
+    /// Adds the full level of detail of `mesh`, baked or not, unless it has no triangles.
+    fn mesh(&mut self, mesh: &ExportMesh, bake: bool, out_dir: &Path) -> Option<json::Index<json::Mesh>> {
+        let (vertices, submesh_indices) = mesh.prepared(bake);
+        if vertices.is_empty() {
+            return None;
+        }
+        let bounds = Aabb::from_vertices(&vertices);
+        let flat = |f: &dyn Fn(&MeshVertex) -> Vec<f32>| vertices.iter().flat_map(f).collect::<Vec<f32>>();
+        let count = vertices.len();
+        let position = self.push_floats(
+            &flat(&|v| vec![v.pos.x, v.pos.y, v.pos.z]),
+            count,
+            json::accessor::Type::Vec3,
+            Some((bounds.min, bounds.max)),
+        );
+        let normal = self.push_floats(&flat(&|v| vec![v.normal.x, v.normal.y, v.normal.z]), count, json::accessor::Type::Vec3, None);
+        let tangent = self.push_floats(
+            &flat(&|v| vec![v.tangent.x, v.tangent.y, v.tangent.z, v.tangent.w]),
+            count,
+            json::accessor::Type::Vec4,
+            None,
+        );
+        // Back to glTF's top-left texture origin.
+        let uv = self.push_floats(&flat(&|v| vec![v.uv.x, 1.0 - v.uv.y]), count, json::accessor::Type::Vec2, None);
+        let color = self.push_floats(&flat(&|v| vec![v.color.x, v.color.y, v.color.z]), count, json::accessor::Type::Vec3, None);
+
+        let mut primitives = vec![];
+        for (submesh, indices) in mesh.submeshes.iter().zip(submesh_indices.iter()) {
+            if indices.is_empty() {
+                continue;
+            }
+            let mut attributes = HashMap::new();
+            attributes.insert(Valid(json::mesh::Semantic::Positions), position);
+            attributes.insert(Valid(json::mesh::Semantic::Normals), normal);
+            attributes.insert(Valid(json::mesh::Semantic::Tangents), tangent);
+            attributes.insert(Valid(json::mesh::Semantic::TexCoords(0)), uv);
+            attributes.insert(Valid(json::mesh::Semantic::Colors(0)), color);
+            let material = submesh.material.and_then(|m| mesh.materials.get(m as usize));
+            primitives.push(json::mesh::Primitive {
+                attributes,
+                indices: Some(self.push_indices(indices)),
+                material: material.map(|material| self.material(material, out_dir)),
+                mode: Valid(json::mesh::Mode::Triangles),
+                targets: None,
+                extensions: None,
+                extras: Default::default(),
+            });
+        }
+        self.root.meshes.push(json::Mesh {
+            name: Some(mesh.name.clone()),
+            primitives,
+            weights: None,
+            extensions: None,
+            extras: Default::default(),
+        });
+        Some(json::Index::new(self.root.meshes.len() as u32 - 1))
+    }
+
+    fn node(&mut self, name: &str, mesh: json::Index<json::Mesh>, matrix: Option<[f32; 16]>) {
+        self.root.nodes.push(json::Node {
+            camera: None,
+            children: None,
+            matrix,
+            mesh: Some(mesh),
+            name: Some(name.to_string()),
+            rotation: None,
+            scale: None,
+            translation: None,
+            skin: None,
+            weights: None,
+            extensions: None,
+            extras: Default::default(),
+        });
+    }
+}
+
+/// glTF 2.0 as a .gltf plus a .bin with the same stem. Every submesh becomes a primitive; texture coordinates are flipped
+/// back to glTF's top-left origin. Materials only carry their name and diffuse texture. Meshes without triangles are
+/// left out.
+pub fn write_gltf(path: &Path, meshes: &[ExportMesh], mode: InstanceExport) -> io::Result<()> {
+    let bin_path = path.with_extension("bin");
+    let out_dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut builder = GltfBuilder::default();
+    // Meshes already written in node mode, keyed by their vertex data.
+    let mut shared_meshes: HashMap<*const MeshVertex, Option<json::Index<json::Mesh>>> = HashMap::new();
+
+    for mesh in meshes.iter() {
+        let (mesh_index, matrix) = match mode {
+            InstanceExport::Baked => (builder.mesh(mesh, true, out_dir), None),
+            InstanceExport::Nodes => {
+                let mesh_index = match shared_meshes.get(&mesh.vertices.as_ptr()) {
+                    Some(&mesh_index) => mesh_index,
+                    None => {
+                        let mesh_index = builder.mesh(mesh, false, out_dir);
+                        shared_meshes.insert(mesh.vertices.as_ptr(), mesh_index);
+                        mesh_index
+                    }
+                };
+                let mut matrix = [0.0; 16];
+                matrix.copy_from_slice(mesh.transform.as_slice());
+                (mesh_index, Some(matrix).filter(|_| mesh.transform != Mat4::identity()))
+            }
+        };
+        if let Some(mesh_index) = mesh_index {
+            builder.node(&mesh.name, mesh_index, matrix);
+        }
+    }
+
+    let node_count = builder.root.nodes.len() as u32;
+    builder.root.asset = json::Asset {
+        generator: Some(String::from("ash-test")),
+        ..Default::default()
+    };
+    builder.root.scenes.push(json::Scene {
+        name: None,
+        nodes: (0..node_count).map(json::Index::new).collect(),
+        extensions: None,
+        extras: Default::default(),
+    });
+    builder.root.scene = Some(json::Index::new(0));
+    builder.root.buffers.push(json::Buffer {
+        byte_length: builder.bin.len() as u32,
+        name: None,
+        uri: Some(uri_encode(&bin_path.file_name().unwrap().to_string_lossy())),
+        extensions: None,
+        extras: Default::default(),
+    });
+
+    let document = builder.root.to_vec_pretty().map_err(io::Error::other)?;
+    fs::write(&bin_path, &builder.bin)?;
+    fs::write(path, document)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra_glm::Vec2;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("ash_test_export_{}_{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn vertex(x: f32, y: f32) -> MeshVertex {
+        MeshVertex::new(Vec3::new(x, y, 0.0), Vec3::new(1.0, 1.0, 1.0), Vec2::new(x, y))
+    }
+
+    /// A quad whose second triangle is only used by a reduced level of detail, through a fifth vertex.
+    fn quad_with_lod() -> MeshData {
+        let submesh = Submesh {
+            first_index: 0,
+            index_count: 6,
+            material: Some(0),
+        };
+        MeshData {
+            vertices: vec![vertex(0.0, 0.0), vertex(1.0, 0.0), vertex(1.0, 1.0), vertex(0.0, 1.0), vertex(9.0, 9.0)],
+            indices: vec![0, 1, 2, 0, 2, 3, 0, 1, 4],
+            submeshes: vec![submesh],
+            materials: vec![MaterialRef {
+                name: String::from("brick"),
+                diffuse_texture: String::from("assets/textures/brick wall.png"),
+            }],
+            lods: vec![],
+            bounds: Aabb::empty(),
+        }
+    }
+
+    #[test]
+    fn relative_paths_walk_up_from_the_output_directory() {
+        let cases = [
+            ("/a/textures/t.png", "/a/export", "../textures/t.png"),
+            ("/a/export/t.png", "/a/export", "t.png"),
+            ("/a/b/../textures/./t.png", "/a/export/deep", "../../textures/t.png"),
+        ];
+        for &(target, from_dir, expected) in cases.iter() {
+            assert_eq!(portable_path(&relative_path(Path::new(target), Path::new(from_dir))), expected);
+        }
+        let cwd = env::current_dir().unwrap();
+        assert_eq!(relative_path(&cwd.join("assets/t.png"), Path::new("export")), Path::new("../assets/t.png"));
+    }
+
+    #[test]
+    fn uri_encoding_keeps_slashes() {
+        assert_eq!(uri_encode("../textures/brick wall#2.png"), "../textures/brick%20wall%232.png");
+    }
+
+    #[test]
+    fn only_full_detail_vertices_are_prepared() {
+        let mesh = quad_with_lod();
+        let (vertices, indices) = ExportMesh::from_mesh_data("quad", &mesh).prepared(false);
+        assert_eq!(vertices.len(), 4);
+        assert_eq!(indices, vec![vec![0, 1, 2, 0, 2, 3]]);
+        assert!(vertices.iter().all(|v| v.pos.x < 2.0));
+    }
+
+    #[test]
+    fn mirroring_transforms_flip_the_winding() {
+        let mesh = quad_with_lod();
+        let mirror = nalgebra_glm::scaling(&Vec3::new(-1.0, 1.0, 1.0));
+        let (vertices, indices) = ExportMesh::from_mesh_data("quad", &mesh).with_transform(mirror).prepared(true);
+        assert_eq!(indices[0][..3], [0, 2, 1]);
+        assert_eq!(vertices[1].pos, Vec3::new(-1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn gltf_round_trips_through_the_importer() {
+        let dir = temp_dir("gltf");
+        let mut mesh = quad_with_lod();
+        mesh.vertices[3].pos.y = f32::NAN;
+        let meshes = [
+            ExportMesh::from_mesh_data("a", &mesh),
+            ExportMesh::from_mesh_data("b", &mesh).with_transform(nalgebra_glm::translation(&Vec3::new(3.0, 0.0, 0.0))),
+        ];
+        let path = dir.join("out").join("scene.gltf");
+        export_meshes(&path, &meshes, InstanceExport::Nodes).unwrap();
+
+        // Validates the document. The texture doesn't exist, so the buffer is read by hand rather than with `import`.
+        let document = gltf::Gltf::open(&path).unwrap().document;
+        let bin = fs::read(path.with_extension("bin")).unwrap();
+        let document_text = String::from_utf8(fs::read(&path).unwrap()).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        // Both instances share the one mesh.
+        assert_eq!(document.meshes().count(), 1);
+        assert_eq!(document.nodes().count(), 2);
+        assert!(document.nodes().nth(1).unwrap().transform().matrix()[3][0] == 3.0);
+
+        let primitive = document.meshes().next().unwrap().primitives().next().unwrap();
+        let positions: Vec<[f32; 3]> = primitive.reader(|_| Some(&bin[..])).read_positions().unwrap().collect();
+        assert_eq!(positions.len(), 4);
+        assert_eq!(positions[3], [0.0, 0.0, 0.0]);
+        let bounds = primitive.bounding_box();
+        assert_eq!((bounds.min, bounds.max), ([0.0, 0.0, 0.0], [1.0, 1.0, 0.0]));
+        assert!(!document_text.contains("null"));
+
+        let image = document.images().next().unwrap();
+        match image.source() {
+            gltf::image::Source::Uri { uri, .. } => {
+                let expected = uri_encode(&texture_reference("assets/textures/brick wall.png", &dir.join("out")));
+                assert_eq!(uri, expected);
+                assert!(uri.ends_with("assets/textures/brick%20wall.png") && uri.starts_with(".."));
+            }
+            _ => panic!("texture should be referenced by uri"),
+        }
+    }
+
+    #[test]
+    fn obj_texture_references_survive_a_round_trip() {
+        let dir = temp_dir("obj");
+        let mesh = quad_with_lod();
+        let path = dir.join("out").join("quad.obj");
+        export_mesh(&path, ExportMesh::from_mesh_data("quad", &mesh)).unwrap();
+        let imported = crate::vk_assist::model_loader::import_obj(&path, &Default::default()).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(imported.warnings.is_empty(), "{:?}", imported.warnings);
+        let cwd = env::current_dir().unwrap();
+        let texture = Path::new(&imported.mesh.materials[0].diffuse_texture);
+        assert_eq!(relative_path(texture, &cwd), Path::new("assets/textures/brick wall.png"));
+    }
+}
@@ -0,0 +1,372 @@
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+
+use ash::vk;
+
+use crate::vk_assist::structures::MeshVertex;
+use crate::vk_model::mesh_data::Submesh;
+
+/// Size of the simulated post-transform cache. Most desktop GPUs behave like a FIFO of roughly this size or larger.
+pub const VERTEX_CACHE_SIZE: usize = 32;
+
+// Scoring constants from Tom Forsyth's "Linear-Speed Vertex Cache Optimisation".
+const CACHE_DECAY_POWER: f32 = 1.5;
+const LAST_TRI_SCORE: f32 = 0.75;
+const VALENCE_BOOST_SCALE: f32 = 2.0;
+const VALENCE_BOOST_POWER: f32 = 0.5;
+
+#[derive(Clone, Copy, Debug)]
+pub struct OptimizeStats {
+    pub vertices_before: usize,
+    pub vertices_after: usize,
+    pub acmr_before: f32,
+    pub acmr_after: f32,
+}
+
+/// Smallest index type able to address `vertex_count` vertices.
+pub fn index_type_for(vertex_count: usize) -> vk::IndexType {
+    if vertex_count <= u16::MAX as usize + 1 {
+        vk::IndexType::UINT16
+    } else {
+        vk::IndexType::UINT32
+    }
+}
+
+pub fn index_size(index_type: vk::IndexType) -> vk::DeviceSize {
+    match index_type {
+        vk::IndexType::UINT16 => std::mem::size_of::<u16>() as vk::DeviceSize,
+        _ => std::mem::size_of::<u32>() as vk::DeviceSize,
+    }
+}
+
+fn vertex_key(v: &MeshVertex) -> [u32; 15] {
+    let floats = [
+        v.pos.x, v.pos.y, v.pos.z, v.color.x, v.color.y, v.color.z, v.uv.x, v.uv.y, v.normal.x, v.normal.y, v.normal.z, v.tangent.x, v.tangent.y,
+        v.tangent.z, v.tangent.w,
+    ];
+    let mut key = [0u32; 15];
+    for (k, f) in key.iter_mut().zip(floats.iter()) {
+        *k = (f + 0.0).to_bits();
+    }
+    key
+}
+
+/// Runs the full import-time optimisation: weld, reorder triangles for the post-transform cache, then reorder vertices
+/// for fetch locality. Triangles are only reordered within their submesh so submesh index ranges stay valid.
+pub fn optimize_mesh(vertices: &mut Vec<MeshVertex>, indices: &mut [u32], submeshes: &[Submesh]) -> OptimizeStats {
+    let vertices_before = vertices.len();
+    let acmr_before = acmr(indices, VERTEX_CACHE_SIZE);
+
+    weld_vertices(vertices, indices);
+    for submesh in submeshes.iter() {
+        let range = submesh.first_index as usize..(submesh.first_index + submesh.index_count) as usize;
+        optimize_vertex_cache(&mut indices[range], vertices.len());
+    }
+    optimize_vertex_fetch(vertices, indices);
+
+    OptimizeStats {
+        vertices_before,
+        vertices_after: vertices.len(),
+        acmr_before,
+        acmr_after: acmr(indices, VERTEX_CACHE_SIZE),
+    }
+}
+
+/// Merges vertices whose attributes are bit-identical and drops vertices no triangle references.
+pub fn weld_vertices(vertices: &mut Vec<MeshVertex>, indices: &mut [u32]) {
+    let mut unique: HashMap<[u32; 15], u32> = HashMap::with_capacity(vertices.len());
+    let mut welded = Vec::with_capacity(vertices.len());
+    let mut remap = vec![u32::MAX; vertices.len()];
+
+    for index in indices.iter_mut() {
+        let old = *index as usize;
+        if remap[old] == u32::MAX {
+            let vertex = vertices[old];
+            remap[old] = *unique.entry(vertex_key(&vertex)).or_insert_with(|| {
+                welded.push(vertex);
+                (welded.len() - 1) as u32
+            });
+        }
+        *index = remap[old];
+    }
+
+    *vertices = welded;
+}
+
+fn vertex_score(cache_position: Option<usize>, active_triangles: u32) -> f32 {
+    if active_triangles == 0 {
+        return -1.0;
+    }
+
+    let cache_score = match cache_position {
+        None => 0.0,
+        // The three vertices of the last triangle get a fixed score so the next triangle doesn't just reuse them.
+        Some(position) if position < 3 => LAST_TRI_SCORE,
+        Some(position) => {
+            let scaler = 1.0 / (VERTEX_CACHE_SIZE - 3) as f32;
+            (1.0 - (position - 3) as f32 * scaler).powf(CACHE_DECAY_POWER)
+        }
+    };
+
+    cache_score + VALENCE_BOOST_SCALE * (active_triangles as f32).powf(-VALENCE_BOOST_POWER)
+}
+
+/// Reorders triangles so consecutive triangles reuse recently transformed vertices (Forsyth's algorithm).
+pub fn optimize_vertex_cache(indices: &mut [u32], vertex_count: usize) {
+    let triangle_count = indices.len() / 3;
+    if triangle_count == 0 {
+        return;
+    }
+
+    let mut vertex_triangles: Vec<Vec<usize>> = vec![Vec::new(); vertex_count];
+    for (corner, &index) in indices.iter().enumerate() {
+        vertex_triangles[index as usize].push(corner / 3);
+    }
+
+    let mut cache_position: Vec<Option<usize>> = vec![None; vertex_count];
+    let mut vertex_scores: Vec<f32> = vertex_triangles.iter().map(|tris| vertex_score(None, tris.len() as u32)).collect();
+    let mut triangle_scores: Vec<f32> = (0..triangle_count)
+        .map(|t| (0..3).map(|k| vertex_scores[indices[t * 3 + k] as usize]).sum())
+        .collect();
+    let mut emitted = vec![false; triangle_count];
+
+    let mut cache: Vec<u32> = Vec::with_capacity(VERTEX_CACHE_SIZE + 3);
+    let mut output = Vec::with_capacity(indices.len());
+    let mut best_triangle = (0..triangle_count).max_by(|&a, &b| triangle_scores[a].partial_cmp(&triangle_scores[b]).unwrap());
+    let mut scan_cursor = 0;
+
+    while let Some(triangle) = best_triangle {
+        emitted[triangle] = true;
+        let corners = [indices[triangle * 3], indices[triangle * 3 + 1], indices[triangle * 3 + 2]];
+        output.extend_from_slice(&corners);
+
+        for &vertex in corners.iter() {
+            vertex_triangles[vertex as usize].retain(|&t| t != triangle);
+        }
+
+        // Push the triangle's vertices to the front of the cache, the rest shift back and the overflow is evicted.
+        let mut new_cache: Vec<u32> = corners.to_vec();
+        new_cache.extend(cache.iter().filter(|v| !corners.contains(v)));
+        new_cache.truncate(VERTEX_CACHE_SIZE);
+        for &evicted in cache.iter() {
+            if !new_cache.contains(&evicted) {
+                cache_position[evicted as usize] = None;
+                vertex_scores[evicted as usize] = vertex_score(None, vertex_triangles[evicted as usize].len() as u32);
+            }
+        }
+        cache = new_cache;
+
+        for (position, &vertex) in cache.iter().enumerate() {
+            cache_position[vertex as usize] = Some(position);
+            vertex_scores[vertex as usize] = vertex_score(Some(position), vertex_triangles[vertex as usize].len() as u32);
+        }
+
+        // Only triangles touching the cache can have changed score; the best of those is the next candidate.
+        best_triangle = None;
+        let mut best_score = -1.0;
+        for &vertex in cache.iter() {
+            for &t in vertex_triangles[vertex as usize].iter() {
+                let score: f32 = (0..3).map(|k| vertex_scores[indices[t * 3 + k] as usize]).sum();
+                triangle_scores[t] = score;
+                if score > best_score {
+                    best_score = score;
+                    best_triangle = Some(t);
+                }
+            }
+        }
+
+        if best_triangle.is_none() {
+            while scan_cursor < triangle_count && emitted[scan_cursor] {
+                scan_cursor += 1;
+            }
+            if scan_cursor < triangle_count {
+                best_triangle = Some(scan_cursor);
+            }
+        }
+    }
+
+    indices[..output.len()].copy_from_slice(&output);
+}
+
+/// Reorders vertices into the order the index buffer first references them, so vertex fetches walk memory linearly.
+pub fn optimize_vertex_fetch(vertices: &mut Vec<MeshVertex>, indices: &mut [u32]) {
+    let mut remap = vec![u32::MAX; vertices.len()];
+    let mut reordered = Vec::with_capacity(vertices.len());
+
+    for index in indices.iter_mut() {
+        let old = *index as usize;
+        if remap[old] == u32::MAX {
+            remap[old] = reordered.len() as u32;
+            reordered.push(vertices[old]);
+        }
+        *index = remap[old];
+    }
+
+    *vertices = reordered;
+}
+
+/// Average cache miss ratio: transformed vertices per triangle for a FIFO cache of `cache_size`. 3.0 is the worst case,
+/// around 0.6-0.7 is typical for a well ordered mesh.
+pub fn acmr(indices: &[u32], cache_size: usize) -> f32 {
+    let triangle_count = indices.len() / 3;
+    if triangle_count == 0 {
+        return 0.0;
+    }
+
+    let mut fifo: std::collections::VecDeque<u32> = std::collections::VecDeque::with_capacity(cache_size);
+    let mut misses = 0;
+    for &index in indices.iter() {
+        if !fifo.contains(&index) {
+            misses += 1;
+            if fifo.len() == cache_size {
+                fifo.pop_front();
+            }
+            fifo.push_back(index);
+        }
+    }
+
+    misses as f32 / triangle_count as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vk_assist::mesh_processing::position_key;
+    use crate::vk_assist::primitives;
+    use nalgebra_glm::{Vec2, Vec3};
+
+    fn vertex(x: f32, y: f32) -> MeshVertex {
+        MeshVertex::new(Vec3::new(x, y, 0.0), Vec3::new(1.0, 1.0, 1.0), Vec2::new(x, y))
+    }
+
+    /// The grid's triangles in a scattered order, as a poorly exported mesh might have them.
+    fn scattered_grid() -> (Vec<MeshVertex>, Vec<u32>) {
+        let grid = primitives::plane(1.0, 1.0, 24, 24);
+        let triangle_count = grid.indices.len() / 3;
+        // 7919 is prime and doesn't divide the triangle count, so this visits every triangle once.
+        let indices = (0..triangle_count)
+            .flat_map(|t| {
+                let source = (t * 7919) % triangle_count;
+                grid.indices[source * 3..source * 3 + 3].to_vec()
+            })
+            .collect();
+        (grid.vertices, indices)
+    }
+
+    /// Each triangle by its corner positions, starting from its smallest corner so reordering corners within a
+    /// triangle while keeping its winding doesn't count as a change.
+    fn triangle_set(vertices: &[MeshVertex], indices: &[u32]) -> Vec<[[u32; 3]; 3]> {
+        let mut triangles: Vec<[[u32; 3]; 3]> = indices
+            .chunks(3)
+            .map(|triangle| {
+                let corners: Vec<[u32; 3]> = triangle.iter().map(|&index| position_key(&vertices[index as usize].pos)).collect();
+                let first = (0..3).min_by_key(|&k| corners[k]).unwrap();
+                [corners[first], corners[(first + 1) % 3], corners[(first + 2) % 3]]
+            })
+            .collect();
+        triangles.sort_unstable();
+        triangles
+    }
+
+    #[test]
+    fn weld_merges_duplicates_and_drops_unused_vertices() {
+        // Vertex 3 is unused and vertex 4 repeats vertex 1.
+        let mut vertices: Vec<MeshVertex> = [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0), (5.0, 5.0), (1.0, 0.0), (1.0, 1.0)]
+            .iter()
+            .map(|&(x, y)| vertex(x, y))
+            .collect();
+        let mut indices = vec![0, 1, 2, 2, 4, 5];
+        weld_vertices(&mut vertices, &mut indices);
+        assert_eq!(vertices.len(), 4);
+        assert_eq!(indices, vec![0, 1, 2, 2, 1, 3]);
+        assert!(vertices.iter().all(|vertex| vertex.pos.x != 5.0));
+
+        // Negative zero is the same position.
+        let mut signed = vec![vertex(0.0, 0.0), vertex(-0.0, 0.0), vertex(1.0, 0.0)];
+        let mut signed_indices = vec![0, 2, 1];
+        weld_vertices(&mut signed, &mut signed_indices);
+        assert_eq!(signed_indices, vec![0, 1, 0]);
+    }
+
+    #[test]
+    fn cache_optimization_does_not_make_acmr_worse() {
+        let (vertices, mut indices) = scattered_grid();
+        let before = acmr(&indices, VERTEX_CACHE_SIZE);
+        optimize_vertex_cache(&mut indices, vertices.len());
+        let after = acmr(&indices, VERTEX_CACHE_SIZE);
+        assert!(after <= before, "ACMR went from {} to {}", before, after);
+        assert!(after < 1.0, "ACMR {}", after);
+
+        let grid = primitives::plane(1.0, 1.0, 24, 24);
+        let mut ordered = grid.indices.clone();
+        optimize_vertex_cache(&mut ordered, grid.vertices.len());
+        assert!(acmr(&ordered, VERTEX_CACHE_SIZE) <= acmr(&grid.indices, VERTEX_CACHE_SIZE));
+    }
+
+    #[test]
+    fn fetch_order_follows_first_use_and_triangles_survive() {
+        let (mut vertices, mut indices) = scattered_grid();
+        let original = triangle_set(&vertices, &indices);
+        let vertex_count = vertices.len();
+        optimize_vertex_cache(&mut indices, vertices.len());
+        optimize_vertex_fetch(&mut vertices, &mut indices);
+
+        assert_eq!(vertices.len(), vertex_count);
+        let mut next = 0;
+        for &index in indices.iter() {
+            assert!(index <= next, "vertex {} used before vertex {}", index, next);
+            if index == next {
+                next += 1;
+            }
+        }
+        assert_eq!(triangle_set(&vertices, &indices), original);
+    }
+
+    #[test]
+    fn acmr_counts_misses_per_triangle() {
+        assert_eq!(acmr(&[], VERTEX_CACHE_SIZE), 0.0);
+        assert_eq!(acmr(&[0, 1, 2, 3, 4, 5], VERTEX_CACHE_SIZE), 3.0);
+        assert_eq!(acmr(&[0, 1, 2, 2, 1, 3], VERTEX_CACHE_SIZE), 2.0);
+        // With a cache of 3 the first triangle's vertices are evicted by the time they come round again.
+        assert_eq!(acmr(&[0, 1, 2, 3, 4, 5, 0, 1, 2], 3), 3.0);
+    }
+
+    #[test]
+    fn index_type_fits_the_vertex_count() {
+        assert_eq!(index_type_for(0), vk::IndexType::UINT16);
+        assert_eq!(index_type_for(65536), vk::IndexType::UINT16);
+        assert_eq!(index_type_for(65537), vk::IndexType::UINT32);
+        assert_eq!(index_size(vk::IndexType::UINT16), 2);
+        assert_eq!(index_size(vk::IndexType::UINT32), 4);
+    }
+
+    #[test]
+    fn optimize_mesh_keeps_submeshes_apart() {
+        let (vertices, indices) = scattered_grid();
+        let half = (indices.len() / 6 * 3) as u32;
+        let submeshes = [
+            Submesh {
+                first_index: 0,
+                index_count: half,
+                material: Some(0),
+            },
+            Submesh {
+                first_index: half,
+                index_count: indices.len() as u32 - half,
+                material: Some(1),
+            },
+        ];
+        let (mut optimized_vertices, mut optimized) = (vertices.clone(), indices.clone());
+        let stats = optimize_mesh(&mut optimized_vertices, &mut optimized, &submeshes);
+        assert!(stats.acmr_after <= stats.acmr_before);
+        assert_eq!(stats.vertices_after, optimized_vertices.len());
+        let range = |indices: &[u32], vertices: &[MeshVertex], submesh: &Submesh| {
+            let (first, count) = (submesh.first_index as usize, submesh.index_count as usize);
+            triangle_set(vertices, &indices[first..first + count])
+        };
+        for submesh in submeshes.iter() {
+            assert_eq!(range(&optimized, &optimized_vertices, submesh), range(&indices, &vertices, submesh));
+        }
+    }
+}
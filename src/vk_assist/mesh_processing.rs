@@ -0,0 +1,298 @@
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::f32::consts::PI;
+
+use nalgebra_glm::{Vec2, Vec3, Vec4};
+
+use crate::vk_assist::structures::MeshVertex;
+
+/// Faces meeting at a sharper angle than this keep a hard edge when smoothing normals.
+pub const DEFAULT_CREASE_ANGLE: f32 = PI / 3.0;
+
+const EPSILON: f32 = 1.0e-8;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NormalMode {
+    /// Average the normals of faces sharing a position when they are within `crease_angle` radians of each other.
+    Smooth { crease_angle: f32 },
+    /// Every face gets its own normal.
+    Flat,
+}
+
+impl Default for NormalMode {
+    fn default() -> NormalMode {
+        NormalMode::Smooth {
+            crease_angle: DEFAULT_CREASE_ANGLE,
+        }
+    }
+}
+
+/// Key for grouping vertices by position. Adding 0.0 folds -0.0 into 0.0 so both hash the same.
+pub fn position_key(pos: &Vec3) -> [u32; 3] {
+    [(pos.x + 0.0).to_bits(), (pos.y + 0.0).to_bits(), (pos.z + 0.0).to_bits()]
+}
+
+fn triangle(indices: &[u32], t: usize) -> [usize; 3] {
+    [indices[t * 3] as usize, indices[t * 3 + 1] as usize, indices[t * 3 + 2] as usize]
+}
+
+fn corner_angle(corner: &Vec3, a: &Vec3, b: &Vec3) -> f32 {
+    let u = a - corner;
+    let v = b - corner;
+    let len = u.norm() * v.norm();
+    if len < EPSILON {
+        return 0.0;
+    }
+    (u.dot(&v) / len).clamp(-1.0, 1.0).acos()
+}
+
+/// Angle of each triangle corner, in index buffer order. Used to weight per-face contributions to a vertex.
+fn corner_angles(vertices: &[MeshVertex], indices: &[u32]) -> Vec<f32> {
+    let mut angles = Vec::with_capacity(indices.len());
+    for t in 0..indices.len() / 3 {
+        let [a, b, c] = triangle(indices, t);
+        let (pa, pb, pc) = (&vertices[a].pos, &vertices[b].pos, &vertices[c].pos);
+        angles.push(corner_angle(pa, pb, pc));
+        angles.push(corner_angle(pb, pc, pa));
+        angles.push(corner_angle(pc, pa, pb));
+    }
+    angles
+}
+
+fn any_orthogonal(n: &Vec3) -> Vec3 {
+    let axis = if n.x.abs() < 0.9 { Vec3::new(1.0, 0.0, 0.0) } else { Vec3::new(0.0, 1.0, 0.0) };
+    let t = n.cross(&axis);
+    if t.norm() < EPSILON {
+        Vec3::new(1.0, 0.0, 0.0)
+    } else {
+        t.normalize()
+    }
+}
+
+/// Replaces the normals of an indexed triangle list. Vertices are split wherever a position needs more than one normal
+/// (hard edges, flat shading) and re-shared wherever they end up identical. Returns, for each output vertex, the input
+/// vertex it came from, so data kept alongside the vertices can be remapped the same way.
+pub fn generate_normals(vertices: &mut Vec<MeshVertex>, indices: &mut Vec<u32>, mode: NormalMode) -> Vec<u32> {
+    let triangle_count = indices.len() / 3;
+    let face_normals: Vec<Vec3> = (0..triangle_count)
+        .map(|t| {
+            let [a, b, c] = triangle(indices, t);
+            let n = (vertices[b].pos - vertices[a].pos).cross(&(vertices[c].pos - vertices[a].pos));
+            if n.norm() < EPSILON {
+                Vec3::zeros()
+            } else {
+                n.normalize()
+            }
+        })
+        .collect();
+    let angles = corner_angles(vertices, indices);
+
+    // Every (triangle, corner) touching a position, so hard edges can be found without relying on shared indices.
+    let mut corners_at: HashMap<[u32; 3], Vec<usize>> = HashMap::new();
+    if let NormalMode::Smooth { .. } = mode {
+        for (corner, &index) in indices.iter().enumerate() {
+            corners_at.entry(position_key(&vertices[index as usize].pos)).or_default().push(corner);
+        }
+    }
+
+    let mut new_vertices = Vec::with_capacity(vertices.len());
+    let mut new_indices = Vec::with_capacity(indices.len());
+    let mut source = Vec::with_capacity(vertices.len());
+    let mut remap: HashMap<(u32, [u32; 3]), u32> = HashMap::new();
+
+    for (corner, &index) in indices.iter().enumerate() {
+        let face_normal = face_normals[corner / 3];
+        let normal = match mode {
+            NormalMode::Flat => face_normal,
+            NormalMode::Smooth { crease_angle } => {
+                let cos_crease = crease_angle.cos();
+                let mut sum = Vec3::zeros();
+                for &other in corners_at[&position_key(&vertices[index as usize].pos)].iter() {
+                    let other_normal = face_normals[other / 3];
+                    if face_normal.dot(&other_normal) >= cos_crease {
+                        sum += other_normal * angles[other];
+                    }
+                }
+                if sum.norm() < EPSILON {
+                    face_normal
+                } else {
+                    sum.normalize()
+                }
+            }
+        };
+
+        let key = (index, position_key(&normal));
+        let new_index = *remap.entry(key).or_insert_with(|| {
+            let mut vertex = vertices[index as usize];
+            vertex.normal = normal;
+            new_vertices.push(vertex);
+            source.push(index);
+            (new_vertices.len() - 1) as u32
+        });
+        new_indices.push(new_index);
+    }
+
+    *vertices = new_vertices;
+    *indices = new_indices;
+    source
+}
+
+/// Generates per-vertex tangents following the MikkTSpace conventions: contributions are angle weighted, tangents are
+/// orthogonalised against the vertex normal, `tangent.w` is the bitangent sign, and vertices shared by triangles of
+/// opposite UV winding (mirrored UVs) are split so each side keeps its own frame. Normals must already be set.
+/// Split copies are appended; the returned list maps every output vertex to the input vertex it came from.
+pub fn generate_tangents(vertices: &mut Vec<MeshVertex>, indices: &mut [u32]) -> Vec<u32> {
+    let angles = corner_angles(vertices, indices);
+
+    // (vertex, flipped) -> slot in the accumulators.
+    let mut slots: HashMap<(u32, bool), usize> = HashMap::new();
+    let mut slot_vertex: Vec<u32> = Vec::new();
+    let mut corner_slot: Vec<usize> = Vec::with_capacity(indices.len());
+    let mut tangents: Vec<Vec3> = Vec::new();
+    let mut bitangents: Vec<Vec3> = Vec::new();
+
+    for t in 0..indices.len() / 3 {
+        let [a, b, c] = triangle(indices, t);
+        let e1 = vertices[b].pos - vertices[a].pos;
+        let e2 = vertices[c].pos - vertices[a].pos;
+        let duv1: Vec2 = vertices[b].uv - vertices[a].uv;
+        let duv2: Vec2 = vertices[c].uv - vertices[a].uv;
+        let det = duv1.x * duv2.y - duv2.x * duv1.y;
+
+        let (tangent, bitangent) = if det.abs() < EPSILON {
+            (Vec3::zeros(), Vec3::zeros())
+        } else {
+            let r = 1.0 / det;
+            ((e1 * duv2.y - e2 * duv1.y) * r, (e2 * duv1.x - e1 * duv2.x) * r)
+        };
+        let flipped = det < 0.0;
+
+        for k in 0..3 {
+            let corner = t * 3 + k;
+            let index = indices[corner];
+            let slot = *slots.entry((index, flipped)).or_insert_with(|| {
+                slot_vertex.push(index);
+                tangents.push(Vec3::zeros());
+                bitangents.push(Vec3::zeros());
+                slot_vertex.len() - 1
+            });
+            if tangent.norm() > EPSILON {
+                tangents[slot] += tangent.normalize() * angles[corner];
+            }
+            if bitangent.norm() > EPSILON {
+                bitangents[slot] += bitangent.normalize() * angles[corner];
+            }
+            corner_slot.push(slot);
+        }
+    }
+
+    // The first slot seen for a vertex keeps its index, any other handedness gets a copy appended to the buffer.
+    let mut slot_index = vec![0u32; slot_vertex.len()];
+    let mut claimed = vec![false; vertices.len()];
+    let mut source: Vec<u32> = (0..vertices.len() as u32).collect();
+    for (slot, &vertex) in slot_vertex.iter().enumerate() {
+        slot_index[slot] = if claimed[vertex as usize] {
+            vertices.push(vertices[vertex as usize]);
+            source.push(vertex);
+            (vertices.len() - 1) as u32
+        } else {
+            claimed[vertex as usize] = true;
+            vertex
+        };
+    }
+
+    for (slot, &index) in slot_index.iter().enumerate() {
+        let vertex = &mut vertices[index as usize];
+        let n = vertex.normal;
+        let t = tangents[slot] - n * n.dot(&tangents[slot]);
+        let t = if t.norm() < EPSILON { any_orthogonal(&n) } else { t.normalize() };
+        let w = if n.cross(&t).dot(&bitangents[slot]) < 0.0 { -1.0 } else { 1.0 };
+        vertex.tangent = Vec4::new(t.x, t.y, t.z, w);
+    }
+
+    for (corner, index) in indices.iter_mut().enumerate() {
+        *index = slot_index[corner_slot[corner]];
+    }
+    source
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vertex(x: f32, y: f32, z: f32, u: f32, v: f32) -> MeshVertex {
+        MeshVertex::new(Vec3::new(x, y, z), Vec3::new(1.0, 1.0, 1.0), Vec2::new(u, v))
+    }
+
+    /// Two triangles folded 90 degrees along the x axis, sharing the edge's two vertices.
+    fn folded_quad() -> (Vec<MeshVertex>, Vec<u32>) {
+        let vertices = vec![
+            vertex(0.0, 0.0, 0.0, 0.0, 0.0),
+            vertex(1.0, 0.0, 0.0, 1.0, 0.0),
+            vertex(0.0, 0.0, -1.0, 0.0, 1.0),
+            vertex(0.0, 1.0, 0.0, 0.0, 1.0),
+        ];
+        (vertices, vec![0, 1, 2, 0, 3, 1])
+    }
+
+    #[test]
+    fn flat_normals_split_shared_vertices() {
+        let (mut vertices, mut indices) = folded_quad();
+        let source = generate_normals(&mut vertices, &mut indices, NormalMode::Flat);
+        assert_eq!(vertices.len(), 6);
+        assert_eq!(source, vec![0, 1, 2, 0, 3, 1]);
+        for t in 0..2 {
+            let [a, b, c] = triangle(&indices, t);
+            assert_eq!(vertices[a].normal, vertices[b].normal);
+            assert_eq!(vertices[a].normal, vertices[c].normal);
+        }
+        assert!((vertices[indices[0] as usize].normal - Vec3::new(0.0, 1.0, 0.0)).norm() < 1.0e-6);
+        assert!((vertices[indices[3] as usize].normal - Vec3::new(0.0, 0.0, -1.0)).norm() < 1.0e-6);
+    }
+
+    #[test]
+    fn smooth_normals_keep_creases_sharper_than_the_angle() {
+        let (mut vertices, mut indices) = folded_quad();
+        generate_normals(&mut vertices, &mut indices, NormalMode::default());
+        // 90 degrees is past the default 60 degree crease, so the fold stays hard.
+        assert_eq!(vertices.len(), 6);
+
+        let (mut vertices, mut indices) = folded_quad();
+        generate_normals(&mut vertices, &mut indices, NormalMode::Smooth { crease_angle: PI });
+        assert_eq!(vertices.len(), 4);
+        let shared = vertices[indices[0] as usize].normal;
+        assert!((shared - Vec3::new(0.0, 1.0, -1.0).normalize()).norm() < 1.0e-6);
+    }
+
+    #[test]
+    fn tangents_follow_u_and_split_mirrored_uvs() {
+        let mut vertices = vec![
+            vertex(0.0, 0.0, 0.0, 0.0, 0.0),
+            vertex(1.0, 0.0, 0.0, 1.0, 0.0),
+            vertex(0.0, 1.0, 0.0, 0.0, 1.0),
+            vertex(-1.0, 0.0, 0.0, 1.0, 0.0),
+        ];
+        for vertex in vertices.iter_mut() {
+            vertex.normal = Vec3::new(0.0, 0.0, 1.0);
+        }
+        // The second triangle mirrors u, so the vertices it shares with the first need their own tangents.
+        let mut indices = vec![0, 1, 2, 0, 2, 3];
+        let source = generate_tangents(&mut vertices, &mut indices);
+
+        assert_eq!(vertices.len(), 6);
+        assert_eq!(&source[4..], &[0, 2]);
+        let first = vertices[indices[0] as usize].tangent;
+        let mirrored = vertices[indices[3] as usize].tangent;
+        assert_ne!(indices[0], indices[3]);
+        assert!((first.xyz() - Vec3::new(1.0, 0.0, 0.0)).norm() < 1.0e-6);
+        assert_eq!(first.w, 1.0);
+        assert!((mirrored.xyz() - Vec3::new(-1.0, 0.0, 0.0)).norm() < 1.0e-6);
+        assert_eq!(mirrored.w, -1.0);
+    }
+
+    #[test]
+    fn position_key_folds_negative_zero() {
+        assert_eq!(position_key(&Vec3::new(-0.0, 0.0, -0.0)), position_key(&Vec3::zeros()));
+    }
+}
@@ -0,0 +1,546 @@
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+
+use nalgebra_glm::Vec3;
+
+use crate::vk_assist::mesh_optimizer;
+use crate::vk_assist::mesh_processing as mp;
+use crate::vk_assist::mesh_processing::NormalMode;
+use crate::vk_assist::structures::MeshVertex;
+use crate::vk_model::mesh_data::{MeshData, MeshLod, Submesh};
+
+/// Collapses whose normal turns by more than this (as a cosine) would visibly fold the surface, so they are rejected.
+const MIN_NORMAL_DOT: f64 = 0.25;
+/// A level that removes less than this fraction of the previous level's triangles is not worth keeping.
+const MIN_LEVEL_REDUCTION: f32 = 0.05;
+
+/// How the import-time LOD chain is built. Part of the import settings, so changing it rebuilds baked caches.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LodChainSettings {
+    /// Number of reduced levels after the full-resolution mesh.
+    pub levels: u32,
+    /// Triangle count of each level relative to the one before it.
+    pub reduction: f32,
+    /// Largest geometric error a collapse may introduce, relative to the mesh's bounding box diagonal.
+    pub max_error: f32,
+}
+
+impl Default for LodChainSettings {
+    fn default() -> LodChainSettings {
+        LodChainSettings {
+            levels: 3,
+            reduction: 0.5,
+            max_error: 0.05,
+        }
+    }
+}
+
+/// Symmetric 4x4 error quadric, upper triangle only: a2 ab ac ad b2 bc bd c2 cd d2.
+#[derive(Clone, Copy, Default)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    fn from_plane(normal: &Vec3, point: &Vec3, weight: f64) -> Quadric {
+        let (a, b, c) = (normal.x as f64, normal.y as f64, normal.z as f64);
+        let d = -(a * point.x as f64 + b * point.y as f64 + c * point.z as f64);
+        Quadric([a * a, a * b, a * c, a * d, b * b, b * c, b * d, c * c, c * d, d * d]).scaled(weight)
+    }
+
+    fn scaled(mut self, weight: f64) -> Quadric {
+        for value in self.0.iter_mut() {
+            *value *= weight;
+        }
+        self
+    }
+
+    fn add(&mut self, other: &Quadric) {
+        for (value, other) in self.0.iter_mut().zip(other.0.iter()) {
+            *value += other;
+        }
+    }
+
+    /// Sum of squared distances from `p` to the planes accumulated in the quadric.
+    fn error(&self, p: &Vec3) -> f64 {
+        let q = &self.0;
+        let (x, y, z) = (p.x as f64, p.y as f64, p.z as f64);
+        let e = q[0] * x * x + 2.0 * q[1] * x * y + 2.0 * q[2] * x * z + 2.0 * q[3] * x + q[4] * y * y + 2.0 * q[5] * y * z + 2.0 * q[6] * y
+            + q[7] * z * z
+            + 2.0 * q[8] * z
+            + q[9];
+        e.max(0.0)
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum VertexKind {
+    Interior,
+    /// On an open edge; may only slide along the border.
+    Border,
+    /// On a non-manifold edge; never moved.
+    Locked,
+}
+
+fn edge_key(a: usize, b: usize) -> (usize, usize) {
+    if a < b {
+        (a, b)
+    } else {
+        (b, a)
+    }
+}
+
+fn face_normal(a: &Vec3, b: &Vec3, c: &Vec3) -> nalgebra_glm::DVec3 {
+    let e1 = nalgebra_glm::DVec3::new((b.x - a.x) as f64, (b.y - a.y) as f64, (b.z - a.z) as f64);
+    let e2 = nalgebra_glm::DVec3::new((c.x - a.x) as f64, (c.y - a.y) as f64, (c.z - a.z) as f64);
+    e1.cross(&e2)
+}
+
+/// Quadric error metric simplification (Garland & Heckbert) using half-edge collapses, so every surviving vertex keeps
+/// its original attributes. Vertices sharing a position but not attributes (UV seams) move together and only along the
+/// seam. Returns the reduced index list, referencing `vertices`, and the largest error introduced in the same units as
+/// the positions. Stops at `target_index_count` or when the next collapse would exceed `max_error`.
+pub fn simplify(vertices: &[MeshVertex], indices: &[u32], target_index_count: usize, max_error: f32) -> (Vec<u32>, f32) {
+    // Vertices are grouped by position: the groups are what collapses, the vertices in a group are its attribute wedges.
+    let mut group_of_position: HashMap<[u32; 3], usize> = HashMap::new();
+    let mut group_position: Vec<Vec3> = vec![];
+    let group: Vec<usize> = vertices
+        .iter()
+        .map(|vertex| {
+            *group_of_position.entry(mp::position_key(&vertex.pos)).or_insert_with(|| {
+                group_position.push(vertex.pos);
+                group_position.len() - 1
+            })
+        })
+        .collect();
+    let group_count = group_position.len();
+
+    let mut triangles: Vec<[u32; 3]> = indices.chunks(3).map(|t| [t[0], t[1], t[2]]).collect();
+    let mut alive: Vec<bool> = triangles.iter().map(|t| group[t[0] as usize] != group[t[1] as usize] && group[t[1] as usize] != group[t[2] as usize] && group[t[2] as usize] != group[t[0] as usize]).collect();
+    let mut alive_count = alive.iter().filter(|&&a| a).count();
+    let target_triangles = target_index_count / 3;
+
+    let mut group_triangles: Vec<Vec<usize>> = vec![Vec::new(); group_count];
+    let mut quadrics = vec![Quadric::default(); group_count];
+    for (t, tri) in triangles.iter().enumerate() {
+        if !alive[t] {
+            continue;
+        }
+        let [a, b, c] = [group[tri[0] as usize], group[tri[1] as usize], group[tri[2] as usize]];
+        let n = face_normal(&group_position[a], &group_position[b], &group_position[c]);
+        if n.norm() > 0.0 {
+            let n = n.normalize();
+            let quadric = Quadric::from_plane(&Vec3::new(n.x as f32, n.y as f32, n.z as f32), &group_position[a], 1.0);
+            for &g in [a, b, c].iter() {
+                quadrics[g].add(&quadric);
+            }
+        }
+        for &g in [a, b, c].iter() {
+            group_triangles[g].push(t);
+        }
+    }
+
+    // Open edges get a plane perpendicular to their face, so moving a border vertex off the border is penalised.
+    let mut edge_triangles: HashMap<(usize, usize), Vec<usize>> = HashMap::new();
+    for (t, tri) in triangles.iter().enumerate() {
+        if alive[t] {
+            for k in 0..3 {
+                edge_triangles
+                    .entry(edge_key(group[tri[k] as usize], group[tri[(k + 1) % 3] as usize]))
+                    .or_default()
+                    .push(t);
+            }
+        }
+    }
+    for (&(a, b), tris) in edge_triangles.iter() {
+        if tris.len() == 1 {
+            let tri = triangles[tris[0]];
+            let n = face_normal(
+                &group_position[group[tri[0] as usize]],
+                &group_position[group[tri[1] as usize]],
+                &group_position[group[tri[2] as usize]],
+            );
+            let edge = group_position[b] - group_position[a];
+            let plane = edge.cross(&Vec3::new(n.x as f32, n.y as f32, n.z as f32));
+            if plane.norm() > 0.0 {
+                let quadric = Quadric::from_plane(&plane.normalize(), &group_position[a], 1.0);
+                quadrics[a].add(&quadric);
+                quadrics[b].add(&quadric);
+            }
+        }
+    }
+
+    let max_error_squared = (max_error as f64) * (max_error as f64);
+    let mut worst_error = 0.0_f64;
+    let mut removed = vec![false; group_count];
+
+    // Each pass collapses the cheapest edges that don't touch each other, then rebuilds the topology for the next pass.
+    while alive_count > target_triangles {
+        let mut edge_count: HashMap<(usize, usize), u32> = HashMap::new();
+        for (t, tri) in triangles.iter().enumerate() {
+            if alive[t] {
+                for k in 0..3 {
+                    *edge_count.entry(edge_key(group[tri[k] as usize], group[tri[(k + 1) % 3] as usize])).or_insert(0) += 1;
+                }
+            }
+        }
+        let mut kind = vec![VertexKind::Interior; group_count];
+        for (&(a, b), &count) in edge_count.iter() {
+            let edge_kind = match count {
+                2 => continue,
+                1 => VertexKind::Border,
+                _ => VertexKind::Locked,
+            };
+            for &g in [a, b].iter() {
+                if kind[g] != VertexKind::Locked {
+                    kind[g] = edge_kind;
+                }
+            }
+        }
+
+        let mut candidates: Vec<(f64, usize, usize)> = vec![];
+        for (&(a, b), &count) in edge_count.iter() {
+            for &(from, to) in [(a, b), (b, a)].iter() {
+                let allowed = match kind[from] {
+                    VertexKind::Interior => true,
+                    VertexKind::Border => count == 1 && kind[to] == VertexKind::Border,
+                    VertexKind::Locked => false,
+                };
+                if allowed {
+                    let mut quadric = quadrics[from];
+                    quadric.add(&quadrics[to]);
+                    let cost = quadric.error(&group_position[to]);
+                    if cost <= max_error_squared {
+                        candidates.push((cost, from, to));
+                    }
+                }
+            }
+        }
+        candidates.sort_by(|x, y| x.0.partial_cmp(&y.0).unwrap());
+
+        let mut touched = vec![false; group_count];
+        let mut collapsed = 0;
+        for &(cost, from, to) in candidates.iter() {
+            if alive_count <= target_triangles {
+                break;
+            }
+            if touched[from] || touched[to] || removed[from] || removed[to] {
+                continue;
+            }
+            let remap = match collapse_remap(&triangles, &alive, &group, &group_triangles[from], from, to) {
+                Some(remap) => remap,
+                None => continue,
+            };
+            if flips(&triangles, &alive, &group, &group_position, &group_triangles[from], from, to) {
+                continue;
+            }
+
+            let from_triangles = std::mem::take(&mut group_triangles[from]);
+            for &t in from_triangles.iter() {
+                if !alive[t] {
+                    continue;
+                }
+                if triangles[t].iter().any(|&v| group[v as usize] == to) {
+                    alive[t] = false;
+                    alive_count -= 1;
+                } else {
+                    for corner in triangles[t].iter_mut() {
+                        if group[*corner as usize] == from {
+                            *corner = remap[&*corner];
+                        }
+                    }
+                    group_triangles[to].push(t);
+                }
+            }
+            let from_quadric = quadrics[from];
+            quadrics[to].add(&from_quadric);
+            removed[from] = true;
+            worst_error = worst_error.max(cost);
+            collapsed += 1;
+
+            for &t in group_triangles[to].iter() {
+                if alive[t] {
+                    for &v in triangles[t].iter() {
+                        touched[group[v as usize]] = true;
+                    }
+                }
+            }
+        }
+
+        if collapsed == 0 {
+            break;
+        }
+    }
+
+    let mut result = Vec::with_capacity(alive_count * 3);
+    for (t, tri) in triangles.iter().enumerate() {
+        if alive[t] {
+            result.extend_from_slice(tri);
+        }
+    }
+    (result, worst_error.sqrt() as f32)
+}
+
+/// For each wedge of `from`, the wedge of `to` it becomes: the one it shares a collapsing triangle with. None when some
+/// wedge has no such triangle, which means the collapse would drag a seam across the surface.
+fn collapse_remap(triangles: &[[u32; 3]], alive: &[bool], group: &[usize], from_triangles: &[usize], from: usize, to: usize) -> Option<HashMap<u32, u32>> {
+    let mut remap: HashMap<u32, u32> = HashMap::new();
+    for &t in from_triangles.iter() {
+        if !alive[t] {
+            continue;
+        }
+        let tri = &triangles[t];
+        if let Some(&to_vertex) = tri.iter().find(|&&v| group[v as usize] == to) {
+            for &v in tri.iter().filter(|&&v| group[v as usize] == from) {
+                remap.entry(v).or_insert(to_vertex);
+            }
+        }
+    }
+    for &t in from_triangles.iter() {
+        if alive[t] && triangles[t].iter().any(|&v| group[v as usize] == from && !remap.contains_key(&v)) {
+            return None;
+        }
+    }
+    Some(remap)
+}
+
+/// True if moving `from` onto `to` would flip or fold any of the triangles that survive the collapse.
+fn flips(triangles: &[[u32; 3]], alive: &[bool], group: &[usize], positions: &[Vec3], from_triangles: &[usize], from: usize, to: usize) -> bool {
+    for &t in from_triangles.iter() {
+        if !alive[t] {
+            continue;
+        }
+        let groups = [group[triangles[t][0] as usize], group[triangles[t][1] as usize], group[triangles[t][2] as usize]];
+        if groups.contains(&to) {
+            continue;
+        }
+        let old = face_normal(&positions[groups[0]], &positions[groups[1]], &positions[groups[2]]);
+        let moved: Vec<Vec3> = groups.iter().map(|&g| if g == from { positions[to] } else { positions[g] }).collect();
+        let new = face_normal(&moved[0], &moved[1], &moved[2]);
+        let length = old.norm() * new.norm();
+        if length <= 0.0 || old.dot(&new) / length < MIN_NORMAL_DOT {
+            return true;
+        }
+    }
+    false
+}
+
+/// Builds the LOD chain of an imported mesh and appends it to the mesh's buffers. Each level is simplified from the one
+/// before it, per submesh so materials stay separate. Levels get their own vertices: UVs and colours are carried over
+/// from the source, normals are regenerated from the simplified surface with `normal_mode`.
+pub fn generate_lods(mesh: &mut MeshData, settings: &LodChainSettings, normal_mode: NormalMode) {
+    mesh.lods.clear();
+    if settings.levels == 0 || mesh.indices.is_empty() {
+        return;
+    }
+
+    // Weld on everything but the shading frame, which is rebuilt per level anyway. This lets the simplifier see hard
+    // edges and flat-shaded faces as connected surface.
+    let mut wedge_of: HashMap<[u32; 8], u32> = HashMap::new();
+    let mut wedges: Vec<MeshVertex> = vec![];
+    let wedge_index: Vec<u32> = mesh
+        .vertices
+        .iter()
+        .map(|v| {
+            let floats = [v.pos.x, v.pos.y, v.pos.z, v.color.x, v.color.y, v.color.z, v.uv.x, v.uv.y];
+            let mut key = [0u32; 8];
+            for (k, f) in key.iter_mut().zip(floats.iter()) {
+                *k = (f + 0.0).to_bits();
+            }
+            *wedge_of.entry(key).or_insert_with(|| {
+                wedges.push(*v);
+                (wedges.len() - 1) as u32
+            })
+        })
+        .collect();
+
+    let max_error = settings.max_error * (mesh.bounds.max - mesh.bounds.min).norm();
+    let mut level_indices: Vec<Vec<u32>> = mesh
+        .submeshes
+        .iter()
+        .map(|s| mesh.indices[s.first_index as usize..(s.first_index + s.index_count) as usize].iter().map(|&i| wedge_index[i as usize]).collect())
+        .collect();
+    let mut previous_count: usize = level_indices.iter().map(|indices| indices.len()).sum();
+    // Each level starts from the previous one, so errors add up along the chain.
+    let mut error = 0.0_f32;
+
+    for _ in 0..settings.levels {
+        let mut level_error = 0.0_f32;
+        for indices in level_indices.iter_mut() {
+            let target = ((indices.len() / 3) as f32 * settings.reduction).ceil() as usize * 3;
+            let (simplified, submesh_error) = simplify(&wedges, indices, target.max(3), max_error);
+            // A submesh that would vanish entirely keeps its previous level instead.
+            if !simplified.is_empty() {
+                *indices = simplified;
+            }
+            level_error = level_error.max(submesh_error);
+        }
+
+        let count: usize = level_indices.iter().map(|indices| indices.len()).sum();
+        if count as f32 > previous_count as f32 * (1.0 - MIN_LEVEL_REDUCTION) {
+            break;
+        }
+        previous_count = count;
+        error += level_error;
+        append_level(mesh, &wedges, &level_indices, error, normal_mode);
+    }
+}
+
+fn append_level(mesh: &mut MeshData, wedges: &[MeshVertex], level_indices: &[Vec<u32>], error: f32, normal_mode: NormalMode) {
+    let mut remap: HashMap<u32, u32> = HashMap::new();
+    let mut vertices = vec![];
+    let mut indices = vec![];
+    let mut ranges = vec![];
+    for submesh_indices in level_indices.iter() {
+        let first = indices.len();
+        for &wedge in submesh_indices.iter() {
+            indices.push(*remap.entry(wedge).or_insert_with(|| {
+                vertices.push(wedges[wedge as usize]);
+                (vertices.len() - 1) as u32
+            }));
+        }
+        ranges.push(first..indices.len());
+    }
+
+    mp::generate_normals(&mut vertices, &mut indices, normal_mode);
+    mp::generate_tangents(&mut vertices, &mut indices);
+    for range in ranges.iter() {
+        mesh_optimizer::optimize_vertex_cache(&mut indices[range.clone()], vertices.len());
+    }
+    mesh_optimizer::optimize_vertex_fetch(&mut vertices, &mut indices);
+
+    let base_vertex = mesh.vertices.len() as u32;
+    let base_index = mesh.indices.len() as u32;
+    mesh.vertices.extend(vertices);
+    mesh.indices.extend(indices.iter().map(|index| index + base_vertex));
+    mesh.lods.push(MeshLod {
+        submeshes: ranges
+            .iter()
+            .zip(mesh.submeshes.iter())
+            .map(|(range, submesh)| Submesh {
+                first_index: base_index + range.start as u32,
+                index_count: range.len() as u32,
+                material: submesh.material,
+            })
+            .collect(),
+        error,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vk_assist::primitives;
+    use nalgebra_glm::Vec2;
+
+    fn referenced(indices: &[u32]) -> Vec<u32> {
+        let mut used = indices.to_vec();
+        used.sort_unstable();
+        used.dedup();
+        used
+    }
+
+    #[test]
+    fn flat_plane_simplifies_to_the_target() {
+        let plane = primitives::plane(2.0, 2.0, 8, 8);
+        assert_eq!(plane.indices.len(), 8 * 8 * 6);
+        let (indices, error) = simplify(&plane.vertices, &plane.indices, 96, 0.01);
+        assert!(indices.len() <= 96, "{} indices left", indices.len());
+        assert!(!indices.is_empty());
+        assert!(error <= 0.01);
+        assert!(indices.iter().all(|&index| (index as usize) < plane.vertices.len()));
+    }
+
+    #[test]
+    fn curved_surfaces_stop_at_the_max_error() {
+        let sphere = primitives::icosphere(1.0, 2);
+        let (indices, error) = simplify(&sphere.vertices, &sphere.indices, 3, 0.02);
+        assert!(error <= 0.02);
+        // A sphere can't lose most of its triangles without moving its surface by more than that.
+        let (left, total) = (indices.len(), sphere.indices.len());
+        assert!(left > total / 4, "{} of {} indices left", left, total);
+        let (_, loose_error) = simplify(&sphere.vertices, &sphere.indices, 3, 1.0);
+        assert!(loose_error > 0.02);
+    }
+
+    #[test]
+    fn border_corners_are_kept() {
+        let plane = primitives::plane(2.0, 2.0, 8, 8);
+        let (indices, _) = simplify(&plane.vertices, &plane.indices, 3, 0.01);
+        let positions: Vec<Vec3> = referenced(&indices).iter().map(|&index| plane.vertices[index as usize].pos).collect();
+        for &(x, z) in [(-1.0, -1.0), (-1.0, 1.0), (1.0, -1.0), (1.0, 1.0)].iter() {
+            assert!(positions.contains(&Vec3::new(x, 0.0, z)), "corner ({}, {}) was collapsed", x, z);
+        }
+        // Border vertices only slide along the border, so nothing ends up outside the original square.
+        assert!(positions.iter().all(|pos| pos.x.abs() <= 1.0 && pos.z.abs() <= 1.0 && pos.y == 0.0));
+    }
+
+    #[test]
+    fn uv_seams_stay_closed() {
+        // Two halves meeting at x = 0 with different UVs, so the seam vertices share positions but not attributes.
+        let mut vertices = vec![];
+        let mut indices = vec![];
+        for &(offset, uv_offset) in [(-0.5, 0.0), (0.5, 10.0)].iter() {
+            let half = primitives::plane(1.0, 2.0, 4, 8);
+            let base = vertices.len() as u32;
+            vertices.extend(half.vertices.iter().map(|vertex| MeshVertex {
+                pos: vertex.pos + Vec3::new(offset, 0.0, 0.0),
+                uv: vertex.uv + Vec2::new(uv_offset, 0.0),
+                ..*vertex
+            }));
+            indices.extend(half.indices.iter().map(|index| index + base));
+        }
+
+        let (simplified, _) = simplify(&vertices, &indices, 3, 0.01);
+        assert!(simplified.len() < indices.len());
+        let mut seam_sides: Vec<Vec<[u32; 3]>> = vec![vec![], vec![]];
+        for triangle in simplified.chunks(3) {
+            let sides: Vec<bool> = triangle.iter().map(|&index| vertices[index as usize].uv.x >= 10.0).collect();
+            assert!(sides.iter().all(|&side| side == sides[0]), "a triangle spans the seam");
+            for &index in triangle.iter() {
+                let pos = vertices[index as usize].pos;
+                if pos.x == 0.0 {
+                    seam_sides[sides[0] as usize].push(mp::position_key(&pos));
+                }
+            }
+        }
+        for side in seam_sides.iter_mut() {
+            side.sort_unstable();
+            side.dedup();
+        }
+        assert_eq!(seam_sides[0], seam_sides[1]);
+        assert!(seam_sides[0].contains(&mp::position_key(&Vec3::new(0.0, 0.0, 1.0))));
+        assert!(seam_sides[0].contains(&mp::position_key(&Vec3::new(0.0, 0.0, -1.0))));
+    }
+
+    #[test]
+    fn generated_levels_stay_inside_the_buffers() {
+        let mut mesh = primitives::uv_sphere(1.0, 24, 12);
+        let (full_vertices, full_indices) = (mesh.vertices.len(), mesh.indices.len());
+        generate_lods(&mut mesh, &LodChainSettings::default(), NormalMode::default());
+        assert!(!mesh.lods.is_empty());
+        let mut previous = full_indices as u32;
+        for lod in mesh.lods.iter() {
+            assert_eq!(lod.submeshes.len(), mesh.submeshes.len());
+            let (first, count) = crate::vk_model::mesh_data::index_range(&lod.submeshes);
+            assert!(first as usize >= full_indices);
+            assert!((first + count) as usize <= mesh.indices.len());
+            assert!(count < previous);
+            previous = count;
+            for &index in mesh.indices[first as usize..(first + count) as usize].iter() {
+                assert!((index as usize) >= full_vertices && (index as usize) < mesh.vertices.len());
+            }
+        }
+        assert!(mesh.lods.windows(2).all(|pair| pair[0].error <= pair[1].error));
+    }
+
+    #[test]
+    fn no_levels_without_indices_or_levels() {
+        let settings = LodChainSettings::default();
+        let mut mesh = primitives::cube(1.0);
+        generate_lods(&mut mesh, &LodChainSettings { levels: 0, ..settings }, NormalMode::default());
+        assert!(mesh.lods.is_empty());
+        let mut empty = MeshData::new(vec![], vec![]);
+        generate_lods(&mut empty, &settings, NormalMode::default());
+        assert!(empty.lods.is_empty());
+    }
+}
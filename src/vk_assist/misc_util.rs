@@ -31,7 +31,7 @@ use app::debug::ValidationInfo;
 use ash::vk::make_version;
 pub const APPLICATION_VERSION: u32 = make_version(1, 0, 0);
 pub const ENGINE_VERSION: u32 = make_version(1, 0, 0);
-pub const API_VERSION: u32 = make_version(1, 0, 92);
+pub const API_VERSION: u32 = make_version(1, 1, 0);
 pub const VALIDATION: ValidationInfo = ValidationInfo {
     is_enable: true,
     required_validation_layers: ["VK_LAYER_KHRONOS_validation"],
@@ -116,54 +116,6 @@ pub fn get_max_usable_sample_count(instance: Arc<ash::Instance>, physical_device
     vk::SampleCountFlags::TYPE_1
 }
 
-pub fn create_color_resources(
-    device: Arc<VulkanDevice>,
-    swapchain_format: vk::Format,
-    swapchain_extent: vk::Extent2D,
-    msaa_samples: vk::SampleCountFlags,
-) -> img::Image {
-    let color_format = swapchain_format;
-
-    let color_image = img::Image::new(
-        device.clone(),
-        swapchain_extent.width,
-        swapchain_extent.height,
-        1,
-        msaa_samples,
-        color_format,
-        vk::ImageTiling::OPTIMAL,
-        vk::ImageUsageFlags::TRANSIENT_ATTACHMENT | vk::ImageUsageFlags::COLOR_ATTACHMENT,
-        vk::MemoryPropertyFlags::DEVICE_LOCAL,
-    );
-
-    color_image
-}
-
-pub fn create_depth_resources(
-    instance: Arc<ash::Instance>,
-    device: Arc<VulkanDevice>,
-    physical_device: vk::PhysicalDevice,
-    _command_pool: vk::CommandPool,
-    _submit_queue: vk::Queue,
-    swapchain_extent: vk::Extent2D,
-    msaa_samples: vk::SampleCountFlags,
-) -> img::Image {
-    let depth_format = find_depth_format(instance.clone(), physical_device);
-    let depth_image = img::Image::new_depth_map(
-        device.clone(),
-        swapchain_extent.width,
-        swapchain_extent.height,
-        1,
-        msaa_samples,
-        depth_format,
-        vk::ImageTiling::OPTIMAL,
-        vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
-        vk::MemoryPropertyFlags::DEVICE_LOCAL,
-    );
-
-    depth_image
-}
-
 pub fn reallocate_command_buffer(device: Arc<VulkanDevice>, command_pool: vk::CommandPool) -> vk::CommandBuffer {
     //Allocate
     let command_buffer_allocate_info = vk::CommandBufferAllocateInfo {
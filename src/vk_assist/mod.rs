@@ -1,4 +1,16 @@
+pub mod gltf_loader;
+pub mod mesh_cache;
+pub mod mesh_export;
+pub mod mesh_optimizer;
+pub mod mesh_processing;
+pub mod mesh_simplify;
 pub mod misc_util;
 pub mod model_loader;
+pub mod ply_loader;
+pub mod primitives;
+pub mod render_graph;
+pub mod shader_compiler;
+pub mod spirv_reflect;
+pub mod stl_loader;
 pub mod structures;
 pub mod types;
@@ -14,47 +14,508 @@ use memoffset::offset_of;
 use nalgebra_glm::{identity, look_at, perspective};
 use nalgebra_glm::{Mat4, Vec2, Vec3, Vec4};
 
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::ffi::CString;
+use std::fs;
+use std::io::BufReader;
 use std::path::Path;
 use std::ptr;
 
 use image::GenericImageView;
 
+use crate::app::tools;
+use vk_assist::mesh_cache;
+use vk_assist::mesh_optimizer;
+use vk_assist::mesh_processing as mp;
+use vk_assist::mesh_processing::NormalMode;
+use vk_assist::mesh_simplify;
+use vk_assist::mesh_simplify::LodChainSettings;
 use vk_assist::misc_util as misc;
-use vk_assist::structures::{get_rect_as_intermediate, UniformBufferObject, Vertex};
+use vk_assist::ply_loader;
+use vk_assist::stl_loader;
+use vk_assist::structures::{get_rect_as_intermediate, MeshVertex, UniformBufferObject, Vertex};
 use vk_assist::types::buffer as bfr;
 use vk_assist::types::command as cmd;
 use vk_assist::types::image as img;
 use vk_assist::types::{buffer, command, vulkan_device, vulkan_device::VulkanDevice, vulkan_surface::VulkanSurface, vulkan_swap_chain::*};
 use vk_model::advanced_model::*;
+use vk_model::bounds::Aabb;
+use vk_model::mesh_data::{MaterialRef, MeshData, Submesh};
 use vk_model::MeshSize;
 
-pub fn load_model(model_path: &Path, diffuse_tex: img::Image) -> GFXModel {
-    let model_obj = tobj::load_obj(model_path).expect("Failed to load model object!");
+/// Bump whenever import processing changes in a way that affects the output, so baked caches get rebuilt.
+pub const IMPORTER_VERSION: u32 = 4;
+
+/// What to do with meshes that come without texture coordinates.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MissingUvs {
+    /// Every vertex gets (0, 0), which samples a single texel.
+    Zero,
+    /// Projected along the axis in which the mesh is thinnest, stretched over 0..1 on the other two.
+    Planar,
+}
+
+/// Up axis of the source data. The renderer is Y-up.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum UpAxis {
+    Y,
+    /// Rotated so +Z becomes +Y, as for most CAD and Blender exports.
+    Z,
+}
+
+/// Everything that shapes an import. All of it goes into the baked cache key.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImportOptions {
+    pub normal_mode: NormalMode,
+    pub lod_chain: LodChainSettings,
+    pub missing_uvs: MissingUvs,
+    /// Read the `v x y z r g b` extension of OBJ into `MeshVertex::color`. PLY colours are always read.
+    pub vertex_colors: bool,
+    pub up_axis: UpAxis,
+    /// Positions are multiplied by this, e.g. 0.01 for a source in centimetres.
+    pub unit_scale: f32,
+    /// Reverse the triangles of sources wound clockwise.
+    pub flip_winding: bool,
+    /// Move the centre of the bounding box to the origin.
+    pub recenter: bool,
+}
+
+impl Default for ImportOptions {
+    fn default() -> ImportOptions {
+        ImportOptions {
+            normal_mode: NormalMode::default(),
+            lod_chain: LodChainSettings::default(),
+            missing_uvs: MissingUvs::Planar,
+            vertex_colors: true,
+            up_axis: UpAxis::Y,
+            unit_scale: 1.0,
+            flip_winding: false,
+            recenter: false,
+        }
+    }
+}
+
+/// A mesh import's result along with the problems the importer worked around, for the caller to report.
+#[derive(Clone, Debug)]
+pub struct ImportedMesh {
+    pub mesh: MeshData,
+    pub warnings: Vec<String>,
+}
+
+pub fn load_model(model_path: &Path) -> Result<(GFXModel, Vec<String>), String> {
+    load_model_with_options(model_path, &ImportOptions::default())
+}
+
+pub fn load_model_with_normals(model_path: &Path, normal_mode: NormalMode) -> Result<(GFXModel, Vec<String>), String> {
+    let options = ImportOptions {
+        normal_mode,
+        ..ImportOptions::default()
+    };
+    load_model_with_options(model_path, &options)
+}
+
+/// The model and its import warnings.
+pub fn load_model_with_options(model_path: &Path, options: &ImportOptions) -> Result<(GFXModel, Vec<String>), String> {
+    let imported = load_mesh_data(model_path, options)?;
+    Ok((GFXModel::from_mesh_data(imported.mesh), imported.warnings))
+}
+
+/// Loads the baked copy of `model_path` when it is up to date, otherwise imports the source file and writes a new baked
+/// copy. A cache hit has no warnings, they were returned when the copy was baked.
+pub fn load_mesh_data(model_path: &Path, options: &ImportOptions) -> Result<ImportedMesh, String> {
+    let settings_hash = tools::hash_bytes(format!("{:?}", options).as_bytes());
+    let key = mesh_cache::CacheKey::for_source(model_path, settings_hash, IMPORTER_VERSION)
+        .map_err(|err| format!("Failed to read model {:?}: {}", model_path, err))?;
+    let cache_path = mesh_cache::cache_path(model_path);
+
+    if let Some(mesh) = mesh_cache::read_mesh_cache(&cache_path, &key) {
+        return Ok(ImportedMesh { mesh, warnings: vec![] });
+    }
+
+    let imported = import_mesh(model_path, options)?;
+    if let Err(err) = mesh_cache::write_mesh_cache(&cache_path, &key, &imported.mesh) {
+        println!("Failed to write mesh cache {:?}: {}", cache_path, err);
+    }
+    Ok(imported)
+}
+
+/// Imports OBJ, PLY or STL, picked by file extension. Problems the importer can work around come back as warnings,
+/// unreadable or malformed files as errors.
+pub fn import_mesh(model_path: &Path, options: &ImportOptions) -> Result<ImportedMesh, String> {
+    let extension = model_path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+    match extension.as_str() {
+        "obj" => import_obj(model_path, options),
+        "ply" => import_ply(model_path, options),
+        "stl" => import_stl(model_path, options),
+        _ => Err(format!("Unsupported model format {:?}", model_path)),
+    }
+}
+
+/// Applies the axis conversion and unit scale to positions and normals, and the winding flip to `indices`.
+fn convert_space(vertices: &mut [MeshVertex], indices: &mut [u32], options: &ImportOptions) {
+    for vertex in vertices.iter_mut() {
+        if options.up_axis == UpAxis::Z {
+            vertex.pos = Vec3::new(vertex.pos.x, vertex.pos.z, -vertex.pos.y);
+            vertex.normal = Vec3::new(vertex.normal.x, vertex.normal.z, -vertex.normal.y);
+        }
+        vertex.pos *= options.unit_scale;
+    }
+    if options.flip_winding {
+        for triangle in indices.chunks_exact_mut(3) {
+            triangle.swap(1, 2);
+        }
+    }
+}
+
+/// Fills in texture coordinates for a mesh that has none.
+fn fill_missing_uvs(vertices: &mut [MeshVertex], missing_uvs: MissingUvs) {
+    let bounds = Aabb::from_vertices(vertices);
+    let extent = bounds.max - bounds.min;
+    // Project along the thinnest axis, onto the other two in order.
+    let thinnest = (0..3).min_by(|&a, &b| extent[a].partial_cmp(&extent[b]).unwrap()).unwrap_or(2);
+    let (u_axis, v_axis) = match thinnest {
+        0 => (2, 1),
+        1 => (0, 2),
+        _ => (0, 1),
+    };
+    for vertex in vertices.iter_mut() {
+        vertex.uv = match missing_uvs {
+            MissingUvs::Zero => Vec2::zeros(),
+            MissingUvs::Planar => {
+                let along = |axis: usize| {
+                    if extent[axis] > 0.0 {
+                        (vertex.pos[axis] - bounds.min[axis]) / extent[axis]
+                    } else {
+                        0.0
+                    }
+                };
+                Vec2::new(along(u_axis), along(v_axis))
+            }
+        };
+    }
+}
+
+/// Colours from `v x y z r g b` lines, keyed by the exact bits of the position since tobj doesn't keep position indices.
+fn read_obj_vertex_colors(source: &str) -> HashMap<[u32; 3], Vec3> {
+    let mut colors = HashMap::new();
+    for line in source.lines() {
+        let mut words = line.split_whitespace();
+        if words.next() != Some("v") {
+            continue;
+        }
+        let values: Vec<f32> = words.filter_map(|word| word.parse().ok()).collect();
+        if values.len() >= 6 {
+            colors.insert(
+                [values[0].to_bits(), values[1].to_bits(), values[2].to_bits()],
+                Vec3::new(values[3], values[4], values[5]),
+            );
+        }
+    }
+    colors
+}
+
+/// Checks every face corner refers to a position, texture coordinate and normal defined before it, which tobj indexes
+/// without checking.
+fn check_obj_faces(source: &str) -> Result<(), String> {
+    let mut counts = [0i64; 3];
+    for (line_number, line) in source.lines().enumerate() {
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("v") => counts[0] += 1,
+            Some("vt") => counts[1] += 1,
+            Some("vn") => counts[2] += 1,
+            Some("f") => {
+                for corner in words {
+                    for (kind, index) in corner.split('/').enumerate().take(3) {
+                        if index.is_empty() && kind > 0 {
+                            continue;
+                        }
+                        let index: i64 = index.parse().map_err(|_| format!("line {}: bad face corner '{}'", line_number + 1, corner))?;
+                        let resolved = if index < 0 { counts[kind] + index } else { index - 1 };
+                        if index == 0 || resolved < 0 || resolved >= counts[kind] {
+                            return Err(format!("line {}: face corner '{}' refers to an undefined vertex", line_number + 1, corner));
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Parses an OBJ, keeping the file's normals where it has them and generating them with `normal_mode` where it does not.
+/// Tangents are always generated from the final normals and UVs. Each OBJ object becomes a submesh. The LOD chain is
+/// built last, from the optimised mesh. A missing or broken MTL file only costs the materials.
+pub fn import_obj(model_path: &Path, options: &ImportOptions) -> Result<ImportedMesh, String> {
+    let source = fs::read_to_string(model_path).map_err(|err| format!("Failed to read OBJ model {:?}: {}", model_path, err))?;
+    check_obj_faces(&source).map_err(|err| format!("Failed to parse OBJ model {:?}: {}", model_path, err))?;
+    let mut warnings = vec![];
+    let material_warnings = RefCell::new(vec![]);
+    let model_obj = tobj::load_obj_buf(&mut BufReader::new(source.as_bytes()), |mtl_path| {
+        let full_path = model_path.parent().map_or(mtl_path.to_path_buf(), |parent| parent.join(mtl_path));
+        tobj::load_mtl(&full_path).or_else(|err| {
+            material_warnings
+                .borrow_mut()
+                .push(format!("material library {:?} could not be loaded ({:?}), using no materials", full_path, err));
+            Ok((vec![], HashMap::new()))
+        })
+    })
+    .map_err(|err| format!("Failed to parse OBJ model {:?}: {:?}", model_path, err))?;
+    warnings.extend(material_warnings.into_inner());
+    let colors = if options.vertex_colors {
+        read_obj_vertex_colors(&source)
+    } else {
+        HashMap::new()
+    };
 
     let mut vertices = vec![];
     let mut indices = vec![];
+    let mut submesh_materials = vec![];
 
-    let (models, _) = model_obj;
+    let (models, materials) = model_obj;
     for m in models.iter() {
         let mesh = &m.mesh;
+        let total_vertices_count = mesh.positions.len() / 3;
 
-        if mesh.texcoords.len() == 0 {
-            panic!("Missing texture coordinate for the model.")
+        let has_uvs = mesh.texcoords.len() == total_vertices_count * 2;
+        if !has_uvs {
+            warnings.push(if mesh.texcoords.is_empty() {
+                format!("'{}' has no texture coordinates, generating {:?} ones", m.name, options.missing_uvs)
+            } else {
+                format!(
+                    "'{}' has texture coordinates on only some faces, replacing them with {:?} ones",
+                    m.name, options.missing_uvs
+                )
+            });
+        }
+        let has_normals = mesh.normals.len() == mesh.positions.len();
+        if !has_normals && !mesh.normals.is_empty() {
+            warnings.push(format!("'{}' has normals on only some faces, generating all of them", m.name));
         }
 
-        let total_vertices_count = mesh.positions.len() / 3;
+        let mut mesh_vertices = vec![];
         for i in 0..total_vertices_count {
-            let vertex = Vertex {
-                pos: Vec3::new(mesh.positions[i * 3], mesh.positions[i * 3 + 1], mesh.positions[i * 3 + 2]),
-                color: Vec3::new(1.0, 1.0, 1.0),
-                uv: Vec2::new(mesh.texcoords[i * 2], mesh.texcoords[i * 2 + 1]),
+            let pos = Vec3::new(mesh.positions[i * 3], mesh.positions[i * 3 + 1], mesh.positions[i * 3 + 2]);
+            let color = colors.get(&[pos.x.to_bits(), pos.y.to_bits(), pos.z.to_bits()]).cloned();
+            let uv = if has_uvs {
+                Vec2::new(mesh.texcoords[i * 2], mesh.texcoords[i * 2 + 1])
+            } else {
+                Vec2::zeros()
             };
-            vertices.push(vertex);
+            let mut vertex = MeshVertex::new(pos, color.unwrap_or_else(|| Vec3::new(1.0, 1.0, 1.0)), uv);
+            if has_normals {
+                let normal = Vec3::new(mesh.normals[i * 3], mesh.normals[i * 3 + 1], mesh.normals[i * 3 + 2]);
+                vertex.normal = if normal.norm() > 0.0 { normal.normalize() } else { normal };
+            }
+            mesh_vertices.push(vertex);
         }
 
-        indices = mesh.indices.clone();
+        let mut mesh_indices = mesh.indices.clone();
+        if mesh_indices.len() % 3 != 0 {
+            warnings.push(format!("'{}' has an index count that isn't a multiple of 3, dropping the remainder", m.name));
+            mesh_indices.truncate(mesh_indices.len() / 3 * 3);
+        }
+        convert_space(&mut mesh_vertices, &mut mesh_indices, options);
+        if !has_uvs {
+            fill_missing_uvs(&mut mesh_vertices, options.missing_uvs);
+        }
+        if !has_normals {
+            mp::generate_normals(&mut mesh_vertices, &mut mesh_indices, options.normal_mode);
+        }
+
+        let base_vertex = vertices.len() as u32;
+        vertices.extend(mesh_vertices);
+        indices.extend(mesh_indices.iter().map(|index| index + base_vertex));
+        submesh_materials.push((mesh_indices.len(), mesh.material_id));
+    }
+
+    let mut submeshes = vec![];
+    let mut first_index = 0;
+    for &(index_count, material) in submesh_materials.iter() {
+        submeshes.push(Submesh {
+            first_index,
+            index_count: index_count as u32,
+            material: material.map(|id| id as u32),
+        });
+        first_index += index_count as u32;
+    }
+
+    let model_dir = model_path.parent().unwrap_or_else(|| Path::new(""));
+    let materials = materials
+        .iter()
+        .map(|material| MaterialRef {
+            name: material.name.clone(),
+            // MTL paths are relative to the OBJ, keep them usable from anywhere.
+            diffuse_texture: if material.diffuse_texture.is_empty() {
+                String::new()
+            } else {
+                model_dir.join(&material.diffuse_texture).to_string_lossy().into_owned()
+            },
+        })
+        .collect();
+    Ok(ImportedMesh {
+        mesh: finish_import(vertices, indices, submeshes, materials, options),
+        warnings,
+    })
+}
+
+/// Reads an ascii or binary PLY as a single submesh. Vertex colours go into `color`, missing normals are generated.
+pub fn import_ply(model_path: &Path, options: &ImportOptions) -> Result<ImportedMesh, String> {
+    let ply = ply_loader::load_ply(model_path).map_err(|err| format!("Failed to load PLY model {:?}: {}", model_path, err))?;
+    let mut warnings = vec![];
+    let mut vertices = ply.vertices;
+    let mut indices = ply.indices;
+    convert_space(&mut vertices, &mut indices, options);
+    if !ply.has_uvs {
+        warnings.push(format!("no texture coordinates, generating {:?} ones", options.missing_uvs));
+        fill_missing_uvs(&mut vertices, options.missing_uvs);
+    }
+    if !ply.has_normals {
+        mp::generate_normals(&mut vertices, &mut indices, options.normal_mode);
+    }
+    let submeshes = vec![Submesh {
+        first_index: 0,
+        index_count: indices.len() as u32,
+        material: None,
+    }];
+    Ok(ImportedMesh {
+        mesh: finish_import(vertices, indices, submeshes, vec![], options),
+        warnings,
+    })
+}
+
+/// Reads an ascii or binary STL as a single submesh. The facets' own normals are only used to fix their winding, shading
+/// normals are generated with `normal_mode`, which welds the unshared STL corners where the mode smooths. STL never has
+/// texture coordinates, so they are always generated.
+pub fn import_stl(model_path: &Path, options: &ImportOptions) -> Result<ImportedMesh, String> {
+    let facets = stl_loader::load_stl(model_path).map_err(|err| format!("Failed to load STL model {:?}: {}", model_path, err))?;
+    let (mut vertices, mut indices) = stl_loader::facets_to_mesh(&facets);
+    convert_space(&mut vertices, &mut indices, options);
+    fill_missing_uvs(&mut vertices, options.missing_uvs);
+    mp::generate_normals(&mut vertices, &mut indices, options.normal_mode);
+    let submeshes = vec![Submesh {
+        first_index: 0,
+        index_count: indices.len() as u32,
+        material: None,
+    }];
+    Ok(ImportedMesh {
+        mesh: finish_import(vertices, indices, submeshes, vec![], options),
+        warnings: vec![],
+    })
+}
+
+/// Shared tail of every importer: recentering, tangents, vertex cache/fetch optimisation and the LOD chain.
+fn finish_import(
+    mut vertices: Vec<MeshVertex>,
+    mut indices: Vec<u32>,
+    submeshes: Vec<Submesh>,
+    materials: Vec<MaterialRef>,
+    options: &ImportOptions,
+) -> MeshData {
+    if options.recenter {
+        let bounds = Aabb::from_vertices(&vertices);
+        let center = (bounds.min + bounds.max) * 0.5;
+        for vertex in vertices.iter_mut() {
+            vertex.pos -= center;
+        }
     }
 
-    GFXModel::new(vertices, indices, diffuse_tex)
+    mp::generate_tangents(&mut vertices, &mut indices);
+    mesh_optimizer::optimize_mesh(&mut vertices, &mut indices, &submeshes);
+
+    let mut mesh = MeshData {
+        bounds: Aabb::from_vertices(&vertices),
+        vertices,
+        indices,
+        submeshes,
+        materials,
+        lods: vec![],
+    };
+
+    mesh_simplify::generate_lods(&mut mesh, &options.lod_chain, options.normal_mode);
+    mesh
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ash_test_loader_{}_{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    const QUAD_OBJ: &str = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nf 1 2 3\nf 1 3 4\n";
+
+    #[test]
+    fn unreadable_files_are_errors() {
+        let dir = temp_dir("missing");
+        for name in ["missing.obj", "missing.ply", "missing.stl", "missing.fbx"].iter() {
+            let path = dir.join(name);
+            assert!(import_mesh(&path, &ImportOptions::default()).is_err(), "{}", name);
+            assert!(load_mesh_data(&path, &ImportOptions::default()).is_err(), "{}", name);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn malformed_files_are_errors() {
+        let dir = temp_dir("malformed");
+        let ply = dir.join("bad.ply");
+        fs::write(&ply, "ply\nformat ascii 1.0\nelement vertex 1\nproperty float x\nproperty float y\nproperty float z\nelement face 1\nproperty list uchar int vertex_indices\nend_header\n0 0 0\n3 0 1 2\n").unwrap();
+        let obj = dir.join("bad.obj");
+        fs::write(&obj, "v 0 0 0\nf 1 2 3\n").unwrap();
+        let ply_result = import_mesh(&ply, &ImportOptions::default());
+        let obj_result = import_mesh(&obj, &ImportOptions::default());
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(ply_result.unwrap_err().contains("bad.ply"));
+        assert!(obj_result.is_err());
+    }
+
+    #[test]
+    fn face_corners_must_exist() {
+        assert!(check_obj_faces(QUAD_OBJ).is_ok());
+        assert!(check_obj_faces("v 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0 0\nf -3/1 -2/1 -1/1\n").is_ok());
+        assert!(check_obj_faces("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1//1 2//1 3//1\n").is_err());
+        assert!(check_obj_faces("v 0 0 0\nf 1 2 3\nv 1 0 0\nv 0 1 0\n").is_err());
+        assert!(check_obj_faces("v 0 0 0\nf 0 -4 x\n").is_err());
+    }
+
+    #[test]
+    fn workarounds_come_back_as_warnings() {
+        let dir = temp_dir("warnings");
+        let obj = dir.join("quad.obj");
+        fs::write(&obj, format!("mtllib gone.mtl\n{}", QUAD_OBJ)).unwrap();
+        let imported = import_mesh(&obj, &ImportOptions::default());
+        fs::remove_dir_all(&dir).unwrap();
+
+        let imported = imported.unwrap();
+        assert_eq!(imported.mesh.indices.len(), 6);
+        assert_eq!(imported.warnings.len(), 2, "{:?}", imported.warnings);
+        assert!(imported.warnings.iter().any(|warning| warning.contains("gone.mtl")));
+        assert!(imported.warnings.iter().any(|warning| warning.contains("texture coordinates")));
+    }
+
+    #[test]
+    fn stl_imports_with_generated_uvs() {
+        let dir = temp_dir("stl");
+        let stl = dir.join("tri.stl");
+        fs::write(
+            &stl,
+            "solid tri\nfacet normal 0 0 1\nouter loop\nvertex 0 0 0\nvertex 1 0 0\nvertex 0 1 0\nendloop\nendfacet\nendsolid tri\n",
+        )
+        .unwrap();
+        let imported = import_mesh(&stl, &ImportOptions::default());
+        fs::remove_dir_all(&dir).unwrap();
+
+        let imported = imported.unwrap();
+        assert!(imported.warnings.is_empty());
+        assert_eq!(imported.mesh.indices.len(), 3);
+        assert!(imported.mesh.vertices.iter().any(|vertex| vertex.uv != Vec2::zeros()));
+    }
 }
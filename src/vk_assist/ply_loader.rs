@@ -0,0 +1,417 @@
+#![allow(dead_code)]
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use nalgebra_glm::{Vec2, Vec3};
+
+use crate::vk_assist::structures::MeshVertex;
+
+/// Vertices and triangle indices read from a PLY file. Polygons are triangulated as fans.
+pub struct PlyMesh {
+    pub vertices: Vec<MeshVertex>,
+    pub indices: Vec<u32>,
+    /// Whether the file had nx/ny/nz; otherwise the normals are zero and still need generating.
+    pub has_normals: bool,
+    pub has_uvs: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> io::Result<Scalar> {
+        match name {
+            "char" | "int8" => Ok(Scalar::I8),
+            "uchar" | "uint8" => Ok(Scalar::U8),
+            "short" | "int16" => Ok(Scalar::I16),
+            "ushort" | "uint16" => Ok(Scalar::U16),
+            "int" | "int32" => Ok(Scalar::I32),
+            "uint" | "uint32" => Ok(Scalar::U32),
+            "float" | "float32" => Ok(Scalar::F32),
+            "double" | "float64" => Ok(Scalar::F64),
+            _ => Err(invalid(format!("unknown PLY property type '{}'", name))),
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Scalar::I8 | Scalar::U8 => 1,
+            Scalar::I16 | Scalar::U16 => 2,
+            Scalar::I32 | Scalar::U32 | Scalar::F32 => 4,
+            Scalar::F64 => 8,
+        }
+    }
+
+    /// Full scale of the type when it holds a colour channel, so 8 bit and float colours both end up in 0..1.
+    fn color_scale(self) -> f32 {
+        match self {
+            Scalar::U8 | Scalar::I8 => 255.0,
+            Scalar::U16 | Scalar::I16 => 65535.0,
+            _ => 1.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Property {
+    name: String,
+    scalar: Scalar,
+    /// Type of the element count for list properties.
+    list_count: Option<Scalar>,
+}
+
+#[derive(Clone, Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Reads the values of one element, property by property, whatever the file format.
+struct ValueReader<'a> {
+    format: Format,
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> ValueReader<'a> {
+    fn next_token(&mut self) -> io::Result<&'a str> {
+        while self.offset < self.bytes.len() && self.bytes[self.offset].is_ascii_whitespace() {
+            self.offset += 1;
+        }
+        let start = self.offset;
+        while self.offset < self.bytes.len() && !self.bytes[self.offset].is_ascii_whitespace() {
+            self.offset += 1;
+        }
+        if start == self.offset {
+            return Err(invalid("PLY data ends early".to_string()));
+        }
+        std::str::from_utf8(&self.bytes[start..self.offset]).map_err(|_| invalid("PLY data is not ascii".to_string()))
+    }
+
+    fn value(&mut self, scalar: Scalar) -> io::Result<f64> {
+        if self.format == Format::Ascii {
+            let token = self.next_token()?;
+            return token.parse::<f64>().map_err(|_| invalid(format!("bad PLY value '{}'", token)));
+        }
+
+        let size = scalar.size();
+        if self.offset + size > self.bytes.len() {
+            return Err(invalid("PLY data ends early".to_string()));
+        }
+        let mut raw = [0u8; 8];
+        raw[..size].copy_from_slice(&self.bytes[self.offset..self.offset + size]);
+        self.offset += size;
+        if self.format == Format::BinaryBigEndian {
+            raw[..size].reverse();
+        }
+        Ok(match scalar {
+            Scalar::I8 => raw[0] as i8 as f64,
+            Scalar::U8 => raw[0] as f64,
+            Scalar::I16 => i16::from_le_bytes([raw[0], raw[1]]) as f64,
+            Scalar::U16 => u16::from_le_bytes([raw[0], raw[1]]) as f64,
+            Scalar::I32 => i32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f64,
+            Scalar::U32 => u32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f64,
+            Scalar::F32 => f32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f64,
+            Scalar::F64 => f64::from_le_bytes(raw),
+        })
+    }
+
+    /// Number of items in a list property.
+    fn list_len(&mut self, count_scalar: Scalar) -> io::Result<usize> {
+        let count = self.value(count_scalar)?;
+        if count < 0.0 || count.fract() != 0.0 {
+            return Err(invalid(format!("bad PLY list length {}", count)));
+        }
+        Ok(count as usize)
+    }
+}
+
+/// Where the values of a property go.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Target {
+    Position(usize),
+    Normal(usize),
+    Uv(usize),
+    /// Colour channel, divided by the full scale of the property's type.
+    Color(usize, f32),
+    FaceIndices,
+    Skip,
+}
+
+impl Target {
+    fn of(element: &str, property: &Property) -> Target {
+        match (element, property.list_count, property.name.as_str()) {
+            ("vertex", None, "x") => Target::Position(0),
+            ("vertex", None, "y") => Target::Position(1),
+            ("vertex", None, "z") => Target::Position(2),
+            ("vertex", None, "nx") => Target::Normal(0),
+            ("vertex", None, "ny") => Target::Normal(1),
+            ("vertex", None, "nz") => Target::Normal(2),
+            ("vertex", None, "u") | ("vertex", None, "s") | ("vertex", None, "texture_u") => Target::Uv(0),
+            ("vertex", None, "v") | ("vertex", None, "t") | ("vertex", None, "texture_v") => Target::Uv(1),
+            ("vertex", None, "red") | ("vertex", None, "r") => Target::Color(0, property.scalar.color_scale()),
+            ("vertex", None, "green") | ("vertex", None, "g") => Target::Color(1, property.scalar.color_scale()),
+            ("vertex", None, "blue") | ("vertex", None, "b") => Target::Color(2, property.scalar.color_scale()),
+            ("face", Some(_), "vertex_indices") | ("face", Some(_), "vertex_index") => Target::FaceIndices,
+            _ => Target::Skip,
+        }
+    }
+}
+
+/// A face corner as a vertex index. Negative, fractional and too large values are errors rather than wrapping.
+fn vertex_index(value: f64) -> io::Result<u32> {
+    if value < 0.0 || value.fract() != 0.0 || value > u32::MAX as f64 {
+        return Err(invalid(format!("bad PLY vertex index {}", value)));
+    }
+    Ok(value as u32)
+}
+
+fn parse_header(bytes: &[u8]) -> io::Result<(Format, Vec<Element>, usize)> {
+    let end = b"end_header";
+    let header_end = bytes
+        .windows(end.len())
+        .position(|window| window == end)
+        .ok_or_else(|| invalid("PLY header has no end_header".to_string()))?;
+    // The body starts after the line break that ends the end_header line.
+    let mut body_start = header_end + end.len();
+    while body_start < bytes.len() && bytes[body_start] != b'\n' {
+        body_start += 1;
+    }
+    body_start += 1;
+
+    let header = String::from_utf8_lossy(&bytes[..header_end]);
+    let mut lines = header.lines();
+    if lines.next().map(|line| line.trim()) != Some("ply") {
+        return Err(invalid("not a PLY file".to_string()));
+    }
+
+    let mut format = None;
+    let mut elements: Vec<Element> = vec![];
+    for line in lines {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["format", "ascii", _] => format = Some(Format::Ascii),
+            ["format", "binary_little_endian", _] => format = Some(Format::BinaryLittleEndian),
+            ["format", "binary_big_endian", _] => format = Some(Format::BinaryBigEndian),
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count.parse().map_err(|_| invalid(format!("bad PLY element count '{}'", count)))?,
+                properties: vec![],
+            }),
+            ["property", "list", count_type, item_type, name] => {
+                let element = elements.last_mut().ok_or_else(|| invalid("PLY property outside an element".to_string()))?;
+                element.properties.push(Property {
+                    name: name.to_string(),
+                    scalar: Scalar::parse(item_type)?,
+                    list_count: Some(Scalar::parse(count_type)?),
+                });
+            }
+            ["property", scalar_type, name] => {
+                let element = elements.last_mut().ok_or_else(|| invalid("PLY property outside an element".to_string()))?;
+                element.properties.push(Property {
+                    name: name.to_string(),
+                    scalar: Scalar::parse(scalar_type)?,
+                    list_count: None,
+                });
+            }
+            // comment, obj_info and blank lines
+            _ => {}
+        }
+    }
+
+    let format = format.ok_or_else(|| invalid("PLY header has no format line".to_string()))?;
+    Ok((format, elements, body_start))
+}
+
+/// Reads an ascii or binary PLY. Positions, normals (nx, ny, nz), texture coordinates (u/v, s/t or texture_u/texture_v)
+/// and colours (red, green, blue) are picked up from the vertex element; faces come from vertex_indices or
+/// vertex_index. Vertices without colour are white. Any other element is skipped.
+pub fn load_ply(path: &Path) -> io::Result<PlyMesh> {
+    parse_ply(&fs::read(path)?)
+}
+
+/// `load_ply` on the contents of a file.
+pub fn parse_ply(bytes: &[u8]) -> io::Result<PlyMesh> {
+    let (format, elements, body_start) = parse_header(bytes)?;
+    let mut reader = ValueReader {
+        format,
+        bytes,
+        offset: body_start,
+    };
+
+    let mut vertices = vec![];
+    let mut indices = vec![];
+    let mut has_normals = false;
+    let mut has_uvs = false;
+    let mut corners: Vec<u32> = vec![];
+    for element in elements.iter() {
+        let targets: Vec<Target> = element.properties.iter().map(|property| Target::of(&element.name, property)).collect();
+        if element.name == "vertex" {
+            has_normals = (0..3).all(|i| targets.contains(&Target::Normal(i)));
+            has_uvs = (0..2).all(|i| targets.contains(&Target::Uv(i)));
+        }
+
+        for _ in 0..element.count {
+            let mut position = Vec3::zeros();
+            let mut normal = Vec3::zeros();
+            let mut uv = Vec2::zeros();
+            let mut color = Vec3::new(1.0, 1.0, 1.0);
+            corners.clear();
+            for (property, &target) in element.properties.iter().zip(targets.iter()) {
+                if let Some(count_scalar) = property.list_count {
+                    for _ in 0..reader.list_len(count_scalar)? {
+                        let value = reader.value(property.scalar)?;
+                        if target == Target::FaceIndices {
+                            corners.push(vertex_index(value)?);
+                        }
+                    }
+                    continue;
+                }
+                let value = reader.value(property.scalar)? as f32;
+                match target {
+                    Target::Position(i) => position[i] = value,
+                    Target::Normal(i) => normal[i] = value,
+                    Target::Uv(i) => uv[i] = value,
+                    Target::Color(i, scale) => color[i] = value / scale,
+                    Target::FaceIndices | Target::Skip => {}
+                }
+            }
+
+            if element.name == "vertex" {
+                let mut vertex = MeshVertex::new(position, color, uv);
+                if has_normals {
+                    vertex.normal = if normal.norm() > 0.0 { normal.normalize() } else { normal };
+                }
+                vertices.push(vertex);
+            } else {
+                for i in 2..corners.len() {
+                    indices.extend_from_slice(&[corners[0], corners[i - 1], corners[i]]);
+                }
+            }
+        }
+    }
+
+    if let Some(&index) = indices.iter().find(|&&index| index as usize >= vertices.len()) {
+        return Err(invalid(format!("PLY face references vertex {} of {}", index, vertices.len())));
+    }
+    Ok(PlyMesh {
+        vertices,
+        indices,
+        has_normals,
+        has_uvs,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const XYZ: &str = "property float x\nproperty float y\nproperty float z\n";
+    const HEADER: &str = "element vertex 3\nproperty float x\nproperty float y\nproperty float z\nproperty uchar red\nproperty uchar green\nproperty uchar blue\nelement face 1\nproperty list uchar int vertex_indices\nend_header\n";
+
+    fn ascii(vertex_properties: &str, body: &str) -> Vec<u8> {
+        format!(
+            "ply\nformat ascii 1.0\ncomment test\nelement vertex 3\n{}element face 1\nproperty list uchar int vertex_indices\nend_header\n{}",
+            vertex_properties, body
+        )
+        .into_bytes()
+    }
+
+    /// The triangle (0,0,0) (1,0,0) (0,1,0) with red, green and blue corners, in binary. `float` and `int` write the byte order of `format`.
+    fn binary(format: &str, float: fn(f32) -> [u8; 4], int: fn(i32) -> [u8; 4]) -> Vec<u8> {
+        let mut data = format!("ply\nformat {} 1.0\n{}", format, HEADER).into_bytes();
+        let corners = [([0.0, 0.0, 0.0], [255, 0, 0]), ([1.0, 0.0, 0.0], [0, 255, 0]), ([0.0, 1.0, 0.0], [0, 0, 255])];
+        for (position, color) in corners.iter() {
+            for &coordinate in position.iter() {
+                data.extend_from_slice(&float(coordinate));
+            }
+            data.extend_from_slice(color);
+        }
+        data.push(3);
+        for index in 0..3 {
+            data.extend_from_slice(&int(index));
+        }
+        data
+    }
+
+    fn check_triangle(mesh: &PlyMesh) {
+        assert_eq!(mesh.indices, vec![0, 1, 2]);
+        assert_eq!(mesh.vertices[1].pos, Vec3::new(1.0, 0.0, 0.0));
+        assert_eq!(mesh.vertices[2].pos, Vec3::new(0.0, 1.0, 0.0));
+        assert_eq!(mesh.vertices[0].color, Vec3::new(1.0, 0.0, 0.0));
+        assert_eq!(mesh.vertices[2].color, Vec3::new(0.0, 0.0, 1.0));
+        assert!(!mesh.has_normals && !mesh.has_uvs);
+    }
+
+    #[test]
+    fn binary_little_endian() {
+        check_triangle(&parse_ply(&binary("binary_little_endian", f32::to_le_bytes, i32::to_le_bytes)).unwrap());
+    }
+
+    #[test]
+    fn binary_big_endian() {
+        check_triangle(&parse_ply(&binary("binary_big_endian", f32::to_be_bytes, i32::to_be_bytes)).unwrap());
+    }
+
+    #[test]
+    fn truncated_binary_is_an_error() {
+        let data = binary("binary_little_endian", f32::to_le_bytes, i32::to_le_bytes);
+        assert!(parse_ply(&data[..data.len() - 2]).is_err());
+    }
+
+    #[test]
+    fn uchar_and_float_colors_end_up_in_the_same_range() {
+        let uchar = "property float x\nproperty float y\nproperty float z\nproperty uchar red\nproperty uchar green\nproperty uchar blue\n";
+        let float = "property float x\nproperty float y\nproperty float z\nproperty float red\nproperty float green\nproperty float blue\n";
+        let uchar_mesh = parse_ply(&ascii(uchar, "0 0 0 255 51 0\n1 0 0 0 0 0\n0 1 0 0 0 0\n3 0 1 2\n")).unwrap();
+        let float_mesh = parse_ply(&ascii(float, "0 0 0 1 0.2 0\n1 0 0 0 0 0\n0 1 0 0 0 0\n3 0 1 2\n")).unwrap();
+        assert_eq!(uchar_mesh.vertices[0].color, Vec3::new(1.0, 0.2, 0.0));
+        assert_eq!(float_mesh.vertices[0].color, Vec3::new(1.0, 0.2, 0.0));
+        // Without colour properties vertices are white.
+        let plain = parse_ply(&ascii(XYZ, "0 0 0\n1 0 0\n0 1 0\n3 0 1 2\n")).unwrap();
+        assert_eq!(plain.vertices[0].color, Vec3::new(1.0, 1.0, 1.0));
+    }
+
+    #[test]
+    fn normals_uvs_and_polygons() {
+        let properties = "property float x\nproperty float y\nproperty float z\nproperty float nx\nproperty float ny\nproperty float nz\nproperty float s\nproperty float t\n";
+        let mesh = parse_ply(&ascii(properties, "0 0 0 0 0 2 0 0\n1 0 0 0 0 1 1 0\n0 1 0 0 0 1 0 1\n3 0 1 2\n")).unwrap();
+        assert!(mesh.has_normals && mesh.has_uvs);
+        assert_eq!(mesh.vertices[0].normal, Vec3::new(0.0, 0.0, 1.0));
+        assert_eq!(mesh.vertices[2].uv, Vec2::new(0.0, 1.0));
+
+        let quad = "ply\nformat ascii 1.0\nelement vertex 4\nproperty float x\nproperty float y\nproperty float z\nelement face 1\nproperty list uchar uint vertex_index\nend_header\n0 0 0\n1 0 0\n1 1 0\n0 1 0\n4 0 1 2 3\n";
+        assert_eq!(parse_ply(quad.as_bytes()).unwrap().indices, vec![0, 1, 2, 0, 2, 3]);
+    }
+
+    #[test]
+    fn bad_face_indices_are_errors() {
+        for faces in ["3 0 1 -1\n", "3 0 1 3\n", "3 0 1 1.5\n", "3 0 1 4294967296\n"].iter() {
+            let result = parse_ply(&ascii(XYZ, &format!("0 0 0\n1 0 0\n0 1 0\n{}", faces)));
+            assert!(result.is_err(), "{}", faces.trim());
+        }
+    }
+}
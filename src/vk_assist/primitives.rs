@@ -0,0 +1,552 @@
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::f32::consts::PI;
+
+use nalgebra_glm::{Vec2, Vec3};
+use serde_derive::Deserialize;
+
+use crate::vk_assist::mesh_optimizer;
+use crate::vk_assist::mesh_processing as mp;
+use crate::vk_assist::structures::MeshVertex;
+use crate::vk_model::mesh_data::MeshData;
+
+// All primitives are centred on the origin with +Y up, wound counter-clockwise when seen from outside and use the same
+// UV convention as loaded OBJs (v = 0 at the bottom). Tangents are generated, so they are ready for normal mapping.
+
+const WHITE: [f32; 3] = [1.0, 1.0, 1.0];
+
+fn vertex(pos: Vec3, normal: Vec3, uv: Vec2) -> MeshVertex {
+    let mut vertex = MeshVertex::new(pos, Vec3::new(WHITE[0], WHITE[1], WHITE[2]), uv);
+    vertex.normal = normal;
+    vertex
+}
+
+fn finish(mut vertices: Vec<MeshVertex>, mut indices: Vec<u32>) -> MeshData {
+    mp::generate_tangents(&mut vertices, &mut indices);
+    // Also drops the pole vertices no triangle ended up using.
+    mesh_optimizer::optimize_vertex_fetch(&mut vertices, &mut indices);
+    MeshData::new(vertices, indices)
+}
+
+/// A point of a profile curve in the (radius, y) plane, revolved around the Y axis by `revolve`.
+#[derive(Clone, Copy)]
+struct ProfilePoint {
+    radius: f32,
+    y: f32,
+    /// Normal in the (radial, y) plane.
+    normal: Vec2,
+    v: f32,
+}
+
+/// Revolves each strip of the profile around Y. Strips are separate so hard edges (cylinder rims) can be expressed as two
+/// strips sharing a position. Strips must run bottom to top for the winding to face outward.
+fn revolve(strips: &[Vec<ProfilePoint>], segments: u32) -> (Vec<MeshVertex>, Vec<u32>) {
+    let segments = segments.max(3);
+    let mut vertices = vec![];
+    let mut indices = vec![];
+
+    for strip in strips.iter() {
+        let base = vertices.len() as u32;
+        for point in strip.iter() {
+            for i in 0..=segments {
+                let u = i as f32 / segments as f32;
+                let phi = u * 2.0 * PI;
+                let (sin, cos) = phi.sin_cos();
+                vertices.push(vertex(
+                    Vec3::new(point.radius * cos, point.y, -point.radius * sin),
+                    Vec3::new(point.normal.x * cos, point.normal.y, -point.normal.x * sin).normalize(),
+                    Vec2::new(u, point.v),
+                ));
+            }
+        }
+
+        let row = segments + 1;
+        for j in 0..strip.len().saturating_sub(1) as u32 {
+            for i in 0..segments {
+                let a = base + j * row + i;
+                let b = a + 1;
+                let c = b + row;
+                let d = a + row;
+                // A ring of radius zero is a pole: one of the two triangles collapses, so only emit the other.
+                if strip[j as usize].radius > 0.0 {
+                    indices.extend_from_slice(&[a, b, c]);
+                }
+                if strip[j as usize + 1].radius > 0.0 {
+                    indices.extend_from_slice(&[c, d, a]);
+                }
+            }
+        }
+    }
+
+    (vertices, indices)
+}
+
+/// Flat disc strip from the axis out to `radius` (bottom cap) or from `radius` in to the axis (top cap).
+fn cap_strip(radius: f32, y: f32, facing_up: bool) -> Vec<ProfilePoint> {
+    let normal = Vec2::new(0.0, if facing_up { 1.0 } else { -1.0 });
+    let center = ProfilePoint { radius: 0.0, y, normal, v: 0.5 };
+    let rim = ProfilePoint { radius, y, normal, v: if facing_up { 0.0 } else { 1.0 } };
+    if facing_up {
+        vec![rim, center]
+    } else {
+        vec![center, rim]
+    }
+}
+
+/// Axis aligned cube with one set of vertices per face, so every face is flat shaded and fully textured.
+pub fn cube(size: f32) -> MeshData {
+    let half = size / 2.0;
+    // (normal, u axis, v axis) per face, with u x v == normal so the quad winds counter-clockwise from outside.
+    let faces = [
+        (Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0), Vec3::new(0.0, 1.0, 0.0)),
+        (Vec3::new(-1.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0), Vec3::new(0.0, 1.0, 0.0)),
+        (Vec3::new(0.0, 1.0, 0.0), Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0)),
+        (Vec3::new(0.0, -1.0, 0.0), Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 0.0, 1.0)),
+        (Vec3::new(0.0, 0.0, 1.0), Vec3::new(1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0)),
+        (Vec3::new(0.0, 0.0, -1.0), Vec3::new(-1.0, 0.0, 0.0), Vec3::new(0.0, 1.0, 0.0)),
+    ];
+
+    let mut vertices = vec![];
+    let mut indices = vec![];
+    for (normal, u_axis, v_axis) in faces.iter() {
+        let base = vertices.len() as u32;
+        for &(u, v) in [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)].iter() {
+            let pos = (normal + u_axis * (u * 2.0 - 1.0) + v_axis * (v * 2.0 - 1.0)) * half;
+            vertices.push(vertex(pos, *normal, Vec2::new(u, v)));
+        }
+        indices.extend_from_slice(&[base, base + 1, base + 2, base + 2, base + 3, base]);
+    }
+
+    finish(vertices, indices)
+}
+
+/// Plane in XZ facing +Y, split into `subdivisions_x` by `subdivisions_z` quads.
+pub fn plane(width: f32, depth: f32, subdivisions_x: u32, subdivisions_z: u32) -> MeshData {
+    let (columns, rows) = (subdivisions_x.max(1), subdivisions_z.max(1));
+    let mut vertices = vec![];
+    let mut indices = vec![];
+
+    for j in 0..=rows {
+        for i in 0..=columns {
+            let u = i as f32 / columns as f32;
+            let v = j as f32 / rows as f32;
+            // v runs towards -Z so that x cross -z == +y keeps the winding counter-clockwise from above.
+            vertices.push(vertex(
+                Vec3::new((u - 0.5) * width, 0.0, (0.5 - v) * depth),
+                Vec3::new(0.0, 1.0, 0.0),
+                Vec2::new(u, v),
+            ));
+        }
+    }
+
+    let row = columns + 1;
+    for j in 0..rows {
+        for i in 0..columns {
+            let a = j * row + i;
+            indices.extend_from_slice(&[a, a + 1, a + 1 + row, a + 1 + row, a + row, a]);
+        }
+    }
+
+    finish(vertices, indices)
+}
+
+/// Latitude/longitude sphere with `segments` around and `rings` from pole to pole.
+pub fn uv_sphere(radius: f32, segments: u32, rings: u32) -> MeshData {
+    let rings = rings.max(2);
+    let strip: Vec<ProfilePoint> = (0..=rings)
+        .map(|j| {
+            let v = j as f32 / rings as f32;
+            let theta = PI * (1.0 - v);
+            let normal = Vec2::new(theta.sin(), theta.cos());
+            ProfilePoint {
+                radius: if j == 0 || j == rings { 0.0 } else { radius * normal.x },
+                y: radius * normal.y,
+                normal,
+                v,
+            }
+        })
+        .collect();
+
+    let (vertices, indices) = revolve(&[strip], segments);
+    finish(vertices, indices)
+}
+
+/// Sphere built by subdividing an icosahedron `subdivisions` times, giving evenly sized triangles without pole pinching.
+pub fn icosphere(radius: f32, subdivisions: u32) -> MeshData {
+    let t = (1.0 + 5.0_f32.sqrt()) / 2.0;
+    let mut positions: Vec<Vec3> = [
+        (-1.0, t, 0.0),
+        (1.0, t, 0.0),
+        (-1.0, -t, 0.0),
+        (1.0, -t, 0.0),
+        (0.0, -1.0, t),
+        (0.0, 1.0, t),
+        (0.0, -1.0, -t),
+        (0.0, 1.0, -t),
+        (t, 0.0, -1.0),
+        (t, 0.0, 1.0),
+        (-t, 0.0, -1.0),
+        (-t, 0.0, 1.0),
+    ]
+    .iter()
+    .map(|&(x, y, z)| Vec3::new(x, y, z).normalize())
+    .collect();
+    let mut triangles: Vec<[u32; 3]> = vec![
+        [0, 11, 5],
+        [0, 5, 1],
+        [0, 1, 7],
+        [0, 7, 10],
+        [0, 10, 11],
+        [1, 5, 9],
+        [5, 11, 4],
+        [11, 10, 2],
+        [10, 7, 6],
+        [7, 1, 8],
+        [3, 9, 4],
+        [3, 4, 2],
+        [3, 2, 6],
+        [3, 6, 8],
+        [3, 8, 9],
+        [4, 9, 5],
+        [2, 4, 11],
+        [6, 2, 10],
+        [8, 6, 7],
+        [9, 8, 1],
+    ];
+
+    for _ in 0..subdivisions {
+        let mut midpoints: HashMap<(u32, u32), u32> = HashMap::new();
+        let mut midpoint = |a: u32, b: u32, positions: &mut Vec<Vec3>| -> u32 {
+            let key = if a < b { (a, b) } else { (b, a) };
+            *midpoints.entry(key).or_insert_with(|| {
+                positions.push(((positions[a as usize] + positions[b as usize]) * 0.5).normalize());
+                (positions.len() - 1) as u32
+            })
+        };
+
+        let mut subdivided = Vec::with_capacity(triangles.len() * 4);
+        for &[a, b, c] in triangles.iter() {
+            let ab = midpoint(a, b, &mut positions);
+            let bc = midpoint(b, c, &mut positions);
+            let ca = midpoint(c, a, &mut positions);
+            subdivided.extend_from_slice(&[[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]);
+        }
+        triangles = subdivided;
+    }
+
+    let spherical_uv = |n: &Vec3| {
+        let u = (-n.z).atan2(n.x) / (2.0 * PI);
+        Vec2::new(if u < 0.0 { u + 1.0 } else { u }, 0.5 + n.y.clamp(-1.0, 1.0).asin() / PI)
+    };
+    let mut vertices: Vec<MeshVertex> = positions.iter().map(|n| vertex(n * radius, *n, spherical_uv(n))).collect();
+
+    // Triangles straddling the u seam would interpolate across the whole texture; give them their own wrapped copies.
+    let mut wrapped: HashMap<u32, u32> = HashMap::new();
+    let mut indices = Vec::with_capacity(triangles.len() * 3);
+    for triangle in triangles.iter() {
+        let us: Vec<f32> = triangle.iter().map(|&i| vertices[i as usize].uv.x).collect();
+        let max_u = us.iter().cloned().fold(0.0, f32::max);
+        for (k, &index) in triangle.iter().enumerate() {
+            if max_u - us[k] > 0.5 {
+                let copy = *wrapped.entry(index).or_insert_with(|| {
+                    let mut v = vertices[index as usize];
+                    v.uv.x += 1.0;
+                    vertices.push(v);
+                    (vertices.len() - 1) as u32
+                });
+                indices.push(copy);
+            } else {
+                indices.push(index);
+            }
+        }
+    }
+
+    finish(vertices, indices)
+}
+
+/// Capped cylinder along Y with hard edges around the rims.
+pub fn cylinder(radius: f32, height: f32, segments: u32) -> MeshData {
+    let half = height / 2.0;
+    let side_normal = Vec2::new(1.0, 0.0);
+    let strips = vec![
+        cap_strip(radius, -half, false),
+        vec![
+            ProfilePoint { radius, y: -half, normal: side_normal, v: 0.0 },
+            ProfilePoint { radius, y: half, normal: side_normal, v: 1.0 },
+        ],
+        cap_strip(radius, half, true),
+    ];
+
+    let (vertices, indices) = revolve(&strips, segments);
+    finish(vertices, indices)
+}
+
+/// Cone along Y with its base at -height/2 and its apex at +height/2.
+pub fn cone(radius: f32, height: f32, segments: u32) -> MeshData {
+    let half = height / 2.0;
+    let slant_normal = Vec2::new(height, radius).normalize();
+    let strips = vec![
+        cap_strip(radius, -half, false),
+        vec![
+            ProfilePoint { radius, y: -half, normal: slant_normal, v: 0.0 },
+            ProfilePoint { radius: 0.0, y: half, normal: slant_normal, v: 1.0 },
+        ],
+    ];
+
+    let (vertices, indices) = revolve(&strips, segments);
+    finish(vertices, indices)
+}
+
+/// Torus lying in XZ. `major_radius` is to the centre of the tube, `minor_radius` is the tube's radius.
+pub fn torus(major_radius: f32, minor_radius: f32, segments: u32, tube_segments: u32) -> MeshData {
+    let tube_segments = tube_segments.max(3);
+    // Starts on the inside of the ring and goes down, out, up and back in, which is bottom to top on the outer half.
+    let strip: Vec<ProfilePoint> = (0..=tube_segments)
+        .map(|j| {
+            let v = j as f32 / tube_segments as f32;
+            let alpha = PI + v * 2.0 * PI;
+            let normal = Vec2::new(alpha.cos(), alpha.sin());
+            ProfilePoint {
+                radius: major_radius + minor_radius * normal.x,
+                y: minor_radius * normal.y,
+                normal,
+                v,
+            }
+        })
+        .collect();
+
+    let (vertices, indices) = revolve(&[strip], segments);
+    finish(vertices, indices)
+}
+
+/// Capsule along Y: a cylinder of `height` between two hemispheres, so the total height is `height + 2 * radius`.
+pub fn capsule(radius: f32, height: f32, segments: u32, hemisphere_rings: u32) -> MeshData {
+    let half = height / 2.0;
+    let rings = hemisphere_rings.max(1);
+    let total_length = height + PI * radius;
+
+    let mut strip = vec![];
+    let mut arc = 0.0;
+    for j in 0..=rings {
+        let theta = PI - (j as f32 / rings as f32) * (PI / 2.0);
+        let normal = Vec2::new(theta.sin(), theta.cos());
+        arc = (j as f32 / rings as f32) * (PI / 2.0) * radius;
+        strip.push(ProfilePoint {
+            radius: if j == 0 { 0.0 } else { radius * normal.x },
+            y: -half + radius * normal.y,
+            normal,
+            v: arc / total_length,
+        });
+    }
+    for j in 0..=rings {
+        let theta = (PI / 2.0) - (j as f32 / rings as f32) * (PI / 2.0);
+        let normal = Vec2::new(theta.sin(), theta.cos());
+        let top_arc = arc + height + (j as f32 / rings as f32) * (PI / 2.0) * radius;
+        strip.push(ProfilePoint {
+            radius: if j == rings { 0.0 } else { radius * normal.x },
+            y: half + radius * normal.y,
+            normal,
+            v: top_arc / total_length,
+        });
+    }
+
+    let (vertices, indices) = revolve(&[strip], segments);
+    finish(vertices, indices)
+}
+
+/// One of the shapes above with its parameters, for when which shape to build is data, like a manifest entry. It
+/// deserializes with the shape's name in `shape` next to the parameters, e.g. `{ "shape": "cube", "size": 1.0 }`. Sizes
+/// are in model units, the counts are how finely curves are split.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(tag = "shape", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Primitive {
+    Cube {
+        size: f32,
+    },
+    Plane {
+        width: f32,
+        depth: f32,
+        subdivisions_x: u32,
+        subdivisions_z: u32,
+    },
+    UvSphere {
+        radius: f32,
+        segments: u32,
+        rings: u32,
+    },
+    Icosphere {
+        radius: f32,
+        subdivisions: u32,
+    },
+    Cylinder {
+        radius: f32,
+        height: f32,
+        segments: u32,
+    },
+    Cone {
+        radius: f32,
+        height: f32,
+        segments: u32,
+    },
+    Torus {
+        major_radius: f32,
+        minor_radius: f32,
+        segments: u32,
+        tube_segments: u32,
+    },
+    Capsule {
+        radius: f32,
+        height: f32,
+        segments: u32,
+        hemisphere_rings: u32,
+    },
+}
+
+impl Primitive {
+    /// The shape's lengths by parameter name, which all have to be above 0 for a usable mesh.
+    pub fn sizes(&self) -> Vec<(&'static str, f32)> {
+        match *self {
+            Primitive::Cube { size } => vec![("size", size)],
+            Primitive::Plane { width, depth, .. } => vec![("width", width), ("depth", depth)],
+            Primitive::UvSphere { radius, .. } | Primitive::Icosphere { radius, .. } => vec![("radius", radius)],
+            Primitive::Cylinder { radius, height, .. } | Primitive::Cone { radius, height, .. } | Primitive::Capsule { radius, height, .. } => {
+                vec![("radius", radius), ("height", height)]
+            }
+            Primitive::Torus {
+                major_radius, minor_radius, ..
+            } => vec![("major_radius", major_radius), ("minor_radius", minor_radius)],
+        }
+    }
+
+    pub fn mesh(&self) -> MeshData {
+        match *self {
+            Primitive::Cube { size } => cube(size),
+            Primitive::Plane {
+                width,
+                depth,
+                subdivisions_x,
+                subdivisions_z,
+            } => plane(width, depth, subdivisions_x, subdivisions_z),
+            Primitive::UvSphere { radius, segments, rings } => uv_sphere(radius, segments, rings),
+            Primitive::Icosphere { radius, subdivisions } => icosphere(radius, subdivisions),
+            Primitive::Cylinder { radius, height, segments } => cylinder(radius, height, segments),
+            Primitive::Cone { radius, height, segments } => cone(radius, height, segments),
+            Primitive::Torus {
+                major_radius,
+                minor_radius,
+                segments,
+                tube_segments,
+            } => torus(major_radius, minor_radius, segments, tube_segments),
+            Primitive::Capsule {
+                radius,
+                height,
+                segments,
+                hemisphere_rings,
+            } => capsule(radius, height, segments, hemisphere_rings),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn all() -> Vec<Primitive> {
+        vec![
+            Primitive::Cube { size: 2.0 },
+            Primitive::Plane {
+                width: 4.0,
+                depth: 2.0,
+                subdivisions_x: 3,
+                subdivisions_z: 2,
+            },
+            Primitive::UvSphere {
+                radius: 1.0,
+                segments: 16,
+                rings: 8,
+            },
+            Primitive::Icosphere { radius: 1.0, subdivisions: 2 },
+            Primitive::Cylinder {
+                radius: 1.0,
+                height: 2.0,
+                segments: 12,
+            },
+            Primitive::Cone {
+                radius: 1.0,
+                height: 2.0,
+                segments: 12,
+            },
+            Primitive::Torus {
+                major_radius: 2.0,
+                minor_radius: 0.5,
+                segments: 16,
+                tube_segments: 8,
+            },
+            Primitive::Capsule {
+                radius: 0.5,
+                height: 1.0,
+                segments: 12,
+                hemisphere_rings: 4,
+            },
+        ]
+    }
+
+    #[test]
+    fn meshes_are_indexed_triangles_with_unit_normals() {
+        for primitive in all() {
+            let mesh = primitive.mesh();
+            assert!(!mesh.indices.is_empty() && mesh.indices.len() % 3 == 0, "{:?}", primitive);
+            assert!(mesh.indices.iter().all(|&i| (i as usize) < mesh.vertices.len()), "{:?}", primitive);
+            for vertex in mesh.vertices.iter() {
+                assert!((vertex.normal.norm() - 1.0).abs() < 1e-3, "{:?} has normal {:?}", primitive, vertex.normal);
+                // The icosphere's copies of vertices on the u seam run past 1 so the texture repeats instead of flipping back.
+                assert!((0.0..=2.0).contains(&vertex.uv.x) && (0.0..=1.0).contains(&vertex.uv.y), "{:?}", primitive);
+            }
+        }
+    }
+
+    #[test]
+    fn triangles_wind_counter_clockwise_from_outside() {
+        // On these convex shapes every face normal points away from the centre.
+        for primitive in all()
+            .into_iter()
+            .filter(|primitive| !matches!(primitive, Primitive::Plane { .. } | Primitive::Torus { .. }))
+        {
+            let mesh = primitive.mesh();
+            for triangle in mesh.indices.chunks(3) {
+                let [a, b, c] = [0, 1, 2].map(|k| mesh.vertices[triangle[k] as usize].pos);
+                let face_normal = (b - a).cross(&(c - a));
+                if face_normal.norm() < 1e-6 {
+                    continue;
+                }
+                assert!(face_normal.dot(&((a + b + c) / 3.0)) > 0.0, "{:?} has an inward facing triangle", primitive);
+            }
+        }
+    }
+
+    #[test]
+    fn shapes_have_the_requested_size() {
+        let cube = Primitive::Cube { size: 2.0 }.mesh();
+        assert!((cube.bounds.max - Vec3::new(1.0, 1.0, 1.0)).norm() < 1e-5);
+        assert_eq!(cube.vertices.len(), 24);
+        let plane = Primitive::Plane {
+            width: 4.0,
+            depth: 2.0,
+            subdivisions_x: 3,
+            subdivisions_z: 2,
+        }
+        .mesh();
+        assert_eq!(plane.vertices.len(), 4 * 3);
+        assert_eq!(plane.indices.len(), 3 * 2 * 6);
+        assert!((plane.bounds.max - Vec3::new(2.0, 0.0, 1.0)).norm() < 1e-5);
+        let capsule = Primitive::Capsule {
+            radius: 0.5,
+            height: 1.0,
+            segments: 12,
+            hemisphere_rings: 4,
+        }
+        .mesh();
+        assert!((capsule.bounds.max.y - 1.0).abs() < 1e-5 && (capsule.bounds.min.y + 1.0).abs() < 1e-5);
+    }
+}
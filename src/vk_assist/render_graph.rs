@@ -0,0 +1,1508 @@
+#![allow(dead_code)]
+
+use crate::vk_assist;
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::sync::Arc;
+
+use ash::version::DeviceV1_0;
+use ash::vk;
+
+use std::ptr;
+
+use vk_assist::misc_util as misc;
+use vk_assist::types::command::find_memory_type;
+use vk_assist::types::image as img;
+use vk_assist::types::vulkan_device::VulkanDevice;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ResourceId(usize);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PassId(usize);
+
+/// Size of an image the graph creates.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImageExtent {
+    /// The extent the graph is compiled for, the swapchain's.
+    Full,
+    /// The compiled extent scaled by a factor, at least a pixel on each side.
+    Scaled(f32),
+    Fixed(vk::Extent2D),
+}
+
+impl ImageExtent {
+    fn resolve(self, full: vk::Extent2D) -> vk::Extent2D {
+        match self {
+            ImageExtent::Full => full,
+            ImageExtent::Scaled(scale) => vk::Extent2D {
+                width: ((full.width as f32 * scale) as u32).max(1),
+                height: ((full.height as f32 * scale) as u32).max(1),
+            },
+            ImageExtent::Fixed(extent) => extent,
+        }
+    }
+}
+
+/// An image the graph creates and owns. Its contents only live from its first to its last use in a frame, so images
+/// whose lifetimes don't overlap share memory.
+#[derive(Clone, Copy, Debug)]
+pub struct ImageDesc {
+    pub format: vk::Format,
+    pub samples: vk::SampleCountFlags,
+    pub extent: ImageExtent,
+}
+
+impl ImageDesc {
+    pub fn new(format: vk::Format, samples: vk::SampleCountFlags) -> ImageDesc {
+        ImageDesc {
+            format,
+            samples,
+            extent: ImageExtent::Full,
+        }
+    }
+
+    pub fn extent(mut self, extent: ImageExtent) -> ImageDesc {
+        self.extent = extent;
+        self
+    }
+}
+
+/// An image owned outside the graph, such as the swapchain's. With several images, frame `i` uses image `i % len`.
+#[derive(Clone, Debug)]
+pub struct ImportedImage {
+    pub images: Vec<vk::Image>,
+    pub views: Vec<vk::ImageView>,
+    pub format: vk::Format,
+    pub samples: vk::SampleCountFlags,
+    pub extent: vk::Extent2D,
+    /// Layout of the image when a frame starts, and the stage and accesses that last wrote it.
+    pub initial_layout: vk::ImageLayout,
+    pub initial_stage: vk::PipelineStageFlags,
+    pub initial_access: vk::AccessFlags,
+    /// Layout the image is left in when a frame ends.
+    pub final_layout: vk::ImageLayout,
+}
+
+impl ImportedImage {
+    /// Swapchain images start out with nothing worth keeping and end ready to present. The first write waits on the
+    /// stage the acquire semaphore is waited at.
+    pub fn swapchain(images: &[vk::Image], views: &[vk::ImageView], format: vk::Format, extent: vk::Extent2D) -> ImportedImage {
+        ImportedImage {
+            images: images.to_vec(),
+            views: views.to_vec(),
+            format,
+            samples: vk::SampleCountFlags::TYPE_1,
+            extent,
+            initial_layout: vk::ImageLayout::UNDEFINED,
+            initial_stage: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            initial_access: vk::AccessFlags::empty(),
+            final_layout: vk::ImageLayout::PRESENT_SRC_KHR,
+        }
+    }
+}
+
+/// How a pass uses a resource.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Access {
+    ColorAttachment,
+    DepthAttachment,
+    /// Depth tested but not written.
+    DepthRead,
+    /// Target a multisampled color attachment resolves into.
+    Resolve,
+    Sampled(vk::PipelineStageFlags),
+    StorageRead(vk::PipelineStageFlags),
+    StorageWrite(vk::PipelineStageFlags),
+    Uniform(vk::PipelineStageFlags),
+    Vertex,
+    Index,
+    Indirect,
+    TransferSrc,
+    TransferDst,
+}
+
+impl Access {
+    pub fn is_write(self) -> bool {
+        matches!(
+            self,
+            Access::ColorAttachment | Access::DepthAttachment | Access::Resolve | Access::StorageWrite(_) | Access::TransferDst
+        )
+    }
+
+    fn is_attachment(self) -> bool {
+        matches!(self, Access::ColorAttachment | Access::DepthAttachment | Access::DepthRead | Access::Resolve)
+    }
+
+    fn is_image_only(self) -> bool {
+        self.is_attachment() || matches!(self, Access::Sampled(_))
+    }
+
+    fn is_buffer_only(self) -> bool {
+        matches!(self, Access::Uniform(_) | Access::Vertex | Access::Index | Access::Indirect)
+    }
+
+    fn stage(self) -> vk::PipelineStageFlags {
+        match self {
+            Access::ColorAttachment | Access::Resolve => vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            Access::DepthAttachment | Access::DepthRead => vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            Access::Sampled(stages) | Access::StorageRead(stages) | Access::StorageWrite(stages) | Access::Uniform(stages) => stages,
+            Access::Vertex | Access::Index => vk::PipelineStageFlags::VERTEX_INPUT,
+            Access::Indirect => vk::PipelineStageFlags::DRAW_INDIRECT,
+            Access::TransferSrc | Access::TransferDst => vk::PipelineStageFlags::TRANSFER,
+        }
+    }
+
+    fn access_mask(self) -> vk::AccessFlags {
+        match self {
+            Access::ColorAttachment => vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            Access::Resolve => vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            Access::DepthAttachment => vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            Access::DepthRead => vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ,
+            Access::Sampled(_) | Access::StorageRead(_) => vk::AccessFlags::SHADER_READ,
+            Access::StorageWrite(_) => vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+            Access::Uniform(_) => vk::AccessFlags::UNIFORM_READ,
+            Access::Vertex => vk::AccessFlags::VERTEX_ATTRIBUTE_READ,
+            Access::Index => vk::AccessFlags::INDEX_READ,
+            Access::Indirect => vk::AccessFlags::INDIRECT_COMMAND_READ,
+            Access::TransferSrc => vk::AccessFlags::TRANSFER_READ,
+            Access::TransferDst => vk::AccessFlags::TRANSFER_WRITE,
+        }
+    }
+
+    fn layout(self) -> vk::ImageLayout {
+        match self {
+            Access::ColorAttachment | Access::Resolve => vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            Access::DepthAttachment => vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            Access::DepthRead => vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+            Access::Sampled(_) => vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            Access::StorageRead(_) | Access::StorageWrite(_) => vk::ImageLayout::GENERAL,
+            Access::TransferSrc => vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            Access::TransferDst => vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            Access::Uniform(_) | Access::Vertex | Access::Index | Access::Indirect => vk::ImageLayout::UNDEFINED,
+        }
+    }
+
+    fn image_usage(self) -> vk::ImageUsageFlags {
+        match self {
+            Access::ColorAttachment | Access::Resolve => vk::ImageUsageFlags::COLOR_ATTACHMENT,
+            Access::DepthAttachment | Access::DepthRead => vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+            Access::Sampled(_) => vk::ImageUsageFlags::SAMPLED,
+            Access::StorageRead(_) | Access::StorageWrite(_) => vk::ImageUsageFlags::STORAGE,
+            Access::TransferSrc => vk::ImageUsageFlags::TRANSFER_SRC,
+            Access::TransferDst => vk::ImageUsageFlags::TRANSFER_DST,
+            _ => vk::ImageUsageFlags::empty(),
+        }
+    }
+}
+
+fn write_access() -> vk::AccessFlags {
+    vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+        | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE
+        | vk::AccessFlags::SHADER_WRITE
+        | vk::AccessFlags::TRANSFER_WRITE
+        | vk::AccessFlags::HOST_WRITE
+        | vk::AccessFlags::MEMORY_WRITE
+}
+
+fn is_depth_format(format: vk::Format) -> bool {
+    matches!(
+        format,
+        vk::Format::D16_UNORM
+            | vk::Format::X8_D24_UNORM_PACK32
+            | vk::Format::D32_SFLOAT
+            | vk::Format::D16_UNORM_S8_UINT
+            | vk::Format::D24_UNORM_S8_UINT
+            | vk::Format::D32_SFLOAT_S8_UINT
+    )
+}
+
+fn aspect_mask(format: vk::Format) -> vk::ImageAspectFlags {
+    if misc::has_stencil_component(format) {
+        vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+    } else if is_depth_format(format) {
+        vk::ImageAspectFlags::DEPTH
+    } else {
+        vk::ImageAspectFlags::COLOR
+    }
+}
+
+/// What an attachment holds when its pass starts.
+#[derive(Clone, Copy)]
+pub enum Load {
+    Clear(vk::ClearValue),
+    /// Whatever earlier passes left in it.
+    Keep,
+    DontCare,
+}
+
+#[derive(Clone, Copy)]
+struct Attachment {
+    resource: ResourceId,
+    access: Access,
+    load: Load,
+}
+
+/// One use of a resource by a pass. `reads` is set when the pass needs what earlier passes left in it.
+#[derive(Clone, Copy)]
+struct Use {
+    resource: ResourceId,
+    access: Access,
+    reads: bool,
+}
+
+/// The resources a pass reads and writes. Passes with attachments get a render pass and framebuffers; the others
+/// only get their barriers.
+pub struct PassDesc {
+    name: String,
+    colors: Vec<Attachment>,
+    /// Resolve target of each color attachment, if it has one.
+    resolves: Vec<Option<ResourceId>>,
+    depth: Option<Attachment>,
+    accesses: Vec<(ResourceId, Access)>,
+}
+
+impl PassDesc {
+    pub fn new(name: &str) -> PassDesc {
+        PassDesc {
+            name: name.to_string(),
+            colors: vec![],
+            resolves: vec![],
+            depth: None,
+            accesses: vec![],
+        }
+    }
+
+    pub fn color(mut self, resource: ResourceId, load: Load) -> PassDesc {
+        self.colors.push(Attachment {
+            resource,
+            access: Access::ColorAttachment,
+            load,
+        });
+        self.resolves.push(None);
+        self
+    }
+
+    /// Resolves the last color attachment into `target`.
+    pub fn resolve(mut self, target: ResourceId) -> PassDesc {
+        let resolve = self.resolves.last_mut().expect("Resolve needs a color attachment to resolve!");
+        assert!(resolve.is_none(), "Color attachment of pass {} already resolves", self.name);
+        *resolve = Some(target);
+        self
+    }
+
+    pub fn depth(mut self, resource: ResourceId, load: Load) -> PassDesc {
+        self.depth = Some(Attachment {
+            resource,
+            access: Access::DepthAttachment,
+            load,
+        });
+        self
+    }
+
+    /// Depth tests against `resource` without writing it.
+    pub fn depth_read(mut self, resource: ResourceId) -> PassDesc {
+        self.depth = Some(Attachment {
+            resource,
+            access: Access::DepthRead,
+            load: Load::Keep,
+        });
+        self
+    }
+
+    pub fn sampled(self, resource: ResourceId, stages: vk::PipelineStageFlags) -> PassDesc {
+        self.access(resource, Access::Sampled(stages))
+    }
+
+    /// Any use other than as an attachment.
+    pub fn access(mut self, resource: ResourceId, access: Access) -> PassDesc {
+        assert!(
+            !access.is_attachment(),
+            "Attachments of pass {} are declared with color, resolve and depth",
+            self.name
+        );
+        self.accesses.push((resource, access));
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn has_attachments(&self) -> bool {
+        !self.colors.is_empty() || self.depth.is_some()
+    }
+
+    fn uses(&self) -> Vec<Use> {
+        let attachment_use = |attachment: &Attachment| Use {
+            resource: attachment.resource,
+            access: attachment.access,
+            reads: matches!(attachment.load, Load::Keep),
+        };
+        let mut uses: Vec<Use> = self.colors.iter().map(attachment_use).collect();
+        uses.extend(self.resolves.iter().flatten().map(|&resource| Use {
+            resource,
+            access: Access::Resolve,
+            reads: false,
+        }));
+        uses.extend(self.depth.iter().map(attachment_use));
+        uses.extend(self.accesses.iter().map(|&(resource, access)| Use {
+            resource,
+            access,
+            reads: !access.is_write(),
+        }));
+        uses
+    }
+
+    fn writes(&self, resource: ResourceId) -> bool {
+        self.uses().iter().any(|u| u.resource == resource && u.access.is_write())
+    }
+
+    fn reads(&self, resource: ResourceId) -> bool {
+        self.uses().iter().any(|u| u.resource == resource && u.reads)
+    }
+}
+
+enum ResourceKind {
+    Transient(ImageDesc),
+    Image(ImportedImage),
+    Buffer(Vec<vk::Buffer>),
+}
+
+struct Resource {
+    name: String,
+    kind: ResourceKind,
+}
+
+impl Resource {
+    fn is_image(&self) -> bool {
+        !matches!(self.kind, ResourceKind::Buffer(_))
+    }
+}
+
+/// Passes and the resources they use, in the order they were added. `compile` works out the rest.
+pub struct RenderGraph {
+    resources: Vec<Resource>,
+    passes: Vec<PassDesc>,
+}
+
+impl Default for RenderGraph {
+    fn default() -> RenderGraph {
+        RenderGraph::new()
+    }
+}
+
+impl RenderGraph {
+    pub fn new() -> RenderGraph {
+        RenderGraph {
+            resources: vec![],
+            passes: vec![],
+        }
+    }
+
+    pub fn create_image(&mut self, name: &str, desc: ImageDesc) -> ResourceId {
+        self.add_resource(name, ResourceKind::Transient(desc))
+    }
+
+    pub fn import_image(&mut self, name: &str, image: ImportedImage) -> ResourceId {
+        assert_eq!(image.images.len(), image.views.len(), "Imported image {} needs a view per image", name);
+        self.add_resource(name, ResourceKind::Image(image))
+    }
+
+    /// Buffers written or read on the GPU. With several buffers, frame `i` uses buffer `i % len`.
+    pub fn import_buffer(&mut self, name: &str, buffers: &[vk::Buffer]) -> ResourceId {
+        self.add_resource(name, ResourceKind::Buffer(buffers.to_vec()))
+    }
+
+    pub fn add_pass(&mut self, pass: PassDesc) -> PassId {
+        let uses = pass.uses();
+        for (i, u) in uses.iter().enumerate() {
+            let resource = self.resources.get(u.resource.0).expect("Pass uses a resource of another graph!");
+            if resource.is_image() {
+                assert!(!u.access.is_buffer_only(), "Pass {} uses image {} as {:?}", pass.name, resource.name, u.access);
+            } else {
+                assert!(!u.access.is_image_only(), "Pass {} uses buffer {} as {:?}", pass.name, resource.name, u.access);
+            }
+            assert!(
+                uses[..i].iter().all(|earlier| earlier.resource != u.resource),
+                "Pass {} uses {} more than once",
+                pass.name,
+                resource.name
+            );
+        }
+        self.passes.push(pass);
+        PassId(self.passes.len() - 1)
+    }
+
+    /// Orders and culls the passes, creates the transient images, render passes and framebuffers, and plans the
+    /// barriers between passes. Transient images sized relative to `extent` have to be compiled again when it changes.
+    pub fn compile(self, device: Arc<VulkanDevice>, extent: vk::Extent2D) -> CompiledGraph {
+        let order = self.order();
+        let mut compiled = CompiledGraph {
+            device,
+            extent,
+            passes: self.passes.iter().map(|_| CompiledPass::default()).collect(),
+            images: self.resources.iter().map(|_| None).collect(),
+            memory: vec![],
+            end_barriers: vec![],
+            graph: self,
+            order,
+        };
+        compiled.create_transients();
+        compiled.plan_barriers();
+        compiled.create_render_passes();
+        compiled
+    }
+
+    fn add_resource(&mut self, name: &str, kind: ResourceKind) -> ResourceId {
+        self.resources.push(Resource { name: name.to_string(), kind });
+        ResourceId(self.resources.len() - 1)
+    }
+
+    /// Every use of each resource by the passes in `order`, with its position in it.
+    fn resource_uses(&self, order: &[PassId]) -> Vec<Vec<(usize, Use)>> {
+        let mut uses = vec![vec![]; self.resources.len()];
+        for (position, id) in order.iter().enumerate() {
+            for u in self.passes[id.0].uses() {
+                uses[u.resource.0].push((position, u));
+            }
+        }
+        uses
+    }
+
+    /// Transient images that are used, with the positions of their first and last use, by first use.
+    fn transient_lifetimes(&self, uses: &[Vec<(usize, Use)>]) -> Vec<(ResourceId, usize, usize)> {
+        let mut lifetimes: Vec<(ResourceId, usize, usize)> = self
+            .resources
+            .iter()
+            .enumerate()
+            .filter(|(index, resource)| matches!(resource.kind, ResourceKind::Transient(_)) && !uses[*index].is_empty())
+            .map(|(index, _)| (ResourceId(index), uses[index][0].0, uses[index].last().unwrap().0))
+            .collect();
+        lifetimes.sort_by_key(|&(resource, first_use, _)| (first_use, resource));
+        lifetimes
+    }
+
+    /// Passes that write something the frame needs: an imported resource, or something a needed pass reads.
+    fn live_passes(&self) -> Vec<bool> {
+        let mut needed: Vec<bool> = self.resources.iter().map(|r| !matches!(r.kind, ResourceKind::Transient(_))).collect();
+        let mut live = vec![false; self.passes.len()];
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (index, pass) in self.passes.iter().enumerate() {
+                if live[index] || !pass.uses().iter().any(|u| u.access.is_write() && needed[u.resource.0]) {
+                    continue;
+                }
+                live[index] = true;
+                changed = true;
+                for u in pass.uses().iter().filter(|u| u.reads) {
+                    needed[u.resource.0] = true;
+                }
+            }
+        }
+        live
+    }
+
+    /// Live passes in execution order. Passes writing a resource run in the order they were added, and before every
+    /// pass that only reads it; otherwise passes keep the order they were added in.
+    fn order(&self) -> Vec<PassId> {
+        let live = self.live_passes();
+        let mut successors: Vec<BTreeSet<usize>> = vec![BTreeSet::new(); self.passes.len()];
+        for resource in (0..self.resources.len()).map(ResourceId) {
+            let writers: Vec<usize> = (0..self.passes.len()).filter(|&p| live[p] && self.passes[p].writes(resource)).collect();
+            let readers: Vec<usize> = (0..self.passes.len())
+                .filter(|&p| live[p] && self.passes[p].reads(resource) && !self.passes[p].writes(resource))
+                .collect();
+            for pair in writers.windows(2) {
+                successors[pair[0]].insert(pair[1]);
+            }
+            for &writer in writers.iter() {
+                successors[writer].extend(readers.iter().copied());
+            }
+        }
+
+        let mut predecessors = vec![0; self.passes.len()];
+        for &next in successors.iter().flatten() {
+            predecessors[next] += 1;
+        }
+        let mut ready: BTreeSet<usize> = (0..self.passes.len()).filter(|&p| live[p] && predecessors[p] == 0).collect();
+        let mut order = vec![];
+        while let Some(&pass) = ready.iter().next() {
+            ready.remove(&pass);
+            order.push(PassId(pass));
+            for &next in successors[pass].iter() {
+                predecessors[next] -= 1;
+                if predecessors[next] == 0 {
+                    ready.insert(next);
+                }
+            }
+        }
+        let live_count = live.iter().filter(|&&l| l).count();
+        if order.len() < live_count {
+            let stuck: Vec<&str> = (0..self.passes.len())
+                .filter(|&p| live[p] && predecessors[p] > 0)
+                .map(|p| self.passes[p].name.as_str())
+                .collect();
+            panic!("Render graph has a cycle between passes {:?}", stuck);
+        }
+        order
+    }
+}
+
+struct GraphImage {
+    image: vk::Image,
+    view: vk::ImageView,
+    extent: vk::Extent2D,
+    /// Index of the memory block the image is bound to.
+    block: usize,
+}
+
+struct MemoryBlock {
+    memory: vk::DeviceMemory,
+    size: vk::DeviceSize,
+    type_index: u32,
+    /// Position in the pass order of the last use of any image bound to the block.
+    last_use: usize,
+    resources: Vec<ResourceId>,
+}
+
+/// Binds `resource`, used from `lifetime.0` to `lifetime.1` in the pass order, to the smallest block of `type_index`
+/// memory that fits `size` and whose images are all done with by its first use, or to a new block from `allocate`.
+/// Returns the block's index.
+fn place_in_memory(
+    blocks: &mut Vec<MemoryBlock>,
+    resource: ResourceId,
+    size: vk::DeviceSize,
+    type_index: u32,
+    lifetime: (usize, usize),
+    allocate: impl FnOnce() -> vk::DeviceMemory,
+) -> usize {
+    let (first_use, last_use) = lifetime;
+    let reusable = blocks
+        .iter()
+        .enumerate()
+        .filter(|(_, block)| block.type_index == type_index && block.size >= size && block.last_use < first_use)
+        .min_by_key(|(_, block)| block.size)
+        .map(|(block, _)| block);
+    let block = match reusable {
+        Some(block) => block,
+        None => {
+            blocks.push(MemoryBlock {
+                memory: allocate(),
+                size,
+                type_index,
+                last_use,
+                resources: vec![],
+            });
+            blocks.len() - 1
+        }
+    };
+    blocks[block].last_use = last_use;
+    blocks[block].resources.push(resource);
+    block
+}
+
+/// Synchronization state of a resource while walking the passes in order.
+#[derive(Clone, Copy)]
+struct ResourceState {
+    layout: vk::ImageLayout,
+    /// Stages and accesses of the last write, or of the last layout transition with no accesses.
+    write_stage: vk::PipelineStageFlags,
+    write_access: vk::AccessFlags,
+    /// Stages that read it since.
+    read_stage: vk::PipelineStageFlags,
+    /// Stages and accesses the last write was already made visible to.
+    visible_stage: vk::PipelineStageFlags,
+    visible_access: vk::AccessFlags,
+}
+
+impl ResourceState {
+    fn new(layout: vk::ImageLayout, write_stage: vk::PipelineStageFlags, write_access: vk::AccessFlags) -> ResourceState {
+        ResourceState {
+            layout,
+            write_stage,
+            write_access,
+            read_stage: vk::PipelineStageFlags::empty(),
+            visible_stage: vk::PipelineStageFlags::empty(),
+            visible_access: vk::AccessFlags::empty(),
+        }
+    }
+
+    /// The barrier `access` in `layout` needs after what came before, if any, as source stage, source access and old
+    /// layout.
+    fn transition(&mut self, access: Access, layout: vk::ImageLayout) -> Option<(vk::PipelineStageFlags, vk::AccessFlags, vk::ImageLayout)> {
+        let (stage, mask) = (access.stage(), access.access_mask());
+        if !access.is_write() && layout == self.layout {
+            self.read_stage |= stage;
+            if self.write_stage.is_empty() || (self.visible_stage.contains(stage) && self.visible_access.contains(mask)) {
+                return None;
+            }
+            self.visible_stage |= stage;
+            self.visible_access |= mask;
+            return Some((self.write_stage, self.write_access, layout));
+        }
+
+        let src_stage = self.write_stage | self.read_stage;
+        let barrier = if src_stage.is_empty() && layout == self.layout {
+            None
+        } else if src_stage.is_empty() {
+            Some((vk::PipelineStageFlags::TOP_OF_PIPE, self.write_access, self.layout))
+        } else {
+            Some((src_stage, self.write_access, self.layout))
+        };
+        let written = if access.is_write() { mask & write_access() } else { vk::AccessFlags::empty() };
+        *self = ResourceState {
+            layout,
+            write_stage: stage,
+            write_access: written,
+            read_stage: if access.is_write() { vk::PipelineStageFlags::empty() } else { stage },
+            visible_stage: stage,
+            visible_access: mask,
+        };
+        barrier
+    }
+}
+
+struct Barrier {
+    resource: ResourceId,
+    src_stage: vk::PipelineStageFlags,
+    dst_stage: vk::PipelineStageFlags,
+    src_access: vk::AccessFlags,
+    dst_access: vk::AccessFlags,
+    old_layout: vk::ImageLayout,
+    new_layout: vk::ImageLayout,
+}
+
+#[derive(Default)]
+struct CompiledPass {
+    /// Null for passes without attachments.
+    render_pass: vk::RenderPass,
+    /// One per image of the imported attachments, or a single one.
+    framebuffers: Vec<vk::Framebuffer>,
+    extent: vk::Extent2D,
+    clear_values: Vec<vk::ClearValue>,
+    /// Recorded before the pass begins.
+    barriers: Vec<Barrier>,
+}
+
+/// A render graph ready to record. Owns its transient images, render passes and framebuffers.
+pub struct CompiledGraph {
+    device: Arc<VulkanDevice>,
+    graph: RenderGraph,
+    extent: vk::Extent2D,
+    order: Vec<PassId>,
+    /// Indexed by pass id. Culled passes keep the default.
+    passes: Vec<CompiledPass>,
+    /// Indexed by resource id, for the transient images live passes use.
+    images: Vec<Option<GraphImage>>,
+    memory: Vec<MemoryBlock>,
+    /// Brings imported images to their final layouts after the last pass.
+    end_barriers: Vec<Barrier>,
+}
+
+impl CompiledGraph {
+    /// Render pass of a pass with attachments, for the pipelines drawing in it.
+    pub fn render_pass(&self, pass: PassId) -> vk::RenderPass {
+        let render_pass = self.passes[pass.0].render_pass;
+        assert_ne!(
+            render_pass,
+            vk::RenderPass::null(),
+            "Pass {} has no render pass",
+            self.graph.passes[pass.0].name
+        );
+        render_pass
+    }
+
+    pub fn extent(&self, pass: PassId) -> vk::Extent2D {
+        self.passes[pass.0].extent
+    }
+
+    pub fn is_culled(&self, pass: PassId) -> bool {
+        !self.order.contains(&pass)
+    }
+
+    /// View of a transient image, for binding it to the passes that sample it.
+    pub fn image_view(&self, resource: ResourceId) -> vk::ImageView {
+        self.images[resource.0]
+            .as_ref()
+            .map(|image| image.view)
+            .unwrap_or_else(|| panic!("{} is not a transient image in use", self.graph.resources[resource.0].name))
+    }
+
+    /// Records the frame into `command_buffer`: every live pass in order with its barriers, and `record` for each one
+    /// inside its render pass. `frame` picks the imported images and buffers.
+    pub fn execute<F: FnMut(PassId, vk::CommandBuffer)>(&self, command_buffer: vk::CommandBuffer, frame: usize, mut record: F) {
+        let device = &self.device.logical_device;
+        for &id in self.order.iter() {
+            let pass = &self.passes[id.0];
+            self.record_barriers(command_buffer, frame, &pass.barriers);
+            if pass.render_pass == vk::RenderPass::null() {
+                record(id, command_buffer);
+                continue;
+            }
+            let render_pass_begin_info = vk::RenderPassBeginInfo {
+                s_type: vk::StructureType::RENDER_PASS_BEGIN_INFO,
+                p_next: ptr::null(),
+                render_pass: pass.render_pass,
+                framebuffer: pass.framebuffers[frame % pass.framebuffers.len()],
+                render_area: vk::Rect2D {
+                    offset: vk::Offset2D { x: 0, y: 0 },
+                    extent: pass.extent,
+                },
+                clear_value_count: pass.clear_values.len() as u32,
+                p_clear_values: pass.clear_values.as_ptr(),
+            };
+            unsafe {
+                device.cmd_begin_render_pass(command_buffer, &render_pass_begin_info, vk::SubpassContents::INLINE);
+                record(id, command_buffer);
+                device.cmd_end_render_pass(command_buffer);
+            }
+        }
+        self.record_barriers(command_buffer, frame, &self.end_barriers);
+    }
+
+    /// The graph in Graphviz dot: passes in execution order with their barrier counts, culled passes dashed, and
+    /// transient images with the memory block they share.
+    pub fn to_graphviz(&self) -> String {
+        let mut dot = String::from("digraph render_graph {\n    rankdir=LR;\n    node [fontname=\"monospace\"];\n");
+        for (index, pass) in self.graph.passes.iter().enumerate() {
+            let name = escape(&pass.name);
+            match self.order.iter().position(|&id| id.0 == index) {
+                Some(position) => writeln!(
+                    dot,
+                    "    p{} [shape=box, style=filled, fillcolor=lightblue, label=\"{}: {}\\n{} barriers\"];",
+                    index,
+                    position,
+                    name,
+                    self.passes[index].barriers.len()
+                ),
+                None => writeln!(dot, "    p{} [shape=box, style=dashed, color=gray, label=\"{}\\n(culled)\"];", index, name),
+            }
+            .unwrap();
+        }
+        for (index, resource) in self.graph.resources.iter().enumerate() {
+            let name = escape(&resource.name);
+            match &resource.kind {
+                ResourceKind::Transient(desc) => {
+                    let extent = desc.extent.resolve(self.extent);
+                    let memory = match &self.images[index] {
+                        Some(image) => format!("memory {}", image.block),
+                        None => "unused".to_string(),
+                    };
+                    writeln!(
+                        dot,
+                        "    r{} [shape=ellipse, label=\"{}\\n{:?} {}x{} {:?}\\n{}\"];",
+                        index, name, desc.format, extent.width, extent.height, desc.samples, memory
+                    )
+                }
+                ResourceKind::Image(image) => writeln!(
+                    dot,
+                    "    r{} [shape=ellipse, peripheries=2, label=\"{}\\n{:?} {}x{}\\n{:?}\"];",
+                    index, name, image.format, image.extent.width, image.extent.height, image.final_layout
+                ),
+                ResourceKind::Buffer(_) => writeln!(dot, "    r{} [shape=cylinder, label=\"{}\"];", index, name),
+            }
+            .unwrap();
+        }
+        for (index, pass) in self.graph.passes.iter().enumerate() {
+            for u in pass.uses() {
+                if u.reads {
+                    writeln!(dot, "    r{} -> p{} [label=\"{:?}\"];", u.resource.0, index, u.access).unwrap();
+                }
+                if u.access.is_write() {
+                    writeln!(dot, "    p{} -> r{} [label=\"{:?}\"];", index, u.resource.0, u.access).unwrap();
+                }
+            }
+        }
+        for block in self.memory.iter() {
+            for pair in block.resources.windows(2) {
+                writeln!(dot, "    r{} -> r{} [style=dotted, constraint=false, label=\"aliases\"];", pair[0].0, pair[1].0).unwrap();
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    pub fn vk_destroy(&mut self) {
+        let device = &self.device.logical_device;
+        unsafe {
+            for pass in self.passes.iter_mut() {
+                for framebuffer in pass.framebuffers.drain(..) {
+                    device.destroy_framebuffer(framebuffer, None);
+                }
+                if pass.render_pass != vk::RenderPass::null() {
+                    device.destroy_render_pass(pass.render_pass, None);
+                    pass.render_pass = vk::RenderPass::null();
+                }
+            }
+            for image in self.images.iter_mut().filter_map(Option::take) {
+                device.destroy_image_view(image.view, None);
+                device.destroy_image(image.image, None);
+            }
+            for block in self.memory.drain(..) {
+                device.free_memory(block.memory, None);
+            }
+        }
+    }
+
+    fn resource_uses(&self) -> Vec<Vec<(usize, Use)>> {
+        self.graph.resource_uses(&self.order)
+    }
+
+    /// Creates the transient images live passes use. An image goes into the smallest memory block of the right type
+    /// whose images are all done with by its first use, or into a new block.
+    fn create_transients(&mut self) {
+        let uses = self.resource_uses();
+        let memory_properties = self.device.get_physical_device_memory_properties();
+        for (resource, first_use, last_use) in self.graph.transient_lifetimes(&uses) {
+            let index = resource.0;
+            let desc = match self.graph.resources[index].kind {
+                ResourceKind::Transient(desc) => desc,
+                _ => unreachable!(),
+            };
+            let mut usage = uses[index]
+                .iter()
+                .fold(vk::ImageUsageFlags::empty(), |usage, (_, u)| usage | u.access.image_usage());
+            if uses[index].iter().all(|(_, u)| u.access.is_attachment()) {
+                usage |= vk::ImageUsageFlags::TRANSIENT_ATTACHMENT;
+            }
+            let extent = desc.extent.resolve(self.extent);
+            let image = create_image(&self.device, &desc, extent, usage);
+            let requirements = unsafe { self.device.logical_device.get_image_memory_requirements(image) };
+            let type_index = find_memory_type(requirements.memory_type_bits, vk::MemoryPropertyFlags::DEVICE_LOCAL, &memory_properties);
+
+            let device = &self.device;
+            let block = place_in_memory(&mut self.memory, resource, requirements.size, type_index, (first_use, last_use), || {
+                allocate_memory(device, requirements.size, type_index)
+            });
+
+            unsafe {
+                self.device
+                    .logical_device
+                    .bind_image_memory(image, self.memory[block].memory, 0)
+                    .expect("Failed to bind render graph image memory!");
+            }
+            let view_aspect = if is_depth_format(desc.format) {
+                vk::ImageAspectFlags::DEPTH
+            } else {
+                vk::ImageAspectFlags::COLOR
+            };
+            let view = img::create_image_view(self.device.clone(), image, desc.format, view_aspect, 1);
+            self.images[index] = Some(GraphImage { image, view, extent, block });
+        }
+    }
+
+    /// State of each resource when a frame starts. A transient image waits on every use of its memory block, by the
+    /// previous frame or by the images it aliases, and has no contents worth keeping.
+    fn initial_states(&self, uses: &[Vec<(usize, Use)>]) -> Vec<ResourceState> {
+        let empty = ResourceState::new(vk::ImageLayout::UNDEFINED, vk::PipelineStageFlags::empty(), vk::AccessFlags::empty());
+        self.graph
+            .resources
+            .iter()
+            .enumerate()
+            .map(|(index, resource)| match (&resource.kind, &self.images[index]) {
+                (ResourceKind::Transient(_), Some(image)) => {
+                    let mut state = empty;
+                    for &member in self.memory[image.block].resources.iter() {
+                        for (_, u) in uses[member.0].iter() {
+                            if u.access.is_write() {
+                                state.write_stage |= u.access.stage();
+                                state.write_access |= u.access.access_mask() & write_access();
+                            } else {
+                                state.read_stage |= u.access.stage();
+                            }
+                        }
+                    }
+                    state
+                }
+                (ResourceKind::Image(image), _) => ResourceState::new(image.initial_layout, image.initial_stage, image.initial_access),
+                _ => empty,
+            })
+            .collect()
+    }
+
+    fn plan_barriers(&mut self) {
+        let uses = self.resource_uses();
+        let mut states = self.initial_states(&uses);
+        for &id in self.order.iter() {
+            let mut barriers = vec![];
+            for u in self.graph.passes[id.0].uses() {
+                let resource = &self.graph.resources[u.resource.0];
+                let layout = if resource.is_image() { u.access.layout() } else { vk::ImageLayout::UNDEFINED };
+                if let Some((src_stage, src_access, old_layout)) = states[u.resource.0].transition(u.access, layout) {
+                    barriers.push(Barrier {
+                        resource: u.resource,
+                        src_stage,
+                        dst_stage: u.access.stage(),
+                        src_access,
+                        dst_access: u.access.access_mask(),
+                        old_layout,
+                        new_layout: layout,
+                    });
+                }
+            }
+            self.passes[id.0].barriers = barriers;
+        }
+
+        for (index, resource) in self.graph.resources.iter().enumerate() {
+            if let ResourceKind::Image(image) = &resource.kind {
+                let state = states[index];
+                if uses[index].is_empty() || state.layout == image.final_layout {
+                    continue;
+                }
+                self.end_barriers.push(Barrier {
+                    resource: ResourceId(index),
+                    src_stage: state.write_stage | state.read_stage,
+                    dst_stage: vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                    src_access: state.write_access,
+                    dst_access: vk::AccessFlags::empty(),
+                    old_layout: state.layout,
+                    new_layout: image.final_layout,
+                });
+            }
+        }
+    }
+
+    /// Whether a pass after `position` needs what `resource` holds, or the outside does.
+    fn read_later(&self, resource: ResourceId, position: usize) -> bool {
+        if !matches!(self.graph.resources[resource.0].kind, ResourceKind::Transient(_)) {
+            return true;
+        }
+        self.order[position + 1..].iter().any(|id| self.graph.passes[id.0].reads(resource))
+    }
+
+    /// Format, samples, extent and view count of an image resource.
+    fn image_info(&self, resource: ResourceId) -> (vk::Format, vk::SampleCountFlags, vk::Extent2D, usize) {
+        match &self.graph.resources[resource.0].kind {
+            ResourceKind::Transient(desc) => (desc.format, desc.samples, self.images[resource.0].as_ref().unwrap().extent, 1),
+            ResourceKind::Image(image) => (image.format, image.samples, image.extent, image.views.len()),
+            ResourceKind::Buffer(_) => panic!("{} is a buffer, not an image", self.graph.resources[resource.0].name),
+        }
+    }
+
+    fn image_view_for(&self, resource: ResourceId, frame: usize) -> vk::ImageView {
+        match &self.graph.resources[resource.0].kind {
+            ResourceKind::Image(image) => image.views[frame % image.views.len()],
+            _ => self.image_view(resource),
+        }
+    }
+
+    fn create_render_passes(&mut self) {
+        for position in 0..self.order.len() {
+            let id = self.order[position];
+            let pass = &self.graph.passes[id.0];
+            if !pass.has_attachments() {
+                continue;
+            }
+            let mut attachments: Vec<Attachment> = pass.colors.clone();
+            attachments.extend(pass.resolves.iter().flatten().map(|&resource| Attachment {
+                resource,
+                access: Access::Resolve,
+                load: Load::DontCare,
+            }));
+            attachments.extend(pass.depth.iter().copied());
+
+            let (_, _, extent, _) = self.image_info(attachments[0].resource);
+            let mut frame_count = 1;
+            let mut descriptions = vec![];
+            for attachment in attachments.iter() {
+                let (format, samples, attachment_extent, view_count) = self.image_info(attachment.resource);
+                assert_eq!(
+                    (attachment_extent.width, attachment_extent.height),
+                    (extent.width, extent.height),
+                    "Attachments of pass {} differ in size",
+                    pass.name
+                );
+                frame_count = frame_count.max(view_count);
+                descriptions.push(self.attachment_description(attachment, format, samples, position));
+            }
+            let render_pass = create_render_pass(&self.device, pass, &descriptions);
+
+            let framebuffers = (0..frame_count)
+                .map(|frame| {
+                    let views: Vec<vk::ImageView> = attachments.iter().map(|a| self.image_view_for(a.resource, frame)).collect();
+                    create_framebuffer(&self.device, render_pass, &views, extent)
+                })
+                .collect();
+            let clear_values = attachments
+                .iter()
+                .map(|attachment| match attachment.load {
+                    Load::Clear(value) => value,
+                    _ => vk::ClearValue::default(),
+                })
+                .collect();
+
+            let compiled = &mut self.passes[id.0];
+            compiled.render_pass = render_pass;
+            compiled.framebuffers = framebuffers;
+            compiled.extent = extent;
+            compiled.clear_values = clear_values;
+        }
+    }
+
+    /// Barriers outside the render pass do every layout transition, so attachments start and end the render pass in
+    /// the layout the subpass uses them in.
+    fn attachment_description(&self, attachment: &Attachment, format: vk::Format, samples: vk::SampleCountFlags, position: usize) -> vk::AttachmentDescription {
+        let load_op = match attachment.load {
+            Load::Clear(_) => vk::AttachmentLoadOp::CLEAR,
+            Load::Keep => vk::AttachmentLoadOp::LOAD,
+            Load::DontCare => vk::AttachmentLoadOp::DONT_CARE,
+        };
+        let store_op = if self.read_later(attachment.resource, position) {
+            vk::AttachmentStoreOp::STORE
+        } else {
+            vk::AttachmentStoreOp::DONT_CARE
+        };
+        let (stencil_load_op, stencil_store_op) = if misc::has_stencil_component(format) {
+            (load_op, store_op)
+        } else {
+            (vk::AttachmentLoadOp::DONT_CARE, vk::AttachmentStoreOp::DONT_CARE)
+        };
+        let layout = attachment.access.layout();
+        vk::AttachmentDescription {
+            flags: vk::AttachmentDescriptionFlags::empty(),
+            format,
+            samples,
+            load_op,
+            store_op,
+            stencil_load_op,
+            stencil_store_op,
+            initial_layout: layout,
+            final_layout: layout,
+        }
+    }
+
+    fn record_barriers(&self, command_buffer: vk::CommandBuffer, frame: usize, barriers: &[Barrier]) {
+        if barriers.is_empty() {
+            return;
+        }
+        let mut src_stage = vk::PipelineStageFlags::empty();
+        let mut dst_stage = vk::PipelineStageFlags::empty();
+        let mut image_barriers = vec![];
+        let mut buffer_barriers = vec![];
+        for barrier in barriers.iter() {
+            src_stage |= barrier.src_stage;
+            dst_stage |= barrier.dst_stage;
+            let resource = &self.graph.resources[barrier.resource.0];
+            let (image, format) = match &resource.kind {
+                ResourceKind::Transient(desc) => (self.images[barrier.resource.0].as_ref().unwrap().image, desc.format),
+                ResourceKind::Image(image) => (image.images[frame % image.images.len()], image.format),
+                ResourceKind::Buffer(buffers) => {
+                    buffer_barriers.push(vk::BufferMemoryBarrier {
+                        s_type: vk::StructureType::BUFFER_MEMORY_BARRIER,
+                        p_next: ptr::null(),
+                        src_access_mask: barrier.src_access,
+                        dst_access_mask: barrier.dst_access,
+                        src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                        dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                        buffer: buffers[frame % buffers.len()],
+                        offset: 0,
+                        size: vk::WHOLE_SIZE,
+                    });
+                    continue;
+                }
+            };
+            image_barriers.push(vk::ImageMemoryBarrier {
+                s_type: vk::StructureType::IMAGE_MEMORY_BARRIER,
+                p_next: ptr::null(),
+                src_access_mask: barrier.src_access,
+                dst_access_mask: barrier.dst_access,
+                old_layout: barrier.old_layout,
+                new_layout: barrier.new_layout,
+                src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                image,
+                subresource_range: vk::ImageSubresourceRange {
+                    aspect_mask: aspect_mask(format),
+                    base_mip_level: 0,
+                    level_count: vk::REMAINING_MIP_LEVELS,
+                    base_array_layer: 0,
+                    layer_count: vk::REMAINING_ARRAY_LAYERS,
+                },
+            });
+        }
+        unsafe {
+            self.device.logical_device.cmd_pipeline_barrier(
+                command_buffer,
+                src_stage,
+                dst_stage,
+                vk::DependencyFlags::empty(),
+                &[],
+                &buffer_barriers,
+                &image_barriers,
+            );
+        }
+    }
+}
+
+fn escape(name: &str) -> String {
+    name.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// An image without memory, which the graph binds to a block it may share.
+fn create_image(device: &VulkanDevice, desc: &ImageDesc, extent: vk::Extent2D, usage: vk::ImageUsageFlags) -> vk::Image {
+    let image_create_info = vk::ImageCreateInfo {
+        s_type: vk::StructureType::IMAGE_CREATE_INFO,
+        p_next: ptr::null(),
+        flags: vk::ImageCreateFlags::empty(),
+        image_type: vk::ImageType::TYPE_2D,
+        format: desc.format,
+        mip_levels: 1,
+        array_layers: 1,
+        samples: desc.samples,
+        tiling: vk::ImageTiling::OPTIMAL,
+        usage,
+        sharing_mode: vk::SharingMode::EXCLUSIVE,
+        queue_family_index_count: 0,
+        p_queue_family_indices: ptr::null(),
+        initial_layout: vk::ImageLayout::UNDEFINED,
+        extent: vk::Extent3D {
+            width: extent.width,
+            height: extent.height,
+            depth: 1,
+        },
+    };
+    unsafe {
+        device
+            .logical_device
+            .create_image(&image_create_info, None)
+            .expect("Failed to create render graph image!")
+    }
+}
+
+fn allocate_memory(device: &VulkanDevice, size: vk::DeviceSize, type_index: u32) -> vk::DeviceMemory {
+    let memory_allocate_info = vk::MemoryAllocateInfo {
+        s_type: vk::StructureType::MEMORY_ALLOCATE_INFO,
+        p_next: ptr::null(),
+        allocation_size: size,
+        memory_type_index: type_index,
+    };
+    unsafe {
+        device
+            .logical_device
+            .allocate_memory(&memory_allocate_info, None)
+            .expect("Failed to allocate render graph memory!")
+    }
+}
+
+/// A single subpass render pass over `attachments`: the colors, then their resolve targets, then depth.
+fn create_render_pass(device: &VulkanDevice, pass: &PassDesc, attachments: &[vk::AttachmentDescription]) -> vk::RenderPass {
+    let color_refs: Vec<vk::AttachmentReference> = (0..pass.colors.len())
+        .map(|i| vk::AttachmentReference {
+            attachment: i as u32,
+            layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        })
+        .collect();
+    let mut next_resolve = pass.colors.len() as u32;
+    let resolve_refs: Vec<vk::AttachmentReference> = pass
+        .resolves
+        .iter()
+        .map(|resolve| match resolve {
+            Some(_) => {
+                next_resolve += 1;
+                vk::AttachmentReference {
+                    attachment: next_resolve - 1,
+                    layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                }
+            }
+            None => vk::AttachmentReference {
+                attachment: vk::ATTACHMENT_UNUSED,
+                layout: vk::ImageLayout::UNDEFINED,
+            },
+        })
+        .collect();
+    let depth_ref = pass.depth.map(|depth| vk::AttachmentReference {
+        attachment: next_resolve,
+        layout: depth.access.layout(),
+    });
+
+    let subpasses = [vk::SubpassDescription {
+        flags: vk::SubpassDescriptionFlags::empty(),
+        pipeline_bind_point: vk::PipelineBindPoint::GRAPHICS,
+        input_attachment_count: 0,
+        p_input_attachments: ptr::null(),
+        color_attachment_count: color_refs.len() as u32,
+        p_color_attachments: color_refs.as_ptr(),
+        p_resolve_attachments: if pass.resolves.iter().any(Option::is_some) {
+            resolve_refs.as_ptr()
+        } else {
+            ptr::null()
+        },
+        p_depth_stencil_attachment: depth_ref.as_ref().map_or(ptr::null(), |depth_ref| depth_ref as *const _),
+        preserve_attachment_count: 0,
+        p_preserve_attachments: ptr::null(),
+    }];
+
+    let renderpass_create_info = vk::RenderPassCreateInfo {
+        s_type: vk::StructureType::RENDER_PASS_CREATE_INFO,
+        flags: vk::RenderPassCreateFlags::empty(),
+        p_next: ptr::null(),
+        attachment_count: attachments.len() as u32,
+        p_attachments: attachments.as_ptr(),
+        subpass_count: subpasses.len() as u32,
+        p_subpasses: subpasses.as_ptr(),
+        dependency_count: 0,
+        p_dependencies: ptr::null(),
+    };
+    unsafe {
+        device
+            .logical_device
+            .create_render_pass(&renderpass_create_info, None)
+            .expect("Failed to create render pass!")
+    }
+}
+
+fn create_framebuffer(device: &VulkanDevice, render_pass: vk::RenderPass, views: &[vk::ImageView], extent: vk::Extent2D) -> vk::Framebuffer {
+    let framebuffer_create_info = vk::FramebufferCreateInfo {
+        s_type: vk::StructureType::FRAMEBUFFER_CREATE_INFO,
+        p_next: ptr::null(),
+        flags: vk::FramebufferCreateFlags::empty(),
+        render_pass,
+        attachment_count: views.len() as u32,
+        p_attachments: views.as_ptr(),
+        width: extent.width,
+        height: extent.height,
+        layers: 1,
+    };
+    unsafe {
+        device
+            .logical_device
+            .create_framebuffer(&framebuffer_create_info, None)
+            .expect("Failed to create Framebuffer!")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXTENT: vk::Extent2D = vk::Extent2D { width: 64, height: 64 };
+    const FRAGMENT: vk::PipelineStageFlags = vk::PipelineStageFlags::FRAGMENT_SHADER;
+    const DEPTH_TESTS: vk::PipelineStageFlags =
+        vk::PipelineStageFlags::from_raw(vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS.as_raw() | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS.as_raw());
+
+    fn transient(graph: &mut RenderGraph, name: &str) -> ResourceId {
+        graph.create_image(name, ImageDesc::new(vk::Format::R16G16B16A16_SFLOAT, vk::SampleCountFlags::TYPE_1))
+    }
+
+    fn swapchain(graph: &mut RenderGraph) -> ResourceId {
+        let image = ImportedImage::swapchain(&[vk::Image::null()], &[vk::ImageView::null()], vk::Format::B8G8R8A8_SRGB, EXTENT);
+        graph.import_image("swapchain", image)
+    }
+
+    fn names(graph: &RenderGraph) -> Vec<&str> {
+        graph.order().iter().map(|id| graph.passes[id.0].name()).collect()
+    }
+
+    #[test]
+    fn readers_run_after_the_pass_writing_what_they_read() {
+        let mut graph = RenderGraph::new();
+        let swapchain = swapchain(&mut graph);
+        let hdr = transient(&mut graph, "hdr");
+        graph.add_pass(PassDesc::new("tonemap").sampled(hdr, FRAGMENT).color(swapchain, Load::DontCare));
+        graph.add_pass(PassDesc::new("scene").color(hdr, Load::Clear(vk::ClearValue::default())));
+        assert_eq!(names(&graph), vec!["scene", "tonemap"]);
+    }
+
+    #[test]
+    fn writers_of_one_resource_keep_the_order_they_were_added_in() {
+        let mut graph = RenderGraph::new();
+        let swapchain = swapchain(&mut graph);
+        let hdr = transient(&mut graph, "hdr");
+        graph.add_pass(PassDesc::new("post").sampled(hdr, FRAGMENT).color(swapchain, Load::DontCare));
+        graph.add_pass(PassDesc::new("opaque").color(hdr, Load::Clear(vk::ClearValue::default())));
+        graph.add_pass(PassDesc::new("transparent").color(hdr, Load::Keep));
+        assert_eq!(names(&graph), vec!["opaque", "transparent", "post"]);
+    }
+
+    #[test]
+    #[should_panic(expected = "cycle")]
+    fn cycles_are_rejected() {
+        let mut graph = RenderGraph::new();
+        let swapchain = swapchain(&mut graph);
+        let (x, y) = (transient(&mut graph, "x"), transient(&mut graph, "y"));
+        graph.add_pass(PassDesc::new("a").sampled(x, FRAGMENT).color(y, Load::DontCare));
+        graph.add_pass(PassDesc::new("b").sampled(y, FRAGMENT).color(x, Load::DontCare));
+        graph.add_pass(PassDesc::new("present").sampled(y, FRAGMENT).color(swapchain, Load::DontCare));
+        graph.order();
+    }
+
+    #[test]
+    fn passes_nothing_needs_are_culled() {
+        let mut graph = RenderGraph::new();
+        let swapchain = swapchain(&mut graph);
+        let stats = graph.import_buffer("stats", &[vk::Buffer::null()]);
+        let (shadow_map, hdr) = (transient(&mut graph, "shadow_map"), transient(&mut graph, "hdr"));
+        let (debug, unread) = (transient(&mut graph, "debug"), transient(&mut graph, "unread"));
+        graph.add_pass(PassDesc::new("shadows").depth(shadow_map, Load::Clear(vk::ClearValue::default())));
+        graph.add_pass(PassDesc::new("debug").color(debug, Load::DontCare));
+        graph.add_pass(PassDesc::new("debug_blur").sampled(debug, FRAGMENT).color(unread, Load::DontCare));
+        graph.add_pass(PassDesc::new("scene").sampled(shadow_map, FRAGMENT).color(hdr, Load::DontCare));
+        graph.add_pass(PassDesc::new("tonemap").sampled(hdr, FRAGMENT).color(swapchain, Load::DontCare));
+        graph.add_pass(PassDesc::new("count").access(stats, Access::StorageWrite(vk::PipelineStageFlags::COMPUTE_SHADER)));
+
+        assert_eq!(graph.live_passes(), vec![true, false, false, true, true, true]);
+        assert_eq!(names(&graph), vec!["shadows", "scene", "tonemap", "count"]);
+    }
+
+    fn fresh() -> ResourceState {
+        ResourceState::new(vk::ImageLayout::UNDEFINED, vk::PipelineStageFlags::empty(), vk::AccessFlags::empty())
+    }
+
+    /// The barrier `access` gets after `earlier` went through `state` in order.
+    fn barrier_after(earlier: &[Access], access: Access, image: bool) -> Option<(vk::PipelineStageFlags, vk::AccessFlags, vk::ImageLayout)> {
+        let layout = |access: Access| if image { access.layout() } else { vk::ImageLayout::UNDEFINED };
+        let mut state = fresh();
+        for &earlier in earlier {
+            state.transition(earlier, layout(earlier));
+        }
+        state.transition(access, layout(access))
+    }
+
+    #[test]
+    fn each_layout_transition_waits_on_what_came_before() {
+        use vk::AccessFlags as A;
+        use vk::ImageLayout as L;
+        let color_output = vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT;
+        let top = vk::PipelineStageFlags::TOP_OF_PIPE;
+        let cases = [
+            (vec![], Access::ColorAttachment, Some((top, A::empty(), L::UNDEFINED))),
+            (vec![], Access::DepthAttachment, Some((top, A::empty(), L::UNDEFINED))),
+            (vec![], Access::TransferDst, Some((top, A::empty(), L::UNDEFINED))),
+            (vec![], Access::StorageWrite(FRAGMENT), Some((top, A::empty(), L::UNDEFINED))),
+            (
+                vec![Access::ColorAttachment],
+                Access::Sampled(FRAGMENT),
+                Some((color_output, A::COLOR_ATTACHMENT_WRITE, L::COLOR_ATTACHMENT_OPTIMAL)),
+            ),
+            (
+                vec![Access::ColorAttachment],
+                Access::TransferSrc,
+                Some((color_output, A::COLOR_ATTACHMENT_WRITE, L::COLOR_ATTACHMENT_OPTIMAL)),
+            ),
+            (
+                vec![Access::ColorAttachment],
+                Access::StorageRead(FRAGMENT),
+                Some((color_output, A::COLOR_ATTACHMENT_WRITE, L::COLOR_ATTACHMENT_OPTIMAL)),
+            ),
+            (
+                vec![Access::DepthAttachment],
+                Access::DepthRead,
+                Some((DEPTH_TESTS, A::DEPTH_STENCIL_ATTACHMENT_WRITE, L::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)),
+            ),
+            (
+                vec![Access::DepthAttachment],
+                Access::Sampled(FRAGMENT),
+                Some((DEPTH_TESTS, A::DEPTH_STENCIL_ATTACHMENT_WRITE, L::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)),
+            ),
+            (
+                vec![Access::TransferDst],
+                Access::Sampled(FRAGMENT),
+                Some((vk::PipelineStageFlags::TRANSFER, A::TRANSFER_WRITE, L::TRANSFER_DST_OPTIMAL)),
+            ),
+            (
+                vec![Access::StorageWrite(FRAGMENT)],
+                Access::Sampled(FRAGMENT),
+                Some((FRAGMENT, A::SHADER_WRITE, L::GENERAL)),
+            ),
+            // Writing over what was read waits for the reads, with nothing to make visible.
+            (
+                vec![Access::ColorAttachment, Access::Sampled(FRAGMENT)],
+                Access::ColorAttachment,
+                Some((FRAGMENT, A::empty(), L::SHADER_READ_ONLY_OPTIMAL)),
+            ),
+            // Writes in the same layout still wait on each other.
+            (
+                vec![Access::ColorAttachment],
+                Access::ColorAttachment,
+                Some((color_output, A::COLOR_ATTACHMENT_WRITE, L::COLOR_ATTACHMENT_OPTIMAL)),
+            ),
+        ];
+        for (earlier, access, expected) in cases.iter() {
+            assert_eq!(barrier_after(earlier, *access, true), *expected, "{:?} after {:?}", access, earlier);
+        }
+    }
+
+    #[test]
+    fn reads_in_the_same_layout_only_wait_once_per_stage() {
+        let earlier = [Access::ColorAttachment, Access::Sampled(FRAGMENT)];
+        assert_eq!(barrier_after(&earlier, Access::Sampled(FRAGMENT), true), None);
+        assert_eq!(
+            barrier_after(&earlier, Access::Sampled(vk::PipelineStageFlags::VERTEX_SHADER), true),
+            Some((FRAGMENT, vk::AccessFlags::empty(), vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL))
+        );
+    }
+
+    #[test]
+    fn buffers_only_wait_on_writes() {
+        let vertex = vk::PipelineStageFlags::VERTEX_SHADER;
+        assert_eq!(barrier_after(&[], Access::Uniform(vertex), false), None);
+        let compute = vk::PipelineStageFlags::COMPUTE_SHADER;
+        assert_eq!(
+            barrier_after(&[Access::StorageWrite(compute)], Access::Uniform(vertex), false),
+            Some((compute, vk::AccessFlags::SHADER_WRITE, vk::ImageLayout::UNDEFINED))
+        );
+        assert_eq!(
+            barrier_after(&[Access::StorageWrite(compute), Access::Uniform(vertex)], Access::Uniform(vertex), false),
+            None
+        );
+    }
+
+    /// Places every transient of `graph` in memory as `create_transients` would, each needing `size(resource)` bytes of
+    /// one memory type. Returns the blocks and each transient's block.
+    fn alias(graph: &RenderGraph, size: impl Fn(ResourceId) -> vk::DeviceSize) -> (Vec<MemoryBlock>, Vec<(ResourceId, usize)>) {
+        let uses = graph.resource_uses(&graph.order());
+        let mut blocks = vec![];
+        let placed = graph
+            .transient_lifetimes(&uses)
+            .into_iter()
+            .map(|(resource, first_use, last_use)| {
+                let block = place_in_memory(&mut blocks, resource, size(resource), 0, (first_use, last_use), vk::DeviceMemory::null);
+                (resource, block)
+            })
+            .collect();
+        (blocks, placed)
+    }
+
+    /// a writes t1, b reads t1 and writes t2, c reads t2 and writes t3, d reads t3 and presents.
+    fn chain() -> (RenderGraph, [ResourceId; 3]) {
+        let mut graph = RenderGraph::new();
+        let swapchain = swapchain(&mut graph);
+        let t = [transient(&mut graph, "t1"), transient(&mut graph, "t2"), transient(&mut graph, "t3")];
+        graph.add_pass(PassDesc::new("a").color(t[0], Load::DontCare));
+        graph.add_pass(PassDesc::new("b").sampled(t[0], FRAGMENT).color(t[1], Load::DontCare));
+        graph.add_pass(PassDesc::new("c").sampled(t[1], FRAGMENT).color(t[2], Load::DontCare));
+        graph.add_pass(PassDesc::new("d").sampled(t[2], FRAGMENT).color(swapchain, Load::DontCare));
+        (graph, t)
+    }
+
+    #[test]
+    fn images_whose_lifetimes_dont_overlap_share_memory() {
+        let (graph, t) = chain();
+        let uses = graph.resource_uses(&graph.order());
+        assert_eq!(graph.transient_lifetimes(&uses), vec![(t[0], 0, 1), (t[1], 1, 2), (t[2], 2, 3)]);
+
+        let (blocks, placed) = alias(&graph, |_| 1024);
+        assert_eq!(placed, vec![(t[0], 0), (t[1], 1), (t[2], 0)]);
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks[0].resources, vec![t[0], t[2]]);
+        assert_eq!(blocks[0].last_use, 3);
+    }
+
+    #[test]
+    fn images_only_alias_blocks_big_enough_for_them() {
+        let (graph, t) = chain();
+        let (blocks, placed) = alias(&graph, |resource| if resource == t[2] { 4096 } else { 1024 });
+        assert_eq!(placed, vec![(t[0], 0), (t[1], 1), (t[2], 2)]);
+        assert_eq!(blocks.len(), 3);
+
+        // Of the blocks that fit, the smallest is used.
+        let mut blocks = vec![];
+        place_in_memory(&mut blocks, t[0], 4096, 0, (0, 0), vk::DeviceMemory::null);
+        place_in_memory(&mut blocks, t[1], 1024, 0, (0, 0), vk::DeviceMemory::null);
+        assert_eq!(place_in_memory(&mut blocks, t[2], 512, 0, (1, 1), vk::DeviceMemory::null), 1);
+        // Other memory types never share.
+        assert_eq!(place_in_memory(&mut blocks, t[2], 512, 1, (2, 2), vk::DeviceMemory::null), 2);
+    }
+}
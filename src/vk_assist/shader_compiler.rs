@@ -0,0 +1,423 @@
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::app::tools::hash_bytes;
+
+/// Bump when the compiler settings below change, so cached SPIR-V gets rebuilt.
+pub const COMPILER_VERSION: u32 = 2;
+/// Where compiled SPIR-V is cached, keyed by a hash of the expanded source and defines.
+pub const SHADER_CACHE_DIR: &str = "shaders/cache";
+/// Nested includes deeper than this are assumed to be a cycle.
+const MAX_INCLUDE_DEPTH: usize = 32;
+
+const OP_CAPABILITY: u32 = 17;
+const CAPABILITY_SHADER_NON_UNIFORM: u32 = 5301;
+const CAPABILITY_RUNTIME_DESCRIPTOR_ARRAY: u32 = 5302;
+const CAPABILITY_SAMPLED_IMAGE_ARRAY_NON_UNIFORM_INDEXING: u32 = 5307;
+
+/// SPIR-V for one shader stage, plus every file it was built from so they can be watched.
+pub struct CompiledShader {
+    pub spirv: Vec<u8>,
+    /// The shader itself first, then its includes.
+    pub dependencies: Vec<PathBuf>,
+}
+
+/// Stage picked from the file extension, as glslc does.
+pub fn stage_for(path: &Path) -> Option<naga::ShaderStage> {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("vert") => Some(naga::ShaderStage::Vertex),
+        Some("frag") => Some(naga::ShaderStage::Fragment),
+        Some("comp") => Some(naga::ShaderStage::Compute),
+        _ => None,
+    }
+}
+
+/// GLSL with its includes pasted in, and for every line of it the file and line it came from.
+struct ExpandedSource {
+    text: String,
+    origins: Vec<(PathBuf, usize)>,
+    dependencies: Vec<PathBuf>,
+}
+
+impl ExpandedSource {
+    fn location(&self, line_number: usize) -> String {
+        match self.origins.get(line_number.wrapping_sub(1)) {
+            Some((path, line)) => format!("{}:{}", path.display(), line),
+            None => "<unknown>".to_string(),
+        }
+    }
+}
+
+/// Pastes in `#include "file"` lines, resolved relative to the including file. Each file is included at most once, so
+/// headers need no include guards.
+fn expand_includes(path: &Path, source: &mut ExpandedSource, depth: usize) -> Result<(), String> {
+    if depth > MAX_INCLUDE_DEPTH {
+        return Err(format!("{}: includes nest too deep, is there a cycle?", path.display()));
+    }
+    let text = fs::read_to_string(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+    source.dependencies.push(path.to_path_buf());
+
+    for (line_index, line) in text.lines().enumerate() {
+        let trimmed = line.trim();
+        if let Some(rest) = trimmed.strip_prefix("#include") {
+            let name = rest.trim().trim_matches(|c| c == '"' || c == '<' || c == '>');
+            if name.is_empty() {
+                return Err(format!("{}:{}: #include without a file name", path.display(), line_index + 1));
+            }
+            let include_path = path.parent().map_or(PathBuf::from(name), |parent| parent.join(name));
+            if !source.dependencies.contains(&include_path) {
+                expand_includes(&include_path, source, depth + 1).map_err(|err| format!("{}\n  included from {}:{}", err, path.display(), line_index + 1))?;
+            }
+            continue;
+        }
+        source.text.push_str(line);
+        source.text.push('\n');
+        source.origins.push((path.to_path_buf(), line_index + 1));
+    }
+    Ok(())
+}
+
+/// naga's GLSL frontend reads `uniform texture2D textures[]` as a plain array, which can't hold images, and loads its
+/// elements through a pointer. Turns arrays of images and samplers into binding arrays indexed directly, as glslc would.
+/// Returns whether any of them is runtime-sized.
+fn bind_handle_arrays(module: &mut naga::Module) -> bool {
+    let mut binding_arrays = vec![];
+    let mut runtime_sized = false;
+    for (handle, global) in module.global_variables.iter_mut() {
+        if let naga::TypeInner::Array { base, size, .. } = module.types[global.ty].inner {
+            if let naga::TypeInner::Image { .. } | naga::TypeInner::Sampler { .. } = module.types[base].inner {
+                let binding_array = naga::Type {
+                    name: None,
+                    inner: naga::TypeInner::BindingArray { base, size },
+                };
+                module.types.replace(global.ty, binding_array);
+                global.space = naga::AddressSpace::Handle;
+                binding_arrays.push(handle);
+                runtime_sized |= size == naga::ArraySize::Dynamic;
+            }
+        }
+    }
+    if binding_arrays.is_empty() {
+        return false;
+    }
+
+    let functions = module.functions.iter_mut().map(|(_, function)| function);
+    for function in functions.chain(module.entry_points.iter_mut().map(|entry_point| &mut entry_point.function)) {
+        let loads: Vec<_> = function
+            .expressions
+            .iter()
+            .filter_map(|(handle, expression)| match *expression {
+                naga::Expression::Load { pointer } => Some((handle, pointer)),
+                _ => None,
+            })
+            .collect();
+        for (load, pointer) in loads {
+            let base = match function.expressions[pointer] {
+                naga::Expression::Access { base, .. } | naga::Expression::AccessIndex { base, .. } => base,
+                _ => continue,
+            };
+            if let naga::Expression::GlobalVariable(global) = function.expressions[base] {
+                if binding_arrays.contains(&global) {
+                    *function.expressions.get_mut(load) = function.expressions[pointer].clone();
+                }
+            }
+        }
+    }
+    runtime_sized
+}
+
+/// Declares the capabilities runtime-sized binding arrays need, which naga leaves out. Capabilities come first in a
+/// module, right after the five word header.
+fn declare_runtime_arrays(words: &mut Vec<u32>) {
+    let mut capabilities = vec![CAPABILITY_RUNTIME_DESCRIPTOR_ARRAY];
+    let non_uniform = words[5..]
+        .chunks(2)
+        .take_while(|instruction| instruction[0] == (2 << 16) | OP_CAPABILITY)
+        .any(|instruction| instruction[1] == CAPABILITY_SHADER_NON_UNIFORM);
+    if non_uniform {
+        capabilities.push(CAPABILITY_SAMPLED_IMAGE_ARRAY_NON_UNIFORM_INDEXING);
+    }
+    for capability in capabilities {
+        words.splice(5..5, [(2 << 16) | OP_CAPABILITY, capability]);
+    }
+}
+
+/// Where the SPIR-V for `text`, the expanded source of the shader at `path`, is cached. Any change to the source, the
+/// defines or `COMPILER_VERSION` gives a new file.
+fn cache_path(path: &Path, stage: naga::ShaderStage, defines: &[(String, String)], text: &str) -> PathBuf {
+    let key = hash_bytes(format!("{} {:?} {:?} {}", COMPILER_VERSION, stage, defines, text).as_bytes());
+    let file_name = path.file_name().map_or("shader".into(), |name| name.to_string_lossy());
+    Path::new(SHADER_CACHE_DIR).join(format!("{}.{:016x}.spv", file_name, key))
+}
+
+/// Compiles a GLSL shader to SPIR-V, with `defines` set as if by `#define`. Results are cached in `SHADER_CACHE_DIR`,
+/// so unchanged shaders only cost reading their sources. Errors point at the file and line they came from.
+pub fn compile_glsl(path: &Path, defines: &[(String, String)]) -> Result<CompiledShader, String> {
+    let stage = stage_for(path).ok_or_else(|| format!("{}: unknown shader stage, expected .vert, .frag or .comp", path.display()))?;
+    let mut source = ExpandedSource {
+        text: String::new(),
+        origins: vec![],
+        dependencies: vec![],
+    };
+    expand_includes(path, &mut source, 0)?;
+
+    let cache_path = cache_path(path, stage, defines, &source.text);
+    if let Ok(spirv) = fs::read(&cache_path) {
+        return Ok(CompiledShader {
+            spirv,
+            dependencies: source.dependencies,
+        });
+    }
+
+    let mut options = naga::front::glsl::Options {
+        stage,
+        defines: defines.iter().cloned().collect(),
+    };
+    // naga doesn't know the qualifier, and works out which indices aren't uniform by itself.
+    options.defines.insert("nonuniformEXT".to_string(), String::new());
+    let mut module = naga::front::glsl::Frontend::default().parse(&options, &source.text).map_err(|errors| {
+        errors
+            .iter()
+            .map(|err| {
+                let line = err.meta.location(&source.text).line_number as usize;
+                format!("{}: {}", source.location(line), err.kind)
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    })?;
+    let runtime_arrays = bind_handle_arrays(&mut module);
+    let info = naga::valid::Validator::new(naga::valid::ValidationFlags::all(), naga::valid::Capabilities::all())
+        .validate(&module)
+        .map_err(|err| format!("{}: {}", path.display(), err.into_inner()))?;
+
+    let mut spv_options = naga::back::spv::Options {
+        lang_version: (1, 0),
+        ..naga::back::spv::Options::default()
+    };
+    // The projection matrix already flips Y for Vulkan.
+    spv_options.flags.remove(naga::back::spv::WriterFlags::ADJUST_COORDINATE_SPACE);
+    let pipeline_options = naga::back::spv::PipelineOptions {
+        shader_stage: stage,
+        entry_point: "main".to_string(),
+    };
+    let mut words = naga::back::spv::write_vec(&module, &info, &spv_options, Some(&pipeline_options)).map_err(|err| format!("{}: {}", path.display(), err))?;
+    if runtime_arrays {
+        declare_runtime_arrays(&mut words);
+    }
+    let spirv: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes().to_vec()).collect();
+
+    if let Err(err) = fs::create_dir_all(SHADER_CACHE_DIR).and_then(|_| fs::write(&cache_path, &spirv)) {
+        println!("Failed to cache shader {:?}: {}", cache_path, err);
+    }
+    Ok(CompiledShader {
+        spirv,
+        dependencies: source.dependencies,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ash_test_shader_compiler_{}_{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn expand(path: &Path) -> Result<ExpandedSource, String> {
+        let mut source = ExpandedSource {
+            text: String::new(),
+            origins: vec![],
+            dependencies: vec![],
+        };
+        expand_includes(path, &mut source, 0).map(|_| source)
+    }
+
+    fn parse_fragment(text: &str) -> naga::Module {
+        let mut options = naga::front::glsl::Options::from(naga::ShaderStage::Fragment);
+        options.defines.insert("nonuniformEXT".to_string(), String::new());
+        naga::front::glsl::Frontend::default()
+            .parse(&options, text)
+            .expect("Failed to parse the test shader")
+    }
+
+    fn validate(module: &naga::Module) -> Result<naga::valid::ModuleInfo, String> {
+        naga::valid::Validator::new(naga::valid::ValidationFlags::all(), naga::valid::Capabilities::all())
+            .validate(module)
+            .map_err(|err| format!("{:?}", err.into_inner()))
+    }
+
+    const TEXTURE_ARRAY_SHADER: &str = "#version 450
+#extension GL_EXT_nonuniform_qualifier : enable
+layout (set = 0, binding = 0) uniform texture2D textures[SIZE];
+layout (set = 0, binding = 1) uniform sampler textureSampler;
+layout (push_constant) uniform PushConstants { uint slot; } pushConstants;
+layout (location = 0) in vec2 uv;
+layout (location = 0) out vec4 color;
+void main() {
+    color = texture(sampler2D(textures[nonuniformEXT(pushConstants.slot)], textureSampler), uv);
+}
+";
+
+    #[test]
+    fn includes_are_pasted_in_once_with_their_origins() {
+        let dir = temp_dir("includes");
+        fs::create_dir_all(dir.join("common")).unwrap();
+        fs::write(
+            dir.join("main.frag"),
+            "#version 450\n#include \"common/a.glsl\"\n#include \"common/b.glsl\"\nvoid main() {}\n",
+        )
+        .unwrap();
+        fs::write(dir.join("common/a.glsl"), "#include \"b.glsl\"\nfloat a() { return b(); }\n").unwrap();
+        fs::write(dir.join("common/b.glsl"), "float b() { return 1.0; }\n").unwrap();
+
+        let source = expand(&dir.join("main.frag")).unwrap();
+        assert_eq!(
+            source.text,
+            "#version 450\nfloat b() { return 1.0; }\nfloat a() { return b(); }\nvoid main() {}\n"
+        );
+        assert_eq!(
+            source.dependencies,
+            vec![dir.join("main.frag"), dir.join("common/a.glsl"), dir.join("common/b.glsl")]
+        );
+        assert_eq!(source.location(2), format!("{}:1", dir.join("common/b.glsl").display()));
+        assert_eq!(source.location(4), format!("{}:4", dir.join("main.frag").display()));
+        assert_eq!(source.location(5), "<unknown>");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn missing_includes_name_the_including_line() {
+        let dir = temp_dir("missing_include");
+        fs::write(dir.join("main.frag"), "#version 450\n\n#include \"missing.glsl\"\n").unwrap();
+        fs::write(dir.join("empty.frag"), "#include\n").unwrap();
+
+        let err = expand(&dir.join("main.frag")).err().unwrap();
+        assert!(err.contains("missing.glsl"), "{}", err);
+        assert!(err.ends_with(&format!("included from {}:3", dir.join("main.frag").display())), "{}", err);
+        let err = expand(&dir.join("empty.frag")).err().unwrap();
+        assert!(err.ends_with(":1: #include without a file name"), "{}", err);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn runtime_sized_image_arrays_become_binding_arrays() {
+        let mut module = parse_fragment(&TEXTURE_ARRAY_SHADER.replace("SIZE", ""));
+        assert!(validate(&module).is_err());
+        assert!(bind_handle_arrays(&mut module));
+
+        let (_, textures) = module
+            .global_variables
+            .iter()
+            .find(|(_, global)| global.name.as_deref() == Some("textures"))
+            .unwrap();
+        assert_eq!(textures.space, naga::AddressSpace::Handle);
+        match module.types[textures.ty].inner {
+            naga::TypeInner::BindingArray { base, size } => {
+                assert_eq!(size, naga::ArraySize::Dynamic);
+                assert!(matches!(module.types[base].inner, naga::TypeInner::Image { .. }));
+            }
+            ref other => panic!("textures is {:?}", other),
+        }
+        // No loads through the array are left, the images are used directly.
+        validate(&module).unwrap();
+    }
+
+    #[test]
+    fn sized_image_arrays_are_bound_but_not_runtime_sized() {
+        let mut module = parse_fragment(&TEXTURE_ARRAY_SHADER.replace("SIZE", "4"));
+        assert!(!bind_handle_arrays(&mut module));
+        validate(&module).unwrap();
+
+        let mut plain = parse_fragment("#version 450\nlayout (location = 0) out vec4 color;\nvoid main() { color = vec4(1.0); }\n");
+        let types_before = plain.types.len();
+        assert!(!bind_handle_arrays(&mut plain));
+        assert_eq!(plain.types.len(), types_before);
+    }
+
+    const HEADER: [u32; 5] = [0x0723_0203, 0x0001_0000, 0, 10, 0];
+    const CAPABILITY_SHADER: u32 = 1;
+    const OP_MEMORY_MODEL: u32 = (3 << 16) | 14;
+
+    fn capability(capability: u32) -> [u32; 2] {
+        [(2 << 16) | OP_CAPABILITY, capability]
+    }
+
+    #[test]
+    fn runtime_arrays_get_their_capability_after_the_header() {
+        let mut words = HEADER.to_vec();
+        words.extend_from_slice(&capability(CAPABILITY_SHADER));
+        words.extend_from_slice(&[OP_MEMORY_MODEL, 0, 1]);
+        declare_runtime_arrays(&mut words);
+
+        let mut expected = HEADER.to_vec();
+        expected.extend_from_slice(&capability(CAPABILITY_RUNTIME_DESCRIPTOR_ARRAY));
+        expected.extend_from_slice(&capability(CAPABILITY_SHADER));
+        expected.extend_from_slice(&[OP_MEMORY_MODEL, 0, 1]);
+        assert_eq!(words, expected);
+    }
+
+    #[test]
+    fn non_uniform_indexing_also_gets_the_sampled_image_capability() {
+        let mut words = HEADER.to_vec();
+        words.extend_from_slice(&capability(CAPABILITY_SHADER));
+        words.extend_from_slice(&capability(CAPABILITY_SHADER_NON_UNIFORM));
+        words.extend_from_slice(&[OP_MEMORY_MODEL, 0, 1]);
+        declare_runtime_arrays(&mut words);
+
+        let capabilities: Vec<u32> = words[5..].chunks(2).take(4).map(|instruction| instruction[1]).collect();
+        assert_eq!(
+            capabilities,
+            vec![
+                CAPABILITY_SAMPLED_IMAGE_ARRAY_NON_UNIFORM_INDEXING,
+                CAPABILITY_RUNTIME_DESCRIPTOR_ARRAY,
+                CAPABILITY_SHADER,
+                CAPABILITY_SHADER_NON_UNIFORM
+            ]
+        );
+    }
+
+    #[test]
+    fn cache_files_are_keyed_by_source_and_defines() {
+        let stage = naga::ShaderStage::Fragment;
+        let path = Path::new("shaders/test.frag");
+        let defines = vec![("SHADOWS".to_string(), "1".to_string())];
+        let cached = cache_path(path, stage, &[], "void main() {}");
+        assert_eq!(cached, cache_path(path, stage, &[], "void main() {}"));
+        assert_eq!(cached.parent(), Some(Path::new(SHADER_CACHE_DIR)));
+        assert!(cached.file_name().unwrap().to_string_lossy().starts_with("test.frag."));
+        assert_ne!(cached, cache_path(path, stage, &[], "void main() { }"));
+        assert_ne!(cached, cache_path(path, stage, &defines, "void main() {}"));
+        assert_ne!(cached, cache_path(path, naga::ShaderStage::Vertex, &[], "void main() {}"));
+    }
+
+    #[test]
+    fn cached_spirv_is_used_until_the_source_changes() {
+        let dir = temp_dir("cache");
+        let path = dir.join("cached.frag");
+        let text = format!(
+            "#version 450\nlayout (location = 0) out vec4 color;\nvoid main() {{ color = vec4({}.0); }}\n",
+            std::process::id()
+        );
+        fs::write(&path, &text).unwrap();
+
+        let compiled = compile_glsl(&path, &[]).unwrap();
+        let cached = cache_path(&path, naga::ShaderStage::Fragment, &[], &text);
+        assert_eq!(fs::read(&cached).unwrap(), compiled.spirv);
+        assert_eq!(compiled.dependencies, vec![path.clone()]);
+
+        // A planted cache entry is returned as is, proving the compiler didn't run.
+        fs::write(&cached, b"cached").unwrap();
+        assert_eq!(compile_glsl(&path, &[]).unwrap().spirv, b"cached");
+        fs::write(&path, text.replace("vec4(", "vec4(0.5 + ")).unwrap();
+        assert_ne!(compile_glsl(&path, &[]).unwrap().spirv, b"cached");
+
+        let text = fs::read_to_string(&path).unwrap();
+        fs::remove_file(cache_path(&path, naga::ShaderStage::Fragment, &[], &text)).unwrap();
+        fs::remove_file(&cached).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,505 @@
+#![allow(dead_code)]
+
+use ash::vk;
+
+use std::collections::HashMap;
+
+const SPIRV_MAGIC: u32 = 0x0723_0203;
+const HEADER_WORDS: usize = 5;
+
+const OP_NAME: u32 = 5;
+const OP_ENTRY_POINT: u32 = 15;
+const OP_TYPE_BOOL: u32 = 20;
+const OP_TYPE_INT: u32 = 21;
+const OP_TYPE_FLOAT: u32 = 22;
+const OP_TYPE_VECTOR: u32 = 23;
+const OP_TYPE_MATRIX: u32 = 24;
+const OP_TYPE_IMAGE: u32 = 25;
+const OP_TYPE_SAMPLER: u32 = 26;
+const OP_TYPE_SAMPLED_IMAGE: u32 = 27;
+const OP_TYPE_ARRAY: u32 = 28;
+const OP_TYPE_RUNTIME_ARRAY: u32 = 29;
+const OP_TYPE_STRUCT: u32 = 30;
+const OP_TYPE_POINTER: u32 = 32;
+const OP_CONSTANT: u32 = 43;
+const OP_VARIABLE: u32 = 59;
+const OP_DECORATE: u32 = 71;
+const OP_MEMBER_DECORATE: u32 = 72;
+
+const DECORATION_BUFFER_BLOCK: u32 = 3;
+const DECORATION_ARRAY_STRIDE: u32 = 6;
+const DECORATION_MATRIX_STRIDE: u32 = 7;
+const DECORATION_BUILT_IN: u32 = 11;
+const DECORATION_LOCATION: u32 = 30;
+const DECORATION_BINDING: u32 = 33;
+const DECORATION_DESCRIPTOR_SET: u32 = 34;
+const DECORATION_OFFSET: u32 = 35;
+
+const STORAGE_UNIFORM_CONSTANT: u32 = 0;
+const STORAGE_INPUT: u32 = 1;
+const STORAGE_UNIFORM: u32 = 2;
+const STORAGE_PUSH_CONSTANT: u32 = 9;
+const STORAGE_STORAGE_BUFFER: u32 = 12;
+
+const DIM_BUFFER: u32 = 5;
+const DIM_SUBPASS_DATA: u32 = 6;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScalarKind {
+    Float,
+    Sint,
+    Uint,
+    Bool,
+}
+
+/// A descriptor a shader declares. `count` is 0 for runtime-sized arrays.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReflectedBinding {
+    pub set: u32,
+    pub binding: u32,
+    pub descriptor_type: vk::DescriptorType,
+    pub count: u32,
+    pub stage_flags: vk::ShaderStageFlags,
+    pub name: String,
+}
+
+/// A vertex shader input, as the vertex layout has to provide it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReflectedInput {
+    pub location: u32,
+    pub kind: ScalarKind,
+    pub components: u32,
+    pub name: String,
+}
+
+/// What a shader stage expects from its pipeline layout and, for vertex shaders, its vertex input.
+#[derive(Clone, Debug)]
+pub struct ShaderReflection {
+    pub stage: vk::ShaderStageFlags,
+    pub bindings: Vec<ReflectedBinding>,
+    /// Bytes of the push constant block, 0 without one.
+    pub push_constant_size: u32,
+    /// Only filled in for vertex shaders.
+    pub inputs: Vec<ReflectedInput>,
+}
+
+enum Type {
+    Scalar { kind: ScalarKind, width: u32 },
+    Vector { component: u32, count: u32 },
+    Matrix { column: u32, count: u32 },
+    Image { dim: u32, sampled: u32 },
+    Sampler,
+    SampledImage,
+    Array { element: u32, length: u32 },
+    RuntimeArray { element: u32 },
+    Struct { members: Vec<u32> },
+    Pointer { pointee: u32 },
+}
+
+/// The parts of a module reflection needs, indexed by result id.
+#[derive(Default)]
+struct Module {
+    stage: Option<vk::ShaderStageFlags>,
+    names: HashMap<u32, String>,
+    types: HashMap<u32, Type>,
+    constants: HashMap<u32, u32>,
+    decorations: HashMap<(u32, u32), u32>,
+    member_decorations: HashMap<(u32, u32, u32), u32>,
+    variables: Vec<(u32, u32, u32)>,
+}
+
+fn read_string(words: &[u32]) -> String {
+    let bytes: Vec<u8> = words
+        .iter()
+        .flat_map(|word| word.to_le_bytes().to_vec())
+        .take_while(|&byte| byte != 0)
+        .collect();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Operands `parse` reads from an instruction, so short instructions are errors rather than out of bounds reads.
+fn min_operands(opcode: u32) -> usize {
+    match opcode {
+        OP_NAME | OP_ENTRY_POINT | OP_TYPE_BOOL | OP_TYPE_SAMPLER | OP_TYPE_SAMPLED_IMAGE | OP_TYPE_STRUCT => 1,
+        OP_TYPE_FLOAT | OP_TYPE_RUNTIME_ARRAY | OP_DECORATE => 2,
+        OP_TYPE_INT | OP_TYPE_VECTOR | OP_TYPE_MATRIX | OP_TYPE_ARRAY | OP_TYPE_POINTER | OP_CONSTANT | OP_VARIABLE | OP_MEMBER_DECORATE => 3,
+        OP_TYPE_IMAGE => 7,
+        _ => 0,
+    }
+}
+
+fn parse(spirv: &[u8]) -> Result<Module, String> {
+    if !spirv.len().is_multiple_of(4) || spirv.len() < HEADER_WORDS * 4 {
+        return Err(format!("{} bytes is not a SPIR-V module", spirv.len()));
+    }
+    let words: Vec<u32> = spirv
+        .chunks(4)
+        .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .collect();
+    if words[0] != SPIRV_MAGIC {
+        return Err(format!("bad SPIR-V magic number {:#010x}", words[0]));
+    }
+
+    let mut module = Module::default();
+    let mut at = HEADER_WORDS;
+    while at < words.len() {
+        let opcode = words[at] & 0xffff;
+        let word_count = (words[at] >> 16) as usize;
+        if word_count == 0 || at + word_count > words.len() {
+            return Err(format!("truncated instruction at word {}", at));
+        }
+        let operands = &words[at + 1..at + word_count];
+        if operands.len() < min_operands(opcode) {
+            return Err(format!("instruction {} at word {} has {} operands", opcode, at, operands.len()));
+        }
+        at += word_count;
+
+        match opcode {
+            OP_NAME => {
+                module.names.insert(operands[0], read_string(&operands[1..]));
+            }
+            OP_ENTRY_POINT if module.stage.is_none() => {
+                module.stage = Some(match operands[0] {
+                    0 => vk::ShaderStageFlags::VERTEX,
+                    1 => vk::ShaderStageFlags::TESSELLATION_CONTROL,
+                    2 => vk::ShaderStageFlags::TESSELLATION_EVALUATION,
+                    3 => vk::ShaderStageFlags::GEOMETRY,
+                    4 => vk::ShaderStageFlags::FRAGMENT,
+                    5 => vk::ShaderStageFlags::COMPUTE,
+                    model => return Err(format!("unsupported execution model {}", model)),
+                });
+            }
+            OP_TYPE_BOOL => {
+                module.types.insert(
+                    operands[0],
+                    Type::Scalar {
+                        kind: ScalarKind::Bool,
+                        width: 32,
+                    },
+                );
+            }
+            OP_TYPE_INT => {
+                let kind = if operands[2] == 0 { ScalarKind::Uint } else { ScalarKind::Sint };
+                module.types.insert(operands[0], Type::Scalar { kind, width: operands[1] });
+            }
+            OP_TYPE_FLOAT => {
+                module.types.insert(
+                    operands[0],
+                    Type::Scalar {
+                        kind: ScalarKind::Float,
+                        width: operands[1],
+                    },
+                );
+            }
+            OP_TYPE_VECTOR => {
+                module.types.insert(
+                    operands[0],
+                    Type::Vector {
+                        component: operands[1],
+                        count: operands[2],
+                    },
+                );
+            }
+            OP_TYPE_MATRIX => {
+                module.types.insert(
+                    operands[0],
+                    Type::Matrix {
+                        column: operands[1],
+                        count: operands[2],
+                    },
+                );
+            }
+            OP_TYPE_IMAGE => {
+                module.types.insert(
+                    operands[0],
+                    Type::Image {
+                        dim: operands[2],
+                        sampled: operands[6],
+                    },
+                );
+            }
+            OP_TYPE_SAMPLER => {
+                module.types.insert(operands[0], Type::Sampler);
+            }
+            OP_TYPE_SAMPLED_IMAGE => {
+                module.types.insert(operands[0], Type::SampledImage);
+            }
+            OP_TYPE_ARRAY => {
+                let length = *module.constants.get(&operands[2]).ok_or("array length is not a constant")?;
+                module.types.insert(operands[0], Type::Array { element: operands[1], length });
+            }
+            OP_TYPE_RUNTIME_ARRAY => {
+                module.types.insert(operands[0], Type::RuntimeArray { element: operands[1] });
+            }
+            OP_TYPE_STRUCT => {
+                module.types.insert(
+                    operands[0],
+                    Type::Struct {
+                        members: operands[1..].to_vec(),
+                    },
+                );
+            }
+            OP_TYPE_POINTER => {
+                module.types.insert(operands[0], Type::Pointer { pointee: operands[2] });
+            }
+            OP_CONSTANT => {
+                module.constants.insert(operands[1], operands[2]);
+            }
+            OP_VARIABLE => {
+                module.variables.push((operands[1], operands[0], operands[2]));
+            }
+            OP_DECORATE => {
+                module.decorations.insert((operands[0], operands[1]), operands.get(2).cloned().unwrap_or(0));
+            }
+            OP_MEMBER_DECORATE => {
+                module
+                    .member_decorations
+                    .insert((operands[0], operands[1], operands[2]), operands.get(3).cloned().unwrap_or(0));
+            }
+            _ => {}
+        }
+    }
+    Ok(module)
+}
+
+impl Module {
+    fn decoration(&self, id: u32, decoration: u32) -> Option<u32> {
+        self.decorations.get(&(id, decoration)).cloned()
+    }
+
+    fn get_type(&self, id: u32) -> Result<&Type, String> {
+        self.types.get(&id).ok_or_else(|| format!("unknown type %{}", id))
+    }
+
+    /// Size of a block member in bytes, using the strides the block's layout decorations give.
+    fn size_of(&self, id: u32, matrix_stride: Option<u32>) -> Result<u32, String> {
+        Ok(match self.get_type(id)? {
+            Type::Scalar { width, .. } => width / 8,
+            Type::Vector { component, count } => self.size_of(*component, None)? * count,
+            Type::Matrix { column, count } => match matrix_stride {
+                Some(stride) => stride * count,
+                None => self.size_of(*column, None)? * count,
+            },
+            Type::Array { element, length } => match self.decoration(id, DECORATION_ARRAY_STRIDE) {
+                Some(stride) => stride * length,
+                None => self.size_of(*element, matrix_stride)? * length,
+            },
+            Type::Struct { members } => {
+                let mut size = 0;
+                for (index, &member) in members.iter().enumerate() {
+                    let index = index as u32;
+                    let offset = self.member_decorations.get(&(id, index, DECORATION_OFFSET)).cloned().unwrap_or(size);
+                    let stride = self.member_decorations.get(&(id, index, DECORATION_MATRIX_STRIDE)).cloned();
+                    size = size.max(offset + self.size_of(member, stride)?);
+                }
+                size
+            }
+            _ => return Err(format!("type %{} has no size", id)),
+        })
+    }
+
+    fn descriptor_type(&self, id: u32, storage_class: u32) -> Result<vk::DescriptorType, String> {
+        Ok(match (storage_class, self.get_type(id)?) {
+            (STORAGE_UNIFORM, _) if self.decoration(id, DECORATION_BUFFER_BLOCK).is_some() => vk::DescriptorType::STORAGE_BUFFER,
+            (STORAGE_UNIFORM, _) => vk::DescriptorType::UNIFORM_BUFFER,
+            (STORAGE_STORAGE_BUFFER, _) => vk::DescriptorType::STORAGE_BUFFER,
+            (_, Type::Sampler) => vk::DescriptorType::SAMPLER,
+            (_, Type::SampledImage) => vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            (_, Type::Image { dim: DIM_BUFFER, sampled: 2 }) => vk::DescriptorType::STORAGE_TEXEL_BUFFER,
+            (_, Type::Image { dim: DIM_BUFFER, .. }) => vk::DescriptorType::UNIFORM_TEXEL_BUFFER,
+            (_, Type::Image { dim: DIM_SUBPASS_DATA, .. }) => vk::DescriptorType::INPUT_ATTACHMENT,
+            (_, Type::Image { sampled: 2, .. }) => vk::DescriptorType::STORAGE_IMAGE,
+            (_, Type::Image { .. }) => vk::DescriptorType::SAMPLED_IMAGE,
+            _ => return Err(format!("%{} is not a descriptor type", id)),
+        })
+    }
+
+    fn input(&self, id: u32, location: u32) -> Result<ReflectedInput, String> {
+        let (kind, components) = match self.get_type(id)? {
+            Type::Scalar { kind, .. } => (*kind, 1),
+            Type::Vector { component, count } => match self.get_type(*component)? {
+                Type::Scalar { kind, .. } => (*kind, *count),
+                _ => return Err(format!("vector %{} of non-scalars", id)),
+            },
+            _ => return Err(format!("vertex input at location {} is not a scalar or vector", location)),
+        };
+        Ok(ReflectedInput {
+            location,
+            kind,
+            components,
+            name: String::new(),
+        })
+    }
+}
+
+/// Reads the descriptor bindings, push constant block and vertex inputs a SPIR-V module declares. Bindings are
+/// reported whether or not the entry point uses them, as the pipeline layout has to cover them either way.
+pub fn reflect(spirv: &[u8]) -> Result<ShaderReflection, String> {
+    let module = parse(spirv)?;
+    let stage = module.stage.ok_or("no entry point")?;
+
+    let mut reflection = ShaderReflection {
+        stage,
+        bindings: vec![],
+        push_constant_size: 0,
+        inputs: vec![],
+    };
+    for &(id, pointer_type, storage_class) in module.variables.iter() {
+        let pointee = match module.get_type(pointer_type)? {
+            Type::Pointer { pointee } => *pointee,
+            _ => return Err(format!("variable %{} is not a pointer", id)),
+        };
+        let name = module.names.get(&id).cloned().unwrap_or_default();
+
+        match storage_class {
+            STORAGE_UNIFORM_CONSTANT | STORAGE_UNIFORM | STORAGE_STORAGE_BUFFER => {
+                let binding = match module.decoration(id, DECORATION_BINDING) {
+                    Some(binding) => binding,
+                    None => continue,
+                };
+                let (element, count) = match module.get_type(pointee)? {
+                    Type::Array { element, length } => (*element, *length),
+                    Type::RuntimeArray { element } => (*element, 0),
+                    _ => (pointee, 1),
+                };
+                reflection.bindings.push(ReflectedBinding {
+                    set: module.decoration(id, DECORATION_DESCRIPTOR_SET).unwrap_or(0),
+                    binding,
+                    descriptor_type: module.descriptor_type(element, storage_class)?,
+                    count,
+                    stage_flags: stage,
+                    name,
+                });
+            }
+            STORAGE_PUSH_CONSTANT => {
+                reflection.push_constant_size = module.size_of(pointee, None)?;
+            }
+            STORAGE_INPUT if stage == vk::ShaderStageFlags::VERTEX => {
+                if module.decoration(id, DECORATION_BUILT_IN).is_some() {
+                    continue;
+                }
+                if let Some(location) = module.decoration(id, DECORATION_LOCATION) {
+                    let mut input = module.input(pointee, location)?;
+                    input.name = name;
+                    reflection.inputs.push(input);
+                }
+            }
+            _ => {}
+        }
+    }
+    reflection.bindings.sort_by_key(|binding| (binding.set, binding.binding));
+    reflection.inputs.sort_by_key(|input| input.location);
+    Ok(reflection)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reflect_file(name: &str) -> ShaderReflection {
+        let spirv = std::fs::read(format!("shaders/{}.spv", name)).unwrap();
+        reflect(&spirv).unwrap()
+    }
+
+    fn binding_summary(reflection: &ShaderReflection) -> Vec<(u32, u32, vk::DescriptorType, u32, &str)> {
+        reflection
+            .bindings
+            .iter()
+            .map(|binding| (binding.set, binding.binding, binding.descriptor_type, binding.count, binding.name.as_str()))
+            .collect()
+    }
+
+    fn input_summary(reflection: &ShaderReflection) -> Vec<(u32, ScalarKind, u32)> {
+        reflection.inputs.iter().map(|input| (input.location, input.kind, input.components)).collect()
+    }
+
+    #[test]
+    fn static_vertex_shader() {
+        let reflection = reflect_file("pushconst.vert");
+        assert_eq!(reflection.stage, vk::ShaderStageFlags::VERTEX);
+        assert_eq!(binding_summary(&reflection), vec![(0, 0, vk::DescriptorType::UNIFORM_BUFFER, 1, "ubo")]);
+        // The model matrix.
+        assert_eq!(reflection.push_constant_size, 64);
+        assert_eq!(
+            input_summary(&reflection),
+            vec![
+                (0, ScalarKind::Float, 3),
+                (1, ScalarKind::Float, 3),
+                (2, ScalarKind::Float, 2),
+                (3, ScalarKind::Float, 3)
+            ]
+        );
+        assert_eq!(reflection.inputs[0].name, "inPosition");
+    }
+
+    #[test]
+    fn skinned_vertex_shader() {
+        let reflection = reflect_file("skinned.vert");
+        assert_eq!(
+            binding_summary(&reflection),
+            vec![
+                (0, 0, vk::DescriptorType::UNIFORM_BUFFER, 1, "ubo"),
+                (2, 0, vk::DescriptorType::STORAGE_BUFFER, 1, "jointMatrices")
+            ]
+        );
+        assert_eq!(reflection.push_constant_size, 68);
+        assert_eq!(input_summary(&reflection)[4..], [(5, ScalarKind::Uint, 4), (6, ScalarKind::Float, 4)]);
+    }
+
+    #[test]
+    fn fragment_shaders_have_no_vertex_inputs() {
+        let reflection = reflect_file("tonemap.frag");
+        assert_eq!(reflection.stage, vk::ShaderStageFlags::FRAGMENT);
+        assert_eq!(
+            binding_summary(&reflection),
+            vec![
+                (0, 0, vk::DescriptorType::SAMPLER, 1, "postSampler"),
+                (0, 1, vk::DescriptorType::SAMPLED_IMAGE, 1, "hdrColor"),
+                (0, 2, vk::DescriptorType::SAMPLED_IMAGE, 1, "bloom"),
+                (0, 3, vk::DescriptorType::SAMPLED_IMAGE, 1, "gradingLut")
+            ]
+        );
+        assert_eq!(reflection.push_constant_size, 40);
+        assert!(reflection.inputs.is_empty());
+        assert!(reflection.bindings.iter().all(|binding| binding.stage_flags == vk::ShaderStageFlags::FRAGMENT));
+    }
+
+    #[test]
+    fn runtime_arrays_and_combined_samplers() {
+        let bindless = reflect_file("bindless.frag");
+        let textures = bindless.bindings.iter().find(|binding| binding.name == "textures").unwrap();
+        assert_eq!((textures.set, textures.binding, textures.count), (1, 0, 0));
+        assert_eq!(textures.descriptor_type, vk::DescriptorType::SAMPLED_IMAGE);
+        assert_eq!(
+            binding_summary(&reflect_file("texture.frag")),
+            vec![(0, 1, vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 1, "texSampler")]
+        );
+    }
+
+    fn module(instructions: &[u32]) -> Vec<u8> {
+        let mut words = vec![SPIRV_MAGIC, 0x0001_0000, 0, 16, 0];
+        words.extend_from_slice(instructions);
+        words.iter().flat_map(|word| word.to_le_bytes().to_vec()).collect()
+    }
+
+    #[test]
+    fn malformed_modules_are_errors() {
+        let mut bad_magic = module(&[]);
+        bad_magic[0] = 0;
+        assert!(parse(&bad_magic).err().unwrap().contains("magic"));
+        assert!(parse(&bad_magic[..7]).is_err());
+        assert!(parse(&[]).is_err());
+
+        // OpName claiming four words with only two left.
+        let truncated = module(&[(4 << 16) | OP_NAME, 1]);
+        assert_eq!(parse(&truncated).err(), Some(format!("truncated instruction at word {}", HEADER_WORDS)));
+        let zero_length = module(&[0]);
+        assert!(parse(&zero_length).err().unwrap().contains("truncated"));
+
+        let short_name = module(&[(1 << 16) | OP_NAME]);
+        assert!(parse(&short_name).err().unwrap().contains("has 0 operands"));
+        let short_image = module(&[(4 << 16) | OP_TYPE_IMAGE, 1, 2, 1]);
+        assert!(parse(&short_image).is_err());
+        assert_eq!(reflect(&module(&[])).err(), Some("no entry point".to_string()));
+    }
+}
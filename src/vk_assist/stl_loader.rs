@@ -0,0 +1,177 @@
+#![allow(dead_code)]
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use nalgebra_glm::{Vec2, Vec3};
+
+use crate::vk_assist::structures::MeshVertex;
+
+/// Binary STL: 80 byte header, u32 facet count, then 50 bytes per facet.
+const BINARY_HEADER_SIZE: usize = 84;
+const BINARY_FACET_SIZE: usize = 50;
+
+/// One triangle as stored in the file. `normal` is whatever the exporter wrote, often zero.
+#[derive(Clone, Copy, Debug)]
+pub struct Facet {
+    pub normal: Vec3,
+    pub corners: [Vec3; 3],
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn read_vec3(bytes: &[u8]) -> Vec3 {
+    let f = |i: usize| f32::from_le_bytes([bytes[i * 4], bytes[i * 4 + 1], bytes[i * 4 + 2], bytes[i * 4 + 3]]);
+    Vec3::new(f(0), f(1), f(2))
+}
+
+fn parse_binary(bytes: &[u8]) -> Vec<Facet> {
+    bytes[BINARY_HEADER_SIZE..]
+        .chunks_exact(BINARY_FACET_SIZE)
+        .map(|facet| Facet {
+            normal: read_vec3(&facet[0..12]),
+            corners: [read_vec3(&facet[12..24]), read_vec3(&facet[24..36]), read_vec3(&facet[36..48])],
+        })
+        .collect()
+}
+
+fn parse_ascii(text: &str) -> io::Result<Vec<Facet>> {
+    let mut facets = vec![];
+    let mut normal = Vec3::zeros();
+    let mut corners = vec![];
+    for line in text.lines() {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let vec3 = |words: &[&str]| -> io::Result<Vec3> {
+            let v: Vec<f32> = words
+                .iter()
+                .map(|word| word.parse::<f32>().map_err(|_| invalid(format!("bad STL number '{}'", word))))
+                .collect::<io::Result<Vec<f32>>>()?;
+            if v.len() != 3 {
+                return Err(invalid(format!("expected 3 numbers in STL line '{}'", line.trim())));
+            }
+            Ok(Vec3::new(v[0], v[1], v[2]))
+        };
+        match words.as_slice() {
+            ["facet", "normal", rest @ ..] => {
+                normal = vec3(rest)?;
+                corners.clear();
+            }
+            ["vertex", rest @ ..] => corners.push(vec3(rest)?),
+            ["endfacet"] => {
+                if corners.len() != 3 {
+                    return Err(invalid(format!("STL facet has {} vertices", corners.len())));
+                }
+                facets.push(Facet {
+                    normal,
+                    corners: [corners[0], corners[1], corners[2]],
+                });
+            }
+            _ => {}
+        }
+    }
+    Ok(facets)
+}
+
+/// Reads an ascii or binary STL. Binary files are told apart by their size matching the facet count in the header,
+/// since plenty of binary exporters also start the header with "solid".
+pub fn load_stl(path: &Path) -> io::Result<Vec<Facet>> {
+    parse_stl(&fs::read(path)?)
+}
+
+/// `load_stl` on the contents of a file.
+pub fn parse_stl(bytes: &[u8]) -> io::Result<Vec<Facet>> {
+    let binary_size = if bytes.len() >= BINARY_HEADER_SIZE {
+        let count = u32::from_le_bytes([bytes[80], bytes[81], bytes[82], bytes[83]]) as usize;
+        Some(BINARY_HEADER_SIZE + count * BINARY_FACET_SIZE)
+    } else {
+        None
+    };
+    if binary_size == Some(bytes.len()) {
+        return Ok(parse_binary(bytes));
+    }
+    // Ascii STL is plain text without NULs, so anything else that fails the size check is a broken binary file.
+    match std::str::from_utf8(bytes) {
+        Ok(text) if text.starts_with("solid") && !text.contains('\0') => parse_ascii(text),
+        _ => match binary_size {
+            Some(size) => Err(invalid(format!(
+                "binary STL should be {} bytes for its facet count, it is {}",
+                size,
+                bytes.len()
+            ))),
+            None => Err(invalid("not an STL file".to_string())),
+        },
+    }
+}
+
+/// Unshared triangle list for `facets`, with normals left for `generate_normals`. Facets whose stored normal points
+/// against their winding are flipped, so exporters that get the winding wrong still come out counter-clockwise.
+pub fn facets_to_mesh(facets: &[Facet]) -> (Vec<MeshVertex>, Vec<u32>) {
+    let mut vertices = Vec::with_capacity(facets.len() * 3);
+    for facet in facets.iter() {
+        let [a, mut b, mut c] = facet.corners;
+        if (b - a).cross(&(c - a)).dot(&facet.normal) < 0.0 {
+            std::mem::swap(&mut b, &mut c);
+        }
+        for &pos in [a, b, c].iter() {
+            vertices.push(MeshVertex::new(pos, Vec3::new(1.0, 1.0, 1.0), Vec2::zeros()));
+        }
+    }
+    let indices = (0..vertices.len() as u32).collect();
+    (vertices, indices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A binary STL with one facet per entry of `corners`, its header starting with `header`.
+    fn binary(header: &[u8], corners: &[[f32; 9]]) -> Vec<u8> {
+        let mut bytes = vec![0u8; 80];
+        bytes[..header.len()].copy_from_slice(header);
+        bytes.extend_from_slice(&(corners.len() as u32).to_le_bytes());
+        for facet in corners.iter() {
+            bytes.extend(std::iter::repeat(0).take(12));
+            for &coordinate in facet.iter() {
+                bytes.extend_from_slice(&coordinate.to_le_bytes());
+            }
+            bytes.extend_from_slice(&[0, 0]);
+        }
+        bytes
+    }
+
+    #[test]
+    fn binary_with_a_solid_header_loads_as_binary() {
+        let bytes = binary(b"solid exported by a binary writer", &[[0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0]; 2]);
+        let facets = parse_stl(&bytes).unwrap();
+        assert_eq!(facets.len(), 2);
+        assert_eq!(facets[1].corners[1], Vec3::new(1.0, 0.0, 0.0));
+        assert_eq!(facets[0].normal, Vec3::zeros());
+    }
+
+    #[test]
+    fn truncated_binary_is_an_error() {
+        for header in [&b"solid"[..], &b"binary"[..]].iter() {
+            // All zero coordinates keep the data valid UTF-8, so only the NULs give the file away as binary.
+            let bytes = binary(header, &[[0.0; 9]; 2]);
+            let error = parse_stl(&bytes[..bytes.len() - 10]).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+        assert!(parse_stl(b"solid").unwrap().is_empty());
+        assert!(parse_stl(b"garbage").is_err());
+    }
+
+    #[test]
+    fn ascii_facets_and_flipped_winding() {
+        let text = "solid tri\nfacet normal 0 0 -1\nouter loop\nvertex 0 0 0\nvertex 1 0 0\nvertex 0 1 0\nendloop\nendfacet\nendsolid tri\n";
+        let facets = parse_stl(text.as_bytes()).unwrap();
+        assert_eq!(facets.len(), 1);
+        // The stored normal points down, so the corners are swapped to wind towards it.
+        let (vertices, indices) = facets_to_mesh(&facets);
+        assert_eq!(indices, vec![0, 1, 2]);
+        assert_eq!(vertices[1].pos, Vec3::new(0.0, 1.0, 0.0));
+        assert!(parse_stl(b"solid tri\nfacet normal 0 0 1\nouter loop\nvertex 0 0 0\nendloop\nendfacet\n").is_err());
+    }
+}
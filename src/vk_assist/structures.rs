@@ -108,6 +108,126 @@ impl Vertex {
         ]
     }
 }
+/// Vertex layout used by loaded meshes. `normal` and `tangent` feed lighting and normal mapping, `tangent.w` holds the
+/// bitangent sign so the bitangent can be rebuilt as `tangent.w * cross(normal, tangent.xyz)`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct MeshVertex {
+    pub pos: Vec3,
+    pub color: Vec3,
+    pub uv: Vec2,
+    pub normal: Vec3,
+    pub tangent: Vec4,
+}
+#[allow(dead_code)]
+impl MeshVertex {
+    pub fn new(pos: Vec3, color: Vec3, uv: Vec2) -> MeshVertex {
+        MeshVertex {
+            pos,
+            color,
+            uv,
+            normal: Vec3::zeros(),
+            tangent: Vec4::new(1.0, 0.0, 0.0, 1.0),
+        }
+    }
+
+    pub fn get_binding_descriptions() -> [vk::VertexInputBindingDescription; 1] {
+        [vk::VertexInputBindingDescription {
+            binding: 0,
+            stride: ::std::mem::size_of::<Self>() as u32,
+            input_rate: vk::VertexInputRate::VERTEX,
+        }]
+    }
+
+    pub fn get_attribute_descriptions() -> [vk::VertexInputAttributeDescription; 5] {
+        [
+            vk::VertexInputAttributeDescription {
+                binding: 0,
+                location: 0,
+                format: vk::Format::R32G32B32_SFLOAT,
+                offset: offset_of!(Self, pos) as u32,
+            },
+            vk::VertexInputAttributeDescription {
+                binding: 0,
+                location: 1,
+                format: vk::Format::R32G32B32_SFLOAT,
+                offset: offset_of!(Self, color) as u32,
+            },
+            vk::VertexInputAttributeDescription {
+                binding: 0,
+                location: 2,
+                format: vk::Format::R32G32_SFLOAT,
+                offset: offset_of!(Self, uv) as u32,
+            },
+            vk::VertexInputAttributeDescription {
+                binding: 0,
+                location: 3,
+                format: vk::Format::R32G32B32_SFLOAT,
+                offset: offset_of!(Self, normal) as u32,
+            },
+            vk::VertexInputAttributeDescription {
+                binding: 0,
+                location: 4,
+                format: vk::Format::R32G32B32A32_SFLOAT,
+                offset: offset_of!(Self, tangent) as u32,
+            },
+        ]
+    }
+}
+
+/// Per-vertex skinning data, kept in its own vertex buffer (binding 1) so unskinned meshes don't pay for it.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SkinVertex {
+    /// Indices into the skeleton's joints.
+    pub joints: [u16; 4],
+    /// Sums to 1.
+    pub weights: Vec4,
+}
+#[allow(dead_code)]
+impl SkinVertex {
+    pub fn get_binding_descriptions() -> [vk::VertexInputBindingDescription; 1] {
+        [vk::VertexInputBindingDescription {
+            binding: 1,
+            stride: ::std::mem::size_of::<Self>() as u32,
+            input_rate: vk::VertexInputRate::VERTEX,
+        }]
+    }
+
+    pub fn get_attribute_descriptions() -> [vk::VertexInputAttributeDescription; 2] {
+        [
+            vk::VertexInputAttributeDescription {
+                binding: 1,
+                location: 5,
+                format: vk::Format::R16G16B16A16_UINT,
+                offset: offset_of!(Self, joints) as u32,
+            },
+            vk::VertexInputAttributeDescription {
+                binding: 1,
+                location: 6,
+                format: vk::Format::R32G32B32A32_SFLOAT,
+                offset: offset_of!(Self, weights) as u32,
+            },
+        ]
+    }
+}
+
+/// Push constants of the skinned pipeline. `joint_offset` is where this instance's matrices start in the joint buffer.
+#[repr(C)]
+#[derive(Clone, Debug, Copy)]
+pub struct SkinnedPushConstants {
+    pub model: Mat4,
+    pub joint_offset: u32,
+}
+
+/// Push constants of the bindless static pipeline. `material` indexes the bindless material buffer.
+#[repr(C)]
+#[derive(Clone, Debug, Copy)]
+pub struct BindlessPushConstants {
+    pub model: Mat4,
+    pub material: u32,
+}
+
 #[allow(dead_code)]
 pub fn get_rectangle(x_dim: f32, y_dim: f32) -> [SimpleVertex; 4] {
     let half_x = x_dim / 2.0;
@@ -190,3 +190,49 @@ pub fn copy_buffer(
 
     end_single_time_command(device.clone(), command_pool, submit_queue, command_buffer);
 }
+
+/// Creates a device-local buffer with `usage` and fills it with `data` through a staging buffer, which is destroyed once
+/// the copy has finished.
+pub fn create_device_local_buffer<T>(device: Arc<VulkanDevice>, command_pool: vk::CommandPool, data: &[T], usage: vk::BufferUsageFlags) -> Buffer {
+    let buffer_size = std::mem::size_of_val(data) as vk::DeviceSize;
+    let device_memory_properties = device.get_physical_device_memory_properties();
+
+    let mut staging_buffer = create_buffer(
+        device.clone(),
+        buffer_size,
+        vk::BufferUsageFlags::TRANSFER_SRC,
+        vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        &device_memory_properties,
+    );
+
+    unsafe {
+        let data_ptr = device
+            .logical_device
+            .map_memory(staging_buffer.memory, 0, buffer_size, vk::MemoryMapFlags::empty())
+            .expect("Failed to Map Memory") as *mut T;
+
+        data_ptr.copy_from_nonoverlapping(data.as_ptr(), data.len());
+
+        device.logical_device.unmap_memory(staging_buffer.memory);
+    }
+
+    let buffer = create_buffer(
+        device.clone(),
+        buffer_size,
+        vk::BufferUsageFlags::TRANSFER_DST | usage,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        &device_memory_properties,
+    );
+
+    copy_buffer(
+        device.clone(),
+        device.graphics_queue,
+        command_pool,
+        staging_buffer.buffer,
+        buffer.buffer,
+        buffer_size,
+    );
+    staging_buffer.vk_destroy();
+
+    buffer
+}
@@ -0,0 +1,325 @@
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use ash::version::DeviceV1_0;
+use ash::vk;
+
+use std::ptr;
+
+use super::vulkan_device::VulkanDevice;
+
+/// Descriptors of each type a pool holds per set it can allocate. Every core descriptor type is covered, so a layout
+/// using one the app doesn't yet still gets pools it fits in.
+pub const DEFAULT_POOL_RATIOS: [(vk::DescriptorType, f32); 11] = [
+    (vk::DescriptorType::SAMPLER, 2.0),
+    (vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 2.0),
+    (vk::DescriptorType::SAMPLED_IMAGE, 2.0),
+    (vk::DescriptorType::STORAGE_IMAGE, 1.0),
+    (vk::DescriptorType::UNIFORM_TEXEL_BUFFER, 0.5),
+    (vk::DescriptorType::STORAGE_TEXEL_BUFFER, 0.5),
+    (vk::DescriptorType::UNIFORM_BUFFER, 2.0),
+    (vk::DescriptorType::STORAGE_BUFFER, 1.0),
+    (vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC, 1.0),
+    (vk::DescriptorType::STORAGE_BUFFER_DYNAMIC, 0.5),
+    (vk::DescriptorType::INPUT_ATTACHMENT, 0.5),
+];
+/// Sets in the first pool. Every pool after it is twice as big, up to `MAX_SETS_PER_POOL`.
+const INITIAL_SETS_PER_POOL: u32 = 32;
+const MAX_SETS_PER_POOL: u32 = 4096;
+
+/// Allocates descriptor sets of any layout, adding a pool whenever the current one runs out. Sets are never freed one
+/// by one: they all go at once with `reset` or `vk_destroy`.
+pub struct DescriptorAllocator {
+    device: Arc<VulkanDevice>,
+    ratios: Vec<(vk::DescriptorType, f32)>,
+    sets_per_pool: u32,
+    /// Pool sets are allocated from. Null until the first allocation.
+    current: vk::DescriptorPool,
+    /// Pools that ran out, kept until the next reset.
+    full: Vec<vk::DescriptorPool>,
+    /// Reset pools, reused before new ones are created.
+    free: Vec<vk::DescriptorPool>,
+}
+
+impl DescriptorAllocator {
+    pub fn new(device: Arc<VulkanDevice>) -> DescriptorAllocator {
+        DescriptorAllocator::with_ratios(device, &DEFAULT_POOL_RATIOS)
+    }
+
+    pub fn with_ratios(device: Arc<VulkanDevice>, ratios: &[(vk::DescriptorType, f32)]) -> DescriptorAllocator {
+        DescriptorAllocator {
+            device,
+            ratios: ratios.to_vec(),
+            sets_per_pool: INITIAL_SETS_PER_POOL,
+            current: vk::DescriptorPool::null(),
+            full: vec![],
+            free: vec![],
+        }
+    }
+
+    /// One set per layout in `layouts`, all from the same pool.
+    pub fn allocate(&mut self, layouts: &[vk::DescriptorSetLayout]) -> Vec<vk::DescriptorSet> {
+        if self.current == vk::DescriptorPool::null() {
+            self.current = self.next_pool(layouts.len() as u32);
+        }
+        match self.try_allocate(layouts) {
+            Ok(descriptor_sets) => descriptor_sets,
+            Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY) | Err(vk::Result::ERROR_FRAGMENTED_POOL) => {
+                self.full.push(self.current);
+                self.current = self.next_pool(layouts.len() as u32);
+                self.try_allocate(layouts).expect("Descriptor sets don't fit in an empty pool!")
+            }
+            Err(err) => panic!("Failed to allocate descriptor sets: {:?}", err),
+        }
+    }
+
+    pub fn allocate_one(&mut self, layout: vk::DescriptorSetLayout) -> vk::DescriptorSet {
+        self.allocate(&[layout])[0]
+    }
+
+    /// Frees every set allocated so far. None of them may be in use by a pending command buffer.
+    pub fn reset(&mut self) {
+        if self.current != vk::DescriptorPool::null() {
+            self.full.push(self.current);
+            self.current = vk::DescriptorPool::null();
+        }
+        for pool in self.full.drain(..) {
+            unsafe {
+                self.device
+                    .logical_device
+                    .reset_descriptor_pool(pool, vk::DescriptorPoolResetFlags::empty())
+                    .expect("Failed to reset Descriptor Pool!");
+            }
+            self.free.push(pool);
+        }
+    }
+
+    pub fn pool_count(&self) -> usize {
+        let current = if self.current == vk::DescriptorPool::null() { 0 } else { 1 };
+        current + self.full.len() + self.free.len()
+    }
+
+    pub fn vk_destroy(&mut self) {
+        self.reset();
+        for pool in self.free.drain(..) {
+            unsafe {
+                self.device.logical_device.destroy_descriptor_pool(pool, None);
+            }
+        }
+    }
+
+    fn try_allocate(&self, layouts: &[vk::DescriptorSetLayout]) -> ash::prelude::VkResult<Vec<vk::DescriptorSet>> {
+        let descriptor_set_allocate_info = vk::DescriptorSetAllocateInfo {
+            s_type: vk::StructureType::DESCRIPTOR_SET_ALLOCATE_INFO,
+            p_next: ptr::null(),
+            descriptor_pool: self.current,
+            descriptor_set_count: layouts.len() as u32,
+            p_set_layouts: layouts.as_ptr(),
+        };
+        unsafe { self.device.logical_device.allocate_descriptor_sets(&descriptor_set_allocate_info) }
+    }
+
+    /// A reset pool if there is one, otherwise a new pool with room for at least `min_sets` sets.
+    fn next_pool(&mut self, min_sets: u32) -> vk::DescriptorPool {
+        if let Some(pool) = self.free.pop() {
+            return pool;
+        }
+        let set_count = grow_pool(&mut self.sets_per_pool, min_sets);
+        if self.pool_count() > 0 {
+            println!("Descriptor pools full, adding a pool of {} sets", set_count);
+        }
+        create_descriptor_pool(&self.device, &self.ratios, set_count)
+    }
+}
+
+/// Sets in the next pool, at least `min_sets`, and doubles `sets_per_pool` for the one after up to `MAX_SETS_PER_POOL`.
+fn grow_pool(sets_per_pool: &mut u32, min_sets: u32) -> u32 {
+    let set_count = (*sets_per_pool).max(min_sets);
+    *sets_per_pool = (*sets_per_pool * 2).min(MAX_SETS_PER_POOL);
+    set_count
+}
+
+/// Descriptors of each type in a pool of `set_count` sets. Every type gets at least one.
+fn pool_sizes(ratios: &[(vk::DescriptorType, f32)], set_count: u32) -> Vec<vk::DescriptorPoolSize> {
+    ratios
+        .iter()
+        .map(|&(ty, ratio)| vk::DescriptorPoolSize {
+            ty,
+            descriptor_count: ((ratio * set_count as f32).ceil() as u32).max(1),
+        })
+        .collect()
+}
+
+fn create_descriptor_pool(device: &VulkanDevice, ratios: &[(vk::DescriptorType, f32)], set_count: u32) -> vk::DescriptorPool {
+    let pool_sizes = pool_sizes(ratios, set_count);
+
+    let descriptor_pool_create_info = vk::DescriptorPoolCreateInfo {
+        s_type: vk::StructureType::DESCRIPTOR_POOL_CREATE_INFO,
+        p_next: ptr::null(),
+        flags: vk::DescriptorPoolCreateFlags::empty(),
+        max_sets: set_count,
+        pool_size_count: pool_sizes.len() as u32,
+        p_pool_sizes: pool_sizes.as_ptr(),
+    };
+
+    unsafe {
+        device
+            .logical_device
+            .create_descriptor_pool(&descriptor_pool_create_info, None)
+            .expect("Failed to create Descriptor Pool!")
+    }
+}
+
+/// What makes two set layouts the same: binding, descriptor type, count and stages of every binding, in binding order.
+type LayoutKey = Vec<(u32, vk::DescriptorType, u32, vk::ShaderStageFlags)>;
+
+fn layout_key(bindings: &[vk::DescriptorSetLayoutBinding]) -> LayoutKey {
+    let mut key: LayoutKey = bindings
+        .iter()
+        .map(|binding| (binding.binding, binding.descriptor_type, binding.descriptor_count, binding.stage_flags))
+        .collect();
+    key.sort();
+    key
+}
+
+/// Hands out one descriptor set layout per distinct list of bindings, so pipelines declaring the same set share its
+/// layout. The cache owns the layouts.
+pub struct DescriptorLayoutCache {
+    device: Arc<VulkanDevice>,
+    layouts: HashMap<LayoutKey, vk::DescriptorSetLayout>,
+}
+
+impl DescriptorLayoutCache {
+    pub fn new(device: Arc<VulkanDevice>) -> DescriptorLayoutCache {
+        DescriptorLayoutCache {
+            device,
+            layouts: HashMap::new(),
+        }
+    }
+
+    /// The layout for `bindings`, in any order, created on first use. Immutable samplers aren't supported.
+    pub fn get(&mut self, bindings: &[vk::DescriptorSetLayoutBinding]) -> vk::DescriptorSetLayout {
+        assert!(
+            bindings.iter().all(|binding| binding.p_immutable_samplers.is_null()),
+            "Cached set layouts can't have immutable samplers"
+        );
+        let device = &self.device;
+        *self.layouts.entry(layout_key(bindings)).or_insert_with(|| create_set_layout(device, bindings))
+    }
+
+    pub fn len(&self) -> usize {
+        self.layouts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.layouts.is_empty()
+    }
+
+    pub fn vk_destroy(&mut self) {
+        for (_, layout) in self.layouts.drain() {
+            unsafe {
+                self.device.logical_device.destroy_descriptor_set_layout(layout, None);
+            }
+        }
+    }
+}
+
+fn create_set_layout(device: &VulkanDevice, bindings: &[vk::DescriptorSetLayoutBinding]) -> vk::DescriptorSetLayout {
+    let layout_create_info = vk::DescriptorSetLayoutCreateInfo {
+        s_type: vk::StructureType::DESCRIPTOR_SET_LAYOUT_CREATE_INFO,
+        p_next: ptr::null(),
+        flags: vk::DescriptorSetLayoutCreateFlags::empty(),
+        binding_count: bindings.len() as u32,
+        p_bindings: bindings.as_ptr(),
+    };
+
+    unsafe {
+        device
+            .logical_device
+            .create_descriptor_set_layout(&layout_create_info, None)
+            .expect("Failed to create Descriptor Set Layout!")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn binding(binding: u32, descriptor_type: vk::DescriptorType, stage_flags: vk::ShaderStageFlags) -> vk::DescriptorSetLayoutBinding {
+        vk::DescriptorSetLayoutBinding {
+            binding,
+            descriptor_type,
+            descriptor_count: 1,
+            stage_flags,
+            p_immutable_samplers: ptr::null(),
+        }
+    }
+
+    #[test]
+    fn pools_double_until_the_cap() {
+        let mut sets_per_pool = INITIAL_SETS_PER_POOL;
+        let sizes: Vec<u32> = (0..10).map(|_| grow_pool(&mut sets_per_pool, 1)).collect();
+        assert_eq!(sizes, vec![32, 64, 128, 256, 512, 1024, 2048, 4096, 4096, 4096]);
+    }
+
+    #[test]
+    fn pools_fit_the_sets_that_overflowed() {
+        let mut sets_per_pool = INITIAL_SETS_PER_POOL;
+        assert_eq!(grow_pool(&mut sets_per_pool, 100), 100);
+        // The request doesn't skip the doubling of the regular size.
+        assert_eq!(grow_pool(&mut sets_per_pool, 1), 64);
+    }
+
+    #[test]
+    fn pool_sizes_follow_the_ratios() {
+        let sizes = pool_sizes(&DEFAULT_POOL_RATIOS, 32);
+        let count = |ty| sizes.iter().find(|size| size.ty == ty).unwrap().descriptor_count;
+        assert_eq!(count(vk::DescriptorType::UNIFORM_BUFFER), 64);
+        assert_eq!(count(vk::DescriptorType::STORAGE_BUFFER), 32);
+        assert_eq!(count(vk::DescriptorType::INPUT_ATTACHMENT), 16);
+        // Small pools still get one of everything.
+        assert!(pool_sizes(&DEFAULT_POOL_RATIOS, 1).iter().all(|size| size.descriptor_count >= 1));
+    }
+
+    #[test]
+    fn default_ratios_cover_every_core_descriptor_type() {
+        // Core descriptor types are numbered 0 to 10.
+        for raw in 0..=10 {
+            let ty = vk::DescriptorType::from_raw(raw);
+            assert!(DEFAULT_POOL_RATIOS.iter().any(|&(covered, _)| covered == ty), "{:?} has no pool ratio", ty);
+        }
+    }
+
+    #[test]
+    fn layouts_with_the_same_bindings_share_a_key() {
+        let ubo = binding(0, vk::DescriptorType::UNIFORM_BUFFER, vk::ShaderStageFlags::VERTEX);
+        let texture = binding(1, vk::DescriptorType::COMBINED_IMAGE_SAMPLER, vk::ShaderStageFlags::FRAGMENT);
+        assert_eq!(layout_key(&[ubo, texture]), layout_key(&[texture, ubo]));
+
+        let mut cache: HashMap<LayoutKey, u32> = HashMap::new();
+        for bindings in [vec![ubo, texture], vec![texture, ubo], vec![ubo]].iter() {
+            let next = cache.len() as u32;
+            cache.entry(layout_key(bindings)).or_insert(next);
+        }
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn any_binding_difference_makes_a_new_layout() {
+        let ubo = binding(0, vk::DescriptorType::UNIFORM_BUFFER, vk::ShaderStageFlags::VERTEX);
+        let key = layout_key(&[ubo]);
+        let moved = binding(1, vk::DescriptorType::UNIFORM_BUFFER, vk::ShaderStageFlags::VERTEX);
+        let dynamic = binding(0, vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC, vk::ShaderStageFlags::VERTEX);
+        let both_stages = binding(
+            0,
+            vk::DescriptorType::UNIFORM_BUFFER,
+            vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+        );
+        let array = vk::DescriptorSetLayoutBinding { descriptor_count: 4, ..ubo };
+        for other in [moved, dynamic, both_stages, array].iter() {
+            assert_ne!(key, layout_key(&[*other]), "{:?}", other);
+        }
+    }
+}
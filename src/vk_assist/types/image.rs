@@ -60,7 +60,7 @@ impl Image {
         self.layer_count
     }
 
-    pub fn vk_destroy(&mut self) {
+    pub fn vk_destroy(&self) {
         unsafe {
             self.device.logical_device.destroy_image(self.image, None);
             self.device.logical_device.destroy_image_view(self.view, None);
@@ -1,7 +1,9 @@
 pub mod buffer;
 pub mod command;
+pub mod descriptor_allocator;
 pub mod frame_manager;
 pub mod image;
+pub mod pipeline_cache;
 pub mod queue_family;
 pub mod vulkan_device;
 pub mod vulkan_surface;
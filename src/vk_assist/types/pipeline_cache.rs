@@ -0,0 +1,219 @@
+#![allow(dead_code)]
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::ptr;
+
+use ash::version::DeviceV1_0;
+use ash::version::InstanceV1_0;
+use ash::vk;
+
+/// Where pipeline caches are kept, one file per GPU from the driver that saved it last.
+pub const PIPELINE_CACHE_DIR: &str = "pipeline_cache";
+/// `VkPipelineCacheHeaderVersionOne`: header length, header version, vendor ID, device ID and the cache UUID.
+const HEADER_SIZE: usize = 16 + vk::UUID_SIZE;
+
+/// Identifies the driver a pipeline cache was built by. Caches from any other GPU or driver are useless.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PipelineCacheKey {
+    pub vendor_id: u32,
+    pub device_id: u32,
+    pub driver_version: u32,
+    pub uuid: [u8; vk::UUID_SIZE],
+}
+
+impl PipelineCacheKey {
+    pub fn for_device(instance: &ash::Instance, physical_device: vk::PhysicalDevice) -> PipelineCacheKey {
+        let properties = unsafe { instance.get_physical_device_properties(physical_device) };
+        PipelineCacheKey {
+            vendor_id: properties.vendor_id,
+            device_id: properties.device_id,
+            driver_version: properties.driver_version,
+            uuid: properties.pipeline_cache_uuid,
+        }
+    }
+
+    /// Cache file for this driver. The driver version is only in the name, as the cache header doesn't carry it.
+    pub fn path(&self) -> PathBuf {
+        Path::new(PIPELINE_CACHE_DIR).join(self.file_name())
+    }
+
+    fn file_name(&self) -> String {
+        let uuid: String = self.uuid.iter().map(|byte| format!("{:02x}", byte)).collect();
+        format!("{}{:08x}_{}.bin", self.device_prefix(), self.driver_version, uuid)
+    }
+
+    /// Start of the file names of every cache this GPU has had, whatever the driver.
+    fn device_prefix(&self) -> String {
+        format!("pipelines_{:04x}_{:04x}_", self.vendor_id, self.device_id)
+    }
+
+    /// Whether `file_name` is a cache this GPU got from another driver, which nothing will read again.
+    fn is_stale(&self, file_name: &str) -> bool {
+        file_name.starts_with(&self.device_prefix()) && file_name.ends_with(".bin") && file_name != self.file_name()
+    }
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+/// Fails unless `data` starts with a version one header written for `key`'s device.
+pub fn check_header(data: &[u8], key: &PipelineCacheKey) -> Result<(), String> {
+    if data.len() < HEADER_SIZE {
+        return Err(format!("{} bytes is too short for a header", data.len()));
+    }
+    let header_size = read_u32(data, 0) as usize;
+    if header_size < HEADER_SIZE || header_size > data.len() {
+        return Err(format!("bad header length {}", header_size));
+    }
+    let header_version = read_u32(data, 4);
+    if header_version != vk::PipelineCacheHeaderVersion::ONE.as_raw() as u32 {
+        return Err(format!("unknown header version {}", header_version));
+    }
+    if read_u32(data, 8) != key.vendor_id || read_u32(data, 12) != key.device_id {
+        return Err(format!("built for device {:04x}:{:04x}", read_u32(data, 8), read_u32(data, 12)));
+    }
+    if data[16..HEADER_SIZE] != key.uuid {
+        return Err("built by another driver".to_string());
+    }
+    Ok(())
+}
+
+/// Creates a pipeline cache, seeded from `key`'s cache file if there is a valid one.
+pub fn create_pipeline_cache(device: &ash::Device, key: &PipelineCacheKey) -> vk::PipelineCache {
+    let path = key.path();
+    let initial_data = match fs::read(&path) {
+        Ok(data) => match check_header(&data, key) {
+            Ok(()) => data,
+            Err(reason) => {
+                println!("Ignoring pipeline cache {:?}: {}", path, reason);
+                vec![]
+            }
+        },
+        Err(_) => vec![],
+    };
+
+    let pipeline_cache_create_info = vk::PipelineCacheCreateInfo {
+        s_type: vk::StructureType::PIPELINE_CACHE_CREATE_INFO,
+        p_next: ptr::null(),
+        flags: vk::PipelineCacheCreateFlags::empty(),
+        initial_data_size: initial_data.len(),
+        p_initial_data: initial_data.as_ptr() as *const std::ffi::c_void,
+    };
+
+    unsafe {
+        device
+            .create_pipeline_cache(&pipeline_cache_create_info, None)
+            .expect("Failed to create Pipeline Cache!")
+    }
+}
+
+/// Writes the cache's contents to `key`'s cache file, through a temporary file so a crash never leaves half a cache.
+pub fn save_pipeline_cache(device: &ash::Device, pipeline_cache: vk::PipelineCache, key: &PipelineCacheKey) -> io::Result<()> {
+    let data = unsafe { device.get_pipeline_cache_data(pipeline_cache) }.map_err(|err| io::Error::other(format!("{:?}", err)))?;
+    check_header(&data, key).map_err(|reason| io::Error::new(io::ErrorKind::InvalidData, reason))?;
+
+    let path = key.path();
+    fs::create_dir_all(PIPELINE_CACHE_DIR)?;
+    let temp_path = path.with_extension("bin.tmp");
+    fs::write(&temp_path, &data)?;
+    fs::rename(&temp_path, &path)?;
+    remove_stale_caches(Path::new(PIPELINE_CACHE_DIR), key)
+}
+
+/// Deletes the caches in `dir` that `key`'s GPU got from other drivers. Other GPUs' caches are left alone.
+fn remove_stale_caches(dir: &Path, key: &PipelineCacheKey) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.file_name().and_then(|name| name.to_str()).is_some_and(|name| key.is_stale(name)) {
+            fs::remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key() -> PipelineCacheKey {
+        PipelineCacheKey {
+            vendor_id: 0x10de,
+            device_id: 0x1c03,
+            driver_version: 7,
+            uuid: [3; vk::UUID_SIZE],
+        }
+    }
+
+    /// A version one header for `key` followed by some cache data.
+    fn cache_data(key: &PipelineCacheKey) -> Vec<u8> {
+        let mut data = vec![];
+        data.extend_from_slice(&(HEADER_SIZE as u32).to_le_bytes());
+        data.extend_from_slice(&(vk::PipelineCacheHeaderVersion::ONE.as_raw() as u32).to_le_bytes());
+        data.extend_from_slice(&key.vendor_id.to_le_bytes());
+        data.extend_from_slice(&key.device_id.to_le_bytes());
+        data.extend_from_slice(&key.uuid);
+        data.extend_from_slice(&[1, 2, 3, 4]);
+        data
+    }
+
+    #[test]
+    fn headers_for_the_device_are_accepted() {
+        assert_eq!(check_header(&cache_data(&key()), &key()), Ok(()));
+        assert_eq!(check_header(&cache_data(&key())[..HEADER_SIZE], &key()), Ok(()));
+    }
+
+    #[test]
+    fn short_data_is_rejected() {
+        let data = cache_data(&key());
+        assert!(check_header(&data[..HEADER_SIZE - 1], &key()).unwrap_err().contains("too short"));
+        assert!(check_header(&[], &key()).is_err());
+        let mut data = data;
+        data[0] = 200;
+        assert!(check_header(&data, &key()).unwrap_err().contains("header length"));
+    }
+
+    #[test]
+    fn other_header_versions_are_rejected() {
+        let mut data = cache_data(&key());
+        data[4] = 2;
+        assert!(check_header(&data, &key()).unwrap_err().contains("header version"));
+    }
+
+    #[test]
+    fn other_devices_are_rejected() {
+        let other_vendor = PipelineCacheKey { vendor_id: 0x1002, ..key() };
+        let other_device = PipelineCacheKey { device_id: 0x1c02, ..key() };
+        for other in [other_vendor, other_device].iter() {
+            assert!(check_header(&cache_data(other), &key()).unwrap_err().contains("built for device"));
+        }
+    }
+
+    #[test]
+    fn other_drivers_are_rejected() {
+        let mut uuid = key().uuid;
+        uuid[15] = 4;
+        let other_driver = PipelineCacheKey { uuid, ..key() };
+        assert_eq!(check_header(&cache_data(&other_driver), &key()), Err("built by another driver".to_string()));
+    }
+
+    #[test]
+    fn stale_caches_of_the_same_gpu_are_removed() {
+        let dir = std::env::temp_dir().join(format!("ash_test_pipeline_cache_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let old_driver = PipelineCacheKey { driver_version: 6, ..key() };
+        let other_gpu = PipelineCacheKey { device_id: 0x1c02, ..key() };
+        let names = [key().file_name(), old_driver.file_name(), other_gpu.file_name(), "notes.txt".to_string()];
+        for name in names.iter() {
+            fs::write(dir.join(name), b"cache").unwrap();
+        }
+        let result = remove_stale_caches(&dir, &key());
+        let exists: Vec<bool> = names.iter().map(|name| dir.join(name).exists()).collect();
+        fs::remove_dir_all(&dir).unwrap();
+
+        result.unwrap();
+        assert_eq!(exists, vec![true, false, true, true]);
+    }
+}
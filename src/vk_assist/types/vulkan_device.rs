@@ -2,6 +2,7 @@
 //#![allow(unused_imports)]
 
 use crate::app;
+use crate::vk_assist::types::pipeline_cache::{self, PipelineCacheKey};
 use crate::vk_assist::types::{queue_family, vulkan_surface::VulkanSurface, vulkan_swap_chain, vulkan_swap_chain::SwapChainSupportDetail};
 
 use std::ffi::CString;
@@ -14,6 +15,7 @@ use std::sync::Arc;
 use ash::version::DeviceV1_0;
 use ash::version::EntryV1_0;
 use ash::version::InstanceV1_0;
+use ash::version::InstanceV1_1;
 use ash::vk;
 pub const VALIDATION: app::debug::ValidationInfo = app::debug::ValidationInfo {
     is_enable: true,
@@ -26,6 +28,12 @@ pub struct DeviceExtensions {
 }
 
 pub const SWAP_CHAIN_ONLY_EXTENSIONS: DeviceExtensions = DeviceExtensions { names: ["VK_KHR_swapchain"] };
+/// Enabled when the device supports bindless textures.
+pub const DESCRIPTOR_INDEXING_EXTENSIONS: DeviceExtensions = DeviceExtensions {
+    names: ["VK_EXT_descriptor_indexing"],
+};
+/// Length of the bindless texture array. Devices that can't bind that many update-after-bind images don't get bindless.
+pub const MAX_BINDLESS_TEXTURES: u32 = 1024;
 
 pub struct VulkanDevice {
     instance: Arc<ash::Instance>,
@@ -35,14 +43,24 @@ pub struct VulkanDevice {
     pub queue_family: queue_family::QueueFamilyIndices,
     pub graphics_queue: vk::Queue,
     pub present_queue: vk::Queue,
+
+    /// Used for every pipeline created on this device, and saved for the next run by `vk_destroy`.
+    pub pipeline_cache: vk::PipelineCache,
+    pub pipeline_cache_key: PipelineCacheKey,
+    /// Whether `VK_EXT_descriptor_indexing` is enabled with everything bindless textures need.
+    pub descriptor_indexing: bool,
 }
 
 impl VulkanDevice {
     pub fn create_device(instance: Arc<ash::Instance>, surface: &VulkanSurface, required_extensions: DeviceExtensions) -> VulkanDevice {
         let physical_device = pick_physical_device(&instance, &surface, &required_extensions);
-        let (logical_device, queue_family) = create_logical_device(&instance, physical_device, &VALIDATION, &required_extensions, &surface);
+        let descriptor_indexing = supports_descriptor_indexing(&instance, physical_device);
+        let (logical_device, queue_family) =
+            create_logical_device(&instance, physical_device, &VALIDATION, &required_extensions, &surface, descriptor_indexing);
         let graphics_queue = unsafe { logical_device.get_device_queue(queue_family.graphics_family.unwrap(), 0) };
         let present_queue = unsafe { logical_device.get_device_queue(queue_family.present_family.unwrap(), 0) };
+        let pipeline_cache_key = PipelineCacheKey::for_device(&instance, physical_device);
+        let pipeline_cache = pipeline_cache::create_pipeline_cache(&logical_device, &pipeline_cache_key);
 
         VulkanDevice {
             instance: instance.clone(),
@@ -51,12 +69,31 @@ impl VulkanDevice {
             queue_family,
             graphics_queue,
             present_queue,
+            pipeline_cache,
+            pipeline_cache_key,
+            descriptor_indexing,
+        }
+    }
+
+    /// Saves the pipeline cache to disk, then destroys it and the logical device. Everything else created on the device
+    /// has to be destroyed first. Failing to save only costs startup time.
+    pub fn vk_destroy(&self) {
+        if let Err(err) = pipeline_cache::save_pipeline_cache(&self.logical_device, self.pipeline_cache, &self.pipeline_cache_key) {
+            println!("Failed to save pipeline cache {:?}: {}", self.pipeline_cache_key.path(), err);
+        }
+        unsafe {
+            self.logical_device.destroy_pipeline_cache(self.pipeline_cache, None);
+            self.logical_device.destroy_device(None);
         }
     }
 
     pub fn get_physical_device_memory_properties(&self) -> vk::PhysicalDeviceMemoryProperties {
         unsafe { self.instance.get_physical_device_memory_properties(self.physical_device) }
     }
+
+    pub fn get_physical_device_properties(&self) -> vk::PhysicalDeviceProperties {
+        unsafe { self.instance.get_physical_device_properties(self.physical_device) }
+    }
 }
 
 impl Drop for VulkanDevice {
@@ -111,12 +148,47 @@ pub fn is_physical_device_suitable(
     return is_queue_family_supported && is_device_extension_supported && is_swapchain_supported && is_support_sampler_anisotropy;
 }
 
+/// Whether the device can do bindless textures: a runtime-sized, partially bound array of `MAX_BINDLESS_TEXTURES`
+/// sampled images, updated after binding and indexed non-uniformly.
+pub fn supports_descriptor_indexing(instance: &ash::Instance, physical_device: vk::PhysicalDevice) -> bool {
+    let properties = unsafe { instance.get_physical_device_properties(physical_device) };
+    if vk::version_major(properties.api_version) == 1 && vk::version_minor(properties.api_version) == 0 {
+        return false;
+    }
+    if !check_device_extension_support(instance, physical_device, &DESCRIPTOR_INDEXING_EXTENSIONS) {
+        return false;
+    }
+
+    let mut indexing_features = vk::PhysicalDeviceDescriptorIndexingFeatures::default();
+    let mut features = vk::PhysicalDeviceFeatures2 {
+        p_next: &mut indexing_features as *mut _ as *mut c_void,
+        ..Default::default()
+    };
+    let mut indexing_properties = vk::PhysicalDeviceDescriptorIndexingProperties::default();
+    let mut properties2 = vk::PhysicalDeviceProperties2 {
+        p_next: &mut indexing_properties as *mut _ as *mut c_void,
+        ..Default::default()
+    };
+    unsafe {
+        instance.get_physical_device_features2(physical_device, &mut features);
+        instance.get_physical_device_properties2(physical_device, &mut properties2);
+    }
+
+    indexing_features.shader_sampled_image_array_non_uniform_indexing == vk::TRUE
+        && indexing_features.descriptor_binding_sampled_image_update_after_bind == vk::TRUE
+        && indexing_features.descriptor_binding_partially_bound == vk::TRUE
+        && indexing_features.runtime_descriptor_array == vk::TRUE
+        && indexing_properties.max_per_stage_descriptor_update_after_bind_sampled_images >= MAX_BINDLESS_TEXTURES
+        && indexing_properties.max_descriptor_set_update_after_bind_sampled_images >= MAX_BINDLESS_TEXTURES
+}
+
 pub fn create_logical_device(
     instance: &ash::Instance,
     physical_device: vk::PhysicalDevice,
     validation: &app::debug::ValidationInfo,
     device_extensions: &DeviceExtensions,
     surface_stuff: &VulkanSurface,
+    descriptor_indexing: bool,
 ) -> (ash::Device, queue_family::QueueFamilyIndices) {
     let indices = queue_family::find_queue_family(instance, physical_device, surface_stuff);
 
@@ -151,13 +223,29 @@ pub fn create_logical_device(
         .collect();
     let enable_layer_names: Vec<*const c_char> = requred_validation_layer_raw_names.iter().map(|layer_name| layer_name.as_ptr()).collect();
 
-    let enable_extension_raw_names: Vec<CString> = device_extensions.names.iter().map(|name| CString::new(*name).unwrap()).collect();
+    let mut extension_names = device_extensions.names.to_vec();
+    if descriptor_indexing {
+        extension_names.extend_from_slice(&DESCRIPTOR_INDEXING_EXTENSIONS.names);
+    }
+    let enable_extension_raw_names: Vec<CString> = extension_names.iter().map(|name| CString::new(*name).unwrap()).collect();
     // let enable_extension_names = device_extensions.names.get_extensions_raw_names();
     let enable_extension_names: Vec<*const c_char> = enable_extension_raw_names.iter().map(|name| name.as_ptr()).collect();
 
+    let indexing_features = vk::PhysicalDeviceDescriptorIndexingFeatures {
+        shader_sampled_image_array_non_uniform_indexing: vk::TRUE,
+        descriptor_binding_sampled_image_update_after_bind: vk::TRUE,
+        descriptor_binding_partially_bound: vk::TRUE,
+        runtime_descriptor_array: vk::TRUE,
+        ..Default::default()
+    };
+
     let device_create_info = vk::DeviceCreateInfo {
         s_type: vk::StructureType::DEVICE_CREATE_INFO,
-        p_next: ptr::null(),
+        p_next: if descriptor_indexing {
+            &indexing_features as *const _ as *const c_void
+        } else {
+            ptr::null()
+        },
         flags: vk::DeviceCreateFlags::empty(),
         queue_create_info_count: queue_create_infos.len() as u32,
         p_queue_create_infos: queue_create_infos.as_ptr(),
@@ -16,39 +16,156 @@ use std::ffi::CString;
 use std::ptr;
 
 use crate::vk_assist;
-use vk_assist::structures::Vertex;
+use vk_assist::mesh_optimizer;
+use vk_assist::structures::{MeshVertex, SkinVertex};
 use vk_assist::types::buffer as bfr;
 use vk_assist::types::command as cmd;
 use vk_assist::types::image as img;
 use vk_assist::types::{vulkan_device, vulkan_device::VulkanDevice, vulkan_surface::VulkanSurface, vulkan_swap_chain::*};
 
+use super::bounds::{Aabb, BoundingSphere};
+use super::mesh_data::{index_range, MaterialRef, MeshData, MeshLod, Submesh};
+use super::skeleton::Skin;
 use super::MeshSize;
 
 pub struct GFXModel {
-    pub vertices: Vec<Vertex>,
+    pub vertices: Vec<MeshVertex>,
     pub indices: Vec<u32>,
-    pub diffuse_tex: img::Image,
+    /// Type the index buffer is uploaded as. Indices are always kept as u32 on the CPU side.
+    pub index_type: vk::IndexType,
+    pub submeshes: Vec<Submesh>,
+    pub lods: Vec<MeshLod>,
+    pub materials: Vec<MaterialRef>,
+    pub bounds: Aabb,
+    pub bounding_sphere: BoundingSphere,
+    /// Joints, weights and animations for skinned meshes, drawn with the skinned pipeline.
+    pub skin: Option<Skin>,
+    /// Uploaded by the asset registry, which destroys them once no frame in flight draws the model anymore.
+    pub buffers: Option<MeshBuffers>,
 }
 
 impl GFXModel {
-    pub fn new(vertices: Vec<Vertex>, indices: Vec<u32>, diffuse_tex: img::Image) -> GFXModel {
+    pub fn new(vertices: Vec<MeshVertex>, indices: Vec<u32>) -> GFXModel {
+        GFXModel::from_mesh_data(MeshData::new(vertices, indices))
+    }
+
+    /// Textures aren't part of the model, instances get theirs from the material they are drawn with.
+    pub fn from_mesh_data(mesh: MeshData) -> GFXModel {
         GFXModel {
-            vertices,
-            indices,
-            diffuse_tex,
+            index_type: mesh_optimizer::index_type_for(mesh.vertices.len()),
+            bounding_sphere: BoundingSphere::from_vertices(&mesh.vertices),
+            skin: None,
+            vertices: mesh.vertices,
+            indices: mesh.indices,
+            submeshes: mesh.submeshes,
+            lods: mesh.lods,
+            materials: mesh.materials,
+            bounds: mesh.bounds,
+            buffers: None,
+        }
+    }
+
+    pub fn from_skinned_mesh_data(mesh: MeshData, skin: Skin) -> GFXModel {
+        assert_eq!(mesh.vertices.len(), skin.vertices.len(), "Skin data must have one entry per vertex!");
+        let mut model = GFXModel::from_mesh_data(mesh);
+        model.skin = Some(skin);
+        model
+    }
+
+    pub fn skin_size(&self) -> vk::DeviceSize {
+        self.skin
+            .as_ref()
+            .map_or(0, |skin| (skin.vertices.len() * std::mem::size_of::<SkinVertex>()) as vk::DeviceSize)
+    }
+
+    /// Levels of detail including the full mesh, which is level 0.
+    pub fn lod_count(&self) -> usize {
+        self.lods.len() + 1
+    }
+
+    /// Submeshes of `level`, clamped to the coarsest level available.
+    pub fn lod_submeshes(&self, level: usize) -> &[Submesh] {
+        match level.min(self.lods.len()) {
+            0 => &self.submeshes,
+            level => &self.lods[level - 1].submeshes,
+        }
+    }
+
+    /// First index and index count of `level`, for drawing it in one call.
+    pub fn lod_range(&self, level: usize) -> (u32, u32) {
+        index_range(self.lod_submeshes(level))
+    }
+
+    /// Uploads the vertices, the indices as `index_type` and the skin if there is one.
+    pub fn upload(&mut self, device: Arc<VulkanDevice>, command_pool: vk::CommandPool) {
+        self.buffers = Some(MeshBuffers::upload(device, command_pool, self));
+    }
+
+    /// Destroys the GPU buffers, once no frame in flight draws the model.
+    pub fn vk_destroy(&self) {
+        if let Some(buffers) = self.buffers.as_ref() {
+            buffers.vk_destroy();
+        }
+    }
+}
+
+/// Vertex and index buffers of one model, with the joints and weights of skinned ones in a second vertex buffer.
+pub struct MeshBuffers {
+    pub vertex: bfr::Buffer,
+    pub index: bfr::Buffer,
+    pub index_type: vk::IndexType,
+    pub skin: Option<bfr::Buffer>,
+}
+
+impl MeshBuffers {
+    pub fn upload(device: Arc<VulkanDevice>, command_pool: vk::CommandPool, model: &GFXModel) -> MeshBuffers {
+        let vertex = bfr::create_device_local_buffer(device.clone(), command_pool, &model.vertices, vk::BufferUsageFlags::VERTEX_BUFFER);
+        let index = if model.index_type == vk::IndexType::UINT16 {
+            let short_indices: Vec<u16> = model.indices.iter().map(|&index| index as u16).collect();
+            bfr::create_device_local_buffer(device.clone(), command_pool, &short_indices, vk::BufferUsageFlags::INDEX_BUFFER)
+        } else {
+            bfr::create_device_local_buffer(device.clone(), command_pool, &model.indices, vk::BufferUsageFlags::INDEX_BUFFER)
+        };
+        let skin = model
+            .skin
+            .as_ref()
+            .map(|skin| bfr::create_device_local_buffer(device.clone(), command_pool, &skin.vertices, vk::BufferUsageFlags::VERTEX_BUFFER));
+        MeshBuffers {
+            vertex,
+            index,
+            index_type: model.index_type,
+            skin,
         }
     }
 
-    pub fn vk_destroy(&mut self) {
-        self.diffuse_tex.vk_destroy();
+    /// Binds the vertex buffer, followed by the skin buffer when `skinned`, and the index buffer.
+    pub fn bind(&self, device: &ash::Device, command_buffer: vk::CommandBuffer, skinned: bool) {
+        unsafe {
+            match self.skin.as_ref().filter(|_| skinned) {
+                Some(skin) => device.cmd_bind_vertex_buffers(command_buffer, 0, &[self.vertex.buffer, skin.buffer], &[0, 0]),
+                None => device.cmd_bind_vertex_buffers(command_buffer, 0, &[self.vertex.buffer], &[0]),
+            }
+            device.cmd_bind_index_buffer(command_buffer, self.index.buffer, 0, self.index_type);
+        }
+    }
+
+    /// Takes `&self` since the model holding the buffers is shared with the instances drawing it.
+    pub fn vk_destroy(&self) {
+        for buffer in [&self.vertex, &self.index].iter().copied().chain(self.skin.as_ref()) {
+            let device = buffer.get_device();
+            unsafe {
+                device.destroy_buffer(buffer.buffer, None);
+                device.free_memory(buffer.memory, None);
+            }
+        }
     }
 }
 
 impl MeshSize for GFXModel {
     fn vertices_size(&self) -> vk::DeviceSize {
-        self.vertices.len() as u64 * std::mem::size_of::<Vertex>() as u64
+        self.vertices.len() as u64 * std::mem::size_of::<MeshVertex>() as u64
     }
     fn indices_size(&self) -> vk::DeviceSize {
-        self.indices.len() as u64 * std::mem::size_of::<u32>() as u64
+        self.indices.len() as u64 * mesh_optimizer::index_size(self.index_type)
     }
 }
@@ -0,0 +1,289 @@
+#![allow(dead_code)]
+
+use nalgebra_glm::{Vec3, Vec4};
+
+use super::skeleton::{slerp, Pose, Skeleton};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Interpolation {
+    Step,
+    Linear,
+    /// Hermite spline; every keyframe stores an in-tangent, the value and an out-tangent, in that order.
+    CubicSpline,
+}
+
+#[derive(Clone, Debug)]
+pub enum ChannelValues {
+    Translation(Vec<Vec3>),
+    /// Quaternions as (x, y, z, w).
+    Rotation(Vec<Vec4>),
+    Scale(Vec<Vec3>),
+}
+
+/// Keyframes for one property of one joint.
+#[derive(Clone, Debug)]
+pub struct Channel {
+    pub joint: usize,
+    pub interpolation: Interpolation,
+    /// Keyframe times in seconds, ascending.
+    pub times: Vec<f32>,
+    pub values: ChannelValues,
+}
+
+#[derive(Clone, Debug)]
+pub struct AnimationClip {
+    pub name: String,
+    pub duration: f32,
+    pub channels: Vec<Channel>,
+}
+
+/// The keyframe pair around `time` and how far between them it is. Times outside the keyframes clamp to the ends.
+fn keyframe_span(times: &[f32], time: f32) -> (usize, usize, f32, f32) {
+    let last = times.len() - 1;
+    if time <= times[0] {
+        return (0, 0, 0.0, 0.0);
+    }
+    if time >= times[last] {
+        return (last, last, 0.0, 0.0);
+    }
+    let next = times.partition_point(|&t| t <= time);
+    let previous = next - 1;
+    let span = times[next] - times[previous];
+    (previous, next, (time - times[previous]) / span, span)
+}
+
+/// Cubic Hermite interpolation between `p0` and `p1` with out-tangent `m0` and in-tangent `m1`, as glTF defines it.
+fn hermite<T>(p0: T, m0: T, p1: T, m1: T, t: f32, span: f32) -> T
+where
+    T: std::ops::Mul<f32, Output = T> + std::ops::Add<Output = T>,
+{
+    let t2 = t * t;
+    let t3 = t2 * t;
+    p0 * (2.0 * t3 - 3.0 * t2 + 1.0) + m0 * ((t3 - 2.0 * t2 + t) * span) + p1 * (-2.0 * t3 + 3.0 * t2) + m1 * ((t3 - t2) * span)
+}
+
+fn sample_vec3(values: &[Vec3], interpolation: Interpolation, span: (usize, usize, f32, f32)) -> Vec3 {
+    let (previous, next, t, duration) = span;
+    match interpolation {
+        Interpolation::Step => values[previous],
+        Interpolation::Linear => values[previous] + (values[next] - values[previous]) * t,
+        Interpolation::CubicSpline => {
+            if previous == next {
+                return values[previous * 3 + 1];
+            }
+            hermite(
+                values[previous * 3 + 1],
+                values[previous * 3 + 2],
+                values[next * 3 + 1],
+                values[next * 3],
+                t,
+                duration,
+            )
+        }
+    }
+}
+
+fn sample_rotation(values: &[Vec4], interpolation: Interpolation, span: (usize, usize, f32, f32)) -> Vec4 {
+    let (previous, next, t, duration) = span;
+    match interpolation {
+        Interpolation::Step => values[previous],
+        Interpolation::Linear => slerp(&values[previous], &values[next], t),
+        Interpolation::CubicSpline => {
+            if previous == next {
+                return values[previous * 3 + 1].normalize();
+            }
+            hermite(
+                values[previous * 3 + 1],
+                values[previous * 3 + 2],
+                values[next * 3 + 1],
+                values[next * 3],
+                t,
+                duration,
+            )
+            .normalize()
+        }
+    }
+}
+
+impl AnimationClip {
+    /// Overwrites the joints this clip animates with their values at `time`. Joints it doesn't touch keep what `pose`
+    /// already had, so start from the rest pose.
+    pub fn sample(&self, time: f32, pose: &mut Pose) {
+        for channel in self.channels.iter() {
+            if channel.times.is_empty() || channel.joint >= pose.locals.len() {
+                continue;
+            }
+            let span = keyframe_span(&channel.times, time);
+            let local = &mut pose.locals[channel.joint];
+            match &channel.values {
+                ChannelValues::Translation(values) => local.translation = sample_vec3(values, channel.interpolation, span),
+                ChannelValues::Rotation(values) => local.rotation = sample_rotation(values, channel.interpolation, span),
+                ChannelValues::Scale(values) => local.scale = sample_vec3(values, channel.interpolation, span),
+            }
+        }
+    }
+}
+
+/// One clip playing on an instance.
+#[derive(Clone, Copy, Debug)]
+pub struct AnimationLayer {
+    /// Index into the model's clips.
+    pub clip: usize,
+    pub time: f32,
+    pub speed: f32,
+    /// Relative contribution when several layers play at once.
+    pub weight: f32,
+    pub looping: bool,
+}
+
+/// Per-instance playback state: any number of clips blended by weight.
+#[derive(Clone, Debug, Default)]
+pub struct AnimationPlayer {
+    pub layers: Vec<AnimationLayer>,
+}
+
+impl AnimationPlayer {
+    pub fn new(clip: usize) -> AnimationPlayer {
+        AnimationPlayer {
+            layers: vec![AnimationLayer {
+                clip,
+                time: 0.0,
+                speed: 1.0,
+                weight: 1.0,
+                looping: true,
+            }],
+        }
+    }
+
+    pub fn advance(&mut self, delta_t: f32, clips: &[AnimationClip]) {
+        for layer in self.layers.iter_mut() {
+            let duration = clips.get(layer.clip).map_or(0.0, |clip| clip.duration);
+            layer.time += delta_t * layer.speed;
+            if duration <= 0.0 {
+                layer.time = 0.0;
+            } else if layer.looping {
+                layer.time = layer.time.rem_euclid(duration);
+            } else {
+                layer.time = layer.time.clamp(0.0, duration);
+            }
+        }
+    }
+
+    /// Samples every layer and blends them by weight. With no weighted layers this is the rest pose.
+    pub fn pose(&self, skeleton: &Skeleton, clips: &[AnimationClip]) -> Pose {
+        let rest = skeleton.rest_pose();
+        let mut result = rest.clone();
+        let mut total_weight = 0.0;
+        for layer in self.layers.iter() {
+            let clip = match clips.get(layer.clip) {
+                Some(clip) if layer.weight > 0.0 => clip,
+                _ => continue,
+            };
+            let mut sampled = rest.clone();
+            clip.sample(layer.time, &mut sampled);
+            total_weight += layer.weight;
+            // Running weighted average: the n-th layer moves the result by its share of the total so far.
+            result = result.blend(&sampled, layer.weight / total_weight);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vk_model::skeleton::{Joint, JointTransform};
+    use nalgebra_glm::Mat4;
+
+    fn one_joint_skeleton() -> Skeleton {
+        Skeleton::new(vec![Joint {
+            name: String::from("root"),
+            parent: None,
+            inverse_bind: Mat4::identity(),
+            rest: JointTransform::identity(),
+            base: Mat4::identity(),
+        }])
+    }
+
+    fn slide_clip(interpolation: Interpolation) -> AnimationClip {
+        AnimationClip {
+            name: String::from("slide"),
+            duration: 2.0,
+            channels: vec![Channel {
+                joint: 0,
+                interpolation,
+                times: vec![0.0, 2.0],
+                values: ChannelValues::Translation(vec![Vec3::zeros(), Vec3::new(4.0, 0.0, 0.0)]),
+            }],
+        }
+    }
+
+    fn sampled_x(clip: &AnimationClip, time: f32) -> f32 {
+        let mut pose = one_joint_skeleton().rest_pose();
+        clip.sample(time, &mut pose);
+        pose.locals[0].translation.x
+    }
+
+    #[test]
+    fn keyframe_span_clamps_and_interpolates() {
+        let times = [0.0, 1.0, 3.0];
+        assert_eq!(keyframe_span(&times, -1.0), (0, 0, 0.0, 0.0));
+        assert_eq!(keyframe_span(&times, 5.0), (2, 2, 0.0, 0.0));
+        assert_eq!(keyframe_span(&times, 2.0), (1, 2, 0.5, 2.0));
+        assert_eq!(keyframe_span(&times, 1.0), (1, 2, 0.0, 2.0));
+    }
+
+    #[test]
+    fn linear_and_step_sampling() {
+        assert!((sampled_x(&slide_clip(Interpolation::Linear), 0.5) - 1.0).abs() < 1e-6);
+        assert_eq!(sampled_x(&slide_clip(Interpolation::Step), 1.5), 0.0);
+        assert_eq!(sampled_x(&slide_clip(Interpolation::Linear), 9.0), 4.0);
+    }
+
+    #[test]
+    fn cubic_spline_with_flat_tangents_eases() {
+        let clip = AnimationClip {
+            channels: vec![Channel {
+                interpolation: Interpolation::CubicSpline,
+                values: ChannelValues::Translation(vec![
+                    Vec3::zeros(),
+                    Vec3::zeros(),
+                    Vec3::zeros(),
+                    Vec3::zeros(),
+                    Vec3::new(4.0, 0.0, 0.0),
+                    Vec3::zeros(),
+                ]),
+                ..slide_clip(Interpolation::Linear).channels[0].clone()
+            }],
+            ..slide_clip(Interpolation::Linear)
+        };
+        assert!((sampled_x(&clip, 1.0) - 2.0).abs() < 1e-5);
+        // Smoothstep is slower than linear near the ends.
+        assert!(sampled_x(&clip, 0.5) < 1.0);
+    }
+
+    #[test]
+    fn player_loops_or_clamps() {
+        let clips = [slide_clip(Interpolation::Linear)];
+        let mut player = AnimationPlayer::new(0);
+        player.advance(2.5, &clips);
+        assert!((player.layers[0].time - 0.5).abs() < 1e-6);
+        player.layers[0].looping = false;
+        player.advance(5.0, &clips);
+        assert_eq!(player.layers[0].time, 2.0);
+    }
+
+    #[test]
+    fn player_blends_layers_by_weight() {
+        let clips = [slide_clip(Interpolation::Linear)];
+        let mut player = AnimationPlayer::new(0);
+        player.layers.push(AnimationLayer {
+            time: 2.0,
+            weight: 3.0,
+            ..player.layers[0]
+        });
+        let pose = player.pose(&one_joint_skeleton(), &clips);
+        // One part at x = 0, three parts at x = 4.
+        assert!((pose.locals[0].translation.x - 3.0).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,256 @@
+#![allow(dead_code)]
+
+use nalgebra_glm::{Mat4, Vec3, Vec4};
+
+use crate::vk_assist::structures::MeshVertex;
+
+/// Axis aligned bounding box in mesh space.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    /// An inverted box that any point will grow; `is_empty` stays true until something is added.
+    pub fn empty() -> Aabb {
+        Aabb {
+            min: Vec3::new(f32::MAX, f32::MAX, f32::MAX),
+            max: Vec3::new(f32::MIN, f32::MIN, f32::MIN),
+        }
+    }
+
+    pub fn from_vertices(vertices: &[MeshVertex]) -> Aabb {
+        let mut aabb = Aabb::empty();
+        for vertex in vertices.iter() {
+            aabb.grow(&vertex.pos);
+        }
+        aabb
+    }
+
+    pub fn grow(&mut self, point: &Vec3) {
+        self.min = nalgebra_glm::min2(&self.min, point);
+        self.max = nalgebra_glm::max2(&self.max, point);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn extents(&self) -> Vec3 {
+        (self.max - self.min) * 0.5
+    }
+
+    /// Box around this box after `transform`, e.g. a model matrix. Looser than re-bounding the vertices but O(1).
+    pub fn transformed(&self, transform: &Mat4) -> Aabb {
+        if self.is_empty() {
+            return *self;
+        }
+        // Arvo's method: each output axis is the translation plus the extremes of every matrix term times the input axis.
+        let mut min = Vec3::new(transform[(0, 3)], transform[(1, 3)], transform[(2, 3)]);
+        let mut max = min;
+        for row in 0..3 {
+            for column in 0..3 {
+                let a = transform[(row, column)] * self.min[column];
+                let b = transform[(row, column)] * self.max[column];
+                min[row] += a.min(b);
+                max[row] += a.max(b);
+            }
+        }
+        Aabb { min, max }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BoundingSphere {
+    pub center: Vec3,
+    pub radius: f32,
+}
+
+impl BoundingSphere {
+    /// Sphere around the box centre reaching the furthest vertex, which is tighter than the box's own circumsphere.
+    pub fn from_vertices(vertices: &[MeshVertex]) -> BoundingSphere {
+        let aabb = Aabb::from_vertices(vertices);
+        if aabb.is_empty() {
+            return BoundingSphere {
+                center: Vec3::zeros(),
+                radius: 0.0,
+            };
+        }
+        let center = aabb.center();
+        let radius = vertices.iter().map(|vertex| (vertex.pos - center).norm()).fold(0.0, f32::max);
+        BoundingSphere { center, radius }
+    }
+
+    /// Scales the radius by the largest axis scale, so the result stays conservative under non-uniform scaling.
+    pub fn transformed(&self, transform: &Mat4) -> BoundingSphere {
+        let center = transform * Vec4::new(self.center.x, self.center.y, self.center.z, 1.0);
+        let scale = (0..3)
+            .map(|column| Vec3::new(transform[(0, column)], transform[(1, column)], transform[(2, column)]).norm())
+            .fold(0.0, f32::max);
+        BoundingSphere {
+            center: center.xyz(),
+            radius: self.radius * scale,
+        }
+    }
+}
+
+/// The six clip planes of a view-projection, as (normal, distance) with normals pointing inwards.
+#[derive(Clone, Copy, Debug)]
+pub struct Frustum {
+    pub planes: [Vec4; 6],
+}
+
+impl Frustum {
+    /// Gribb/Hartmann plane extraction. The near plane is z >= 0 because Vulkan clips depth to [0, w].
+    pub fn from_view_proj(view_proj: &Mat4) -> Frustum {
+        let row = |i: usize| Vec4::new(view_proj[(i, 0)], view_proj[(i, 1)], view_proj[(i, 2)], view_proj[(i, 3)]);
+        let (x, y, z, w) = (row(0), row(1), row(2), row(3));
+        let mut planes = [w + x, w - x, w + y, w - y, z, w - z];
+        for plane in planes.iter_mut() {
+            let length = plane.xyz().norm();
+            if length > 0.0 {
+                *plane /= length;
+            }
+        }
+        Frustum { planes }
+    }
+
+    fn distance(plane: &Vec4, point: &Vec3) -> f32 {
+        plane.xyz().dot(point) + plane.w
+    }
+
+    pub fn intersects_sphere(&self, sphere: &BoundingSphere) -> bool {
+        self.planes.iter().all(|plane| Frustum::distance(plane, &sphere.center) >= -sphere.radius)
+    }
+
+    /// False only when the box is fully behind one of the planes, so boxes near frustum corners may pass.
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            // The corner furthest along the plane normal; if that one is outside, all of them are.
+            let corner = Vec3::new(
+                if plane.x >= 0.0 { aabb.max.x } else { aabb.min.x },
+                if plane.y >= 0.0 { aabb.max.y } else { aabb.min.y },
+                if plane.z >= 0.0 { aabb.max.z } else { aabb.min.z },
+            );
+            Frustum::distance(plane, &corner) >= 0.0
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    /// The scene camera's projection: 45 degrees vertically, Y flipped for Vulkan, looking down -Z from the origin.
+    fn camera_frustum() -> Frustum {
+        let mut proj = nalgebra_glm::perspective(1.0, PI / 4.0, 0.1, 100.0);
+        proj = nalgebra_glm::scale(&proj, &Vec3::new(1.0, -1.0, 1.0));
+        let view = nalgebra_glm::look_at(&Vec3::zeros(), &Vec3::new(0.0, 0.0, -1.0), &Vec3::new(0.0, 1.0, 0.0));
+        Frustum::from_view_proj(&(proj * view))
+    }
+
+    fn sphere(x: f32, y: f32, z: f32, radius: f32) -> BoundingSphere {
+        BoundingSphere {
+            center: Vec3::new(x, y, z),
+            radius,
+        }
+    }
+
+    fn cube(x: f32, y: f32, z: f32, half_size: f32) -> Aabb {
+        let half = Vec3::new(half_size, half_size, half_size);
+        let center = Vec3::new(x, y, z);
+        Aabb {
+            min: center - half,
+            max: center + half,
+        }
+    }
+
+    /// Half the frustum's width and height at `depth`.
+    fn half_size_at(depth: f32) -> f32 {
+        depth * (PI / 8.0).tan()
+    }
+
+    #[test]
+    fn volumes_in_view_pass() {
+        let frustum = camera_frustum();
+        assert!(frustum.intersects_sphere(&sphere(0.0, 0.0, -10.0, 1.0)));
+        assert!(frustum.intersects_aabb(&cube(0.0, 0.0, -10.0, 1.0)));
+        assert!(frustum.intersects_sphere(&sphere(3.0, -3.0, -10.0, 0.5)));
+        assert!(frustum.intersects_aabb(&cube(3.0, -3.0, -10.0, 0.5)));
+    }
+
+    #[test]
+    fn volumes_outside_each_side_are_culled() {
+        let frustum = camera_frustum();
+        let edge = half_size_at(10.0);
+        for &(x, y) in [(-edge - 2.0, 0.0), (edge + 2.0, 0.0), (0.0, -edge - 2.0), (0.0, edge + 2.0)].iter() {
+            assert!(!frustum.intersects_sphere(&sphere(x, y, -10.0, 1.0)), "sphere at ({}, {})", x, y);
+            assert!(!frustum.intersects_aabb(&cube(x, y, -10.0, 1.0)), "box at ({}, {})", x, y);
+        }
+        assert!(!frustum.intersects_sphere(&sphere(0.0, 0.0, -110.0, 1.0)));
+        assert!(!frustum.intersects_aabb(&cube(0.0, 0.0, -110.0, 1.0)));
+    }
+
+    #[test]
+    fn volumes_behind_the_near_plane_are_culled() {
+        let frustum = camera_frustum();
+        assert!(!frustum.intersects_sphere(&sphere(0.0, 0.0, 5.0, 1.0)));
+        assert!(!frustum.intersects_aabb(&cube(0.0, 0.0, 5.0, 1.0)));
+        assert!(!frustum.intersects_sphere(&sphere(0.0, 0.0, -0.05, 0.01)));
+        assert!(!frustum.intersects_aabb(&cube(0.0, 0.0, -0.05, 0.01)));
+        // Reaching through the near plane is enough to be drawn.
+        assert!(frustum.intersects_sphere(&sphere(0.0, 0.0, 0.5, 1.0)));
+        assert!(frustum.intersects_aabb(&cube(0.0, 0.0, 0.5, 1.0)));
+    }
+
+    #[test]
+    fn volumes_straddling_a_plane_pass() {
+        let frustum = camera_frustum();
+        let edge = half_size_at(10.0);
+        for &(x, y) in [(edge, 0.0), (-edge, 0.0), (0.0, edge), (0.0, -edge)].iter() {
+            assert!(frustum.intersects_sphere(&sphere(x, y, -10.0, 0.5)), "sphere at ({}, {})", x, y);
+            assert!(frustum.intersects_aabb(&cube(x, y, -10.0, 0.5)), "box at ({}, {})", x, y);
+        }
+        assert!(frustum.intersects_sphere(&sphere(0.0, 0.0, -100.0, 1.0)));
+        assert!(frustum.intersects_aabb(&cube(0.0, 0.0, -100.0, 1.0)));
+    }
+
+    #[test]
+    fn rotated_boxes_bound_their_corners() {
+        let transform = nalgebra_glm::translation(&Vec3::new(5.0, 0.0, -2.0))
+            * nalgebra_glm::rotation(PI / 4.0, &Vec3::new(0.0, 1.0, 0.0))
+            * nalgebra_glm::scaling(&Vec3::new(2.0, 1.0, 1.0));
+        let aabb = cube(0.0, 0.0, 0.0, 1.0);
+        let transformed = aabb.transformed(&transform);
+
+        let mut corners = Aabb::empty();
+        for i in 0..8 {
+            let corner = Vec3::new(
+                if i & 1 == 0 { aabb.min.x } else { aabb.max.x },
+                if i & 2 == 0 { aabb.min.y } else { aabb.max.y },
+                if i & 4 == 0 { aabb.min.z } else { aabb.max.z },
+            );
+            corners.grow(&(transform * Vec4::new(corner.x, corner.y, corner.z, 1.0)).xyz());
+        }
+        assert!((transformed.min - corners.min).norm() < 1e-5, "{:?} vs {:?}", transformed, corners);
+        assert!((transformed.max - corners.max).norm() < 1e-5, "{:?} vs {:?}", transformed, corners);
+        // Both 45 degree axes reach (2 + 1) / sqrt(2) from the centre.
+        assert!((transformed.extents().x - 3.0 / 2.0_f32.sqrt()).abs() < 1e-5);
+        assert!((transformed.center() - Vec3::new(5.0, 0.0, -2.0)).norm() < 1e-5);
+        assert!(Aabb::empty().transformed(&transform).is_empty());
+    }
+
+    #[test]
+    fn spheres_scale_by_the_largest_axis() {
+        let transform = nalgebra_glm::translation(&Vec3::new(1.0, 2.0, 3.0)) * nalgebra_glm::scaling(&Vec3::new(1.0, 3.0, 2.0));
+        let transformed = sphere(1.0, 0.0, 0.0, 2.0).transformed(&transform);
+        assert_eq!(transformed.center, Vec3::new(2.0, 2.0, 3.0));
+        assert_eq!(transformed.radius, 6.0);
+    }
+}
@@ -0,0 +1,69 @@
+#![allow(dead_code)]
+
+use crate::vk_assist::structures::MeshVertex;
+
+use super::bounds::Aabb;
+
+/// A range of the index buffer drawn with one material.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Submesh {
+    pub first_index: u32,
+    pub index_count: u32,
+    /// Index into `MeshData::materials`.
+    pub material: Option<u32>,
+}
+
+/// Material as named by the source file. Only references are kept here, textures are loaded separately.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MaterialRef {
+    pub name: String,
+    /// Relative to the working directory, or absolute. Empty for none.
+    pub diffuse_texture: String,
+}
+
+/// A reduced level of detail. Its submeshes mirror the full mesh's, in the same order, over their own index range.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MeshLod {
+    pub submeshes: Vec<Submesh>,
+    /// Upper bound on the geometric error against the full mesh, in mesh units.
+    pub error: f32,
+}
+
+/// First index and index count covering all of `submeshes`, which must be contiguous and in order.
+pub fn index_range(submeshes: &[Submesh]) -> (u32, u32) {
+    match (submeshes.first(), submeshes.last()) {
+        (Some(first), Some(last)) => (first.first_index, last.first_index + last.index_count - first.first_index),
+        _ => (0, 0),
+    }
+}
+
+/// Processed, GPU-ready mesh data as produced by the importers and stored in the baked mesh cache.
+#[derive(Clone, Debug)]
+pub struct MeshData {
+    pub vertices: Vec<MeshVertex>,
+    pub indices: Vec<u32>,
+    pub submeshes: Vec<Submesh>,
+    pub materials: Vec<MaterialRef>,
+    /// Reduced levels after the full mesh, coarsest last. Their vertices and indices are appended to the same buffers.
+    pub lods: Vec<MeshLod>,
+    pub bounds: Aabb,
+}
+
+impl MeshData {
+    /// Wraps a plain vertex/index list as a single submesh without material.
+    pub fn new(vertices: Vec<MeshVertex>, indices: Vec<u32>) -> MeshData {
+        let bounds = Aabb::from_vertices(&vertices);
+        MeshData {
+            submeshes: vec![Submesh {
+                first_index: 0,
+                index_count: indices.len() as u32,
+                material: None,
+            }],
+            materials: Vec::new(),
+            lods: Vec::new(),
+            bounds,
+            vertices,
+            indices,
+        }
+    }
+}
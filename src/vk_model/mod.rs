@@ -1,7 +1,11 @@
 pub mod abstract_model;
 pub mod advanced_model;
+pub mod animation;
 pub mod basic_model;
+pub mod bounds;
 pub mod intermediate_model;
+pub mod mesh_data;
+pub mod skeleton;
 
 use ash::vk;
 
@@ -0,0 +1,323 @@
+#![allow(dead_code)]
+
+use nalgebra_glm::{Mat4, Vec3, Vec4};
+
+use crate::vk_assist::structures::{MeshVertex, SkinVertex};
+
+use super::animation::AnimationClip;
+use super::bounds::Aabb;
+
+/// Local transform of a joint relative to its parent. `rotation` is a unit quaternion stored as (x, y, z, w), the same
+/// order glTF uses.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct JointTransform {
+    pub translation: Vec3,
+    pub rotation: Vec4,
+    pub scale: Vec3,
+}
+
+impl JointTransform {
+    pub fn identity() -> JointTransform {
+        JointTransform {
+            translation: Vec3::zeros(),
+            rotation: Vec4::new(0.0, 0.0, 0.0, 1.0),
+            scale: Vec3::new(1.0, 1.0, 1.0),
+        }
+    }
+
+    /// translation * rotation * scale.
+    pub fn to_mat4(&self) -> Mat4 {
+        let q = &self.rotation;
+        let (x, y, z, w) = (q.x, q.y, q.z, q.w);
+        let s = &self.scale;
+        let t = &self.translation;
+        Mat4::new(
+            (1.0 - 2.0 * (y * y + z * z)) * s.x,
+            (2.0 * (x * y - w * z)) * s.y,
+            (2.0 * (x * z + w * y)) * s.z,
+            t.x,
+            (2.0 * (x * y + w * z)) * s.x,
+            (1.0 - 2.0 * (x * x + z * z)) * s.y,
+            (2.0 * (y * z - w * x)) * s.z,
+            t.y,
+            (2.0 * (x * z - w * y)) * s.x,
+            (2.0 * (y * z + w * x)) * s.y,
+            (1.0 - 2.0 * (x * x + y * y)) * s.z,
+            t.z,
+            0.0,
+            0.0,
+            0.0,
+            1.0,
+        )
+    }
+
+    /// Interpolates towards `other` by `t`; rotations take the shortest path.
+    pub fn blend(&self, other: &JointTransform, t: f32) -> JointTransform {
+        JointTransform {
+            translation: self.translation + (other.translation - self.translation) * t,
+            rotation: nlerp(&self.rotation, &other.rotation, t),
+            scale: self.scale + (other.scale - self.scale) * t,
+        }
+    }
+}
+
+/// Normalised linear quaternion interpolation, flipping `b` when needed so the blend goes the short way round.
+pub fn nlerp(a: &Vec4, b: &Vec4, t: f32) -> Vec4 {
+    let b = if a.dot(b) < 0.0 { -b } else { *b };
+    let q = a + (b - a) * t;
+    let length = q.norm();
+    if length > 0.0 {
+        q / length
+    } else {
+        *a
+    }
+}
+
+/// Spherical quaternion interpolation. Falls back to `nlerp` for nearly identical rotations.
+pub fn slerp(a: &Vec4, b: &Vec4, t: f32) -> Vec4 {
+    let mut cos = a.dot(b);
+    let b = if cos < 0.0 {
+        cos = -cos;
+        -b
+    } else {
+        *b
+    };
+    if cos > 0.9995 {
+        return nlerp(a, &b, t);
+    }
+    let angle = cos.acos();
+    let sin = angle.sin();
+    (a * ((1.0 - t) * angle).sin() + b * (t * angle).sin()) / sin
+}
+
+#[derive(Clone, Debug)]
+pub struct Joint {
+    pub name: String,
+    /// Index of the parent joint in `Skeleton::joints`.
+    pub parent: Option<usize>,
+    /// Moves a mesh-space vertex into the joint's space at bind time.
+    pub inverse_bind: Mat4,
+    pub rest: JointTransform,
+    /// Fixed transform of the plain nodes between the joint and its parent joint, or for root joints of whatever sits
+    /// above them in the source scene. Applied under the local transform, so animating the joint keeps it.
+    pub base: Mat4,
+}
+
+#[derive(Clone, Debug)]
+pub struct Skeleton {
+    pub joints: Vec<Joint>,
+    /// Joint indices with every parent before its children.
+    order: Vec<usize>,
+}
+
+impl Skeleton {
+    pub fn new(joints: Vec<Joint>) -> Skeleton {
+        let mut order = Vec::with_capacity(joints.len());
+        let mut placed = vec![false; joints.len()];
+        while order.len() < joints.len() {
+            let before = order.len();
+            for (i, joint) in joints.iter().enumerate() {
+                if !placed[i] && joint.parent.is_none_or(|parent| placed[parent]) {
+                    placed[i] = true;
+                    order.push(i);
+                }
+            }
+            if order.len() == before {
+                panic!("Skeleton joint hierarchy has a cycle!");
+            }
+        }
+        Skeleton { joints, order }
+    }
+
+    pub fn rest_pose(&self) -> Pose {
+        Pose {
+            locals: self.joints.iter().map(|joint| joint.rest).collect(),
+        }
+    }
+
+    /// Skinning matrices for `pose`: each joint's model-space transform times its inverse bind matrix.
+    pub fn joint_matrices(&self, pose: &Pose) -> Vec<Mat4> {
+        let mut globals = vec![Mat4::identity(); self.joints.len()];
+        for &i in self.order.iter() {
+            let joint = &self.joints[i];
+            let parent = match joint.parent {
+                Some(parent) => globals[parent] * joint.base,
+                None => joint.base,
+            };
+            globals[i] = parent * pose.locals[i].to_mat4();
+        }
+        globals
+            .iter()
+            .zip(self.joints.iter())
+            .map(|(global, joint)| global * joint.inverse_bind)
+            .collect()
+    }
+}
+
+/// Local transform of every joint of a skeleton, in joint order.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pose {
+    pub locals: Vec<JointTransform>,
+}
+
+impl Pose {
+    pub fn blend(&self, other: &Pose, t: f32) -> Pose {
+        Pose {
+            locals: self.locals.iter().zip(other.locals.iter()).map(|(a, b)| a.blend(b, t)).collect(),
+        }
+    }
+}
+
+/// Everything a skinned mesh has on top of its plain mesh data. `vertices` runs parallel to the mesh's vertices.
+#[derive(Clone, Debug)]
+pub struct Skin {
+    pub vertices: Vec<SkinVertex>,
+    pub skeleton: Skeleton,
+    pub clips: Vec<AnimationClip>,
+    /// Mesh-space box of the vertices each joint moves, in joint order. Empty for joints that move none.
+    pub joint_bounds: Vec<Aabb>,
+}
+
+impl Skin {
+    /// `vertices` are the mesh's vertices, which `skin_vertices` runs parallel to.
+    pub fn new(vertices: &[MeshVertex], skin_vertices: Vec<SkinVertex>, skeleton: Skeleton, clips: Vec<AnimationClip>) -> Skin {
+        let mut joint_bounds = vec![Aabb::empty(); skeleton.joints.len()];
+        for (vertex, skin_vertex) in vertices.iter().zip(skin_vertices.iter()) {
+            for (k, &joint) in skin_vertex.joints.iter().enumerate() {
+                if skin_vertex.weights[k] > 0.0 && (joint as usize) < joint_bounds.len() {
+                    joint_bounds[joint as usize].grow(&vertex.pos);
+                }
+            }
+        }
+        Skin {
+            vertices: skin_vertices,
+            skeleton,
+            clips,
+            joint_bounds,
+        }
+    }
+
+    /// Box around the mesh skinned with `joint_matrices`. A skinned vertex is a weighted average of its joints' moves,
+    /// so it stays inside the union of every joint's moved box.
+    pub fn posed_bounds(&self, joint_matrices: &[Mat4]) -> Aabb {
+        let mut bounds = Aabb::empty();
+        for (joint_box, matrix) in self.joint_bounds.iter().zip(joint_matrices.iter()) {
+            if !joint_box.is_empty() {
+                let moved = joint_box.transformed(matrix);
+                bounds.grow(&moved.min);
+                bounds.grow(&moved.max);
+            }
+        }
+        bounds
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra_glm::Vec2;
+
+    fn point(m: &Mat4, p: Vec3) -> Vec3 {
+        (m * Vec4::new(p.x, p.y, p.z, 1.0)).xyz()
+    }
+
+    fn quarter_turn_about_z() -> Vec4 {
+        let half = std::f32::consts::FRAC_PI_4;
+        Vec4::new(0.0, 0.0, half.sin(), half.cos())
+    }
+
+    /// A root at the origin and a child two units up behind a plain node, each binding at its rest position.
+    fn two_joint_skeleton() -> Skeleton {
+        let offset = nalgebra_glm::translation(&Vec3::new(0.0, 2.0, 0.0));
+        Skeleton::new(vec![
+            Joint {
+                name: String::from("root"),
+                parent: None,
+                inverse_bind: Mat4::identity(),
+                rest: JointTransform::identity(),
+                base: Mat4::identity(),
+            },
+            Joint {
+                name: String::from("child"),
+                parent: Some(0),
+                inverse_bind: nalgebra_glm::inverse(&offset),
+                rest: JointTransform::identity(),
+                base: offset,
+            },
+        ])
+    }
+
+    #[test]
+    fn rest_pose_gives_identity_skinning() {
+        let skeleton = two_joint_skeleton();
+        for matrix in skeleton.joint_matrices(&skeleton.rest_pose()) {
+            assert!((matrix - Mat4::identity()).abs().max() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn animated_joint_keeps_the_folded_node_transform() {
+        let skeleton = two_joint_skeleton();
+        let mut pose = skeleton.rest_pose();
+        // What an animation channel does: overwrite the child's local rotation.
+        pose.locals[1].rotation = quarter_turn_about_z();
+        let matrices = skeleton.joint_matrices(&pose);
+        // A vertex one unit right of the child's pivot swings round the pivot, which stays two units up.
+        let moved = point(&matrices[1], Vec3::new(1.0, 2.0, 0.0));
+        assert!((moved - Vec3::new(0.0, 3.0, 0.0)).norm() < 1e-5, "{:?}", moved);
+        assert!((point(&matrices[1], Vec3::new(0.0, 2.0, 0.0)) - Vec3::new(0.0, 2.0, 0.0)).norm() < 1e-5);
+    }
+
+    #[test]
+    fn children_follow_their_parent() {
+        let skeleton = two_joint_skeleton();
+        let mut pose = skeleton.rest_pose();
+        pose.locals[0].translation = Vec3::new(5.0, 0.0, 0.0);
+        let matrices = skeleton.joint_matrices(&pose);
+        assert!((point(&matrices[1], Vec3::new(0.0, 2.0, 0.0)) - Vec3::new(5.0, 2.0, 0.0)).norm() < 1e-5);
+    }
+
+    #[test]
+    fn posed_bounds_contain_the_skinned_vertices() {
+        let skeleton = two_joint_skeleton();
+        let positions = [Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.0, 2.0, 0.0), Vec3::new(0.0, 4.0, 0.5)];
+        let vertices: Vec<MeshVertex> = positions.iter().map(|&p| MeshVertex::new(p, Vec3::new(1.0, 1.0, 1.0), Vec2::zeros())).collect();
+        let skin_vertices = vec![
+            SkinVertex {
+                joints: [0, 0, 0, 0],
+                weights: Vec4::new(1.0, 0.0, 0.0, 0.0),
+            },
+            SkinVertex {
+                joints: [1, 0, 0, 0],
+                weights: Vec4::new(1.0, 0.0, 0.0, 0.0),
+            },
+            SkinVertex {
+                joints: [0, 1, 0, 0],
+                weights: Vec4::new(0.5, 0.5, 0.0, 0.0),
+            },
+        ];
+        let skin = Skin::new(&vertices, skin_vertices.clone(), skeleton.clone(), vec![]);
+        assert_eq!(skin.joint_bounds[1].max, Vec3::new(1.0, 4.0, 0.5));
+
+        let mut pose = skeleton.rest_pose();
+        pose.locals[1].rotation = quarter_turn_about_z();
+        let matrices = skeleton.joint_matrices(&pose);
+        let bounds = skin.posed_bounds(&matrices);
+        for (vertex, skin_vertex) in vertices.iter().zip(skin_vertices.iter()) {
+            let mut skinned = Vec3::zeros();
+            for k in 0..4 {
+                skinned += point(&matrices[skin_vertex.joints[k] as usize], vertex.pos) * skin_vertex.weights[k];
+            }
+            for axis in 0..3 {
+                assert!(
+                    skinned[axis] >= bounds.min[axis] - 1e-5 && skinned[axis] <= bounds.max[axis] + 1e-5,
+                    "{:?} {:?}",
+                    skinned,
+                    bounds
+                );
+            }
+        }
+        // The arm now points left, outside the bind pose's box.
+        assert!(bounds.min.x < -1.0);
+    }
+}